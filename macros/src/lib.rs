@@ -0,0 +1,236 @@
+//! Attribute macros for the `zygisk` crate. Use them through `zygisk::attr` with the `macros`
+//! feature enabled, rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::{parse_macro_input, spanned::Spanned, DeriveInput, FnArg, ItemFn, ReturnType};
+
+/// Register a type as the Zygisk module. See `zygisk::attr::zygisk_module`.
+#[proc_macro_attribute]
+pub fn zygisk_module(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    expand_module(attr.into(), input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Parse the only argument of the attributes, `crate = "path"`, for crates that depend on
+/// `zygisk` under another name or re-export it.
+fn parse_crate(attr: TokenStream2, name: &str) -> syn::Result<syn::Path> {
+    let mut krate = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("crate") {
+            let path: syn::LitStr = meta.value()?.parse()?;
+            krate = Some(path.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error(format!(
+                "`#[{name}]` only takes a `crate = \"...\"` argument"
+            )))
+        }
+    });
+    syn::parse::Parser::parse2(parser, attr)?;
+    Ok(krate.unwrap_or_else(|| syn::parse_quote!(::zygisk)))
+}
+
+fn expand_module(attr: TokenStream2, input: DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = parse_crate(attr, "zygisk_module")?;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "a Zygisk module cannot be generic",
+        ));
+    }
+
+    let ident = &input.ident;
+    Ok(quote! {
+        #input
+
+        const _: () = {
+            static MODULE: ::std::sync::OnceLock<#ident> = ::std::sync::OnceLock::new();
+
+            // Constructed by the first call, which happens inside the panic-catching entry glue.
+            fn module() -> &'static #ident {
+                MODULE.get_or_init(<#ident as ::core::default::Default>::default)
+            }
+
+            #krate::zygisk_module!(module());
+        };
+    })
+}
+
+/// Register a function as the root companion handler. See `zygisk::attr::zygisk_companion`.
+#[proc_macro_attribute]
+pub fn zygisk_companion(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemFn);
+    expand_companion(attr.into(), input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_companion(attr: TokenStream2, input: ItemFn) -> syn::Result<TokenStream2> {
+    let krate = parse_crate(attr, "zygisk_companion")?;
+    let sig = &input.sig;
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "a companion handler cannot be generic",
+        ));
+    }
+    if let Some(unsafety) = &sig.unsafety {
+        return Err(syn::Error::new_spanned(
+            unsafety,
+            "a companion handler cannot be `unsafe`",
+        ));
+    }
+    if let ReturnType::Type(_, ty) = &sig.output {
+        return Err(syn::Error::new_spanned(
+            ty,
+            "a companion handler cannot return a value",
+        ));
+    }
+    let param =
+        match sig.inputs.iter().collect::<Vec<_>>()[..] {
+            [FnArg::Typed(param)] => param,
+            [FnArg::Receiver(receiver)] => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "a companion handler has to be a free function",
+                ))
+            }
+            _ => return Err(syn::Error::new(
+                sig.paren_token.span.join(),
+                "a companion handler takes exactly one argument, the `UnixStream` connected to \
+                 the module",
+            )),
+        };
+
+    let ident = &sig.ident;
+    // Spanned to the argument type, so that a wrong type is reported there as a plain mismatch.
+    let call = quote_spanned!(param.ty.span()=> #ident(stream));
+    let body = match sig.asyncness {
+        Some(_) => quote! {
+            #krate::macros::companion_entry_async(
+                socket_fd,
+                #krate::__companion_protocol!(),
+                #krate::companion::BuiltinServices::empty(),
+                ::core::option::Option::None,
+                |stream| #call,
+            )
+        },
+        None => quote! {
+            let ::core::option::Option::Some(stream) = #krate::macros::companion_accept(
+                socket_fd,
+                #krate::__companion_protocol!(),
+                #krate::companion::BuiltinServices::empty(),
+                ::core::option::Option::None,
+                ::core::option::Option::None,
+            ) else {
+                return;
+            };
+            #call
+        },
+    };
+    Ok(quote! {
+        #input
+
+        const _: () = {
+            #[no_mangle]
+            extern "C" fn zygisk_companion_entry(socket_fd: ::std::os::unix::io::RawFd) {
+                #krate::macros::install_panic_hook(env!("CARGO_PKG_NAME"));
+                if #krate::macros::catch_panic(move || { #body }).is_none() {
+                    // Panic messages are written to logcat by the panic hook.
+                    ::std::process::abort();
+                }
+            }
+        };
+    })
+}
+
+#[test]
+fn test_expand_module() {
+    let expanded = expand_module(
+        TokenStream2::new(),
+        syn::parse_quote!(
+            #[derive(Default)]
+            struct MyModule;
+        ),
+    )
+    .unwrap()
+    .to_string();
+    assert!(expanded.contains("struct MyModule"));
+    assert!(expanded.contains(":: zygisk :: zygisk_module ! (module ())"));
+
+    let expanded = expand_module(
+        quote!(crate = "my_crate::zygisk"),
+        syn::parse_quote!(
+            #[derive(Default)]
+            struct MyModule;
+        ),
+    )
+    .unwrap()
+    .to_string();
+    assert!(expanded.contains("my_crate :: zygisk :: zygisk_module ! (module ())"));
+
+    let error = expand_module(
+        TokenStream2::new(),
+        syn::parse_quote!(
+            struct Generic<T>(T);
+        ),
+    );
+    assert!(error.is_err());
+    let error = expand_module(
+        quote!(foo),
+        syn::parse_quote!(
+            struct MyModule;
+        ),
+    );
+    assert!(error.is_err());
+}
+
+#[test]
+fn test_expand_companion() {
+    let expanded = expand_companion(
+        TokenStream2::new(),
+        syn::parse_quote!(
+            fn companion_main(_stream: UnixStream) {}
+        ),
+    )
+    .unwrap()
+    .to_string();
+    assert!(expanded.contains("companion_accept"));
+    assert!(expanded.contains("companion_main (stream)"));
+
+    let expanded = expand_companion(
+        TokenStream2::new(),
+        syn::parse_quote!(
+            async fn companion_main(_stream: UnixStream) {}
+        ),
+    )
+    .unwrap()
+    .to_string();
+    assert!(expanded.contains("companion_entry_async"));
+
+    for input in [
+        syn::parse_quote!(
+            fn no_args() {}
+        ),
+        syn::parse_quote!(
+            fn two_args(_a: UnixStream, _b: i32) {}
+        ),
+        syn::parse_quote!(
+            fn returns(_stream: UnixStream) -> i32 {
+                0
+            }
+        ),
+        syn::parse_quote!(
+            fn generic<T>(_stream: T) {}
+        ),
+        syn::parse_quote!(
+            unsafe fn not_safe(_stream: UnixStream) {}
+        ),
+    ] {
+        assert!(expand_companion(TokenStream2::new(), input).is_err());
+    }
+}
@@ -1,9 +1,13 @@
+#[cfg(feature = "api-v4")]
+use std::os::fd::{AsFd, AsRawFd, RawFd};
 use std::{
     ffi::CStr,
-    os::unix::{
-        net::UnixStream,
-        prelude::{FromRawFd, RawFd},
+    io,
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::net::UnixStream,
     },
+    time::{Duration, Instant},
 };
 
 use crate::jni::{
@@ -11,9 +15,48 @@ use crate::jni::{
     sys::{jint, JNINativeMethod},
     JNIEnv,
 };
+#[cfg(feature = "api-v4")]
 use crate::libc::{dev_t, ino_t};
+#[cfg(feature = "api-v4")]
+use crate::{exempt, logcat};
 
-use crate::binding::{RawApiTable, StateFlags, ZygiskOption};
+use crate::{
+    binding::{ApiCapabilities, ApiVersion, LegacyApiTable, RawApiTable, StateFlags, ZygiskOption},
+    companion::{
+        self,
+        handshake::{self, Service},
+        ConnectOptions,
+    },
+    error::UnknownFlags,
+    hooks::{
+        self,
+        jni::{JniHookError, JniMethodTable, OriginalMethod},
+        HookBackend, HookKind, InstalledHook,
+    },
+    ModuleDir, PltHookSession, ZygiskError,
+};
+
+mod post;
+mod retained;
+pub use post::PostSpecializeApi;
+pub(crate) use retained::mark_unloaded;
+pub use retained::RetainedApi;
+
+/// The handle passed to [`on_load`](crate::ZygiskModule::on_load) and the `pre[XXX]Specialize`
+/// callbacks, where every API function is available. See also [PostSpecializeApi].
+pub type PreSpecializeApi<'a> = ZygiskApi<'a>;
+
+/// Read an entry that exists in both the current and the legacy API table layouts, failing with
+/// [ZygiskError::ApiFunctionUnavailable] if the host did not provide it.
+macro_rules! entry {
+    ($api: expr, $name: ident) => {
+        match $api.legacy() {
+            Some(table) => table.$name,
+            None => $api.inner.$name,
+        }
+        .ok_or(ZygiskError::ApiFunctionUnavailable(stringify!($name)))
+    };
+}
 
 /// A handle to API functions provided by the Zygisk runtime. Use this to call utility functions
 /// or to interface with Zygisk.
@@ -24,12 +67,17 @@ use crate::binding::{RawApiTable, StateFlags, ZygiskOption};
 /// from the specialized process afterwards. Therefore, it is required that you stop using any
 /// instances of this object after that point.
 ///
+/// `post[XXX]Specialize` get a [PostSpecializeApi] instead, which leaves out the functions that
+/// only work before specialization.
+///
 /// In order to prevent the handle from unexpected use, the handle has a lifetime parameter `'a`
 /// that defaults to the lifetime of each function call in [ZygiskModule](crate::ZygiskModule).
-/// To retain this handle across function calls in some rare cases, call the unsafe function
-/// [Self::retain()].
+/// To retain this handle across function calls in some rare cases, such as in hooks, use
+/// [Self::retained()], or the unsafe function [Self::retain()] if the handle is needed after
+/// specialization.
 pub struct ZygiskApi<'a> {
     inner: &'a RawApiTable,
+    version: ApiVersion,
 }
 
 impl<'a> ZygiskApi<'a> {
@@ -48,20 +96,162 @@ impl<'a> ZygiskApi<'a> {
     ///
     /// Returns a [UnixStream] that is connected to the socket passed to your module's companion
     /// request handler. Returns `Err` if the connection attempt failed.
-    pub fn connect_companion(&self) -> Result<UnixStream, crate::ZygiskError> {
-        let fd = self
-            .inner
-            .connect_companion
-            .map(|func| func(self.inner.this))
-            .unwrap_or(-1);
+    ///
+    /// Before the stream is handed out, the module and the companion exchange a protocol version
+    /// derived from the crate name and version, so the companion has to be registered with
+    /// `zygisk_companion!`. If they were built from different versions, for example because the
+    /// module was updated while the daemon still runs the old companion,
+    /// [ZygiskError::CompanionProtocolMismatch] is returned instead. Modules that are not
+    /// registered with `zygisk_module!` have no version, and are accepted by any companion.
+    ///
+    /// A companion that does not answer the handshake within a second, such as one built before
+    /// the handshake existed, is reported as a mismatch with a companion version of `0`, so that
+    /// zygote is not kept waiting.
+    pub fn connect_companion(&self) -> Result<UnixStream, ZygiskError> {
+        self.connect_companion_service(Service::Handler)
+    }
+
+    /// Connect to the root companion process like [Self::connect_companion()], retrying while
+    /// the daemon is busy.
+    ///
+    /// Failures are reported with more specific errors than [ZygiskError::CompanionConnectionFailed]
+    /// when possible: [ZygiskError::CompanionDenied] if the connection was refused by SELinux,
+    /// and [ZygiskError::CompanionBusy] if the daemon still did not answer after all retries or
+    /// when the deadline has passed. Zygisk does not report why a connection failed, so this is
+    /// best effort: the cause is guessed from `errno`, and a busy daemon may as well look like
+    /// any other failure, which is not retried.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use zygisk::{companion::ConnectOptions, ZygiskApi};
+    ///
+    /// fn connect(api: &ZygiskApi) {
+    ///     let options = ConnectOptions::new()
+    ///         .retries(5)
+    ///         .deadline(Duration::from_secs(1));
+    ///     let _stream = api.connect_companion_with(&options);
+    /// }
+    /// ```
+    pub fn connect_companion_with(
+        &self,
+        options: &ConnectOptions,
+    ) -> Result<UnixStream, ZygiskError> {
+        let start = Instant::now();
+        let mut backoff = options.backoff;
+        let mut retries = options.retries;
+        loop {
+            let remaining = options
+                .deadline
+                .map(|deadline| deadline.saturating_sub(start.elapsed()));
+            let error = match self.connect_companion_until(remaining) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => companion::classify(e),
+            };
+
+            let ZygiskError::CompanionBusy(_) = error else {
+                return Err(error);
+            };
+            if retries == 0 || remaining.is_some_and(|remaining| remaining <= backoff) {
+                return Err(error);
+            }
+            retries -= 1;
+            std::thread::sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+
+    fn connect_companion_until(
+        &self,
+        remaining: Option<Duration>,
+    ) -> Result<UnixStream, ZygiskError> {
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            return Err(ZygiskError::CompanionConnectionFailed(
+                io::ErrorKind::TimedOut.into(),
+            ));
+        }
+        let mut stream = self.connect_companion_raw()?;
+        let protocol = handshake::module_protocol();
+        handshake::client(&mut stream, protocol, Service::Handler, remaining)?;
+        Ok(stream)
+    }
+
+    /// Connect to one of the services that the companion glue of this crate provides.
+    pub(crate) fn connect_companion_service(
+        &self,
+        service: Service,
+    ) -> Result<UnixStream, ZygiskError> {
+        let mut stream = self.connect_companion_raw()?;
+        handshake::client(&mut stream, handshake::module_protocol(), service, None)?;
+        Ok(stream)
+    }
+
+    fn connect_companion_raw(&self) -> Result<UnixStream, ZygiskError> {
+        let connect = entry!(self, connect_companion)?;
+        // Zygisk does not report the cause, so `errno` is the best we have, as long as it was
+        // set by this call.
+        crate::module_dir::clear_errno();
+        let fd = connect(self.inner.this);
 
         if fd >= 0 {
-            Ok(unsafe { UnixStream::from_raw_fd(fd) })
+            // SAFETY: Zygisk hands over the ownership of the socket.
+            Ok(UnixStream::from(unsafe { OwnedFd::from_raw_fd(fd) }))
         } else {
-            Err(crate::ZygiskError)
+            let error = io::Error::last_os_error();
+            Err(ZygiskError::CompanionConnectionFailed(
+                match error.raw_os_error() {
+                    Some(0) => io::Error::other("Zygisk did not report the cause"),
+                    _ => error,
+                },
+            ))
         }
     }
 
+    /// Connect to the root companion process like [Self::connect_companion()], and wrap the
+    /// stream in a typed [CompanionClient](crate::companion::rpc::CompanionClient).
+    ///
+    /// The companion handler is expected to serve the connection with
+    /// [companion::rpc::serve()](crate::companion::rpc::serve).
+    #[cfg(feature = "rpc")]
+    pub fn connect_companion_typed<Req, Resp>(
+        &self,
+    ) -> Result<crate::companion::rpc::CompanionClient<Req, Resp>, ZygiskError>
+    where
+        Req: serde::Serialize,
+        Resp: serde::de::DeserializeOwned,
+    {
+        self.connect_companion()
+            .map(crate::companion::rpc::CompanionClient::new)
+    }
+
+    /// Connect to the root companion process, run `f` on the stream and close it afterwards.
+    ///
+    /// This is the preferred way to do one-shot request/response exchanges: the socket is closed
+    /// as soon as `f` returns, even on errors, so it is never leaked into the specialized
+    /// process. I/O errors returned by `f` are reported as [ZygiskError::CompanionRequestFailed].
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use zygisk::{SocketExt, ZygiskApi};
+    ///
+    /// fn should_hook(api: &ZygiskApi, package: &str) -> bool {
+    ///     api.with_companion(|stream| {
+    ///         stream.send_str(package)?;
+    ///         Ok(stream.recv_u8()? != 0)
+    ///     })
+    ///     .unwrap_or(false)
+    /// }
+    /// ```
+    pub fn with_companion<R>(
+        &self,
+        f: impl FnOnce(&mut UnixStream) -> io::Result<R>,
+    ) -> Result<R, ZygiskError> {
+        let mut stream = self.connect_companion()?;
+        f(&mut stream).map_err(ZygiskError::CompanionRequestFailed)
+    }
+
     /// Get the file descriptor of the root folder of the current module.
     ///
     /// This API only works in the `pre[XXX]Specialize` functions.
@@ -69,46 +259,209 @@ impl<'a> ZygiskApi<'a> {
     /// or in the root companion process (assuming that you sent the fd over the socket).
     /// Both restrictions are due to SELinux and UID.
     ///
-    /// Returns -1 if errors occurred.
-    pub fn get_module_dir(&self) -> RawFd {
-        self.inner
-            .get_module_dir
-            .map(|func| func(self.inner.this))
-            .unwrap_or(-1)
+    /// The returned fd is owned by the caller and is closed when dropped.
+    pub fn get_module_dir(&self) -> Result<OwnedFd, ZygiskError> {
+        let fd = entry!(self, get_module_dir)?(self.inner.this);
+
+        if fd >= 0 {
+            // SAFETY: Zygisk opens a new fd for every call.
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        } else {
+            Err(ZygiskError::ModuleDirUnavailable)
+        }
+    }
+
+    /// Get a [ModuleDir] handle to the root folder of the current module.
+    ///
+    /// See [Self::get_module_dir()] for the restrictions that apply.
+    pub fn module_dir(&self) -> Result<ModuleDir, ZygiskError> {
+        self.get_module_dir().map(ModuleDir::from)
     }
 
     /// Set various options for your module.
     /// Please note that this function accepts one single option at a time.
     /// Check [ZygiskOption] for the full list of options available.
-    pub fn set_option(&self, option: ZygiskOption) {
-        if let Some(func) = self.inner.set_option {
-            func(self.inner.this, option);
+    pub fn set_option(&self, option: ZygiskOption) -> Result<(), ZygiskError> {
+        entry!(self, set_option)?(self.inner.this, option);
+        Ok(())
+    }
+
+    /// Unmount Magisk and module files from the process, like for denylisted apps. Shorthand for
+    /// [ZygiskOption::ForceDenylistUnmount], which only has an effect in `pre_app_specialize`.
+    pub fn force_denylist_unmount(&self) -> Result<(), ZygiskError> {
+        self.set_option(ZygiskOption::ForceDenylistUnmount)
+    }
+
+    /// Unmount Magisk and module files from the process if it is on the denylist, and the module
+    /// is not what gave it root. Call this in `pre_app_specialize`, since unmounting happens
+    /// during specialization and the denylist is only known by then.
+    ///
+    /// Returns whether the unmount was requested. If the flags cannot be read, or the option
+    /// cannot be set, the process is left alone and `false` is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use zygisk::{jni::JNIEnv, AppSpecializeArgs, ProcessDecision, ZygiskApi, ZygiskModule};
+    ///
+    /// struct HidingModule;
+    ///
+    /// impl ZygiskModule for HidingModule {
+    ///     fn pre_app_specialize(
+    ///         &self,
+    ///         api: ZygiskApi,
+    ///         _env: JNIEnv,
+    ///         _args: &mut AppSpecializeArgs,
+    ///     ) -> ProcessDecision {
+    ///         if api.hide_if_denylisted() {
+    ///             eprintln!("unmounted module files from a denylisted app");
+    ///         }
+    ///         ProcessDecision::Continue
+    ///     }
+    /// }
+    /// ```
+    pub fn hide_if_denylisted(&self) -> bool {
+        let Ok(flags) = self.get_flags() else {
+            return false;
+        };
+        flags.contains(StateFlags::PROCESS_ON_DENYLIST)
+            && !flags.contains(StateFlags::PROCESS_GRANTED_ROOT)
+            && self.force_denylist_unmount().is_ok()
+    }
+
+    /// `dlclose` the module library after `post[XXX]Specialize`. Shorthand for
+    /// [ZygiskOption::DlcloseModuleLibrary], see [Self::skip_and_unload()] for a checked
+    /// version.
+    pub fn dlclose_module(&self) -> Result<(), ZygiskError> {
+        self.set_option(ZygiskOption::DlcloseModuleLibrary)
+    }
+
+    /// Leave the process alone: unload the module library after `post[XXX]Specialize`, so that
+    /// it does not stay mapped in a process it has nothing to do in.
+    ///
+    /// Unlike [Self::dlclose_module()], this fails with [ZygiskError::HooksInstalled] if hooks
+    /// were registered through this crate, since they would jump into unmapped code. Returning
+    /// [ProcessDecision::SkipAndUnload](crate::ProcessDecision::SkipAndUnload) from
+    /// `pre[XXX]Specialize` goes through this function, and also skips `post[XXX]Specialize`.
+    pub fn skip_and_unload(&self) -> Result<(), ZygiskError> {
+        match hooks::snapshot().len() {
+            0 => self.dlclose_module(),
+            count => Err(ZygiskError::HooksInstalled(count)),
         }
     }
 
     /// Get information about the current process.
     /// Returns bitwise-or'd [StateFlags] values.
-    pub fn get_flags(&self) -> StateFlags {
-        self.inner
-            .get_flags
-            .map(|func| func(self.inner.this))
-            .map(|raw| StateFlags::from_bits(raw).expect("unsupported flag returned by Magisk"))
-            .unwrap_or(StateFlags::empty())
+    ///
+    /// Flags unknown to this crate, which newer Zygisk versions may return, are dropped like with
+    /// [Self::get_flags_truncate()]. Use [Self::try_get_flags()] to detect them.
+    pub fn get_flags(&self) -> Result<StateFlags, ZygiskError> {
+        self.get_flags_truncate()
+    }
+
+    /// Get information about the current process, dropping the flags unknown to this crate.
+    pub fn get_flags_truncate(&self) -> Result<StateFlags, ZygiskError> {
+        let raw = entry!(self, get_flags)?(self.inner.this);
+        Ok(StateFlags::from_bits_truncate(raw))
+    }
+
+    /// Get information about the current process, failing with [ZygiskError::UnknownFlags] if
+    /// Zygisk returned flags unknown to this crate.
+    pub fn try_get_flags(&self) -> Result<StateFlags, ZygiskError> {
+        let raw = entry!(self, get_flags)?(self.inner.this);
+        StateFlags::from_bits(raw).ok_or_else(|| {
+            UnknownFlags {
+                known: StateFlags::from_bits_truncate(raw),
+                unknown: raw & !StateFlags::all().bits(),
+            }
+            .into()
+        })
     }
 
     /// Exempt the provided file descriptor from being automatically closed.
     ///
     /// This API only make sense in [`pre_app_specialize`](crate::ZygiskModule::pre_app_specialize);\
-    /// calling this method in any other situation is either a no-op (returns true) or an
-    /// error (returns false).
+    /// calling this method in any other situation is either a no-op (returns `Ok`) or an
+    /// error (returns `Err`).
     ///
-    /// When false is returned, the provided file descriptor will eventually be closed by zygote.
-    pub fn exempt_fd(&self, fd: RawFd) {
-        if let Some(func) = self.inner.exempt_fd {
-            func(fd);
+    /// When [ZygiskError::FdNotExempted] is returned, the provided file descriptor will eventually
+    /// be closed by zygote.
+    ///
+    /// Only available since [ApiVersion::V4].
+    #[cfg(feature = "api-v4")]
+    pub fn exempt_fd(&self, fd: impl AsFd) -> Result<(), ZygiskError> {
+        let func = self
+            .current()
+            .and_then(|table| table.exempt_fd)
+            .ok_or(ZygiskError::ApiFunctionUnavailable("exempt_fd"))?;
+
+        let fd = fd.as_fd().as_raw_fd();
+        if func(fd) {
+            #[cfg(feature = "debug-fd-audit")]
+            exempt::record_exempted(fd);
+            Ok(())
+        } else {
+            Err(ZygiskError::FdNotExempted(fd))
         }
     }
 
+    /// Exempt every file descriptor of `fds` with [Self::exempt_fd()], and return those that
+    /// were not exempted and will be closed by zygote.
+    ///
+    /// Only available since [ApiVersion::V4]; when Zygisk does not provide `exempt_fd`, all the
+    /// file descriptors are returned.
+    #[cfg(feature = "api-v4")]
+    #[must_use = "the returned file descriptors will be closed by zygote"]
+    pub fn exempt_all<F: AsFd>(&self, fds: impl IntoIterator<Item = F>) -> Vec<RawFd> {
+        fds.into_iter()
+            .filter_map(|fd| {
+                let raw = fd.as_fd().as_raw_fd();
+                self.exempt_fd(fd).err().map(|_| raw)
+            })
+            .collect()
+    }
+
+    /// Run `f` and exempt every file descriptor it opened, returning its result along with the
+    /// file descriptors that were not exempted and will be closed by zygote.
+    ///
+    /// New file descriptors are found by listing `/proc/self/fd` before and after `f`, so ones
+    /// opened concurrently by other threads are exempted too.
+    ///
+    /// Only available since [ApiVersion::V4].
+    #[cfg(feature = "api-v4")]
+    pub fn exempt_scope<R>(&self, f: impl FnOnce() -> R) -> (R, Vec<RawFd>) {
+        let before = exempt::open_fds();
+        let result = f();
+        let new_fds = match (before, exempt::open_fds()) {
+            (Ok(before), Ok(after)) => after.difference(&before).copied().collect(),
+            (Err(e), _) | (_, Err(e)) => {
+                logcat::write(
+                    logcat::Priority::Error,
+                    &format!("failed to list open file descriptors: {e}"),
+                );
+                Vec::new()
+            }
+        };
+        // SAFETY: the file descriptors are open, and only used for the duration of this call.
+        let failed = self.exempt_all(
+            new_fds
+                .iter()
+                .map(|&fd| unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) }),
+        );
+        (result, failed)
+    }
+
+    /// Check whether the loading Zygisk implementation provides [Self::exempt_fd()].
+    ///
+    /// When it does not, [Self::exempt_fd()] always fails and every fd opened in
+    /// `pre_app_specialize` will be closed by zygote. Modules relying on exemption should check
+    /// this beforehand and avoid carrying fds over (or warn about it).
+    #[cfg(feature = "api-v4")]
+    pub fn supports_exempt_fd(&self) -> bool {
+        self.current()
+            .is_some_and(|table| table.exempt_fd.is_some())
+    }
+
     /// Hook JNI native methods for a Java class.
     ///
     /// This looks up all registered JNI native methods and replaces them with your own functions.
@@ -127,20 +480,145 @@ impl<'a> ZygiskApi<'a> {
         env: JNIEnv,
         class_name: &JNIStr,
         methods: &mut [JNINativeMethod],
-    ) {
-        if let Some(func) = self.inner.hook_jni_native_methods {
-            func(
-                env.get_native_interface(),
-                class_name.as_ptr(),
-                methods.as_mut_ptr(),
-                methods.len() as jint,
+    ) -> Result<(), ZygiskError> {
+        let hook = entry!(self, hook_jni_native_methods)?;
+        let replacements: Vec<_> = methods.iter().map(|method| method.fnPtr).collect();
+        hook(
+            env.get_native_interface(),
+            class_name.as_ptr(),
+            methods.as_mut_ptr(),
+            methods.len() as jint,
+        );
+
+        let class_name = class_name.to_string_lossy();
+        for (method, replacement) in methods.iter().zip(replacements) {
+            // Methods that were not found are left unhooked.
+            if method.fnPtr.is_null() {
+                continue;
+            }
+            let name = CStr::from_ptr(method.name).to_string_lossy();
+            let signature = CStr::from_ptr(method.signature).to_string_lossy();
+            hooks::jni::save_original(&class_name, &name, &signature, method.fnPtr);
+            hooks::record(
+                InstalledHook {
+                    kind: HookKind::Jni,
+                    library: class_name.clone().into_owned(),
+                    symbol: format!("{name}{signature}"),
+                    address: replacement as usize,
+                    committed: true,
+                },
+                None,
             );
         }
+        Ok(())
+    }
+
+    /// Put back the original functions of JNI native methods hooked with
+    /// [Self::hook_jni_native_methods()] or any function built on it.
+    ///
+    /// Methods are matched by name and signature; their `fnPtr` is ignored, so the same table
+    /// used for hooking can be passed. Methods that were not hooked through this crate are left
+    /// untouched.
+    ///
+    /// ## Safety
+    ///
+    /// See [Self::hook_jni_native_methods()]. The replacement functions must not be running or
+    /// be called anymore once the originals are restored.
+    pub unsafe fn restore_jni_native_methods(
+        &self,
+        env: JNIEnv,
+        class_name: &JNIStr,
+        methods: &[JNINativeMethod],
+    ) -> Result<(), ZygiskError> {
+        let hook = entry!(self, hook_jni_native_methods)?;
+        let class = class_name.to_string_lossy();
+        let mut restored = Vec::new();
+        let mut originals = Vec::new();
+        for method in methods {
+            let name = CStr::from_ptr(method.name).to_string_lossy();
+            let signature = CStr::from_ptr(method.signature).to_string_lossy();
+            if let Some(original) = hooks::jni::take_original(&class, &name, &signature) {
+                restored.push(format!("{name}{signature}"));
+                originals.push(JNINativeMethod {
+                    fnPtr: original,
+                    ..*method
+                });
+            }
+        }
+        if originals.is_empty() {
+            return Ok(());
+        }
+
+        hook(
+            env.get_native_interface(),
+            class_name.as_ptr(),
+            originals.as_mut_ptr(),
+            originals.len() as jint,
+        );
+        hooks::forget(|installed| {
+            installed.kind == HookKind::Jni
+                && installed.library == class
+                && restored.contains(&installed.symbol)
+        });
+        Ok(())
+    }
+
+    /// Hook JNI native methods like [Self::hook_jni_native_methods()], without mutating
+    /// `methods`, and report the original function of each method.
+    ///
+    /// Returns [JniHookError::Unmatched] if some of the methods were not found; the other ones
+    /// are hooked regardless.
+    ///
+    /// ## Safety
+    ///
+    /// See [Self::hook_jni_native_methods()].
+    pub unsafe fn hook_jni_native_methods_checked(
+        &self,
+        env: JNIEnv,
+        class_name: &JNIStr,
+        methods: &[JNINativeMethod],
+    ) -> Result<Vec<OriginalMethod>, JniHookError> {
+        let mut hooked = methods.to_vec();
+        self.hook_jni_native_methods(env, class_name, &mut hooked)?;
+
+        let originals: Vec<_> = hooked
+            .iter()
+            .map(|method| OriginalMethod {
+                name: CStr::from_ptr(method.name).to_string_lossy().into_owned(),
+                signature: CStr::from_ptr(method.signature)
+                    .to_string_lossy()
+                    .into_owned(),
+                original: method.fnPtr,
+            })
+            .collect();
+        if originals.iter().all(OriginalMethod::is_hooked) {
+            Ok(originals)
+        } else {
+            Err(JniHookError::Unmatched(originals))
+        }
+    }
+
+    /// Hook the JNI native methods of a [JniMethodTable] built with
+    /// [jni_method_table!](crate::jni_method_table).
+    ///
+    /// Like [Self::hook_jni_native_methods()], the original functions are saved in the table.
+    ///
+    /// ## Safety
+    ///
+    /// See [Self::hook_jni_native_methods()].
+    pub unsafe fn hook_jni_method_table<const N: usize>(
+        &self,
+        env: JNIEnv,
+        table: &mut JniMethodTable<N>,
+    ) -> Result<(), ZygiskError> {
+        let class = table.class();
+        self.hook_jni_native_methods(env, class, table.methods_mut())
     }
 
     /// Hook functions in the PLT (Procedure Linkage Table) of ELFs loaded in memory.
     ///
-    /// Parsing `/proc/[PID]/maps` will give you the memory map of a process. As an example:
+    /// Parsing `/proc/[PID]/maps` will give you the memory map of a process (see
+    /// [maps::find_library()](crate::maps::find_library)). As an example:
     ///
     /// ```text
     ///       <address>       <perms>  <offset>   <dev>  <inode>           <pathname>
@@ -152,10 +630,14 @@ impl<'a> ZygiskApi<'a> {
     /// For matching ELFs loaded in memory, replace function `symbol` with `new_func`.
     /// If `old_func` is not [`None`], the original function pointer will be saved to `old_func`.
     ///
+    /// Only available since [ApiVersion::V4]; older hosts use [Self::plt_hook_register_regex()].
+    ///
     /// ## Safety
     ///
     /// This function is unsafe, since a badly designed hook or misuse of raw pointers may lead to
-    /// memory unsafety.
+    /// memory unsafety. Zygisk only writes the original function to `old_func` when the hooks
+    /// are committed, so the slot has to stay valid until [Self::plt_hook_commit()] returns.
+    #[cfg(feature = "api-v4")]
     pub unsafe fn plt_hook_register(
         &self,
         device: dev_t,
@@ -163,34 +645,268 @@ impl<'a> ZygiskApi<'a> {
         symbol: &CStr,
         new_func: *mut (),
         old_func: Option<&mut *mut ()>,
-    ) {
-        if let Some(func) = self.inner.plt_hook_register {
-            func(
-                device,
-                inode,
-                symbol.as_ptr(),
-                new_func,
-                old_func
-                    .map(|r| r as *mut *mut ())
-                    .unwrap_or(std::ptr::null_mut()),
-            );
-        }
+    ) -> Result<(), ZygiskError> {
+        let func = self
+            .current()
+            .and_then(|table| table.plt_hook_register)
+            .ok_or(ZygiskError::ApiFunctionUnavailable("plt_hook_register"))?;
+        let old_func = old_func.map(|r| r as *mut *mut ());
+
+        func(
+            device,
+            inode,
+            symbol.as_ptr(),
+            new_func,
+            old_func.unwrap_or(std::ptr::null_mut()),
+        );
+        hooks::record_elf(
+            InstalledHook {
+                kind: HookKind::Plt,
+                library: String::new(),
+                symbol: symbol.to_string_lossy().into_owned(),
+                address: new_func as usize,
+                committed: false,
+            },
+            old_func,
+            device,
+            inode,
+        );
+        Ok(())
+    }
+
+    /// Hook functions in the PLT of ELFs loaded in memory, matching ELFs by their pathname.
+    ///
+    /// For ELFs whose pathname matches the regular expression `regex`, replace function `symbol`
+    /// with `new_func`. If `old_func` is not [`None`], the original function pointer will be saved
+    /// to `old_func`.
+    ///
+    /// Only available before [ApiVersion::V4]; newer hosts use [Self::plt_hook_register()].
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe, since a badly designed hook or misuse of raw pointers may lead to
+    /// memory unsafety. Zygisk only writes the original function to `old_func` when the hooks
+    /// are committed, so the slot has to stay valid until [Self::plt_hook_commit()] returns.
+    pub unsafe fn plt_hook_register_regex(
+        &self,
+        regex: &CStr,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&mut *mut ()>,
+    ) -> Result<(), ZygiskError> {
+        let func = self
+            .legacy()
+            .and_then(|table| table.plt_hook_register)
+            .ok_or(ZygiskError::ApiFunctionUnavailable(
+                "plt_hook_register_regex",
+            ))?;
+        let old_func = old_func.map(|r| r as *mut *mut ());
+
+        func(
+            regex.as_ptr(),
+            symbol.as_ptr(),
+            new_func,
+            old_func.unwrap_or(std::ptr::null_mut()),
+        );
+        hooks::record(
+            InstalledHook {
+                kind: HookKind::Plt,
+                library: regex.to_string_lossy().into_owned(),
+                symbol: symbol.to_string_lossy().into_owned(),
+                address: new_func as usize,
+                committed: false,
+            },
+            old_func,
+        );
+        Ok(())
+    }
+
+    /// Exclude ELFs matching `regex` from hooks of `symbol` previously registered with
+    /// [Self::plt_hook_register_regex()].
+    ///
+    /// Only available before [ApiVersion::V4].
+    pub fn plt_hook_exclude(&self, regex: &CStr, symbol: &CStr) -> Result<(), ZygiskError> {
+        let func = self
+            .legacy()
+            .and_then(|table| table.plt_hook_exclude)
+            .ok_or(ZygiskError::ApiFunctionUnavailable("plt_hook_exclude"))?;
+
+        func(regex.as_ptr(), symbol.as_ptr());
+        Ok(())
+    }
+
+    /// Start a [PltHookSession] that registers and commits a group of PLT hooks at once.
+    pub fn plt_hook_session(&self) -> PltHookSession<'a, '_> {
+        PltHookSession::new(self, hooks::backend())
+    }
+
+    /// Start a [PltHookSession] that registers its hooks through `backend`, rather than the one
+    /// set with [hooks::set_backend()].
+    pub fn plt_hook_session_with<'h>(
+        &'h self,
+        backend: &'h dyn HookBackend,
+    ) -> PltHookSession<'a, 'h> {
+        PltHookSession::new(self, backend)
+    }
+
+    /// Whether [Self::plt_hook_register()] is available.
+    #[cfg(feature = "api-v4")]
+    pub(crate) fn supports_plt_hook_register(&self) -> bool {
+        self.current()
+            .is_some_and(|table| table.plt_hook_register.is_some())
+    }
+
+    /// Whether [Self::plt_hook_register_regex()] and [Self::plt_hook_exclude()] are available.
+    pub(crate) fn supports_plt_hook_regex(&self) -> bool {
+        self.legacy().is_some_and(|table| {
+            table.plt_hook_register.is_some() && table.plt_hook_exclude.is_some()
+        })
     }
 
     /// Commit all the hooks that was previously registered.
     ///
-    /// Returns `false` if any error occurs.
-    pub fn plt_hook_commit(&self) -> bool {
-        self.inner
-            .plt_hook_commit
-            .map(|func| func())
-            .unwrap_or(false)
+    /// Hooks registered with an `old_func` are checked individually: if Zygisk did not save the
+    /// original function, the hook was not applied, and [ZygiskError::PltHookFailed] lists the
+    /// symbol and library of every such hook. [ZygiskError::PltHookCommitFailed] is returned if
+    /// the commit failed without any hook to blame.
+    pub fn plt_hook_commit(&self) -> Result<(), ZygiskError> {
+        let success = entry!(self, plt_hook_commit)?();
+        // SAFETY: the `old_func` slots are required to be valid until the commit by
+        // `plt_hook_register`, since Zygisk writes to them here.
+        let failures = unsafe { hooks::commit_plt(success) };
+        if !failures.is_empty() {
+            Err(ZygiskError::PltHookFailed(failures))
+        } else if !success {
+            Err(ZygiskError::PltHookCommitFailed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// List every PLT and JNI hook registered through this crate in the current process.
+    ///
+    /// Hooks registered by other modules, by calling the Zygisk API table directly, or through a
+    /// [HookBackend] other than [ZygiskBackend](hooks::ZygiskBackend), are not included.
+    pub fn installed_hooks(&self) -> Vec<InstalledHook> {
+        hooks::snapshot()
+    }
+
+    /// Get the API version that the module was registered with.
+    ///
+    /// This is the newest version supported by both this crate and the loading Zygisk
+    /// implementation, which may be older than [ApiVersion::LATEST].
+    pub fn api_version(&self) -> ApiVersion {
+        self.version
+    }
+
+    /// Check whether the current process is `system_server`, rather than an app.
+    ///
+    /// This is decided by which specialization callback ran in this process, not by
+    /// [process_name()](crate::process_name), which an app can set to anything. It works in the
+    /// specialization callbacks, as well as in hooks running after them. Before either callback
+    /// runs, such as in `on_load`, this returns `false`.
+    pub fn in_system_server(&self) -> bool {
+        is_system_server(crate::process::specialization())
+    }
+
+    /// Get the API functions that the loading Zygisk implementation provides.
+    ///
+    /// Hosts may leave out functions of the version they accepted, so modules that depend on one
+    /// can check for it up front instead of handling [ZygiskError::ApiFunctionUnavailable] later.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use zygisk::{ApiCapabilities, ZygiskApi};
+    ///
+    /// fn can_talk_to_companion(api: &ZygiskApi) -> bool {
+    ///     api.capabilities().contains(ApiCapabilities::CONNECT_COMPANION)
+    /// }
+    /// ```
+    pub fn capabilities(&self) -> ApiCapabilities {
+        let mut capabilities = ApiCapabilities::empty();
+        macro_rules! check {
+            ($($name: ident => $flag: ident),* $(,)?) => {
+                $(capabilities.set(ApiCapabilities::$flag, entry!(self, $name).is_ok());)*
+            };
+        }
+        check!(
+            hook_jni_native_methods => HOOK_JNI_NATIVE_METHODS,
+            plt_hook_commit => PLT_HOOK_COMMIT,
+            connect_companion => CONNECT_COMPANION,
+            set_option => SET_OPTION,
+            get_module_dir => GET_MODULE_DIR,
+            get_flags => GET_FLAGS,
+        );
+        // The remaining functions only exist in one of the table layouts.
+        match self.legacy() {
+            Some(table) => {
+                capabilities.set(
+                    ApiCapabilities::PLT_HOOK_REGISTER_REGEX,
+                    table.plt_hook_register.is_some(),
+                );
+                capabilities.set(
+                    ApiCapabilities::PLT_HOOK_EXCLUDE,
+                    table.plt_hook_exclude.is_some(),
+                );
+            }
+            None => {
+                let table = self.inner;
+                capabilities.set(
+                    ApiCapabilities::PLT_HOOK_REGISTER,
+                    table.plt_hook_register.is_some(),
+                );
+                capabilities.set(ApiCapabilities::EXEMPT_FD, table.exempt_fd.is_some());
+            }
+        }
+        capabilities
     }
 }
 
 impl<'a> ZygiskApi<'a> {
-    pub(crate) fn from_raw(inner: &'a RawApiTable) -> ZygiskApi {
-        ZygiskApi { inner }
+    pub(crate) fn from_raw(inner: &'a RawApiTable, version: ApiVersion) -> ZygiskApi<'a> {
+        ZygiskApi { inner, version }
+    }
+
+    /// Get the API table as passed by Zygisk, to call functions this crate does not wrap or to
+    /// hand the table to C code.
+    ///
+    /// Before [ApiVersion::V4], the table has the layout of [LegacyApiTable](crate::LegacyApiTable)
+    /// and must be cast to it before use.
+    #[cfg(feature = "raw")]
+    pub fn as_raw(&self) -> &'a RawApiTable {
+        self.inner
+    }
+
+    /// The API table as passed by the host, in the layout of [Self::api_version()].
+    #[cfg(feature = "testing")]
+    pub(crate) fn raw_table(&self) -> &'a RawApiTable {
+        self.inner
+    }
+
+    /// The API table, if it uses the layout of [ApiVersion::V4] and later.
+    #[cfg(feature = "api-v4")]
+    fn current(&self) -> Option<&'a RawApiTable> {
+        (self.version >= ApiVersion::V4).then_some(self.inner)
+    }
+
+    /// The API table, if it uses the layout prior to [ApiVersion::V4].
+    fn legacy(&self) -> Option<&'a LegacyApiTable> {
+        // SAFETY: both layouts are `repr(C)` structs of the same number of pointers, and the host
+        // fills the table according to the version we registered with.
+        (self.version < ApiVersion::V4)
+            .then(|| unsafe { &*(self.inner as *const RawApiTable).cast::<LegacyApiTable>() })
+    }
+
+    /// Get a [RetainedApi] that can be kept after the current callback returns, such as for use in
+    /// hooks.
+    pub fn retained(&self) -> RetainedApi {
+        // SAFETY: the table stays in place until the API is unloaded, which `RetainedApi` checks
+        // before every use.
+        RetainedApi::new(ZygiskApi::from_raw(
+            unsafe { &*(self.inner as *const RawApiTable) },
+            self.version,
+        ))
     }
 
     /// Retain the API handle to be used across function calls to [ZygiskModule](crate::ZygiskModule)
@@ -203,9 +919,335 @@ impl<'a> ZygiskApi<'a> {
     /// This function merely exists for working around Rust's limitations.
     ///
     /// This function should rarely be necessary, since an API handle will be passed to
-    /// every function in [ZygiskModule](crate::ZygiskModule) as an argument.
+    /// every function in [ZygiskModule](crate::ZygiskModule) as an argument. Prefer
+    /// [Self::retained()], which checks that the API is still loaded.
     pub unsafe fn retain(self) -> ZygiskApi<'static> {
         // We only need to extend the lifetime, so a simple transmute is sufficient for this case.
         std::mem::transmute(self)
     }
 }
+
+/// Whether a process is `system_server`, given the specialization callback that ran in it.
+fn is_system_server(specialization: Option<crate::process::Specialization>) -> bool {
+    specialization == Some(crate::process::Specialization::Server)
+}
+
+#[cfg(test)]
+impl ZygiskApi<'static> {
+    /// An API handle of `version`, over a table with only the functions set by `configure`.
+    pub(crate) fn mock(version: ApiVersion, configure: impl FnOnce(&mut RawApiTable)) -> Self {
+        let mut table = RawApiTable::empty();
+        configure(&mut table);
+        ZygiskApi::from_raw(Box::leak(Box::new(table)), version)
+    }
+}
+
+#[cfg(feature = "api-v4")]
+#[test]
+fn test_supports_exempt_fd() {
+    extern "C" fn exempt_fd(fd: std::os::raw::c_int) -> bool {
+        fd != 2
+    }
+
+    assert!(!ZygiskApi::mock(ApiVersion::V5, |_| {}).supports_exempt_fd());
+
+    let with_exempt_fd = |table: &mut RawApiTable| table.exempt_fd = Some(exempt_fd);
+    let api = ZygiskApi::mock(ApiVersion::V5, with_exempt_fd);
+    assert!(api.supports_exempt_fd());
+    assert!(!ZygiskApi::mock(ApiVersion::V3, with_exempt_fd).supports_exempt_fd());
+
+    assert!(api.exempt_fd(std::io::stdout()).is_ok());
+    assert_eq!(
+        api.exempt_all([io::stdin().as_fd(), io::stderr().as_fd()]),
+        [2]
+    );
+}
+
+#[test]
+fn test_with_companion() {
+    use crate::SocketExt;
+    use std::os::fd::IntoRawFd;
+
+    extern "C" fn connect_companion(_this: *const ()) -> std::os::raw::c_int {
+        let (module, mut companion) = UnixStream::pair().unwrap();
+        std::thread::spawn(move || {
+            handshake::server(&mut companion, 1).unwrap();
+            if let Ok(value) = companion.recv_u32() {
+                let _ = companion.send_u32(value + 1);
+            }
+        });
+        module.into_raw_fd()
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V4, |_| {});
+    assert!(matches!(
+        api.with_companion(|_| Ok(())),
+        Err(ZygiskError::ApiFunctionUnavailable("connect_companion"))
+    ));
+
+    let api = ZygiskApi::mock(ApiVersion::V4, |table| {
+        table.connect_companion = Some(connect_companion)
+    });
+    let answer = api.with_companion(|stream| {
+        stream.send_u32(41)?;
+        stream.recv_u32()
+    });
+    assert_eq!(answer.unwrap(), 42);
+    assert!(matches!(
+        api.with_companion(|_| Err::<(), _>(io::ErrorKind::InvalidData.into())),
+        Err(ZygiskError::CompanionRequestFailed(_))
+    ));
+}
+
+#[test]
+fn test_hook_jni_native_methods_checked() {
+    use crate::jni::sys;
+    use std::os::raw::{c_char, c_int};
+
+    extern "C" fn hook(
+        _env: *mut sys::JNIEnv,
+        _class: *const c_char,
+        methods: *mut JNINativeMethod,
+        count: c_int,
+    ) {
+        let methods = unsafe { std::slice::from_raw_parts_mut(methods, count as usize) };
+        for method in methods {
+            let found = unsafe { CStr::from_ptr(method.name) } == c"found";
+            method.fnPtr = if found {
+                0xabc as *mut _
+            } else {
+                std::ptr::null_mut()
+            };
+        }
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V4, |table| {
+        table.hook_jni_native_methods = Some(hook);
+    });
+    let env = unsafe { JNIEnv::from_raw(std::ptr::NonNull::dangling().as_ptr()) }.unwrap();
+    let class = unsafe { JNIStr::from_ptr(c"a/B".as_ptr()) };
+
+    let methods = [
+        JNINativeMethod {
+            name: c"found".as_ptr() as *mut _,
+            signature: c"()V".as_ptr() as *mut _,
+            fnPtr: 0x123 as *mut _,
+        },
+        JNINativeMethod {
+            name: c"missing".as_ptr() as *mut _,
+            signature: c"(I)V".as_ptr() as *mut _,
+            fnPtr: 0x456 as *mut _,
+        },
+    ];
+    let result = unsafe { api.hook_jni_native_methods_checked(env, class, &methods) };
+    let Err(JniHookError::Unmatched(originals)) = result else {
+        panic!("missing method not reported");
+    };
+    assert_eq!(originals[0].original, 0xabc as *mut _);
+    assert!(originals[0].is_hooked());
+    assert_eq!(originals[1].signature, "(I)V");
+    assert!(!originals[1].is_hooked());
+    assert_eq!(methods[0].fnPtr, 0x123 as *mut _);
+}
+
+#[test]
+fn test_restore_jni_native_methods() {
+    use crate::jni::sys;
+    use std::os::raw::{c_char, c_int};
+
+    // Swaps in the given functions and hands back the previous ones, like `RegisterNatives`.
+    static CURRENT: std::sync::Mutex<usize> = std::sync::Mutex::new(0x111);
+    extern "C" fn hook(
+        _env: *mut sys::JNIEnv,
+        _class: *const c_char,
+        methods: *mut JNINativeMethod,
+        count: c_int,
+    ) {
+        let methods = unsafe { std::slice::from_raw_parts_mut(methods, count as usize) };
+        let mut current = CURRENT.lock().unwrap();
+        for method in methods {
+            let previous = *current;
+            *current = method.fnPtr as usize;
+            method.fnPtr = previous as *mut _;
+        }
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V4, |table| {
+        table.hook_jni_native_methods = Some(hook);
+    });
+    let env = || unsafe { JNIEnv::from_raw(std::ptr::NonNull::dangling().as_ptr()) }.unwrap();
+    let class = unsafe { JNIStr::from_ptr(c"a/Restore".as_ptr()) };
+
+    let mut methods = [JNINativeMethod {
+        name: c"restored".as_ptr() as *mut _,
+        signature: c"()V".as_ptr() as *mut _,
+        fnPtr: 0x222 as *mut _,
+    }];
+    unsafe { api.hook_jni_native_methods(env(), class, &mut methods) }.unwrap();
+    assert_eq!(*CURRENT.lock().unwrap(), 0x222);
+    assert!(api
+        .installed_hooks()
+        .iter()
+        .any(|h| h.library == "a/Restore"));
+
+    unsafe { api.restore_jni_native_methods(env(), class, &methods) }.unwrap();
+    assert_eq!(*CURRENT.lock().unwrap(), 0x111);
+    assert!(!api
+        .installed_hooks()
+        .iter()
+        .any(|h| h.library == "a/Restore"));
+
+    // Nothing left to restore.
+    *CURRENT.lock().unwrap() = 0x333;
+    unsafe { api.restore_jni_native_methods(env(), class, &methods) }.unwrap();
+    assert_eq!(*CURRENT.lock().unwrap(), 0x333);
+}
+
+#[test]
+fn test_skip_and_unload() {
+    use std::sync::Mutex;
+
+    static OPTIONS: Mutex<Vec<ZygiskOption>> = Mutex::new(Vec::new());
+    extern "C" fn set_option(_this: *const (), option: ZygiskOption) {
+        OPTIONS.lock().unwrap().push(option);
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V4, |table| {
+        table.set_option = Some(set_option);
+    });
+    api.force_denylist_unmount().unwrap();
+    api.dlclose_module().unwrap();
+
+    let hook = InstalledHook {
+        kind: HookKind::Jni,
+        library: "test_skip_and_unload".into(),
+        symbol: "run()V".into(),
+        address: 0x1234,
+        committed: true,
+    };
+    hooks::record(hook.clone(), None);
+    assert!(matches!(
+        api.skip_and_unload(),
+        Err(ZygiskError::HooksInstalled(count)) if count >= 1
+    ));
+    assert_eq!(
+        *OPTIONS.lock().unwrap(),
+        [
+            ZygiskOption::ForceDenylistUnmount,
+            ZygiskOption::DlcloseModuleLibrary
+        ]
+    );
+
+    // Other tests share the hook registry, so the module may still have hooks of theirs.
+    hooks::forget(|installed| *installed == hook);
+    assert!(!api.installed_hooks().contains(&hook));
+    match api.skip_and_unload() {
+        Ok(()) => assert_eq!(
+            OPTIONS.lock().unwrap().last(),
+            Some(&ZygiskOption::DlcloseModuleLibrary)
+        ),
+        Err(ZygiskError::HooksInstalled(_)) => assert_eq!(OPTIONS.lock().unwrap().len(), 2),
+        Err(e) => panic!("unexpected error {e}"),
+    }
+}
+
+#[test]
+fn test_hide_if_denylisted() {
+    use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+    static FLAGS: AtomicU32 = AtomicU32::new(0);
+    static UNMOUNTS: AtomicUsize = AtomicUsize::new(0);
+    extern "C" fn get_flags(_this: *const ()) -> u32 {
+        FLAGS.load(Ordering::SeqCst)
+    }
+    extern "C" fn set_option(_this: *const (), option: ZygiskOption) {
+        assert_eq!(option, ZygiskOption::ForceDenylistUnmount);
+        UNMOUNTS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    assert!(!ZygiskApi::mock(ApiVersion::V4, |_| {}).hide_if_denylisted());
+
+    let api = ZygiskApi::mock(ApiVersion::V4, |table| {
+        table.get_flags = Some(get_flags);
+        table.set_option = Some(set_option);
+    });
+    assert!(!api.hide_if_denylisted());
+    FLAGS.store(StateFlags::all().bits(), Ordering::SeqCst);
+    assert!(!api.hide_if_denylisted());
+    assert_eq!(UNMOUNTS.load(Ordering::SeqCst), 0);
+
+    FLAGS.store(StateFlags::PROCESS_ON_DENYLIST.bits(), Ordering::SeqCst);
+    assert!(api.hide_if_denylisted());
+    assert_eq!(UNMOUNTS.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_get_flags() {
+    extern "C" fn get_flags(_this: *const ()) -> u32 {
+        0x8000_0002
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V4, |table| {
+        table.get_flags = Some(get_flags);
+    });
+    assert_eq!(api.get_flags().unwrap(), StateFlags::PROCESS_ON_DENYLIST);
+    let Err(ZygiskError::UnknownFlags(flags)) = api.try_get_flags() else {
+        panic!("unknown flags not reported");
+    };
+    assert_eq!(flags.known, StateFlags::PROCESS_ON_DENYLIST);
+    assert_eq!(flags.unknown, 0x8000_0000);
+}
+
+#[test]
+fn test_capabilities() {
+    extern "C" fn get_flags(_this: *const ()) -> u32 {
+        0
+    }
+    extern "C" fn exempt_fd(_fd: std::os::raw::c_int) -> bool {
+        true
+    }
+
+    assert!(ZygiskApi::mock(ApiVersion::V4, |_| {})
+        .capabilities()
+        .is_empty());
+
+    let configure = |table: &mut RawApiTable| {
+        table.get_flags = Some(get_flags);
+        table.exempt_fd = Some(exempt_fd);
+    };
+    assert_eq!(
+        ZygiskApi::mock(ApiVersion::V4, configure).capabilities(),
+        ApiCapabilities::GET_FLAGS | ApiCapabilities::EXEMPT_FD
+    );
+    // The legacy layout has `get_module_dir` and `get_flags` in these slots.
+    assert_eq!(
+        ZygiskApi::mock(ApiVersion::V3, configure).capabilities(),
+        ApiCapabilities::GET_MODULE_DIR | ApiCapabilities::GET_FLAGS
+    );
+}
+
+#[cfg(feature = "raw")]
+#[test]
+fn test_as_raw() {
+    extern "C" fn get_flags(_this: *const ()) -> u32 {
+        0x2
+    }
+
+    let mut table = RawApiTable::empty();
+    table.get_flags = Some(get_flags);
+    let api = ZygiskApi::from_raw(&table, ApiVersion::V4);
+    let raw = api.as_raw();
+    assert!(std::ptr::eq(raw, &table));
+    assert_eq!(raw.get_flags.unwrap()(raw.this), 0x2);
+}
+
+#[test]
+fn test_is_system_server() {
+    use crate::process::Specialization;
+
+    assert!(is_system_server(Some(Specialization::Server)));
+    // An app that named itself `system_server`.
+    assert!(!is_system_server(Some(Specialization::App)));
+    // Before specialization, such as in `on_load`.
+    assert!(!is_system_server(None));
+}
@@ -0,0 +1,210 @@
+#[cfg(feature = "api-v4")]
+use crate::libc::{dev_t, ino_t};
+use std::ffi::CStr;
+
+use crate::{
+    hooks::{
+        jni::{JniHookError, JniMethodTable, OriginalMethod},
+        HookBackend,
+    },
+    jni::{strings::JNIStr, sys::JNINativeMethod, JNIEnv},
+    ApiCapabilities, ApiVersion, InstalledHook, PltHookSession, StateFlags, ZygiskApi, ZygiskError,
+    ZygiskOption,
+};
+
+/// The handle to API functions passed to
+/// [`post_app_specialize`](crate::ZygiskModule::post_app_specialize) and
+/// [`post_server_specialize`](crate::ZygiskModule::post_server_specialize).
+///
+/// Once the process is specialized, it runs under the SELinux context of the app or of
+/// `system_server`, so the functions that only work before specialization
+/// ([ZygiskApi::connect_companion()], [ZygiskApi::get_module_dir()] and `exempt_fd`) are not
+/// available on this handle:
+///
+/// ```compile_fail,E0599
+/// use zygisk::{AppSpecializeArgs, PostSpecializeApi, ZygiskModule};
+/// use zygisk::jni::JNIEnv;
+///
+/// struct LateModule;
+/// impl ZygiskModule for LateModule {
+///     fn post_app_specialize(&self, api: PostSpecializeApi, _env: JNIEnv, _args: &AppSpecializeArgs) {
+///         let _ = api.connect_companion();
+///     }
+/// }
+/// ```
+///
+/// The other functions behave like their [ZygiskApi] counterparts, and the same lifetime rules
+/// apply.
+pub struct PostSpecializeApi<'a> {
+    api: ZygiskApi<'a>,
+}
+
+impl<'a> PostSpecializeApi<'a> {
+    pub(crate) fn new(api: ZygiskApi<'a>) -> Self {
+        PostSpecializeApi { api }
+    }
+
+    /// See [ZygiskApi::set_option()].
+    pub fn set_option(&self, option: ZygiskOption) -> Result<(), ZygiskError> {
+        self.api.set_option(option)
+    }
+
+    /// See [ZygiskApi::dlclose_module()].
+    pub fn dlclose_module(&self) -> Result<(), ZygiskError> {
+        self.api.dlclose_module()
+    }
+
+    /// See [ZygiskApi::skip_and_unload()].
+    pub fn skip_and_unload(&self) -> Result<(), ZygiskError> {
+        self.api.skip_and_unload()
+    }
+
+    /// See [ZygiskApi::get_flags()].
+    pub fn get_flags(&self) -> Result<StateFlags, ZygiskError> {
+        self.api.get_flags()
+    }
+
+    /// See [ZygiskApi::get_flags_truncate()].
+    pub fn get_flags_truncate(&self) -> Result<StateFlags, ZygiskError> {
+        self.api.get_flags_truncate()
+    }
+
+    /// See [ZygiskApi::try_get_flags()].
+    pub fn try_get_flags(&self) -> Result<StateFlags, ZygiskError> {
+        self.api.try_get_flags()
+    }
+
+    /// See [ZygiskApi::hook_jni_native_methods()].
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::hook_jni_native_methods()].
+    pub unsafe fn hook_jni_native_methods(
+        &self,
+        env: JNIEnv,
+        class_name: &JNIStr,
+        methods: &mut [JNINativeMethod],
+    ) -> Result<(), ZygiskError> {
+        self.api.hook_jni_native_methods(env, class_name, methods)
+    }
+
+    /// See [ZygiskApi::restore_jni_native_methods()].
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::restore_jni_native_methods()].
+    pub unsafe fn restore_jni_native_methods(
+        &self,
+        env: JNIEnv,
+        class_name: &JNIStr,
+        methods: &[JNINativeMethod],
+    ) -> Result<(), ZygiskError> {
+        self.api
+            .restore_jni_native_methods(env, class_name, methods)
+    }
+
+    /// See [ZygiskApi::hook_jni_native_methods_checked()].
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::hook_jni_native_methods()].
+    pub unsafe fn hook_jni_native_methods_checked(
+        &self,
+        env: JNIEnv,
+        class_name: &JNIStr,
+        methods: &[JNINativeMethod],
+    ) -> Result<Vec<OriginalMethod>, JniHookError> {
+        self.api
+            .hook_jni_native_methods_checked(env, class_name, methods)
+    }
+
+    /// See [ZygiskApi::hook_jni_method_table()].
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::hook_jni_native_methods()].
+    pub unsafe fn hook_jni_method_table<const N: usize>(
+        &self,
+        env: JNIEnv,
+        table: &mut JniMethodTable<N>,
+    ) -> Result<(), ZygiskError> {
+        self.api.hook_jni_method_table(env, table)
+    }
+
+    /// See [ZygiskApi::plt_hook_register()].
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register()].
+    #[cfg(feature = "api-v4")]
+    pub unsafe fn plt_hook_register(
+        &self,
+        device: dev_t,
+        inode: ino_t,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&mut *mut ()>,
+    ) -> Result<(), ZygiskError> {
+        self.api
+            .plt_hook_register(device, inode, symbol, new_func, old_func)
+    }
+
+    /// See [ZygiskApi::plt_hook_register_regex()].
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register_regex()].
+    pub unsafe fn plt_hook_register_regex(
+        &self,
+        regex: &CStr,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&mut *mut ()>,
+    ) -> Result<(), ZygiskError> {
+        self.api
+            .plt_hook_register_regex(regex, symbol, new_func, old_func)
+    }
+
+    /// See [ZygiskApi::plt_hook_exclude()].
+    pub fn plt_hook_exclude(&self, regex: &CStr, symbol: &CStr) -> Result<(), ZygiskError> {
+        self.api.plt_hook_exclude(regex, symbol)
+    }
+
+    /// See [ZygiskApi::plt_hook_session()].
+    pub fn plt_hook_session(&self) -> PltHookSession<'a, '_> {
+        self.api.plt_hook_session()
+    }
+
+    /// See [ZygiskApi::plt_hook_session_with()].
+    pub fn plt_hook_session_with<'h>(
+        &'h self,
+        backend: &'h dyn HookBackend,
+    ) -> PltHookSession<'a, 'h> {
+        self.api.plt_hook_session_with(backend)
+    }
+
+    /// See [ZygiskApi::plt_hook_commit()].
+    pub fn plt_hook_commit(&self) -> Result<(), ZygiskError> {
+        self.api.plt_hook_commit()
+    }
+
+    /// See [ZygiskApi::installed_hooks()].
+    pub fn installed_hooks(&self) -> Vec<InstalledHook> {
+        self.api.installed_hooks()
+    }
+
+    /// See [ZygiskApi::api_version()].
+    pub fn api_version(&self) -> ApiVersion {
+        self.api.api_version()
+    }
+
+    /// See [ZygiskApi::in_system_server()].
+    pub fn in_system_server(&self) -> bool {
+        self.api.in_system_server()
+    }
+
+    /// See [ZygiskApi::capabilities()].
+    pub fn capabilities(&self) -> ApiCapabilities {
+        self.api.capabilities()
+    }
+}
@@ -0,0 +1,139 @@
+use std::{cell::Cell, sync::RwLock};
+
+use crate::{binding::RawApiTable, ApiVersion, ZygiskApi, ZygiskError};
+
+// How many times `post[XXX]Specialize` has returned, after which Zygisk unloads the API table.
+// Users of a `RetainedApi` hold the read lock, so the glue waits for them before letting Zygisk
+// go. This only ever goes from 0 to 1 on a device, but the simulated lifecycles of
+// `testing::Harness` run one after the other in the same process.
+static UNLOADS: RwLock<u64> = RwLock::new(0);
+
+thread_local! {
+    // The unload count seen by the `RetainedApi::with()` running on this thread, which holds the
+    // read lock until it returns.
+    static HELD: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Clears [HELD] once the outermost `RetainedApi::with()` returns or unwinds.
+struct Held;
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        HELD.set(None);
+    }
+}
+
+/// Mark the API as unloaded. Called by the glue once `post[XXX]Specialize` is done.
+pub(crate) fn mark_unloaded() {
+    *UNLOADS.write().unwrap_or_else(|e| e.into_inner()) += 1;
+}
+
+/// An API handle that can be kept for the lifetime of the process, as returned by
+/// [ZygiskApi::retained()].
+///
+/// Hooks may fire long after the module callbacks have returned, when the API functions are not
+/// there anymore. Instead of trusting the caller like [ZygiskApi::retain()], this handle checks
+/// whether the process has been specialized, and fails with [ZygiskError::ApiUnloaded] once it
+/// has.
+///
+/// ## Example
+///
+/// ```
+/// use std::sync::OnceLock;
+/// use zygisk::{RetainedApi, ZygiskApi};
+///
+/// static API: OnceLock<RetainedApi> = OnceLock::new();
+///
+/// fn on_load(api: ZygiskApi) {
+///     API.get_or_init(|| api.retained());
+/// }
+///
+/// // Called from a hook, possibly after specialization.
+/// fn on_hook() {
+///     if let Some(api) = API.get() {
+///         let _ = api.with(|api| api.get_flags());
+///     }
+/// }
+/// ```
+#[derive(Clone, Copy)]
+pub struct RetainedApi {
+    inner: &'static RawApiTable,
+    version: ApiVersion,
+    unloads: u64,
+}
+
+// SAFETY: the API table is only read, and its functions may be called from any thread.
+unsafe impl Send for RetainedApi {}
+unsafe impl Sync for RetainedApi {}
+
+impl RetainedApi {
+    pub(crate) fn new(api: ZygiskApi<'static>) -> Self {
+        RetainedApi {
+            inner: api.inner,
+            version: api.version,
+            unloads: *UNLOADS.read().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    /// Run `f` with the API handle, unless the API has been unloaded.
+    ///
+    /// Specialization cannot complete while `f` is running, so keep it short, and do not wait in
+    /// it for other threads that use the API. Calls nested in `f` on the same thread, such as from
+    /// a hook it triggers, reuse the check of the outer call instead of waiting again.
+    pub fn with<R>(&self, f: impl FnOnce(&ZygiskApi) -> R) -> Result<R, ZygiskError> {
+        // Taking the read lock again could deadlock with a `mark_unloaded()` waiting for it.
+        if let Some(unloads) = HELD.get() {
+            if unloads != self.unloads {
+                return Err(ZygiskError::ApiUnloaded);
+            }
+            return Ok(f(&ZygiskApi::from_raw(self.inner, self.version)));
+        }
+        let unloads = UNLOADS.read().unwrap_or_else(|e| e.into_inner());
+        if *unloads != self.unloads {
+            return Err(ZygiskError::ApiUnloaded);
+        }
+        HELD.set(Some(*unloads));
+        let _held = Held;
+        Ok(f(&ZygiskApi::from_raw(self.inner, self.version)))
+    }
+
+    /// The API version the module was registered with.
+    #[cfg(feature = "api-v4")]
+    pub(crate) fn version(&self) -> ApiVersion {
+        self.version
+    }
+
+    /// Whether the API functions can still be called.
+    pub fn is_loaded(&self) -> bool {
+        match HELD.get() {
+            Some(unloads) => unloads == self.unloads,
+            None => *UNLOADS.read().unwrap_or_else(|e| e.into_inner()) == self.unloads,
+        }
+    }
+}
+
+#[test]
+fn test_retained_api() {
+    let api = ZygiskApi::mock(ApiVersion::LATEST, |_| {}).retained();
+    assert_eq!(
+        api.with(|api| api.api_version()).unwrap(),
+        ApiVersion::LATEST
+    );
+
+    // Nested calls go through while `mark_unloaded()` waits for the outer one.
+    let nested = api
+        .with(|_| {
+            let unload = std::thread::spawn(mark_unloaded);
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            let nested = api.with(|api| api.api_version());
+            (unload, nested)
+        })
+        .unwrap();
+    assert_eq!(nested.1.unwrap(), ApiVersion::LATEST);
+    nested.0.join().unwrap();
+    assert!(!api.is_loaded());
+    assert!(matches!(
+        api.with(|api| api.api_version()),
+        Err(ZygiskError::ApiUnloaded)
+    ));
+}
@@ -0,0 +1,1092 @@
+use std::{
+    cell::RefCell,
+    fmt::{self, DebugStruct},
+    path::PathBuf,
+};
+
+use crate::jni::{
+    errors::Result as JniResult,
+    objects::{JIntArray, JString},
+    sys::{jint, jintArray, jlong, jsize},
+    JNIEnv,
+};
+
+use crate::{jni_util, libc::gid_t, AppSpecializeArgs, ServerSpecializeArgs, Uid};
+
+/// An error returned by the setters of [AppSpecializeArgs] and [ServerSpecializeArgs].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ArgsError {
+    /// A JNI call failed.
+    Jni(crate::jni::errors::Error),
+
+    /// The value is not valid for the field.
+    InvalidValue {
+        field: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgsError::Jni(e) => write!(f, "JNI error: {e}"),
+            ArgsError::InvalidValue { field, reason } => {
+                write!(f, "invalid value for `{field}`: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArgsError::Jni(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<crate::jni::errors::Error> for ArgsError {
+    fn from(e: crate::jni::errors::Error) -> Self {
+        ArgsError::Jni(e)
+    }
+}
+
+/// Storage mount mode of an app process (`Zygote.MOUNT_EXTERNAL_*` in AOSP).
+///
+/// Values follow Android 12 and later. Android 11 and older used a different numbering, in which
+/// only [None](MountExternal::None) and [Default](MountExternal::Default) keep their values, so
+/// [AppSpecializeArgs::mount_external()] reports every other mode of those releases as
+/// [MountExternal::Other] with the raw value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MountExternal {
+    /// No external storage is mounted.
+    None,
+    /// Default external storage is mounted.
+    Default,
+    /// The app is an installer and gets access to `Android/obb`.
+    Installer,
+    /// The app gets direct (FUSE-bypassing) access to external storage.
+    PassThrough,
+    /// The app gets write access to `Android/data` and `Android/obb`.
+    AndroidWritable,
+    /// A value not known to this crate.
+    Other(jint),
+}
+
+impl From<jint> for MountExternal {
+    /// Decode a mode in the numbering of Android 12 and later.
+    fn from(raw: jint) -> Self {
+        match raw {
+            0 => MountExternal::None,
+            1 => MountExternal::Default,
+            2 => MountExternal::Installer,
+            3 => MountExternal::PassThrough,
+            4 => MountExternal::AndroidWritable,
+            other => MountExternal::Other(other),
+        }
+    }
+}
+
+impl MountExternal {
+    /// The first SDK level numbering the modes like this enum, Android 12.
+    const SDK_LEVEL: i64 = 31;
+
+    /// Decode a mode of a release with the given SDK level, or of the current numbering if it is
+    /// unknown.
+    fn from_sdk(raw: jint, sdk: Option<i64>) -> Self {
+        match raw {
+            0 | 1 => raw.into(),
+            _ if sdk.is_some_and(|sdk| sdk < Self::SDK_LEVEL) => MountExternal::Other(raw),
+            _ => raw.into(),
+        }
+    }
+
+    /// Encode a mode for a release with the given SDK level, or `None` if the release numbers it
+    /// differently.
+    fn to_sdk(self, sdk: Option<i64>) -> Option<jint> {
+        match self {
+            MountExternal::None | MountExternal::Default | MountExternal::Other(_) => {
+                Some(self.into())
+            }
+            _ if sdk.is_some_and(|sdk| sdk < Self::SDK_LEVEL) => None,
+            _ => Some(self.into()),
+        }
+    }
+}
+
+impl From<MountExternal> for jint {
+    fn from(mode: MountExternal) -> Self {
+        match mode {
+            MountExternal::None => 0,
+            MountExternal::Default => 1,
+            MountExternal::Installer => 2,
+            MountExternal::PassThrough => 3,
+            MountExternal::AndroidWritable => 4,
+            MountExternal::Other(raw) => raw,
+        }
+    }
+}
+
+crate::bitflags::bitflags! {
+    /// Runtime flags of an app process (`Zygote.DEBUG_*` and friends in AOSP).
+    ///
+    /// Multi-bit fields such as the hidden API enforcement policy are exposed as masks; unknown
+    /// bits are retained.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct RuntimeFlags: u32 {
+        /// Enable JDWP debugging.
+        const DEBUG_ENABLE_JDWP = 1;
+        /// Enable CheckJNI.
+        const DEBUG_ENABLE_CHECKJNI = 1 << 1;
+        /// Enable Java assertions.
+        const DEBUG_ENABLE_ASSERT = 1 << 2;
+        /// Run in safe mode (no JIT/AOT).
+        const DEBUG_ENABLE_SAFEMODE = 1 << 3;
+        /// Enable JNI logging.
+        const DEBUG_ENABLE_JNI_LOGGING = 1 << 4;
+        /// Generate debug info for JIT-compiled code.
+        const DEBUG_GENERATE_DEBUG_INFO = 1 << 5;
+        /// Always JIT-compile code.
+        const DEBUG_ALWAYS_JIT = 1 << 6;
+        /// The app is debuggable at the native level.
+        const DEBUG_NATIVE_DEBUGGABLE = 1 << 7;
+        /// The app is debuggable at the Java level.
+        const DEBUG_JAVA_DEBUGGABLE = 1 << 8;
+        /// Disable the bytecode verifier.
+        const DISABLE_VERIFIER = 1 << 9;
+        /// Only use OAT files located in `/system`.
+        const ONLY_USE_SYSTEM_OAT_FILES = 1 << 10;
+        /// Generate mini debug info for compiled code.
+        const DEBUG_GENERATE_MINI_DEBUG_INFO = 1 << 11;
+        /// Mask of the hidden API enforcement policy.
+        const API_ENFORCEMENT_POLICY_MASK = (1 << 12) | (1 << 13);
+        /// Profile the system server.
+        const PROFILE_SYSTEM_SERVER = 1 << 14;
+        /// The app is profileable from the shell.
+        const PROFILE_FROM_SHELL = 1 << 15;
+        /// Use the app image startup cache.
+        const USE_APP_IMAGE_STARTUP_CACHE = 1 << 16;
+        /// Ignore the app's signal handlers.
+        const DEBUG_IGNORE_APP_SIGNAL_HANDLER = 1 << 17;
+        /// Disable the test API enforcement policy.
+        const DISABLE_TEST_API_ENFORCEMENT_POLICY = 1 << 18;
+        /// Mask of the memory tagging level.
+        const MEMORY_TAG_LEVEL_MASK = (1 << 19) | (1 << 20);
+        /// Mask of the GWP-ASan level.
+        const GWP_ASAN_LEVEL_MASK = (1 << 21) | (1 << 22);
+        /// Zero-initialize native heap allocations.
+        const NATIVE_HEAP_ZERO_INIT_ENABLED = 1 << 23;
+        /// The app is profileable.
+        const PROFILEABLE = 1 << 24;
+
+        const _ = !0;
+    }
+}
+
+impl From<jint> for RuntimeFlags {
+    fn from(raw: jint) -> Self {
+        RuntimeFlags::from_bits_retain(raw as u32)
+    }
+}
+
+impl From<RuntimeFlags> for jint {
+    fn from(flags: RuntimeFlags) -> Self {
+        flags.bits() as jint
+    }
+}
+
+crate::bitflags::bitflags! {
+    /// A set of Linux capabilities, as used by
+    /// [ServerSpecializeArgs::permitted_capabilities()] and
+    /// [ServerSpecializeArgs::effective_capabilities()].
+    ///
+    /// Each flag is named after the corresponding `CAP_*` constant, without the prefix. Unknown
+    /// bits are retained.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Capabilities: u64 {
+        const CHOWN = 1 << 0;
+        const DAC_OVERRIDE = 1 << 1;
+        const DAC_READ_SEARCH = 1 << 2;
+        const FOWNER = 1 << 3;
+        const FSETID = 1 << 4;
+        const KILL = 1 << 5;
+        const SETGID = 1 << 6;
+        const SETUID = 1 << 7;
+        const SETPCAP = 1 << 8;
+        const LINUX_IMMUTABLE = 1 << 9;
+        const NET_BIND_SERVICE = 1 << 10;
+        const NET_BROADCAST = 1 << 11;
+        const NET_ADMIN = 1 << 12;
+        const NET_RAW = 1 << 13;
+        const IPC_LOCK = 1 << 14;
+        const IPC_OWNER = 1 << 15;
+        const SYS_MODULE = 1 << 16;
+        const SYS_RAWIO = 1 << 17;
+        const SYS_CHROOT = 1 << 18;
+        const SYS_PTRACE = 1 << 19;
+        const SYS_PACCT = 1 << 20;
+        const SYS_ADMIN = 1 << 21;
+        const SYS_BOOT = 1 << 22;
+        const SYS_NICE = 1 << 23;
+        const SYS_RESOURCE = 1 << 24;
+        const SYS_TIME = 1 << 25;
+        const SYS_TTY_CONFIG = 1 << 26;
+        const MKNOD = 1 << 27;
+        const LEASE = 1 << 28;
+        const AUDIT_WRITE = 1 << 29;
+        const AUDIT_CONTROL = 1 << 30;
+        const SETFCAP = 1 << 31;
+        const MAC_OVERRIDE = 1 << 32;
+        const MAC_ADMIN = 1 << 33;
+        const SYSLOG = 1 << 34;
+        const WAKE_ALARM = 1 << 35;
+        const BLOCK_SUSPEND = 1 << 36;
+        const AUDIT_READ = 1 << 37;
+        const PERFMON = 1 << 38;
+        const BPF = 1 << 39;
+        const CHECKPOINT_RESTORE = 1 << 40;
+
+        const _ = !0;
+    }
+}
+
+/// A view over the supplementary group ids (`gids`) of a process being specialized.
+///
+/// Obtained from [AppSpecializeArgs::gids()] or [ServerSpecializeArgs::gids()]. Modifications
+/// replace the Java array passed to zygote with a new one, so they take effect on
+/// specialization.
+pub struct Gids<'a, 'local> {
+    env: &'a mut JNIEnv<'local>,
+    gids: &'a mut jintArray,
+}
+
+impl Gids<'_, '_> {
+    /// Copy the gids into a vector.
+    pub fn to_vec(&mut self) -> JniResult<Vec<jint>> {
+        get_int_array(self.env, *self.gids)
+    }
+
+    /// Iterate over a copy of the gids.
+    pub fn iter(&mut self) -> JniResult<std::vec::IntoIter<jint>> {
+        self.to_vec().map(Vec::into_iter)
+    }
+
+    pub fn contains(&mut self, gid: jint) -> JniResult<bool> {
+        Ok(self.to_vec()?.contains(&gid))
+    }
+
+    /// Add a gid, unless it is already present.
+    pub fn push(&mut self, gid: jint) -> JniResult<()> {
+        let mut gids = self.to_vec()?;
+        if !gids.contains(&gid) {
+            gids.push(gid);
+            self.replace(&gids)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a gid, returning whether it was present.
+    pub fn remove(&mut self, gid: jint) -> JniResult<bool> {
+        let mut gids = self.to_vec()?;
+        let len = gids.len();
+        gids.retain(|&g| g != gid);
+        if gids.len() == len {
+            return Ok(false);
+        }
+        self.replace(&gids)?;
+        Ok(true)
+    }
+
+    /// Replace all gids.
+    pub fn replace(&mut self, gids: &[jint]) -> JniResult<()> {
+        let array = self.env.new_int_array(gids.len() as jsize)?;
+        self.env.set_int_array_region(&array, 0, gids)?;
+        // The local reference stays valid for the whole specialization, see `new_string`.
+        *self.gids = array.into_raw();
+        Ok(())
+    }
+}
+
+fn get_int_array(env: &mut JNIEnv, array: jintArray) -> JniResult<Vec<jint>> {
+    if array.is_null() {
+        return Ok(Vec::new());
+    }
+    // SAFETY: the array is a valid local reference owned by zygote.
+    let array = unsafe { JIntArray::from_raw(array) };
+    let mut buf = vec![0; env.get_array_length(&array)? as usize];
+    env.get_int_array_region(&array, 0, &mut buf)?;
+    Ok(buf)
+}
+
+fn gids_to_owned(env: &mut JNIEnv, gids: jintArray) -> JniResult<Vec<gid_t>> {
+    let gids = get_int_array(env, gids)?;
+    Ok(gids.into_iter().map(|gid| gid as gid_t).collect())
+}
+
+fn get_string(env: &mut JNIEnv, string: &JString) -> JniResult<String> {
+    jni_util::jstring_to_string(env, string)
+}
+
+fn new_string<'a>(
+    env: &mut JNIEnv,
+    field: &'static str,
+    value: &str,
+) -> Result<JString<'a>, ArgsError> {
+    if value.is_empty() {
+        return Err(ArgsError::InvalidValue {
+            field,
+            reason: "must not be empty",
+        });
+    }
+    if value.contains('\0') {
+        return Err(ArgsError::InvalidValue {
+            field,
+            reason: "must not contain NUL characters",
+        });
+    }
+
+    let string = jni_util::string_to_jstring(env, value)?;
+    // SAFETY: the callbacks run inside zygote's `nativeForkAndSpecialize` JNI frame, so the local
+    // reference stays valid until specialization is done with the arguments.
+    Ok(unsafe { JString::from_raw(string.into_raw()) })
+}
+
+fn check_id(field: &'static str, id: jint) -> Result<jint, ArgsError> {
+    if id >= 0 {
+        Ok(id)
+    } else {
+        Err(ArgsError::InvalidValue {
+            field,
+            reason: "must not be negative",
+        })
+    }
+}
+
+/// The SDK level of the running release, if it can be read.
+fn sdk_level() -> Option<i64> {
+    crate::sysprop::get_int("ro.build.version.sdk")
+}
+
+impl<'a> AppSpecializeArgs<'a> {
+    /// The uid the app process will run as.
+    pub fn uid(&self) -> Uid {
+        (*self.uid).into()
+    }
+
+    /// The primary gid the app process will run as.
+    pub fn gid(&self) -> jint {
+        *self.gid
+    }
+
+    /// The supplementary group ids of the app.
+    pub fn gids<'s, 'local>(&'s mut self, env: &'s mut JNIEnv<'local>) -> Gids<'s, 'local> {
+        Gids {
+            env,
+            gids: self.gids,
+        }
+    }
+
+    /// The storage mount mode of the app.
+    pub fn mount_external(&self) -> MountExternal {
+        MountExternal::from_sdk(*self.mount_external, sdk_level())
+    }
+
+    /// The runtime flags of the app.
+    pub fn runtime_flags(&self) -> RuntimeFlags {
+        (*self.runtime_flags).into()
+    }
+
+    /// The process name, usually the package name of the app.
+    pub fn nice_name(&self, env: &mut JNIEnv) -> JniResult<String> {
+        get_string(env, self.nice_name)
+    }
+
+    /// The SELinux info string (`seinfo`) used to compute the SELinux context of the app.
+    pub fn se_info(&self, env: &mut JNIEnv) -> JniResult<String> {
+        get_string(env, self.se_info)
+    }
+
+    /// The instruction set the app runs with, e.g. `arm64`.
+    pub fn instruction_set(&self, env: &mut JNIEnv) -> JniResult<String> {
+        get_string(env, self.instruction_set)
+    }
+
+    /// The data directory of the app, e.g. `/data/user/0/com.example`.
+    pub fn app_data_dir(&self, env: &mut JNIEnv) -> JniResult<PathBuf> {
+        get_string(env, self.app_data_dir).map(PathBuf::from)
+    }
+
+    /// Whether the process is a child zygote (e.g. an app zygote), if known.
+    pub fn is_child_zygote(&self) -> Option<bool> {
+        self.is_child_zygote.map(|&b| b != 0)
+    }
+
+    /// Whether the app is the top (foreground) app, if known.
+    pub fn is_top_app(&self) -> Option<bool> {
+        self.is_top_app.map(|&b| b != 0)
+    }
+
+    /// Copy the arguments into an [AppSpecializeInfo], which can be kept after specialization.
+    pub fn to_owned(&self, env: &mut JNIEnv) -> JniResult<AppSpecializeInfo> {
+        Ok(AppSpecializeInfo {
+            uid: self.uid(),
+            gid: self.gid() as gid_t,
+            gids: gids_to_owned(env, *self.gids)?,
+            runtime_flags: self.runtime_flags(),
+            mount_external: self.mount_external(),
+            se_info: self.se_info(env)?,
+            nice_name: self.nice_name(env)?,
+            instruction_set: self.instruction_set(env)?,
+            app_data_dir: self.app_data_dir(env)?,
+            is_child_zygote: self.is_child_zygote(),
+            is_top_app: self.is_top_app(),
+        })
+    }
+
+    /// Show the arguments with the `String`s and arrays read through `env`, for logging them with
+    /// `log::debug!("{:?}", args.debug(env))`. Fields that are null are left out, and fields
+    /// that cannot be read show the error instead.
+    pub fn debug<'s, 'local>(
+        &'s self,
+        env: &'s mut JNIEnv<'local>,
+    ) -> impl fmt::Debug + use<'s, 'local, 'a> {
+        DebugWithEnv {
+            args: self,
+            env: RefCell::new(env),
+        }
+    }
+
+    fn debug_optional(&self, debug: &mut DebugStruct) {
+        let optional = [
+            ("is_child_zygote", self.is_child_zygote),
+            ("is_top_app", self.is_top_app),
+            ("mount_data_dirs", self.mount_data_dirs),
+            ("mount_sysprop_overrides", self.mount_sysprop_overrides),
+            ("mount_storage_dirs", self.mount_storage_dirs),
+        ];
+        for (name, value) in optional {
+            if let Some(&value) = value {
+                debug.field(name, &(value != 0));
+            }
+        }
+    }
+
+    /// Whether the process is an isolated process, such as an isolated service or a process
+    /// spawned by an app zygote, which run without the permissions and data of their app.
+    ///
+    /// Child zygotes are not isolated themselves, even though they spawn isolated processes.
+    pub fn is_isolated_process(&self) -> bool {
+        self.uid().is_isolated() && self.is_child_zygote() != Some(true)
+    }
+}
+
+/// An owned copy of [AppSpecializeArgs], returned by [AppSpecializeArgs::to_owned()].
+///
+/// The arguments borrow JNI references that are only valid during specialization, while this
+/// copy can be stored for later, such as for hooks running in the app. With the `serde` feature,
+/// it can also be sent to the companion or saved for diagnostics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppSpecializeInfo {
+    pub uid: Uid,
+    pub gid: gid_t,
+    pub gids: Vec<gid_t>,
+    pub runtime_flags: RuntimeFlags,
+    pub mount_external: MountExternal,
+    pub se_info: String,
+    pub nice_name: String,
+    pub instruction_set: String,
+    pub app_data_dir: PathBuf,
+    pub is_child_zygote: Option<bool>,
+    pub is_top_app: Option<bool>,
+}
+
+/// An owned copy of [ServerSpecializeArgs], returned by [ServerSpecializeArgs::to_owned()].
+///
+/// See [AppSpecializeInfo].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerSpecializeInfo {
+    pub uid: Uid,
+    pub gid: gid_t,
+    pub gids: Vec<gid_t>,
+    pub runtime_flags: RuntimeFlags,
+    pub permitted_capabilities: Capabilities,
+    pub effective_capabilities: Capabilities,
+}
+
+/// Setters for the arguments that may be changed in
+/// [`pre_app_specialize`](crate::ZygiskModule::pre_app_specialize).
+///
+/// `post_app_specialize` only gets a shared reference to the arguments, so these cannot be
+/// called once the process has been specialized.
+impl AppSpecializeArgs<'_> {
+    pub fn set_uid(&mut self, uid: Uid) -> Result<(), ArgsError> {
+        *self.uid = check_id("uid", uid.into())?;
+        Ok(())
+    }
+
+    pub fn set_gid(&mut self, gid: jint) -> Result<(), ArgsError> {
+        *self.gid = check_id("gid", gid)?;
+        Ok(())
+    }
+
+    /// Set the storage mount mode of the app.
+    ///
+    /// Modes other than [None](MountExternal::None), [Default](MountExternal::Default) and
+    /// [Other](MountExternal::Other) are rejected on Android 11 and older, which number them
+    /// differently.
+    pub fn set_mount_external(&mut self, mount_external: MountExternal) -> Result<(), ArgsError> {
+        let raw = mount_external
+            .to_sdk(sdk_level())
+            .ok_or(ArgsError::InvalidValue {
+                field: "mount_external",
+                reason: "not supported before Android 12",
+            })?;
+        *self.mount_external = check_id("mount_external", raw)?;
+        Ok(())
+    }
+
+    pub fn set_runtime_flags(&mut self, runtime_flags: RuntimeFlags) {
+        *self.runtime_flags = runtime_flags.into();
+    }
+
+    pub fn set_nice_name(&mut self, env: &mut JNIEnv, nice_name: &str) -> Result<(), ArgsError> {
+        *self.nice_name = new_string(env, "nice_name", nice_name)?;
+        crate::process::set_process_name(nice_name);
+        Ok(())
+    }
+
+    pub fn set_se_info(&mut self, env: &mut JNIEnv, se_info: &str) -> Result<(), ArgsError> {
+        *self.se_info = new_string(env, "se_info", se_info)?;
+        Ok(())
+    }
+}
+
+impl<'a> ServerSpecializeArgs<'a> {
+    /// The uid `system_server` will run as.
+    pub fn uid(&self) -> Uid {
+        (*self.uid).into()
+    }
+
+    /// The primary gid `system_server` will run as.
+    pub fn gid(&self) -> jint {
+        *self.gid
+    }
+
+    /// The supplementary group ids of `system_server`.
+    pub fn gids<'s, 'local>(&'s mut self, env: &'s mut JNIEnv<'local>) -> Gids<'s, 'local> {
+        Gids {
+            env,
+            gids: self.gids,
+        }
+    }
+
+    /// The runtime flags of `system_server`.
+    pub fn runtime_flags(&self) -> RuntimeFlags {
+        (*self.runtime_flags).into()
+    }
+
+    /// Show the arguments with the gids read through `env`, see [AppSpecializeArgs::debug()].
+    pub fn debug<'s, 'local>(
+        &'s self,
+        env: &'s mut JNIEnv<'local>,
+    ) -> impl fmt::Debug + use<'s, 'local, 'a> {
+        DebugWithEnv {
+            args: self,
+            env: RefCell::new(env),
+        }
+    }
+
+    /// Copy the arguments into a [ServerSpecializeInfo], which can be kept after specialization.
+    pub fn to_owned(&self, env: &mut JNIEnv) -> JniResult<ServerSpecializeInfo> {
+        Ok(ServerSpecializeInfo {
+            uid: self.uid(),
+            gid: self.gid() as gid_t,
+            gids: gids_to_owned(env, *self.gids)?,
+            runtime_flags: self.runtime_flags(),
+            permitted_capabilities: self.permitted_capabilities(),
+            effective_capabilities: self.effective_capabilities(),
+        })
+    }
+
+    /// The permitted capability set `system_server` will run with.
+    pub fn permitted_capabilities(&self) -> Capabilities {
+        Capabilities::from_bits_retain(*self.permitted_capabilities as u64)
+    }
+
+    /// The effective capability set `system_server` will run with.
+    pub fn effective_capabilities(&self) -> Capabilities {
+        Capabilities::from_bits_retain(*self.effective_capabilities as u64)
+    }
+
+    /// Set the permitted capability set. Capabilities removed from the permitted set are also
+    /// removed from the effective set, since the latter must be a subset of the former.
+    pub fn set_permitted_capabilities(&mut self, capabilities: Capabilities) {
+        *self.permitted_capabilities = capabilities.bits() as jlong;
+        let effective = self.effective_capabilities() & capabilities;
+        *self.effective_capabilities = effective.bits() as jlong;
+    }
+
+    /// Set the effective capability set, which must be a subset of the permitted set.
+    pub fn set_effective_capabilities(
+        &mut self,
+        capabilities: Capabilities,
+    ) -> Result<(), ArgsError> {
+        if !self.permitted_capabilities().contains(capabilities) {
+            return Err(ArgsError::InvalidValue {
+                field: "effective_capabilities",
+                reason: "must be a subset of the permitted capabilities",
+            });
+        }
+        *self.effective_capabilities = capabilities.bits() as jlong;
+        Ok(())
+    }
+}
+
+/// Shows the plain fields only: `String`s and arrays are JNI references that need a `JNIEnv` to
+/// be read, so use [AppSpecializeArgs::debug()] to see them. Optional fields that zygote did not
+/// pass are left out.
+impl fmt::Debug for AppSpecializeArgs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("AppSpecializeArgs");
+        debug
+            .field("uid", &self.uid())
+            .field("gid", &self.gid())
+            .field("runtime_flags", &self.runtime_flags())
+            .field("mount_external", &self.mount_external());
+        self.debug_optional(&mut debug);
+        debug.finish_non_exhaustive()
+    }
+}
+
+/// Shows the plain fields only, see the `Debug` implementation of [AppSpecializeArgs].
+impl fmt::Debug for ServerSpecializeArgs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerSpecializeArgs")
+            .field("uid", &self.uid())
+            .field("gid", &self.gid())
+            .field("runtime_flags", &self.runtime_flags())
+            .field("permitted_capabilities", &self.permitted_capabilities())
+            .field("effective_capabilities", &self.effective_capabilities())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returned by [AppSpecializeArgs::debug()] and [ServerSpecializeArgs::debug()].
+struct DebugWithEnv<'s, 'local, T> {
+    args: &'s T,
+    // `Debug::fmt()` only gets a shared reference, while reading through JNI needs a mutable one.
+    env: RefCell<&'s mut JNIEnv<'local>>,
+}
+
+impl fmt::Debug for DebugWithEnv<'_, '_, AppSpecializeArgs<'_>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self.args;
+        let env = &mut **self.env.borrow_mut();
+        let mut debug = f.debug_struct("AppSpecializeArgs");
+        debug.field("uid", &args.uid()).field("gid", &args.gid());
+        debug_gids(&mut debug, env, *args.gids);
+        debug
+            .field("runtime_flags", &args.runtime_flags())
+            .field("mount_external", &args.mount_external());
+        let strings = [
+            ("se_info", &*args.se_info),
+            ("nice_name", &*args.nice_name),
+            ("instruction_set", &*args.instruction_set),
+            ("app_data_dir", &*args.app_data_dir),
+        ];
+        for (name, string) in strings {
+            match jni_util::jstring_to_option(env, string) {
+                Ok(Some(value)) => debug.field(name, &value),
+                Ok(None) => &mut debug,
+                Err(e) => debug.field(name, &format_args!("<{e}>")),
+            };
+        }
+        args.debug_optional(&mut debug);
+        debug.finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for DebugWithEnv<'_, '_, ServerSpecializeArgs<'_>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self.args;
+        let mut debug = f.debug_struct("ServerSpecializeArgs");
+        debug.field("uid", &args.uid()).field("gid", &args.gid());
+        debug_gids(&mut debug, &mut self.env.borrow_mut(), *args.gids);
+        debug
+            .field("runtime_flags", &args.runtime_flags())
+            .field("permitted_capabilities", &args.permitted_capabilities())
+            .field("effective_capabilities", &args.effective_capabilities())
+            .finish_non_exhaustive()
+    }
+}
+
+fn debug_gids(debug: &mut DebugStruct, env: &mut JNIEnv, gids: jintArray) {
+    if gids.is_null() {
+        return;
+    }
+    match get_int_array(env, gids) {
+        Ok(gids) => debug.field("gids", &gids),
+        Err(e) => debug.field("gids", &format_args!("<{e}>")),
+    };
+}
+
+/// A one-line summary, such as `com.example:remote (uid 1010123, gid 1010123, arm64)`.
+impl std::fmt::Display for AppSpecializeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (uid {}, gid {}, {})",
+            self.nice_name, self.uid, self.gid, self.instruction_set
+        )
+    }
+}
+
+/// A one-line summary, such as `system_server (uid 1000, gid 1000)`.
+impl std::fmt::Display for ServerSpecializeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "system_server (uid {}, gid {})", self.uid, self.gid)
+    }
+}
+
+#[test]
+fn test_app_args_accessors() {
+    use crate::jni::objects::{JObject, JObjectArray};
+
+    let (mut uid, mut gid, mut gids, mut runtime_flags, mut mount_external) =
+        (10123, 10123, std::ptr::null_mut(), 0, 0);
+    let mut rlimits = JObjectArray::from(JObject::null());
+    let (mut se_info, mut nice_name, mut instruction_set, mut app_data_dir) = (
+        JString::from(JObject::null()),
+        JString::from(JObject::null()),
+        JString::from(JObject::null()),
+        JString::from(JObject::null()),
+    );
+    let is_top_app = 1;
+
+    let mut args = AppSpecializeArgs {
+        uid: &mut uid,
+        gid: &mut gid,
+        gids: &mut gids,
+        runtime_flags: &mut runtime_flags,
+        rlimits: &mut rlimits,
+        mount_external: &mut mount_external,
+        se_info: &mut se_info,
+        nice_name: &mut nice_name,
+        instruction_set: &mut instruction_set,
+        app_data_dir: &mut app_data_dir,
+        fds_to_ignore: None,
+        is_child_zygote: None,
+        is_top_app: Some(&is_top_app),
+        pkg_data_info_list: None,
+        whitelisted_data_info_list: None,
+        mount_data_dirs: None,
+        mount_sysprop_overrides: None,
+        mount_storage_dirs: None,
+    };
+
+    assert_eq!(
+        format!("{args:?}"),
+        "AppSpecializeArgs { uid: Uid(10123), gid: 10123, runtime_flags: RuntimeFlags(0x0), \
+         mount_external: None, is_top_app: true, .. }"
+    );
+    assert_eq!(args.uid(), Uid::from(10123));
+    assert_eq!(args.gid(), 10123);
+    assert_eq!(args.is_child_zygote(), None);
+    assert_eq!(args.is_top_app(), Some(true));
+
+    args.set_uid(Uid::of(0, 10124)).unwrap();
+    assert_eq!(args.uid().app_id(), 10124);
+    assert!(!args.is_isolated_process());
+    args.set_uid(Uid::of(10, 99_001)).unwrap();
+    assert!(args.is_isolated_process());
+    args.is_child_zygote = Some(&is_top_app);
+    assert!(!args.is_isolated_process());
+    assert!(matches!(
+        args.set_gid(-1),
+        Err(ArgsError::InvalidValue { field: "gid", .. })
+    ));
+    assert_eq!(args.gid(), 10123);
+
+    assert_eq!(args.mount_external(), MountExternal::None);
+    args.set_mount_external(MountExternal::PassThrough).unwrap();
+    assert_eq!(*args.mount_external, 3);
+
+    args.set_runtime_flags(RuntimeFlags::DEBUG_ENABLE_JDWP | RuntimeFlags::from(1 << 30));
+    assert!(args
+        .runtime_flags()
+        .contains(RuntimeFlags::DEBUG_ENABLE_JDWP));
+    assert_eq!(*args.runtime_flags, 1 | (1 << 30));
+}
+
+#[test]
+fn test_app_args_layouts() {
+    use crate::{
+        binding::{with_app_args, with_raw_app_args},
+        jni::objects::{JObject, JObjectArray},
+        ApiVersion,
+    };
+
+    for version in [
+        ApiVersion::V2,
+        ApiVersion::V3,
+        ApiVersion::V4,
+        ApiVersion::V5,
+    ] {
+        let (mut uid, mut gid, mut gids, mut runtime_flags, mut mount_external) =
+            (10123, 10124, std::ptr::null_mut(), 0, 1);
+        let mut rlimits = JObjectArray::from(JObject::null());
+        let (mut se_info, mut nice_name, mut instruction_set, mut app_data_dir) = (
+            JString::from(JObject::null()),
+            JString::from(JObject::null()),
+            JString::from(JObject::null()),
+            JString::from(JObject::null()),
+        );
+        let (fds_to_ignore, yes) = (std::ptr::null_mut(), 1);
+        let mut args = AppSpecializeArgs {
+            uid: &mut uid,
+            gid: &mut gid,
+            gids: &mut gids,
+            runtime_flags: &mut runtime_flags,
+            rlimits: &mut rlimits,
+            mount_external: &mut mount_external,
+            se_info: &mut se_info,
+            nice_name: &mut nice_name,
+            instruction_set: &mut instruction_set,
+            app_data_dir: &mut app_data_dir,
+            fds_to_ignore: Some(&fds_to_ignore),
+            is_child_zygote: None,
+            is_top_app: Some(&yes),
+            pkg_data_info_list: None,
+            whitelisted_data_info_list: None,
+            mount_data_dirs: None,
+            mount_sysprop_overrides: Some(&yes),
+            mount_storage_dirs: Some(&yes),
+        };
+
+        with_raw_app_args(version, &mut args, |raw| unsafe {
+            with_app_args(version, raw, |args| {
+                assert_eq!(args.uid(), Uid::from(10123));
+                assert_eq!(args.gid(), 10124);
+                assert_eq!(*args.mount_external, 1);
+                assert_eq!(args.is_top_app(), Some(true));
+                assert_eq!(args.mount_storage_dirs, Some(&1));
+                assert_eq!(args.fds_to_ignore.is_some(), version >= ApiVersion::V3);
+                assert_eq!(
+                    args.mount_sysprop_overrides.is_some(),
+                    version >= ApiVersion::V5
+                );
+                args.set_gid(10125).unwrap();
+            })
+        });
+        assert_eq!(gid, 10125);
+    }
+}
+
+#[test]
+fn test_server_args_capabilities() {
+    let (mut uid, mut gid, mut gids, mut runtime_flags) = (1000, 1000, std::ptr::null_mut(), 0);
+    let mut permitted = (Capabilities::KILL | Capabilities::SYS_NICE).bits() as jlong;
+    let mut effective = permitted;
+
+    let mut args = ServerSpecializeArgs {
+        uid: &mut uid,
+        gid: &mut gid,
+        gids: &mut gids,
+        runtime_flags: &mut runtime_flags,
+        permitted_capabilities: &mut permitted,
+        effective_capabilities: &mut effective,
+    };
+
+    assert_eq!(
+        args.permitted_capabilities(),
+        Capabilities::KILL | Capabilities::SYS_NICE
+    );
+    assert!(args
+        .set_effective_capabilities(Capabilities::SYS_ADMIN)
+        .is_err());
+
+    args.set_permitted_capabilities(Capabilities::KILL);
+    assert_eq!(args.effective_capabilities(), Capabilities::KILL);
+
+    #[cfg(feature = "testing")]
+    {
+        let stub = crate::testing::StubEnv::new();
+        let info = args.to_owned(&mut stub.env()).unwrap();
+        assert_eq!(info.uid, Uid::SYSTEM);
+        assert!(info.gids.is_empty());
+        assert_eq!(info.effective_capabilities, Capabilities::KILL);
+        assert_eq!(info.to_string(), "system_server (uid 1000, gid 1000)");
+
+        *args.gids = stub.new_int_array(&[1065, 3009]);
+        assert_eq!(
+            format!("{:?}", args.debug(&mut stub.env())),
+            "ServerSpecializeArgs { uid: Uid(1000), gid: 1000, gids: [1065, 3009], \
+             runtime_flags: RuntimeFlags(0x0), permitted_capabilities: Capabilities(KILL), \
+             effective_capabilities: Capabilities(KILL), .. }"
+        );
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_app_args_debug_nulls() {
+    use crate::{
+        jni::objects::{JObject, JObjectArray},
+        testing::StubEnv,
+    };
+
+    let stub = StubEnv::new();
+    let (mut uid, mut gid, mut gids, mut runtime_flags, mut mount_external) =
+        (10123, 10123, std::ptr::null_mut(), 0, 7);
+    let mut rlimits = JObjectArray::default();
+    let mut nice_name = unsafe { JString::from_raw(stub.new_string("com.example")) };
+    // Not a string, so it cannot be read.
+    let mut se_info = unsafe { JString::from_raw(stub.new_int_array(&[]).cast()) };
+    let (mut instruction_set, mut app_data_dir) = (
+        JString::from(JObject::null()),
+        JString::from(JObject::null()),
+    );
+    let is_child_zygote = 1;
+    let args = AppSpecializeArgs {
+        uid: &mut uid,
+        gid: &mut gid,
+        gids: &mut gids,
+        runtime_flags: &mut runtime_flags,
+        rlimits: &mut rlimits,
+        mount_external: &mut mount_external,
+        se_info: &mut se_info,
+        nice_name: &mut nice_name,
+        instruction_set: &mut instruction_set,
+        app_data_dir: &mut app_data_dir,
+        fds_to_ignore: None,
+        is_child_zygote: Some(&is_child_zygote),
+        is_top_app: None,
+        pkg_data_info_list: None,
+        whitelisted_data_info_list: None,
+        mount_data_dirs: None,
+        mount_sysprop_overrides: None,
+        mount_storage_dirs: None,
+    };
+
+    let debug = format!("{:?}", args.debug(&mut stub.env()));
+    assert!(
+        debug.starts_with(
+            "AppSpecializeArgs { uid: Uid(10123), gid: 10123, runtime_flags: RuntimeFlags(0x0), \
+             mount_external: Other(7), se_info: <"
+        ),
+        "{debug}"
+    );
+    assert!(
+        debug.ends_with(">, nice_name: \"com.example\", is_child_zygote: true, .. }"),
+        "{debug}"
+    );
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_app_args_to_owned() {
+    use crate::{jni::objects::JObjectArray, testing::StubEnv};
+
+    let stub = StubEnv::new();
+    let mut env = stub.env();
+    let string = |value| unsafe { JString::from_raw(stub.new_string(value)) };
+    let (mut uid, mut gid, mut runtime_flags, mut mount_external) = (10123, 10123, 1, 1);
+    let mut gids = stub.new_int_array(&[3003, 9997]);
+    let mut rlimits = JObjectArray::default();
+    let (mut se_info, mut nice_name, mut instruction_set, mut app_data_dir) = (
+        string("default:targetSdkVersion=34"),
+        string("com.example:remote"),
+        string("arm64"),
+        string("/data/user/0/com.example"),
+    );
+    let args = AppSpecializeArgs {
+        uid: &mut uid,
+        gid: &mut gid,
+        gids: &mut gids,
+        runtime_flags: &mut runtime_flags,
+        rlimits: &mut rlimits,
+        mount_external: &mut mount_external,
+        se_info: &mut se_info,
+        nice_name: &mut nice_name,
+        instruction_set: &mut instruction_set,
+        app_data_dir: &mut app_data_dir,
+        fds_to_ignore: None,
+        is_child_zygote: None,
+        is_top_app: None,
+        pkg_data_info_list: None,
+        whitelisted_data_info_list: None,
+        mount_data_dirs: None,
+        mount_sysprop_overrides: None,
+        mount_storage_dirs: None,
+    };
+
+    assert_eq!(
+        format!("{:?}", args.debug(&mut env)),
+        "AppSpecializeArgs { uid: Uid(10123), gid: 10123, gids: [3003, 9997], \
+         runtime_flags: RuntimeFlags(DEBUG_ENABLE_JDWP), mount_external: Default, \
+         se_info: \"default:targetSdkVersion=34\", nice_name: \"com.example:remote\", \
+         instruction_set: \"arm64\", app_data_dir: \"/data/user/0/com.example\", .. }"
+    );
+
+    let info = args.to_owned(&mut env).unwrap();
+    assert_eq!(
+        info,
+        AppSpecializeInfo {
+            uid: Uid::from(10123),
+            gid: 10123,
+            gids: vec![3003, 9997],
+            runtime_flags: RuntimeFlags::DEBUG_ENABLE_JDWP,
+            mount_external: MountExternal::Default,
+            se_info: "default:targetSdkVersion=34".into(),
+            nice_name: "com.example:remote".into(),
+            instruction_set: "arm64".into(),
+            app_data_dir: "/data/user/0/com.example".into(),
+            is_child_zygote: None,
+            is_top_app: None,
+        }
+    );
+    assert_eq!(
+        info.to_string(),
+        "com.example:remote (uid 10123, gid 10123, arm64)"
+    );
+
+    #[cfg(feature = "serde")]
+    {
+        let bytes = bincode::serialize(&info).unwrap();
+        assert_eq!(
+            bincode::deserialize::<AppSpecializeInfo>(&bytes).unwrap(),
+            info
+        );
+    }
+}
+
+#[test]
+fn test_mount_external_sdk() {
+    // Android 11 numbers READ, WRITE and LEGACY 2 to 4.
+    assert_eq!(MountExternal::from_sdk(1, Some(30)), MountExternal::Default);
+    assert_eq!(
+        MountExternal::from_sdk(3, Some(30)),
+        MountExternal::Other(3)
+    );
+    assert_eq!(
+        MountExternal::from_sdk(3, Some(31)),
+        MountExternal::PassThrough
+    );
+    assert_eq!(MountExternal::from_sdk(3, None), MountExternal::PassThrough);
+    assert_eq!(
+        MountExternal::from_sdk(9, Some(33)),
+        MountExternal::Other(9)
+    );
+
+    assert_eq!(MountExternal::PassThrough.to_sdk(Some(30)), None);
+    assert_eq!(MountExternal::PassThrough.to_sdk(Some(31)), Some(3));
+    assert_eq!(MountExternal::None.to_sdk(Some(30)), Some(0));
+    assert_eq!(MountExternal::Other(7).to_sdk(Some(30)), Some(7));
+}
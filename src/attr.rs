@@ -0,0 +1,83 @@
+//! Attribute macros registering a module or a companion, as an alternative to
+//! [zygisk_module!](crate::zygisk_module) and [zygisk_companion!](crate::zygisk_companion).
+//!
+//! Requires the `macros` feature.
+//!
+//! ## Module
+//!
+//! Put `#[zygisk_module]` on a type implementing [Default] and
+//! [ZygiskModule](crate::ZygiskModule). It is constructed once, when the module is loaded, and
+//! lives as long as the process:
+//!
+//! ```
+//! use std::sync::atomic::AtomicBool;
+//! use zygisk::{attr::zygisk_module, ZygiskModule};
+//!
+//! #[zygisk_module]
+//! #[derive(Default)]
+//! struct MyModule {
+//!     enabled: AtomicBool,
+//! }
+//!
+//! impl ZygiskModule for MyModule {}
+//! ```
+//!
+//! Like with `zygisk_module!`, the module has to be [Sync] and panics abort the process.
+//!
+//! ## Companion
+//!
+//! Put `#[zygisk_companion]` on the function handling companion connections. It takes the
+//! [UnixStream](std::os::unix::net::UnixStream) connected to the module and returns nothing:
+//!
+//! ```
+//! use std::os::unix::net::UnixStream;
+//! use zygisk::attr::zygisk_companion;
+//!
+//! #[zygisk_companion]
+//! fn companion_main(_stream: UnixStream) {}
+//! ```
+//!
+//! The attribute enables none of the [built-in services](crate::companion::BuiltinServices); use
+//! `zygisk_companion!(handler, services = [...])` for those.
+//!
+//! With the `tokio` feature, the function can be `async` and takes a
+//! `tokio::net::UnixStream` instead:
+//!
+//! ```
+//! # #[cfg(feature = "tokio")]
+//! # mod example {
+//! use zygisk::{attr::zygisk_companion, tokio::net::UnixStream};
+//!
+//! #[zygisk_companion]
+//! async fn companion_main(_stream: UnixStream) {}
+//! # }
+//! ```
+//!
+//! Signatures that cannot be registered are reported on the offending part of the function:
+//!
+//! ```compile_fail
+//! use zygisk::attr::zygisk_companion;
+//!
+//! #[zygisk_companion]
+//! fn companion_main(fd: i32) -> bool {
+//!     fd >= 0
+//! }
+//! ```
+//!
+//! ## Renamed crate
+//!
+//! The generated code refers to this crate as `::zygisk`. If it is known under another name, for
+//! example because it is re-exported by another crate, pass its path with `crate`:
+//!
+//! ```
+//! use zygisk as my_zygisk;
+//! use my_zygisk::{attr::zygisk_module, ZygiskModule};
+//!
+//! #[zygisk_module(crate = "my_zygisk")]
+//! #[derive(Default)]
+//! struct MyModule;
+//!
+//! impl ZygiskModule for MyModule {}
+//! ```
+
+pub use zygisk_macros::{zygisk_companion, zygisk_module};
@@ -1,3 +1,120 @@
 pub use bitflags;
 pub use jni;
 pub use libc;
+#[cfg(feature = "tokio")]
+pub use tokio;
+
+use std::io;
+
+/// Capability sets of the current process, as reported by `/proc/self/status`.
+///
+/// Each field is a raw bitmask indexed by `CAP_*` numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessCapabilities {
+    pub inheritable: u64,
+    pub permitted: u64,
+    pub effective: u64,
+    pub bounding: u64,
+}
+
+/// Read the capability sets of the current process.
+///
+/// Zygote drops capabilities while specializing, so the result depends on when this is called;
+/// in `post_server_specialize` it reflects what `system_server` actually runs with (compare
+/// with [ServerSpecializeArgs::permitted_capabilities](crate::ServerSpecializeArgs)).
+pub fn process_capabilities() -> io::Result<ProcessCapabilities> {
+    parse_status_capabilities(&std::fs::read_to_string("/proc/self/status")?)
+}
+
+fn parse_status_capabilities(status: &str) -> io::Result<ProcessCapabilities> {
+    let mut caps = ProcessCapabilities::default();
+    let mut seen = 0;
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let field = match key {
+            "CapInh" => &mut caps.inheritable,
+            "CapPrm" => &mut caps.permitted,
+            "CapEff" => &mut caps.effective,
+            "CapBnd" => &mut caps.bounding,
+            _ => continue,
+        };
+        *field = u64::from_str_radix(value.trim(), 16)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        seen += 1;
+    }
+
+    if seen == 4 {
+        Ok(caps)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing capability lines in /proc/self/status",
+        ))
+    }
+}
+
+/// Set the "dumpable" attribute of the current process (`prctl(PR_SET_DUMPABLE)`).
+///
+/// Zygote clears this flag while specializing app processes; calling this in
+/// [`post_app_specialize`](crate::ZygiskModule::post_app_specialize) re-enables core dumps and
+/// `ptrace` attachment by processes of the same uid, which is handy for debugging crashes.
+///
+/// ## Security
+///
+/// A dumpable process exposes its memory (through core dumps, `/proc/[PID]/mem` and `ptrace`)
+/// to anything running as the same uid, and makes its `/proc/[PID]` entries owned by that uid
+/// instead of root. Never enable this in release builds of a module.
+pub fn set_dumpable(dumpable: bool) -> io::Result<()> {
+    let ret = unsafe { libc::prctl(libc::PR_SET_DUMPABLE, dumpable as libc::c_ulong, 0, 0, 0) };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the "dumpable" attribute of the current process (`prctl(PR_GET_DUMPABLE)`).
+pub fn get_dumpable() -> io::Result<bool> {
+    let ret = unsafe { libc::prctl(libc::PR_GET_DUMPABLE, 0, 0, 0, 0) };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret != 0)
+    }
+}
+
+#[test]
+fn test_parse_status_capabilities() {
+    let status = "Name:\tsystem_server\n\
+                  Uid:\t1000\t1000\t1000\t1000\n\
+                  CapInh:\t0000000000000000\n\
+                  CapPrm:\t0000001007897c20\n\
+                  CapEff:\t0000001007897c20\n\
+                  CapBnd:\t000001ffffffffff\n\
+                  CapAmb:\t0000000000000000\n";
+
+    assert_eq!(
+        parse_status_capabilities(status).unwrap(),
+        ProcessCapabilities {
+            inheritable: 0,
+            permitted: 0x1007897c20,
+            effective: 0x1007897c20,
+            bounding: 0x1ffffffffff,
+        }
+    );
+    assert!(parse_status_capabilities("Name:\tfoo\n").is_err());
+}
+
+#[test]
+fn test_dumpable_round_trip() {
+    let original = get_dumpable().unwrap();
+
+    set_dumpable(false).unwrap();
+    assert!(!get_dumpable().unwrap());
+    set_dumpable(true).unwrap();
+    assert!(get_dumpable().unwrap());
+
+    set_dumpable(original).unwrap();
+}
@@ -10,20 +10,90 @@ use crate::libc::{dev_t, ino_t};
 type c_bool = bool;
 type Module = crate::module::RawModule;
 
-pub const API_VERSION: c_long = 5;
+pub const API_VERSION: c_long = ApiVersion::LATEST as c_long;
 
+/// Zygisk API versions this crate is able to register with.
+///
+/// The newest version the crate registers with is selected through the `api-v2`, `api-v3`,
+/// `api-v4` and `api-v5` cargo features (`api-v5` by default).
+///
+/// When loaded, a module first tries to register with [ApiVersion::LATEST], falling back to
+/// older versions until the host accepts one. The version that was accepted is available from
+/// [ZygiskApi::api_version()](crate::ZygiskApi::api_version).
+///
+/// The features only cap the version the module registers with, and gate the methods of
+/// [ZygiskApi](crate::ZygiskApi) that need a newer one. Everything that depends on the layout
+/// Zygisk uses, such as the API table, the callback table and the layout of the
+/// [AppSpecializeArgs] passed to the module, follows the version that was accepted, so a module
+/// built with `api-v5` still works with hosts that only accept older versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ApiVersion {
+    V2 = 2,
+    V3 = 3,
+    /// PLT hooks are registered by device and inode instead of by path regex,
+    /// and file descriptors can be exempted from being closed.
+    V4 = 4,
+    V5 = 5,
+}
+
+#[cfg(not(feature = "api-v2"))]
+compile_error!("one of the `api-v2`, `api-v3`, `api-v4` or `api-v5` features must be enabled");
+
+impl ApiVersion {
+    /// The newest version enabled through the `api-vN` cargo features.
+    #[cfg(feature = "api-v5")]
+    pub const LATEST: ApiVersion = ApiVersion::V5;
+    #[cfg(all(feature = "api-v4", not(feature = "api-v5")))]
+    pub const LATEST: ApiVersion = ApiVersion::V4;
+    #[cfg(all(feature = "api-v3", not(feature = "api-v4")))]
+    pub const LATEST: ApiVersion = ApiVersion::V3;
+    #[cfg(all(feature = "api-v2", not(feature = "api-v3")))]
+    pub const LATEST: ApiVersion = ApiVersion::V2;
+    // Only there so that the `compile_error!` above is the only error without an API feature.
+    #[cfg(not(any(
+        feature = "api-v2",
+        feature = "api-v3",
+        feature = "api-v4",
+        feature = "api-v5"
+    )))]
+    pub const LATEST: ApiVersion = ApiVersion::V2;
+
+    pub const OLDEST: ApiVersion = ApiVersion::V2;
+
+    /// The version right before this one, if it is supported by this crate.
+    pub(crate) fn previous(self) -> Option<ApiVersion> {
+        match self {
+            ApiVersion::V2 => None,
+            ApiVersion::V3 => Some(ApiVersion::V2),
+            ApiVersion::V4 => Some(ApiVersion::V3),
+            ApiVersion::V5 => Some(ApiVersion::V4),
+        }
+    }
+}
+
+/// The table of callbacks a module registers with Zygisk.
+///
+/// `this` is passed back to each callback. Modules defined with this crate register a
+/// [RawModule](crate::RawModule) as `this`, which is opaque outside the crate.
 #[repr(C)]
-pub(crate) struct ModuleAbi {
+pub struct ModuleAbi {
     pub api_version: c_long,
     pub this: &'static mut Module,
-    pub pre_app_specialize: extern "C" fn(&mut Module, &mut AppSpecializeArgs),
-    pub post_app_specialize: extern "C" fn(&mut Module, &AppSpecializeArgs),
+    /// `args` has the layout of `api_version`: the struct of [ApiVersion::V3] and
+    /// [ApiVersion::V4] lacks `mount_sysprop_overrides`, and the one of [ApiVersion::V2] also
+    /// lacks `rlimits` and `fds_to_ignore`.
+    pub pre_app_specialize: extern "C" fn(&mut Module, *mut AppSpecializeArgs),
+    pub post_app_specialize: extern "C" fn(&mut Module, *const AppSpecializeArgs),
     pub pre_server_specialize: extern "C" fn(&mut Module, &mut ServerSpecializeArgs),
     pub post_server_specialize: extern "C" fn(&mut Module, &ServerSpecializeArgs),
 }
 
+/// The API table Zygisk passes to the module, in the layout of [ApiVersion::V4] and later.
+///
+/// Functions the host does not implement are `None`. Before [ApiVersion::V4], the table has the
+/// layout of [LegacyApiTable] instead.
 #[repr(C)]
-pub(crate) struct RawApiTable {
+pub struct RawApiTable {
     // These first 2 entries are permanent, shall never change across API versions
     pub this: *const (),
     pub register_module: Option<extern "C" fn(*const RawApiTable, *mut ModuleAbi) -> c_bool>,
@@ -43,6 +113,50 @@ pub(crate) struct RawApiTable {
     pub exempt_fd: Option<extern "C" fn(c_int) -> c_bool>,
 }
 
+/// The API table layout used by API versions prior to [ApiVersion::V4].
+///
+/// The first 2 entries are shared with [RawApiTable]. Entries available in both layouts use the
+/// same names, so that they can be accessed the same way regardless of the layout.
+#[repr(C)]
+pub struct LegacyApiTable {
+    pub this: *const (),
+    pub register_module: Option<extern "C" fn(*const RawApiTable, *mut ModuleAbi) -> c_bool>,
+
+    // Utility functions
+    pub hook_jni_native_methods:
+        Option<extern "C" fn(*mut JNIEnv, *const c_char, *mut JNINativeMethod, c_int)>,
+    pub plt_hook_register:
+        Option<extern "C" fn(*const c_char, *const c_char, *mut (), *mut *mut ())>,
+    pub plt_hook_exclude: Option<extern "C" fn(*const c_char, *const c_char)>,
+    pub plt_hook_commit: Option<extern "C" fn() -> c_bool>,
+
+    // Zygisk functions
+    pub connect_companion: Option<extern "C" fn(*const ()) -> c_int>,
+    pub set_option: Option<extern "C" fn(*const (), ZygiskOption)>,
+    pub get_module_dir: Option<extern "C" fn(*const ()) -> c_int>,
+    pub get_flags: Option<extern "C" fn(*const ()) -> u32>,
+}
+
+#[cfg(any(test, feature = "api-v4"))]
+impl RawApiTable {
+    /// A table with no functions available, standing in for the one Zygisk unloaded, or for
+    /// tests.
+    pub(crate) fn empty() -> RawApiTable {
+        RawApiTable {
+            this: std::ptr::null(),
+            register_module: None,
+            hook_jni_native_methods: None,
+            plt_hook_register: None,
+            plt_hook_commit: None,
+            connect_companion: None,
+            set_option: None,
+            get_module_dir: None,
+            get_flags: None,
+            exempt_fd: None,
+        }
+    }
+}
+
 #[repr(C)]
 pub struct AppSpecializeArgs<'a> {
     // Required arguments. These arguments are guaranteed to exist on all Android versions.
@@ -68,6 +182,172 @@ pub struct AppSpecializeArgs<'a> {
     pub mount_storage_dirs: Option<&'a jboolean>,
 }
 
+/// The app arguments passed with [ApiVersion::V3] and [ApiVersion::V4].
+#[repr(C)]
+struct AppSpecializeArgsV3 {
+    uid: *mut jint,
+    gid: *mut jint,
+    gids: *mut jintArray,
+    runtime_flags: *mut jint,
+    rlimits: *mut jobjectArray,
+    mount_external: *mut jint,
+    se_info: *mut jstring,
+    nice_name: *mut jstring,
+    instruction_set: *mut jstring,
+    app_data_dir: *mut jstring,
+
+    fds_to_ignore: *const jintArray,
+    is_child_zygote: *const jboolean,
+    is_top_app: *const jboolean,
+    pkg_data_info_list: *const jobjectArray,
+    whitelisted_data_info_list: *const jobjectArray,
+    mount_data_dirs: *const jboolean,
+    mount_storage_dirs: *const jboolean,
+}
+
+/// The app arguments passed with [ApiVersion::V2].
+#[repr(C)]
+struct AppSpecializeArgsV1 {
+    uid: *mut jint,
+    gid: *mut jint,
+    gids: *mut jintArray,
+    runtime_flags: *mut jint,
+    mount_external: *mut jint,
+    se_info: *mut jstring,
+    nice_name: *mut jstring,
+    instruction_set: *mut jstring,
+    app_data_dir: *mut jstring,
+
+    is_child_zygote: *const jboolean,
+    is_top_app: *const jboolean,
+    pkg_data_info_list: *const jobjectArray,
+    whitelisted_data_info_list: *const jobjectArray,
+    mount_data_dirs: *const jboolean,
+    mount_storage_dirs: *const jboolean,
+}
+
+/// Call `f` with the app arguments `args` points to, which have the layout of `version`.
+///
+/// Arguments missing from older layouts are `None`, except for `rlimits`, which is null.
+///
+/// ## Safety
+///
+/// `args` must point to valid arguments in the layout of `version`.
+pub(crate) unsafe fn with_app_args<R>(
+    version: ApiVersion,
+    args: *mut AppSpecializeArgs,
+    f: impl FnOnce(&mut AppSpecializeArgs) -> R,
+) -> R {
+    match version {
+        ApiVersion::V5 => f(&mut *args),
+        ApiVersion::V3 | ApiVersion::V4 => {
+            let args = &*args.cast::<AppSpecializeArgsV3>();
+            f(&mut AppSpecializeArgs {
+                uid: &mut *args.uid,
+                gid: &mut *args.gid,
+                gids: &mut *args.gids,
+                runtime_flags: &mut *args.runtime_flags,
+                rlimits: &mut *args.rlimits.cast(),
+                mount_external: &mut *args.mount_external,
+                se_info: &mut *args.se_info.cast(),
+                nice_name: &mut *args.nice_name.cast(),
+                instruction_set: &mut *args.instruction_set.cast(),
+                app_data_dir: &mut *args.app_data_dir.cast(),
+                fds_to_ignore: args.fds_to_ignore.as_ref(),
+                is_child_zygote: args.is_child_zygote.as_ref(),
+                is_top_app: args.is_top_app.as_ref(),
+                pkg_data_info_list: args.pkg_data_info_list.as_ref(),
+                whitelisted_data_info_list: args.whitelisted_data_info_list.as_ref(),
+                mount_data_dirs: args.mount_data_dirs.as_ref(),
+                mount_sysprop_overrides: None,
+                mount_storage_dirs: args.mount_storage_dirs.as_ref(),
+            })
+        }
+        ApiVersion::V2 => {
+            let args = &*args.cast::<AppSpecializeArgsV1>();
+            let mut rlimits = JObjectArray::from_raw(std::ptr::null_mut());
+            f(&mut AppSpecializeArgs {
+                uid: &mut *args.uid,
+                gid: &mut *args.gid,
+                gids: &mut *args.gids,
+                runtime_flags: &mut *args.runtime_flags,
+                rlimits: &mut rlimits,
+                mount_external: &mut *args.mount_external,
+                se_info: &mut *args.se_info.cast(),
+                nice_name: &mut *args.nice_name.cast(),
+                instruction_set: &mut *args.instruction_set.cast(),
+                app_data_dir: &mut *args.app_data_dir.cast(),
+                fds_to_ignore: None,
+                is_child_zygote: args.is_child_zygote.as_ref(),
+                is_top_app: args.is_top_app.as_ref(),
+                pkg_data_info_list: args.pkg_data_info_list.as_ref(),
+                whitelisted_data_info_list: args.whitelisted_data_info_list.as_ref(),
+                mount_data_dirs: args.mount_data_dirs.as_ref(),
+                mount_sysprop_overrides: None,
+                mount_storage_dirs: args.mount_storage_dirs.as_ref(),
+            })
+        }
+    }
+}
+
+/// Call `f` with `args` in the layout of `version`, as Zygisk passes them to a module registered
+/// with that version. The arguments missing from that layout are dropped.
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn with_raw_app_args<R>(
+    version: ApiVersion,
+    args: &mut AppSpecializeArgs,
+    f: impl FnOnce(*mut AppSpecializeArgs) -> R,
+) -> R {
+    fn opt<T>(arg: Option<&T>) -> *const T {
+        arg.map_or(std::ptr::null(), |arg| arg)
+    }
+    match version {
+        ApiVersion::V5 => f(args),
+        ApiVersion::V3 | ApiVersion::V4 => {
+            let mut raw = AppSpecializeArgsV3 {
+                uid: args.uid,
+                gid: args.gid,
+                gids: args.gids,
+                runtime_flags: args.runtime_flags,
+                rlimits: (args.rlimits as *mut JObjectArray).cast(),
+                mount_external: args.mount_external,
+                se_info: (args.se_info as *mut JString).cast(),
+                nice_name: (args.nice_name as *mut JString).cast(),
+                instruction_set: (args.instruction_set as *mut JString).cast(),
+                app_data_dir: (args.app_data_dir as *mut JString).cast(),
+                fds_to_ignore: opt(args.fds_to_ignore),
+                is_child_zygote: opt(args.is_child_zygote),
+                is_top_app: opt(args.is_top_app),
+                pkg_data_info_list: opt(args.pkg_data_info_list),
+                whitelisted_data_info_list: opt(args.whitelisted_data_info_list),
+                mount_data_dirs: opt(args.mount_data_dirs),
+                mount_storage_dirs: opt(args.mount_storage_dirs),
+            };
+            f(std::ptr::addr_of_mut!(raw).cast())
+        }
+        ApiVersion::V2 => {
+            let mut raw = AppSpecializeArgsV1 {
+                uid: args.uid,
+                gid: args.gid,
+                gids: args.gids,
+                runtime_flags: args.runtime_flags,
+                mount_external: args.mount_external,
+                se_info: (args.se_info as *mut JString).cast(),
+                nice_name: (args.nice_name as *mut JString).cast(),
+                instruction_set: (args.instruction_set as *mut JString).cast(),
+                app_data_dir: (args.app_data_dir as *mut JString).cast(),
+                is_child_zygote: opt(args.is_child_zygote),
+                is_top_app: opt(args.is_top_app),
+                pkg_data_info_list: opt(args.pkg_data_info_list),
+                whitelisted_data_info_list: opt(args.whitelisted_data_info_list),
+                mount_data_dirs: opt(args.mount_data_dirs),
+                mount_storage_dirs: opt(args.mount_storage_dirs),
+            };
+            f(std::ptr::addr_of_mut!(raw).cast())
+        }
+    }
+}
+
 #[repr(C)]
 pub struct ServerSpecializeArgs<'a> {
     pub uid: &'a mut jint,
@@ -78,9 +358,8 @@ pub struct ServerSpecializeArgs<'a> {
     pub effective_capabilities: &'a mut jlong,
 }
 
-/// Zygisk module options, used in [ZygiskApi::set_option()](crate::ZygiskApi::set_option).
-
 // Note: the original definition is `enum Option : int`. This is a best-effort approach.
+/// Zygisk module options, used in [ZygiskApi::set_option()](crate::ZygiskApi::set_option).
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ZygiskOption {
@@ -102,6 +381,7 @@ pub enum ZygiskOption {
 
 crate::bitflags::bitflags! {
     /// Bit masks of the return value of [ZygiskApi::get_flags()](crate::ZygiskApi::get_flags).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct StateFlags: u32 {
         /// The user has granted root access to the current process.
         const PROCESS_GRANTED_ROOT = (1 << 0);
@@ -110,3 +390,27 @@ crate::bitflags::bitflags! {
         const PROCESS_ON_DENYLIST = (1 << 1);
     }
 }
+
+crate::bitflags::bitflags! {
+    /// The API functions provided by the loading Zygisk implementation, as returned by
+    /// [ZygiskApi::capabilities()](crate::ZygiskApi::capabilities).
+    ///
+    /// Each flag is named after the [ZygiskApi](crate::ZygiskApi) method that fails with
+    /// [ZygiskError::ApiFunctionUnavailable](crate::ZygiskError::ApiFunctionUnavailable) without
+    /// it.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ApiCapabilities: u32 {
+        const HOOK_JNI_NATIVE_METHODS = (1 << 0);
+        /// PLT hooks by device and inode, from [ApiVersion::V4].
+        const PLT_HOOK_REGISTER = (1 << 1);
+        /// PLT hooks by path regular expression, before [ApiVersion::V4].
+        const PLT_HOOK_REGISTER_REGEX = (1 << 2);
+        const PLT_HOOK_EXCLUDE = (1 << 3);
+        const PLT_HOOK_COMMIT = (1 << 4);
+        const CONNECT_COMPANION = (1 << 5);
+        const SET_OPTION = (1 << 6);
+        const GET_MODULE_DIR = (1 << 7);
+        const GET_FLAGS = (1 << 8);
+        const EXEMPT_FD = (1 << 9);
+    }
+}
@@ -0,0 +1,438 @@
+//! Helpers for talking over companion sockets.
+//!
+//! ## Built-in services
+//!
+//! Besides handing connections over to your handler, the companion glue of `zygisk_companion!`
+//! serves a few requests of this crate by itself: [open_as_root()], [exec()], [ring_log()],
+//! [daemon_version()], [ConfigCache] and `logging::forward_to_companion()`. Both the module and
+//! the companion have to be registered with the macros of this crate, and like any connection to
+//! the companion, they only work in the `pre[XXX]Specialize` functions. All of them but
+//! [daemon_version()] act as root on behalf of the module, so they are only served once enabled
+//! (see [BuiltinServices]).
+
+use std::{
+    io::{self, BufWriter, Read, Write},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+};
+
+use crate::{libc, logcat};
+
+mod config;
+mod connect;
+#[doc(hidden)]
+pub mod handshake;
+mod logs;
+mod peer;
+mod ring;
+mod router;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+mod services;
+mod shared;
+mod state;
+mod version;
+mod watchdog;
+
+pub use config::{ConfigBlob, ConfigCache};
+pub(crate) use connect::classify;
+pub use connect::ConnectOptions;
+pub use peer::{AuthenticatedStream, PeerCredentials};
+pub use ring::{ring_log, RingBuffer};
+pub use router::Router;
+#[doc(hidden)]
+pub use services::service_names;
+pub(crate) use services::{authorize, serve_builtin, watch};
+pub use services::{exec, open_as_root, BuiltinServices};
+pub use shared::SharedBuffer;
+pub use state::CompanionState;
+pub use version::{daemon_version, DaemonVersion};
+pub use watchdog::{TimeoutAction, Watchdog, WatchdogGuard};
+
+/// A root companion with daemon lifecycle hooks, registered with
+/// `zygisk_companion!(&HANDLER)`.
+///
+/// Unlike a plain handler function, this gives the companion a sanctioned place for one-time
+/// initialization (opening databases, filling caches, ...) that runs before the first request is
+/// handled.
+///
+/// Note: [Self::on_connect()] may be run concurrently on multiple threads.
+///
+/// ## Example
+///
+/// ```
+/// use std::{os::unix::net::UnixStream, sync::OnceLock};
+/// use zygisk::{zygisk_companion, CompanionHandler};
+///
+/// struct Companion {
+///     config: OnceLock<String>,
+/// }
+///
+/// impl CompanionHandler for Companion {
+///     fn on_daemon_load(&self) {
+///         let _ = self.config.set(String::from("loaded once"));
+///     }
+///
+///     fn on_connect(&self, _stream: UnixStream) {
+///         // ...
+///     }
+/// }
+///
+/// static COMPANION: Companion = Companion { config: OnceLock::new() };
+/// zygisk_companion!(&COMPANION);
+/// ```
+pub trait CompanionHandler: Sync {
+    /// Called once in the companion process, before the first request is handled.
+    ///
+    /// zygiskd does not notify companions when they are loaded, so this runs lazily on the first
+    /// connection; other connections wait until it returns.
+    fn on_daemon_load(&self) {}
+
+    /// Handle a request from the module running in a target process.
+    fn on_connect(&self, stream: UnixStream);
+
+    /// Whether to serve a connection from the process with `credentials`. Rejected connections
+    /// are logged and dropped before [Self::on_connect()]. Defaults to accepting every peer.
+    ///
+    /// This also applies to the enabled [BuiltinServices].
+    fn authorize(&self, credentials: &PeerCredentials) -> bool {
+        let _ = credentials;
+        true
+    }
+
+    /// A deadline applied to every call of [Self::on_connect()], and to the requests of the
+    /// enabled [BuiltinServices]. Defaults to none.
+    fn watchdog(&self) -> Option<Watchdog> {
+        None
+    }
+
+    /// Called when the companion process exits normally (through `atexit`).
+    ///
+    /// This is best-effort: it is not called if the daemon is killed.
+    fn on_daemon_exit(&self) {}
+}
+
+/// A buffered writer for companion sockets that never loses data silently.
+///
+/// [BufWriter] flushes on drop but swallows any error, so a reply written right before a
+/// companion handler returns may never reach the peer without anyone noticing. This writer
+/// also flushes on drop, but reports failures to logcat.
+///
+/// Prefer calling [Self::finish()] once the response is complete, so that flush errors can be
+/// handled instead of merely logged.
+pub struct CompanionWriter<W: Write = UnixStream> {
+    inner: BufWriter<W>,
+    finished: bool,
+}
+
+impl<W: Write> CompanionWriter<W> {
+    /// Wrap `inner` with the default buffer capacity of 8 KiB.
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(8 * 1024, inner)
+    }
+
+    /// Wrap `inner` with a buffer of at least `capacity` bytes.
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        CompanionWriter {
+            inner: BufWriter::with_capacity(capacity, inner),
+            finished: false,
+        }
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Flush all buffered data, returning any error that occurred.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finished = true;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for CompanionWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for CompanionWriter<W> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Err(e) = self.inner.flush() {
+            logcat::write(
+                logcat::Priority::Error,
+                &format!("CompanionWriter: failed to flush on drop: {e}"),
+            );
+        }
+    }
+}
+
+macro_rules! int_methods {
+    ($($ty: ident: $send: ident, $recv: ident;)*) => {
+        $(
+            #[doc = concat!("Send a `", stringify!($ty), "` in native byte order.")]
+            fn $send(&mut self, value: $ty) -> io::Result<()> {
+                self.write_all(&value.to_ne_bytes())
+            }
+
+            #[doc = concat!("Receive a `", stringify!($ty), "` in native byte order.")]
+            fn $recv(&mut self) -> io::Result<$ty> {
+                let mut buf = [0u8; std::mem::size_of::<$ty>()];
+                self.read_exact(&mut buf)?;
+                Ok($ty::from_ne_bytes(buf))
+            }
+        )*
+    };
+}
+
+/// Message framing helpers for companion sockets, usable on both the module side
+/// ([ZygiskApi::connect_companion()](crate::ZygiskApi::connect_companion)) and the companion side
+/// ([zygisk_companion!](crate::zygisk_companion)).
+///
+/// Integers are sent in native byte order, and variable-length messages are prefixed with their
+/// length as a `u32`. This matches what Magisk's own C++ helpers (`read_int`, `write_string`,
+/// ...) do, since both ends of a companion socket always run on the same device with the same ABI.
+///
+/// File descriptors are passed as `SCM_RIGHTS` ancillary data along with their count, which is
+/// compatible with Magisk's `send_fds`/`recv_fds`.
+pub trait SocketExt: Read + Write + AsFd {
+    int_methods! {
+        u8: send_u8, recv_u8;
+        u32: send_u32, recv_u32;
+        i32: send_i32, recv_i32;
+        u64: send_u64, recv_u64;
+        i64: send_i64, recv_i64;
+    }
+
+    /// Send a length-prefixed byte buffer.
+    fn send_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long"))?;
+        self.send_u32(len)?;
+        self.write_all(bytes)
+    }
+
+    /// Receive a length-prefixed byte buffer.
+    ///
+    /// The buffer is allocated with the length announced by the peer, up to 4 GiB. Use
+    /// [Self::recv_bytes_max()] when the peer is not trusted.
+    fn recv_bytes(&mut self) -> io::Result<Vec<u8>> {
+        self.recv_bytes_max(u32::MAX as usize)
+    }
+
+    /// Receive a length-prefixed byte buffer of at most `max` bytes.
+    ///
+    /// Longer buffers fail with [io::ErrorKind::InvalidData] before anything is allocated; the
+    /// stream is then out of sync and should be dropped.
+    fn recv_bytes_max(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        let len = self.recv_u32()? as usize;
+        if len > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message of {len} bytes exceeds the limit of {max} bytes"),
+            ));
+        }
+        let mut buf = vec![0u8; len];
+        self.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Send a length-prefixed UTF-8 string.
+    fn send_str(&mut self, s: &str) -> io::Result<()> {
+        self.send_bytes(s.as_bytes())
+    }
+
+    /// Receive a length-prefixed UTF-8 string.
+    fn recv_str(&mut self) -> io::Result<String> {
+        self.recv_str_max(u32::MAX as usize)
+    }
+
+    /// Receive a length-prefixed UTF-8 string of at most `max` bytes, like
+    /// [Self::recv_bytes_max()].
+    fn recv_str_max(&mut self, max: usize) -> io::Result<String> {
+        String::from_utf8(self.recv_bytes_max(max)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Send a file descriptor to the peer. The descriptor stays open on this side.
+    fn send_fd(&mut self, fd: BorrowedFd<'_>) -> io::Result<()> {
+        self.send_fds(&[fd])
+    }
+
+    /// Receive a single file descriptor sent with [Self::send_fd()].
+    fn recv_fd(&mut self) -> io::Result<OwnedFd> {
+        let mut fds = self.recv_fds(1)?;
+        match fds.pop() {
+            Some(fd) if fds.is_empty() => Ok(fd),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected exactly one file descriptor",
+            )),
+        }
+    }
+
+    /// Send multiple file descriptors to the peer at once. The descriptors stay open on this side.
+    fn send_fds(&mut self, fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        let raw_fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+        send_fds(self.as_fd(), &raw_fds)
+    }
+
+    /// Receive file descriptors sent with [Self::send_fds()], accepting at most `max` of them.
+    fn recv_fds(&mut self, max: usize) -> io::Result<Vec<OwnedFd>> {
+        recv_fds(self.as_fd(), max)
+    }
+}
+
+impl SocketExt for UnixStream {}
+
+fn send_fds(socket: BorrowedFd<'_>, fds: &[RawFd]) -> io::Result<()> {
+    let mut count = i32::try_from(fds.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many fds"))?;
+    let mut iov = libc::iovec {
+        iov_base: (&mut count as *mut i32).cast(),
+        iov_len: std::mem::size_of::<i32>(),
+    };
+
+    let data_len = std::mem::size_of_val(fds) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(data_len) } as usize];
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control.len() as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+            std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast(), fds.len());
+        }
+    }
+
+    if unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn recv_fds(socket: BorrowedFd<'_>, max: usize) -> io::Result<Vec<OwnedFd>> {
+    let mut count = 0i32;
+    let mut iov = libc::iovec {
+        iov_base: (&mut count as *mut i32).cast(),
+        iov_len: std::mem::size_of::<i32>(),
+    };
+
+    let data_len = (max * std::mem::size_of::<RawFd>()) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(data_len) } as usize];
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+
+    let received = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Take ownership of whatever arrived first, so that nothing leaks on error.
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                for i in 0..len / std::mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if received as usize != std::mem::size_of::<i32>() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 || count as usize != fds.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected number of file descriptors received",
+        ));
+    }
+    Ok(fds)
+}
+
+#[test]
+fn test_socket_ext_round_trip() {
+    let (mut a, mut b) = UnixStream::pair().unwrap();
+    a.send_u8(7).unwrap();
+    a.send_i64(-42).unwrap();
+    a.send_bytes(&[1, 2, 3]).unwrap();
+    a.send_str("hello").unwrap();
+    a.send_str("").unwrap();
+
+    assert_eq!(b.recv_u8().unwrap(), 7);
+    assert_eq!(b.recv_i64().unwrap(), -42);
+    assert_eq!(b.recv_bytes().unwrap(), [1, 2, 3]);
+    assert_eq!(b.recv_str().unwrap(), "hello");
+    assert_eq!(b.recv_str().unwrap(), "");
+
+    a.send_bytes(&[1, 2, 3]).unwrap();
+    assert_eq!(b.recv_bytes_max(3).unwrap(), [1, 2, 3]);
+    a.send_u32(u32::MAX).unwrap();
+    let error = b.recv_bytes_max(1 << 20).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_fd_passing() {
+    use std::{fs::File, io::Seek};
+
+    let (mut a, mut b) = UnixStream::pair().unwrap();
+    let (pipe_read, mut pipe_write) = UnixStream::pair().unwrap();
+
+    a.send_fd(pipe_read.as_fd()).unwrap();
+    let received = UnixStream::from(b.recv_fd().unwrap());
+    pipe_write.write_all(b"x").unwrap();
+    let mut buf = [0u8];
+    (&received).read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"x");
+
+    let file = File::open("/proc/self/exe").unwrap();
+    a.send_fds(&[file.as_fd(), file.as_fd()]).unwrap();
+    let fds = b.recv_fds(2).unwrap();
+    assert_eq!(fds.len(), 2);
+    assert!(File::from(fds.into_iter().next().unwrap())
+        .stream_position()
+        .is_ok());
+
+    a.send_fds(&[]).unwrap();
+    assert!(b.recv_fds(1).unwrap().is_empty());
+}
+
+#[test]
+fn test_writer_finish() {
+    let (local, mut peer) = UnixStream::pair().unwrap();
+    let mut writer = CompanionWriter::new(local);
+    writer.write_all(b"hello companion").unwrap();
+    writer.finish().unwrap();
+
+    let mut buf = [0u8; 15];
+    peer.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello companion");
+}
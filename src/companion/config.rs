@@ -0,0 +1,203 @@
+//! Fetching the configuration of the module through the companion, which reads it as root and
+//! caches it until the file changes.
+//!
+//! The module sends the path of the file in the module directory and the version it already has
+//! (`0` for none). The companion answers with [STATUS_UNCHANGED], with [STATUS_CHANGED] followed
+//! by the new version and contents, or with [STATUS_FAILED] and an errno.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Read},
+    os::unix::{fs::MetadataExt, net::UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use super::{handshake::Service, SocketExt};
+use crate::{libc, ModuleDir, ZygiskApi, ZygiskError};
+
+const STATUS_UNCHANGED: u8 = 0;
+const STATUS_CHANGED: u8 = 1;
+const STATUS_FAILED: u8 = 2;
+
+/// The contents of a configuration file, as fetched by [ConfigCache::fetch()].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigBlob {
+    /// Changes whenever the file is modified. Never `0`.
+    pub version: u64,
+    pub data: Arc<[u8]>,
+}
+
+/// A configuration file in the module directory, fetched through the companion and cached in the
+/// module.
+///
+/// The module directory is only accessible before specialization, and reading it from every
+/// process is wasteful. Instead, [Self::fetch()] asks the companion for the file: the companion
+/// reads it as root without following symlinks, and keeps it in memory until the file changes.
+///
+/// The cache of the module lives in the memory of the process, so every new process gets the full
+/// file on its first fetch. Only fetching again in the same process is cheap: the companion then
+/// just confirms that the cached version is still current.
+///
+/// This is one of the [built-in services](super#built-in-services), enabled with
+/// `zygisk_companion!(handler, services = [config], module_dir = "...")`, so the file has to be
+/// fetched in the `pre[XXX]Specialize` functions.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{companion::ConfigCache, ZygiskApi};
+///
+/// static CONFIG: ConfigCache = ConfigCache::new("config.toml");
+///
+/// fn config(api: &ZygiskApi) -> String {
+///     match CONFIG.fetch(api) {
+///         Ok(blob) => String::from_utf8_lossy(&blob.data).into_owned(),
+///         Err(_) => String::new(),
+///     }
+/// }
+/// ```
+pub struct ConfigCache {
+    path: &'static str,
+    cached: Mutex<Option<ConfigBlob>>,
+}
+
+impl ConfigCache {
+    /// A cache of the file at `path`, relative to the module directory.
+    pub const fn new(path: &'static str) -> Self {
+        ConfigCache {
+            path,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Get the current contents of the file, from the cache if it did not change.
+    pub fn fetch(&self, api: &ZygiskApi) -> Result<ConfigBlob, ZygiskError> {
+        let mut stream = api.connect_companion_service(Service::Config)?;
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        let known = cached.as_ref().map_or(0, |blob| blob.version);
+        let reply = send_request(&mut stream, self.path, known)
+            .and_then(|_| recv_reply(&mut stream))
+            .map_err(ZygiskError::CompanionConnectionFailed)?;
+        match reply {
+            Ok(Some(blob)) => Ok(cached.insert(blob).clone()),
+            Ok(None) => cached.clone().ok_or_else(|| {
+                ZygiskError::CompanionRequestFailed(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "companion reported an unknown config version as unchanged",
+                ))
+            }),
+            Err(e) => Err(ZygiskError::CompanionRequestFailed(e)),
+        }
+    }
+
+    /// The contents fetched last, without asking the companion.
+    pub fn cached(&self) -> Option<ConfigBlob> {
+        self.cached
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+fn send_request(stream: &mut UnixStream, path: &str, known: u64) -> io::Result<()> {
+    stream.send_str(path)?;
+    stream.send_u64(known)
+}
+
+/// The new contents, `None` if unchanged, or the error of the companion.
+fn recv_reply(stream: &mut UnixStream) -> io::Result<io::Result<Option<ConfigBlob>>> {
+    match stream.recv_u8()? {
+        STATUS_UNCHANGED => Ok(Ok(None)),
+        STATUS_CHANGED => {
+            let version = stream.recv_u64()?;
+            let data = stream.recv_bytes()?.into();
+            Ok(Ok(Some(ConfigBlob { version, data })))
+        }
+        STATUS_FAILED => Ok(Err(io::Error::from_raw_os_error(stream.recv_i32()?))),
+        status => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown config status {status}"),
+        )),
+    }
+}
+
+/// The files read by the companion, by path in the module directory.
+static FILES: Mutex<Option<HashMap<PathBuf, ConfigBlob>>> = Mutex::new(None);
+
+pub(crate) fn serve_config(stream: &mut UnixStream, module_dir: &ModuleDir) -> io::Result<()> {
+    let path = stream.recv_str_max(libc::PATH_MAX as usize)?;
+    let known = stream.recv_u64()?;
+
+    match read(module_dir, Path::new(&path)) {
+        Ok(blob) if blob.version == known => stream.send_u8(STATUS_UNCHANGED),
+        Ok(blob) => {
+            stream.send_u8(STATUS_CHANGED)?;
+            stream.send_u64(blob.version)?;
+            stream.send_bytes(&blob.data)
+        }
+        Err(e) => {
+            stream.send_u8(STATUS_FAILED)?;
+            stream.send_i32(e.raw_os_error().unwrap_or(libc::EIO))
+        }
+    }
+}
+
+/// Read a file, or get it from the cache if its metadata did not change.
+fn read(module_dir: &ModuleDir, path: &Path) -> io::Result<ConfigBlob> {
+    let mut file = File::from(module_dir.open_nofollow(path, libc::O_RDONLY)?);
+    let metadata = file.metadata()?;
+    let mut hasher = DefaultHasher::new();
+    (metadata.dev(), metadata.ino(), metadata.size()).hash(&mut hasher);
+    (metadata.mtime(), metadata.mtime_nsec()).hash(&mut hasher);
+    let version = hasher.finish().max(1);
+
+    let mut files = FILES.lock().unwrap_or_else(|e| e.into_inner());
+    let files = files.get_or_insert_with(HashMap::new);
+    if let Some(blob) = files.get(path).filter(|blob| blob.version == version) {
+        return Ok(blob.clone());
+    }
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let blob = ConfigBlob {
+        version,
+        data: data.into(),
+    };
+    files.insert(path.to_owned(), blob.clone());
+    Ok(blob)
+}
+
+#[test]
+fn test_serve_config() {
+    use std::fs;
+
+    let root = crate::module_dir::TempDir::new("config-service");
+    fs::write(root.join("config.toml"), "a = 1\n").unwrap();
+    std::os::unix::fs::symlink("/etc/passwd", root.join("passwd")).unwrap();
+    let dir = ModuleDir::open_path(&*root).unwrap();
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    let mut request = |path: &str, known: u64| {
+        send_request(&mut module, path, known).unwrap();
+        serve_config(&mut companion, &dir).unwrap();
+        recv_reply(&mut module).unwrap()
+    };
+
+    let blob = request("config.toml", 0).unwrap().unwrap();
+    assert_eq!(&*blob.data, b"a = 1\n");
+    assert_eq!(request("config.toml", blob.version).unwrap(), None);
+
+    fs::write(root.join("config.toml"), "a = 22\n").unwrap();
+    let changed = request("config.toml", blob.version).unwrap().unwrap();
+    assert_ne!(changed.version, blob.version);
+    assert_eq!(&*changed.data, b"a = 22\n");
+
+    let missing = request("missing.toml", 0).unwrap_err();
+    assert_eq!(missing.raw_os_error(), Some(libc::ENOENT));
+    let escape = request("../config.toml", 0).unwrap_err();
+    assert_eq!(escape.raw_os_error(), Some(libc::EINVAL));
+    let symlink = request("passwd", 0).unwrap_err();
+    assert_eq!(symlink.raw_os_error(), Some(libc::ELOOP));
+}
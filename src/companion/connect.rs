@@ -0,0 +1,94 @@
+use std::{io, time::Duration};
+
+use crate::{libc, ZygiskError};
+
+/// Options for [ZygiskApi::connect_companion_with()](crate::ZygiskApi::connect_companion_with).
+///
+/// The defaults retry 3 times, starting with a 50ms backoff that doubles on every attempt, with
+/// no overall deadline.
+#[derive(Clone, Debug)]
+pub struct ConnectOptions {
+    pub(crate) retries: u32,
+    pub(crate) backoff: Duration,
+    pub(crate) deadline: Option<Duration>,
+}
+
+impl ConnectOptions {
+    pub fn new() -> Self {
+        ConnectOptions {
+            retries: 3,
+            backoff: Duration::from_millis(50),
+            deadline: None,
+        }
+    }
+
+    /// How many times to retry after the first attempt failed because the daemon was busy.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// The delay before the first retry. It is doubled after every failed retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// The total time to spend connecting, including the protocol handshake. No further retries
+    /// are made once it has passed.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turn a generic connection failure into one of the more specific variants of [ZygiskError].
+///
+/// Zygisk does not report why it failed to connect, so this relies on the `errno` it leaves
+/// behind and on the timeouts of the handshake. Anything else, including a companion that
+/// closes the connection, stays a [ZygiskError::CompanionConnectionFailed].
+pub(crate) fn classify(error: ZygiskError) -> ZygiskError {
+    let ZygiskError::CompanionConnectionFailed(e) = error else {
+        return error;
+    };
+    match (e.kind(), e.raw_os_error()) {
+        (_, Some(libc::EACCES | libc::EPERM)) => ZygiskError::CompanionDenied(e),
+        (io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted, _)
+        | (_, Some(libc::EAGAIN | libc::ECONNREFUSED | libc::ECONNRESET | libc::EMFILE)) => {
+            ZygiskError::CompanionBusy(e)
+        }
+        _ => ZygiskError::CompanionConnectionFailed(e),
+    }
+}
+
+#[test]
+fn test_classify() {
+    let failed = |e: io::Error| classify(ZygiskError::CompanionConnectionFailed(e));
+
+    assert!(matches!(
+        failed(io::ErrorKind::UnexpectedEof.into()),
+        ZygiskError::CompanionConnectionFailed(_)
+    ));
+    assert!(matches!(
+        failed(io::Error::from_raw_os_error(libc::ENOENT)),
+        ZygiskError::CompanionConnectionFailed(_)
+    ));
+    assert!(matches!(
+        failed(io::Error::from_raw_os_error(libc::EACCES)),
+        ZygiskError::CompanionDenied(_)
+    ));
+    assert!(matches!(
+        failed(io::Error::from_raw_os_error(libc::ECONNREFUSED)),
+        ZygiskError::CompanionBusy(_)
+    ));
+    assert!(matches!(
+        failed(io::Error::from_raw_os_error(libc::EINVAL)),
+        ZygiskError::CompanionConnectionFailed(_)
+    ));
+}
@@ -0,0 +1,199 @@
+use std::{io, os::unix::net::UnixStream, sync::OnceLock, time::Duration};
+
+use super::SocketExt;
+use crate::ZygiskError;
+
+/// The protocol version of the module side, recorded by `zygisk_module!`.
+static MODULE_PROTOCOL: OnceLock<u32> = OnceLock::new();
+
+/// Derive a protocol version from an identifier, usually `"<crate name>@<crate version>"`.
+///
+/// This is a 32-bit FNV-1a hash so that it can be evaluated at compile time.
+pub const fn protocol_version(id: &str) -> u32 {
+    let bytes = id.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    // Reserved for modules without a version.
+    if hash == UNVERSIONED {
+        hash = 1;
+    }
+    hash
+}
+
+pub(crate) fn set_module_protocol(version: u32) {
+    let _ = MODULE_PROTOCOL.set(version);
+}
+
+/// The version sent by modules that are not registered with `zygisk_module!`, which is accepted
+/// by every companion.
+pub(crate) const UNVERSIONED: u32 = 0;
+
+pub(crate) fn module_protocol() -> u32 {
+    MODULE_PROTOCOL.get().copied().unwrap_or(UNVERSIONED)
+}
+
+/// What the module wants from the companion, sent along with the protocol version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Service {
+    /// Hand the connection over to the handler registered with `zygisk_companion!`.
+    Handler = 0,
+    /// Handled by the crate, see [open_as_root()](super::open_as_root).
+    OpenAsRoot = 1,
+    /// Handled by the crate, see [exec()](super::exec).
+    Exec = 2,
+    /// Handled by the crate, see `logging::forward_to_companion()`.
+    Logs = 3,
+    /// Handled by the crate, see [ring_log()](super::ring_log).
+    RingLog = 4,
+    /// Handled by the crate, see [ConfigCache](super::ConfigCache).
+    Config = 5,
+    /// Handled by the crate, see [daemon_version()](super::daemon_version).
+    Version = 6,
+}
+
+impl Service {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Service::Handler),
+            1 => Some(Service::OpenAsRoot),
+            2 => Some(Service::Exec),
+            3 => Some(Service::Logs),
+            4 => Some(Service::RingLog),
+            5 => Some(Service::Config),
+            6 => Some(Service::Version),
+            _ => None,
+        }
+    }
+}
+
+/// How long the module waits for the companion to answer the handshake. Companions built before
+/// the handshake existed never answer, and the module runs in zygote while it waits.
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Module side of the handshake: send our version first, then check the companion's one.
+///
+/// A companion that does not answer within [HANDSHAKE_TIMEOUT] is reported as a
+/// [ZygiskError::CompanionProtocolMismatch] with [UNVERSIONED] as its version, unless the
+/// `deadline` of the caller passed first, which is reported as a timeout.
+pub(crate) fn client(
+    stream: &mut UnixStream,
+    version: u32,
+    service: Service,
+    deadline: Option<Duration>,
+) -> Result<(), ZygiskError> {
+    let timeout = deadline.map_or(HANDSHAKE_TIMEOUT, |deadline| {
+        deadline.min(HANDSHAKE_TIMEOUT)
+    });
+    let companion = stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.send_u32(version))
+        .and_then(|_| stream.send_u8(service as u8))
+        .and_then(|_| stream.recv_u32());
+    let timed_out = |e: &io::Error| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    };
+    let companion = match companion {
+        Ok(companion) => companion,
+        // A deadline of the caller that passed first is left for it to report.
+        Err(e) if timed_out(&e) && timeout == HANDSHAKE_TIMEOUT => {
+            return Err(ZygiskError::CompanionProtocolMismatch {
+                module: version,
+                companion: UNVERSIONED,
+            })
+        }
+        Err(e) => return Err(ZygiskError::CompanionConnectionFailed(e)),
+    };
+    stream
+        .set_read_timeout(None)
+        .map_err(ZygiskError::CompanionConnectionFailed)?;
+    check(version, companion)
+}
+
+/// Companion side of the handshake: answer with our version whether it matches or not, so that
+/// the module can report the mismatch too.
+pub(crate) fn server(stream: &mut UnixStream, version: u32) -> Result<Service, ZygiskError> {
+    let (module, service) = stream
+        .recv_u32()
+        .and_then(|module| Ok((module, stream.recv_u8()?)))
+        .and_then(|request| stream.send_u32(version).map(|_| request))
+        .map_err(ZygiskError::CompanionConnectionFailed)?;
+    check(module, version)?;
+    Service::from_u8(service).ok_or_else(|| {
+        ZygiskError::CompanionConnectionFailed(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown companion service {service}"),
+        ))
+    })
+}
+
+fn check(module: u32, companion: u32) -> Result<(), ZygiskError> {
+    if module == companion || module == UNVERSIONED {
+        Ok(())
+    } else {
+        Err(ZygiskError::CompanionProtocolMismatch { module, companion })
+    }
+}
+
+#[test]
+fn test_handshake() {
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    let thread = std::thread::spawn(move || server(&mut companion, 2));
+    client(&mut module, 2, Service::OpenAsRoot, None).unwrap();
+    assert_eq!(thread.join().unwrap().unwrap(), Service::OpenAsRoot);
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    let thread = std::thread::spawn(move || server(&mut companion, 2));
+    assert!(matches!(
+        client(&mut module, 1, Service::Handler, None),
+        Err(ZygiskError::CompanionProtocolMismatch {
+            module: 1,
+            companion: 2
+        })
+    ));
+    assert!(thread.join().unwrap().is_err());
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    let thread = std::thread::spawn(move || server(&mut companion, 2));
+    client(&mut module, UNVERSIONED, Service::Handler, None).unwrap();
+    assert_eq!(thread.join().unwrap().unwrap(), Service::Handler);
+
+    assert_ne!(protocol_version("foo@0.1.0"), protocol_version("foo@0.1.1"));
+}
+
+#[test]
+fn test_handshake_unanswered() {
+    use std::time::Instant;
+
+    // A companion from before the handshake, waiting for a request of its own.
+    let (mut module, _companion) = UnixStream::pair().unwrap();
+    let start = Instant::now();
+    assert!(matches!(
+        client(&mut module, 2, Service::Handler, None),
+        Err(ZygiskError::CompanionProtocolMismatch {
+            module: 2,
+            companion: UNVERSIONED
+        })
+    ));
+    assert!(start.elapsed() < HANDSHAKE_TIMEOUT * 2);
+
+    // The deadline of the caller passed first.
+    let deadline = Some(Duration::from_millis(50));
+    match client(&mut module, 2, Service::Handler, deadline) {
+        Err(ZygiskError::CompanionConnectionFailed(e)) => {
+            assert!(matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ))
+        }
+        other => panic!("unexpected handshake result {other:?}"),
+    }
+}
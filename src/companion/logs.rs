@@ -0,0 +1,146 @@
+//! Companion side of the log forwarding set up by
+//! [logging::forward_to_companion()](crate::logging::forward_to_companion).
+//!
+//! The module sends its log tag, then batches of formatted lines until the process exits. Batches
+//! are appended to `logs/<tag>.log` in the module directory known to the companion, which is
+//! rotated to `logs/<tag>.log.1` once it grows past [MAX_LOG_SIZE]. Nothing in `logs/` is
+//! followed if it is a symlink.
+
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{self, Write},
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    path::Path,
+};
+
+use super::SocketExt;
+use crate::{libc, ModuleDir};
+
+/// The size after which a log file is rotated.
+pub(super) const MAX_LOG_SIZE: u64 = 1 << 20;
+/// The longest log tag accepted from the module.
+const MAX_TAG_LEN: usize = 255;
+
+pub(crate) fn serve_logs(stream: &mut UnixStream, module_dir: &ModuleDir) -> io::Result<()> {
+    serve_logs_with_limit(stream, module_dir, MAX_LOG_SIZE)
+}
+
+fn serve_logs_with_limit(
+    stream: &mut UnixStream,
+    module_dir: &ModuleDir,
+    max_size: u64,
+) -> io::Result<()> {
+    let (logs, tag) = recv_log_dir(stream, module_dir)?;
+    let name = format!("{tag}.log");
+    loop {
+        let batch = match stream.recv_bytes_max(MAX_LOG_SIZE as usize) {
+            Ok(batch) => batch,
+            // The process exited or closed the connection.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        append(&logs, &name, &batch, max_size)?;
+    }
+}
+
+/// Receive the log tag, and create the log directory in the module directory.
+///
+/// Returns the log directory and the sanitized tag.
+pub(super) fn recv_log_dir(
+    stream: &mut UnixStream,
+    module_dir: &ModuleDir,
+) -> io::Result<(ModuleDir, String)> {
+    // Tags become file names, so keep them from escaping the log directory.
+    let tag: String = stream
+        .recv_str_max(MAX_TAG_LEN)?
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let name = CString::new("logs")?;
+    if unsafe { libc::mkdirat(module_dir.as_raw_fd(), name.as_ptr(), 0o700) } < 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::AlreadyExists {
+            return Err(error);
+        }
+    }
+    let flags = libc::O_PATH | libc::O_DIRECTORY;
+    let logs = module_dir.open_nofollow(Path::new("logs"), flags)?;
+    Ok((ModuleDir::from(logs), tag))
+}
+
+/// Append `bytes` to `logs/<name>`, rotating it to `logs/<name>.1` once it grows past `max_size`.
+pub(super) fn append(logs: &ModuleDir, name: &str, bytes: &[u8], max_size: u64) -> io::Result<()> {
+    // Other processes of the module append to the same file, so reopen it every time in case
+    // they rotated it.
+    let flags = libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT;
+    let mut file = File::from(logs.open_nofollow(Path::new(name), flags)?);
+    file.write_all(bytes)?;
+    if file.metadata()?.len() > max_size {
+        // `renameat` replaces a symlink at the target instead of following it.
+        let from = CString::new(name)?;
+        let to = CString::new(format!("{name}.1"))?;
+        let dir = logs.as_raw_fd();
+        if unsafe { libc::renameat(dir, from.as_ptr(), dir, to.as_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_serve_logs() {
+    use std::fs;
+
+    let root = crate::module_dir::TempDir::new("logs");
+    let dir = ModuleDir::open_path(&*root).unwrap();
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    let thread = std::thread::spawn(move || serve_logs_with_limit(&mut companion, &dir, 16));
+    module.send_str("my/module").unwrap();
+    module.send_bytes(b"first line\n").unwrap();
+    module.send_bytes(b"second line, past the limit\n").unwrap();
+    module.send_bytes(b"third\n").unwrap();
+    drop(module);
+    thread.join().unwrap().unwrap();
+
+    let logs = root.join("logs");
+    assert_eq!(
+        fs::read_to_string(logs.join("my_module.log.1")).unwrap(),
+        "first line\nsecond line, past the limit\n"
+    );
+    assert_eq!(
+        fs::read_to_string(logs.join("my_module.log")).unwrap(),
+        "third\n"
+    );
+}
+
+#[test]
+fn test_logs_nofollow() {
+    let root = crate::module_dir::TempDir::new("logs-nofollow");
+    let target = crate::module_dir::TempDir::new("logs-nofollow-target");
+    std::os::unix::fs::symlink(&*target, root.join("logs")).unwrap();
+    let dir = ModuleDir::open_path(&*root).unwrap();
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    module.send_str("tag").unwrap();
+    let error = serve_logs(&mut companion, &dir).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::ENOTDIR));
+
+    std::fs::remove_file(root.join("logs")).unwrap();
+    std::fs::create_dir(root.join("logs")).unwrap();
+    std::os::unix::fs::symlink(target.join("victim"), root.join("logs/tag.log")).unwrap();
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    module.send_str("tag").unwrap();
+    module.send_bytes(b"line\n").unwrap();
+    let error = serve_logs(&mut companion, &dir).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::ELOOP));
+    assert!(!target.join("victim").exists());
+}
@@ -0,0 +1,133 @@
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    os::{fd::AsRawFd, unix::net::UnixStream},
+};
+
+use crate::libc::{self, gid_t, pid_t, uid_t};
+
+/// Credentials of the process on the other end of a companion socket (`SO_PEERCRED`).
+///
+/// The kernel records the credentials at the time the connection was made. Since modules connect
+/// in `pre[XXX]Specialize`, [Self::uid] is usually still zygote's (root) rather than the app's;
+/// [Self::pid] identifies the target process either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub pid: pid_t,
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+impl PeerCredentials {
+    /// Read the credentials of the peer of `stream`.
+    pub fn of(stream: &UnixStream) -> io::Result<Self> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&mut cred as *mut libc::ucred).cast(),
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            pid: cred.pid,
+            uid: cred.uid,
+            gid: cred.gid,
+        })
+    }
+}
+
+/// A companion stream along with the credentials of its peer.
+///
+/// Dereferences to the underlying [UnixStream].
+///
+/// ## Example
+///
+/// ```
+/// use zygisk::{companion::AuthenticatedStream, zygisk_companion};
+///
+/// zygisk_companion!(|stream| {
+///     let Ok(stream) = AuthenticatedStream::new(stream).and_then(|s| s.require_uid(&[0])) else {
+///         return;
+///     };
+///     // Only connections made by root reach this point.
+/// });
+/// ```
+#[derive(Debug)]
+pub struct AuthenticatedStream {
+    stream: UnixStream,
+    credentials: PeerCredentials,
+}
+
+impl AuthenticatedStream {
+    pub fn new(stream: UnixStream) -> io::Result<Self> {
+        let credentials = PeerCredentials::of(&stream)?;
+        Ok(AuthenticatedStream {
+            stream,
+            credentials,
+        })
+    }
+
+    pub fn credentials(&self) -> PeerCredentials {
+        self.credentials
+    }
+
+    /// Reject the connection with [io::ErrorKind::PermissionDenied] unless `check` accepts the
+    /// peer credentials.
+    pub fn require(self, check: impl FnOnce(&PeerCredentials) -> bool) -> io::Result<Self> {
+        if check(&self.credentials) {
+            Ok(self)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("companion peer rejected: {:?}", self.credentials),
+            ))
+        }
+    }
+
+    /// Reject the connection unless the peer uid is one of `uids`.
+    pub fn require_uid(self, uids: &[uid_t]) -> io::Result<Self> {
+        self.require(|cred| uids.contains(&cred.uid))
+    }
+
+    pub fn into_inner(self) -> UnixStream {
+        self.stream
+    }
+}
+
+impl Deref for AuthenticatedStream {
+    type Target = UnixStream;
+
+    fn deref(&self) -> &UnixStream {
+        &self.stream
+    }
+}
+
+impl DerefMut for AuthenticatedStream {
+    fn deref_mut(&mut self) -> &mut UnixStream {
+        &mut self.stream
+    }
+}
+
+#[test]
+fn test_peer_credentials() {
+    let (a, _b) = UnixStream::pair().unwrap();
+    let stream = AuthenticatedStream::new(a).unwrap();
+    let uid = unsafe { libc::getuid() };
+    assert_eq!(stream.credentials().pid, std::process::id() as pid_t);
+    assert_eq!(stream.credentials().uid, uid);
+
+    let stream = stream.require_uid(&[uid]).unwrap();
+    let err = stream.require_uid(&[uid.wrapping_add(1)]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+}
@@ -0,0 +1,427 @@
+//! A lock-free ring buffer of log records in shared memory, drained by the companion.
+//!
+//! The buffer is a [SharedBuffer] starting with a [HEADER]-byte header:
+//!
+//! | offset | size | field                                                     |
+//! |--------|------|-----------------------------------------------------------|
+//! | 0      | 4    | [MAGIC]                                                   |
+//! | 4      | 4    | capacity of the data region, a power of two               |
+//! | 8      | 8    | reserve position, advanced by writers                     |
+//! | 16     | 8    | read position, advanced by the companion                  |
+//! | 24     | 8    | number of records dropped because the buffer was full     |
+//!
+//! Positions only ever grow and are taken modulo the capacity. Each record is a 4-byte header,
+//! holding the length of the payload with [READY] set once the payload is written, followed by the
+//! payload padded to 4 bytes. The companion zeroes the records it consumed, so space that was
+//! reserved but not written yet never looks ready.
+
+use std::{
+    fmt,
+    io::{self, Read},
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    ptr,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use super::{handshake::Service, logs, SharedBuffer, SocketExt};
+use crate::{libc, raw_log::StackBuf, ModuleDir, ZygiskApi, ZygiskError};
+
+const MAGIC: u32 = u32::from_be_bytes(*b"ZRNG");
+const HEADER: usize = 64;
+const READY: u32 = 1 << 31;
+const MIN_CAPACITY: usize = 4096;
+const MAX_CAPACITY: usize = 1 << 30;
+
+/// How often the companion drains the buffer.
+const DRAIN_INTERVAL_MS: libc::c_int = 100;
+
+/// A ring buffer of log records shared with the companion, created by [ring_log()].
+///
+/// Writing a record takes a compare-and-swap and a copy into shared memory: no locks, no
+/// allocation and no system calls, which makes it cheap enough to leave enabled in hooks on hot
+/// paths. The companion drains the buffer in the background and appends each record as a line
+/// to `logs/<tag>.ring.log` in the module directory.
+///
+/// When the companion falls behind, new records are dropped rather than blocking the writer;
+/// [Self::dropped()] counts them.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::sync::OnceLock;
+/// use zygisk::{companion::{self, RingBuffer}, ZygiskApi};
+///
+/// static RING: OnceLock<RingBuffer> = OnceLock::new();
+///
+/// fn pre_app_specialize(api: &ZygiskApi) {
+///     if let Ok(ring) = companion::ring_log(api, "my-module", 64 * 1024) {
+///         let _ = RING.set(ring);
+///     }
+/// }
+///
+/// fn hooked_read(fd: i32, len: usize) {
+///     if let Some(ring) = RING.get() {
+///         write!(ring, "read({fd}, {len})");
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RingBuffer {
+    shared: SharedBuffer,
+    mask: u64,
+    // Keeps the companion draining until the process exits.
+    _stream: Option<UnixStream>,
+}
+
+impl RingBuffer {
+    /// The longest record that can be written with [Self::write_fmt()].
+    pub const MAX_FORMATTED: usize = 1024;
+
+    /// Create a buffer with room for at least `capacity` bytes of records.
+    ///
+    /// The capacity is rounded up to a power of two between 4 KiB and 1 GiB.
+    pub fn new(capacity: usize) -> io::Result<Self> {
+        let capacity = capacity
+            .clamp(MIN_CAPACITY, MAX_CAPACITY)
+            .next_power_of_two();
+        let mut shared = SharedBuffer::new(HEADER + capacity)?;
+        let header = shared.as_mut_slice().expect("new buffers are not sealed");
+        header[0..4].copy_from_slice(&MAGIC.to_ne_bytes());
+        header[4..8].copy_from_slice(&(capacity as u32).to_ne_bytes());
+        Ok(Self::from_shared(shared, capacity))
+    }
+
+    /// Map a buffer received from the other side.
+    pub fn recv(socket: &mut impl SocketExt) -> io::Result<Self> {
+        let shared = SharedBuffer::recv(socket)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a ring buffer");
+        if shared.is_sealed() || shared.len() < HEADER {
+            return Err(invalid());
+        }
+        let mut header = [0; 8];
+        shared.read_at(0, &mut header);
+        let magic = u32::from_ne_bytes(header[0..4].try_into().unwrap());
+        let capacity = u32::from_ne_bytes(header[4..8].try_into().unwrap()) as usize;
+        if magic != MAGIC || !capacity.is_power_of_two() || HEADER + capacity != shared.len() {
+            return Err(invalid());
+        }
+        Ok(Self::from_shared(shared, capacity))
+    }
+
+    /// Send the buffer over a companion socket. The buffer stays usable on this side.
+    pub fn send(&self, socket: &mut impl SocketExt) -> io::Result<()> {
+        self.shared.send(socket)
+    }
+
+    fn from_shared(shared: SharedBuffer, capacity: usize) -> Self {
+        RingBuffer {
+            shared,
+            mask: capacity as u64 - 1,
+            _stream: None,
+        }
+    }
+
+    /// The size of the data region in bytes.
+    pub fn capacity(&self) -> usize {
+        self.mask as usize + 1
+    }
+
+    /// The number of records dropped so far because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.counter(24).load(Ordering::Relaxed)
+    }
+
+    /// Append a record. Returns `false` if it was dropped because the buffer is full.
+    ///
+    /// This may be called from any number of threads at once.
+    pub fn write(&self, record: &[u8]) -> bool {
+        let total = record_size(record.len());
+        if total > self.mask + 1 {
+            self.counter(24).fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let reserve = self.counter(8);
+        let mut pos = reserve.load(Ordering::Relaxed);
+        loop {
+            let read = self.counter(16).load(Ordering::Acquire);
+            if pos.saturating_sub(read) + total > self.mask + 1 {
+                self.counter(24).fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+            match reserve.compare_exchange_weak(
+                pos,
+                pos + total,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => pos = current,
+            }
+        }
+        // SAFETY: the space between `pos` and `pos + total` is reserved for us alone.
+        unsafe { self.copy_in(pos + 4, record) };
+        self.record_header(pos)
+            .store(record.len() as u32 | READY, Ordering::Release);
+        true
+    }
+
+    /// Append a formatted record of up to [Self::MAX_FORMATTED] bytes, without allocating.
+    ///
+    /// This is what `write!(ring, ...)` calls. Longer records are truncated.
+    pub fn write_fmt(&self, args: fmt::Arguments) -> bool {
+        let mut record = StackBuf::<{ Self::MAX_FORMATTED }>::new();
+        let _ = fmt::Write::write_fmt(&mut record, args);
+        self.write(record.as_bytes())
+    }
+
+    /// Consume the records written so far, in order. Returns the number of records consumed.
+    ///
+    /// Only one side may drain a buffer, and not from several threads at once. This is what the
+    /// companion does with the buffers sent by [ring_log()].
+    ///
+    /// The other side can write anything to the buffer, so positions and lengths are checked
+    /// before a record is read. If they are inconsistent, the buffer is corrupt and an
+    /// [io::ErrorKind::InvalidData] error is returned; the records consumed before were already
+    /// passed to `f`.
+    pub fn drain(&self, mut f: impl FnMut(&[u8])) -> io::Result<usize> {
+        let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt ring buffer");
+        let read = self.counter(16);
+        let mut record = Vec::new();
+        let mut count = 0;
+        loop {
+            let pos = read.load(Ordering::Relaxed);
+            let reserve = self.counter(8).load(Ordering::Acquire);
+            if pos == reserve {
+                return Ok(count);
+            }
+            if !pos.is_multiple_of(4) || reserve < pos {
+                return Err(corrupt());
+            }
+            let header = self.record_header(pos).load(Ordering::Acquire);
+            if header & READY == 0 {
+                // Reserved, but the writer is not done yet.
+                return Ok(count);
+            }
+            let len = (header & !READY) as usize;
+            let total = record_size(len);
+            if total > self.mask + 1 || total > reserve - pos {
+                return Err(corrupt());
+            }
+            record.resize(len, 0);
+            // SAFETY: the record is complete and no writer touches it until `read` moves past it.
+            unsafe {
+                self.copy_out(pos + 4, &mut record);
+                self.zero(pos, total);
+            }
+            read.store(pos + total, Ordering::Release);
+            f(&record);
+            count += 1;
+        }
+    }
+
+    fn counter(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: the header is in bounds and page-aligned, and only accessed atomically.
+        unsafe { &*self.shared.as_ptr().add(offset).cast::<AtomicU64>() }
+    }
+
+    fn record_header(&self, pos: u64) -> &AtomicU32 {
+        // SAFETY: positions of record headers are multiples of 4, so the header never wraps.
+        unsafe {
+            &*self
+                .data()
+                .add((pos & self.mask) as usize)
+                .cast::<AtomicU32>()
+        }
+    }
+
+    fn data(&self) -> *mut u8 {
+        self.shared.as_ptr().wrapping_add(HEADER)
+    }
+
+    /// Split `len` bytes at `pos` into the parts before and after the end of the data region.
+    fn split(&self, pos: u64, len: usize) -> (usize, usize) {
+        let start = (pos & self.mask) as usize;
+        let first = len.min(self.capacity() - start);
+        (start, first)
+    }
+
+    unsafe fn copy_in(&self, pos: u64, bytes: &[u8]) {
+        let (start, first) = self.split(pos, bytes.len());
+        ptr::copy_nonoverlapping(bytes.as_ptr(), self.data().add(start), first);
+        ptr::copy_nonoverlapping(bytes[first..].as_ptr(), self.data(), bytes.len() - first);
+    }
+
+    unsafe fn copy_out(&self, pos: u64, bytes: &mut [u8]) {
+        let (start, first) = self.split(pos, bytes.len());
+        ptr::copy_nonoverlapping(self.data().add(start), bytes.as_mut_ptr(), first);
+        let rest = bytes.len() - first;
+        ptr::copy_nonoverlapping(self.data(), bytes[first..].as_mut_ptr(), rest);
+    }
+
+    unsafe fn zero(&self, pos: u64, len: u64) {
+        let (start, first) = self.split(pos, len as usize);
+        ptr::write_bytes(self.data().add(start), 0, first);
+        ptr::write_bytes(self.data(), 0, len as usize - first);
+    }
+}
+
+fn record_size(len: usize) -> u64 {
+    4 + ((len as u64 + 3) & !3)
+}
+
+/// Create a [RingBuffer] and have the companion drain it into `logs/<tag>.ring.log` in the
+/// module directory.
+///
+/// This is one of the [built-in services](super#built-in-services), enabled with
+/// `zygisk_companion!(handler, services = [ring_log], module_dir = "...")`. The companion keeps
+/// draining until the process exits; see [RingBuffer::new()] for how `capacity` is rounded.
+pub fn ring_log(api: &ZygiskApi, tag: &str, capacity: usize) -> Result<RingBuffer, ZygiskError> {
+    let mut ring = RingBuffer::new(capacity).map_err(ZygiskError::CompanionRequestFailed)?;
+    let mut stream = api.connect_companion_service(Service::RingLog)?;
+    stream
+        .send_str(tag)
+        .and_then(|_| ring.send(&mut stream))
+        .map_err(ZygiskError::CompanionRequestFailed)?;
+    #[cfg(feature = "api-v4")]
+    if api.supports_exempt_fd() {
+        // The memfd stays open for as long as the buffer is mapped.
+        api.exempt_fd(&stream)?;
+        api.exempt_fd(&ring.shared)?;
+    }
+    ring._stream = Some(stream);
+    Ok(ring)
+}
+
+pub(crate) fn serve_ring_log(stream: &mut UnixStream, module_dir: &ModuleDir) -> io::Result<()> {
+    serve_ring_log_with_limit(stream, module_dir, logs::MAX_LOG_SIZE)
+}
+
+fn serve_ring_log_with_limit(
+    stream: &mut UnixStream,
+    module_dir: &ModuleDir,
+    max_size: u64,
+) -> io::Result<()> {
+    let (logs, tag) = logs::recv_log_dir(stream, module_dir)?;
+    let ring = RingBuffer::recv(stream)?;
+    let name = format!("{tag}.ring.log");
+
+    let mut lines = Vec::new();
+    loop {
+        let mut poll = libc::pollfd {
+            fd: stream.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll, 1, DRAIN_INTERVAL_MS) };
+        if ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return Err(io::Error::last_os_error());
+        }
+        // The module never sends anything, so the stream only becomes readable once the process
+        // exits and its end is closed.
+        let closed = ready > 0 && matches!(stream.read(&mut [0]), Ok(0) | Err(_));
+
+        let drained = ring.drain(|record| {
+            lines.extend_from_slice(record);
+            if !record.ends_with(b"\n") {
+                lines.push(b'\n');
+            }
+        });
+        if closed && ring.dropped() > 0 {
+            lines.extend_from_slice(format!("({} records dropped)\n", ring.dropped()).as_bytes());
+        }
+        if !lines.is_empty() {
+            logs::append(&logs, &name, &lines, max_size)?;
+            lines.clear();
+        }
+        drained?;
+        if closed {
+            return Ok(());
+        }
+    }
+}
+
+#[test]
+fn test_ring_buffer() {
+    let ring = RingBuffer::new(0).unwrap();
+    assert_eq!(ring.capacity(), MIN_CAPACITY);
+
+    // Wrap around the end of the data region a few times.
+    let record = [7u8; 1000];
+    for round in 0..10 {
+        assert!(ring.write(&record[..round * 10 + 1]));
+        assert!(ring.write(&record[..998]));
+        let mut lengths = Vec::new();
+        assert_eq!(ring.drain(|r| lengths.push(r.len())).unwrap(), 2);
+        assert_eq!(lengths, [round * 10 + 1, 998]);
+    }
+
+    // Full: records are dropped instead of overwriting unread ones.
+    while ring.write(&record) {}
+    assert_eq!(ring.dropped(), 1);
+    assert!(!ring.write(&[0; MIN_CAPACITY]));
+    assert_eq!(ring.dropped(), 2);
+    assert_eq!(ring.drain(|r| assert_eq!(r, record)).unwrap(), 4);
+    assert!(write!(ring, "{} {}", "formatted", 42));
+    assert_eq!(ring.drain(|r| assert_eq!(r, b"formatted 42")).unwrap(), 1);
+
+    // The writer side controls the whole buffer: lengths past the reserved space are rejected.
+    assert!(ring.write(b"valid"));
+    let pos = ring.counter(16).load(Ordering::Relaxed);
+    ring.record_header(pos)
+        .store(MIN_CAPACITY as u32 | READY, Ordering::Release);
+    let error = ring.drain(|_| panic!("corrupt record read")).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    ring.record_header(pos).store(64 | READY, Ordering::Release);
+    assert!(ring.drain(|_| panic!("corrupt record read")).is_err());
+    ring.counter(16).store(pos + 1, Ordering::Relaxed);
+    assert!(ring.drain(|_| panic!("corrupt record read")).is_err());
+}
+
+#[test]
+fn test_serve_ring_log() {
+    use std::fs;
+
+    let root = crate::module_dir::TempDir::new("ring");
+    let dir = ModuleDir::open_path(&*root).unwrap();
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    let ring = RingBuffer::new(1 << 16).unwrap();
+    ring.write(b"before the companion started");
+    let thread =
+        std::thread::spawn(move || serve_ring_log_with_limit(&mut companion, &dir, 1 << 20));
+    module.send_str("my/module").unwrap();
+    ring.send(&mut module).unwrap();
+
+    let writers: Vec<_> = (0..4)
+        .map(|i| {
+            let ring = &ring;
+            move || {
+                for j in 0..100 {
+                    while !ring.write(format!("{i} {j}\n").as_bytes()) {
+                        std::thread::yield_now();
+                    }
+                }
+            }
+        })
+        .collect();
+    std::thread::scope(|s| {
+        for writer in writers {
+            s.spawn(writer);
+        }
+    });
+    drop(module);
+    thread.join().unwrap().unwrap();
+
+    let log = fs::read_to_string(root.join("logs/my_module.ring.log")).unwrap();
+    let mut lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.remove(0), "before the companion started");
+    assert_eq!(lines.len(), 400);
+    for i in 0..4 {
+        let own: Vec<_> = lines
+            .iter()
+            .filter(|l| l.starts_with(&format!("{i} ")))
+            .collect();
+        let expected: Vec<_> = (0..100).map(|j| format!("{i} {j}")).collect();
+        assert_eq!(own, expected.iter().collect::<Vec<_>>());
+    }
+}
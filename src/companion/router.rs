@@ -0,0 +1,98 @@
+use std::{collections::HashMap, io, os::unix::net::UnixStream};
+
+use super::{CompanionHandler, SocketExt};
+use crate::logcat;
+
+type Route = Box<dyn Fn(UnixStream) + Send + Sync>;
+
+/// Dispatches companion connections to handlers keyed by a one-byte message tag.
+///
+/// The module sends the tag with [SocketExt::send_u8()] right after connecting, and the router
+/// passes the rest of the stream to the matching handler. Connections with an unknown tag are
+/// dropped and logged.
+///
+/// ## Example
+///
+/// ```
+/// use std::{os::unix::net::UnixStream, sync::LazyLock};
+/// use zygisk::{companion::Router, zygisk_companion};
+///
+/// #[repr(u8)]
+/// enum Command {
+///     ReadFile = 1,
+///     WriteFile = 2,
+/// }
+///
+/// impl From<Command> for u8 {
+///     fn from(command: Command) -> u8 {
+///         command as u8
+///     }
+/// }
+///
+/// fn read_file(_stream: UnixStream) {}
+/// fn write_file(_stream: UnixStream) {}
+///
+/// static ROUTER: LazyLock<Router> = LazyLock::new(|| {
+///     Router::new()
+///         .route(Command::ReadFile, read_file)
+///         .route(Command::WriteFile, write_file)
+/// });
+///
+/// zygisk_companion!(&*ROUTER);
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: HashMap<u8, Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a handler for a tag, replacing any previously registered one.
+    pub fn route<F>(mut self, tag: impl Into<u8>, handler: F) -> Self
+    where
+        F: Fn(UnixStream) + Send + Sync + 'static,
+    {
+        self.routes.insert(tag.into(), Box::new(handler));
+        self
+    }
+
+    /// Read the tag from `stream` and run the matching handler.
+    pub fn dispatch(&self, mut stream: UnixStream) -> io::Result<()> {
+        let tag = stream.recv_u8()?;
+        let handler = self.routes.get(&tag).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no companion route for tag {tag}"),
+            )
+        })?;
+        handler(stream);
+        Ok(())
+    }
+}
+
+impl CompanionHandler for Router {
+    fn on_connect(&self, stream: UnixStream) {
+        if let Err(e) = self.dispatch(stream) {
+            logcat::write(logcat::Priority::Error, &format!("companion router: {e}"));
+        }
+    }
+}
+
+#[test]
+fn test_router_dispatch() {
+    let router = Router::new()
+        .route(1, |mut stream: UnixStream| stream.send_str("one").unwrap())
+        .route(2, |mut stream: UnixStream| stream.send_str("two").unwrap());
+
+    let (mut module, companion) = UnixStream::pair().unwrap();
+    module.send_u8(2).unwrap();
+    router.dispatch(companion).unwrap();
+    assert_eq!(module.recv_str().unwrap(), "two");
+
+    let (mut module, companion) = UnixStream::pair().unwrap();
+    module.send_u8(3).unwrap();
+    assert!(router.dispatch(companion).is_err());
+}
@@ -0,0 +1,109 @@
+//! Typed request/response RPC over companion sockets.
+//!
+//! Messages are encoded with `bincode` and framed with [SocketExt::send_bytes()]. A module
+//! obtains a [CompanionClient] from
+//! [ZygiskApi::connect_companion_typed()](crate::ZygiskApi::connect_companion_typed), while the
+//! companion handler passes its stream to [serve()].
+//!
+//! ## Example
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use zygisk::{companion::rpc, zygisk_companion};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! enum Request {
+//!     GetConfig,
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! enum Response {
+//!     Config(String),
+//! }
+//!
+//! zygisk_companion!(|stream| {
+//!     let _ = rpc::serve(stream, |request: Request| match request {
+//!         Request::GetConfig => Response::Config("enabled=true".into()),
+//!     });
+//! });
+//! ```
+
+use std::{io, marker::PhantomData, os::unix::net::UnixStream};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::SocketExt;
+
+/// The largest request [serve()] accepts.
+pub const MAX_REQUEST_SIZE: usize = 16 << 20;
+
+fn encode<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+    bincode::deserialize(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The module side of a typed companion connection, sending `Req` and receiving `Resp`.
+pub struct CompanionClient<Req, Resp> {
+    stream: UnixStream,
+    _marker: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req: Serialize, Resp: DeserializeOwned> CompanionClient<Req, Resp> {
+    pub fn new(stream: UnixStream) -> Self {
+        CompanionClient {
+            stream,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Send a request and wait for the response.
+    pub fn call(&mut self, request: &Req) -> io::Result<Resp> {
+        self.stream.send_bytes(&encode(request)?)?;
+        decode(&self.stream.recv_bytes()?)
+    }
+
+    /// Get the underlying stream back.
+    pub fn into_inner(self) -> UnixStream {
+        self.stream
+    }
+}
+
+/// Serve typed requests on a companion stream until the module closes the connection.
+///
+/// Every request is decoded, passed to `handler`, and the returned response is sent back.
+/// Returns `Ok(())` once the peer disconnects between requests, and fails on requests larger than
+/// [MAX_REQUEST_SIZE].
+pub fn serve<Req, Resp, F>(mut stream: UnixStream, mut handler: F) -> io::Result<()>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    F: FnMut(Req) -> Resp,
+{
+    loop {
+        let request = match stream.recv_bytes_max(MAX_REQUEST_SIZE) {
+            Ok(bytes) => decode(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        stream.send_bytes(&encode(&handler(request))?)?;
+    }
+}
+
+#[test]
+fn test_rpc_round_trip() {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Add(u32, u32);
+
+    let (client, server) = UnixStream::pair().unwrap();
+    let companion = std::thread::spawn(move || serve(server, |Add(a, b)| a + b));
+
+    let mut client = CompanionClient::<Add, u32>::new(client);
+    assert_eq!(client.call(&Add(1, 2)).unwrap(), 3);
+    assert_eq!(client.call(&Add(40, 2)).unwrap(), 42);
+    drop(client);
+
+    companion.join().unwrap().unwrap();
+}
@@ -0,0 +1,547 @@
+//! Companion services built into the crate, served by the `zygisk_companion!` glue before the
+//! connection reaches the module's own handler.
+
+use std::{
+    ffi::{CString, OsStr, OsString},
+    fs::File,
+    io::{self, Read},
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+        unix::{
+            ffi::{OsStrExt, OsStringExt},
+            net::UnixStream,
+            process::{CommandExt, ExitStatusExt},
+        },
+    },
+    path::Path,
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{handshake::Service, CompanionHandler, PeerCredentials, SocketExt, WatchdogGuard};
+use crate::{libc, logcat, ModuleDir, ZygiskApi, ZygiskError};
+
+crate::bitflags::bitflags! {
+    /// The built-in companion services that act as root on behalf of the module, enabled with
+    /// `zygisk_companion!(handler, services = [open_as_root])`.
+    ///
+    /// They are disabled by default, since any process able to reach the companion could use them.
+    /// The services working in the module directory also need its path, which the companion is
+    /// given with `module_dir = "/data/adb/modules/<id>"` instead of trusting the module with it.
+    /// When the companion is registered with a [CompanionHandler], enabled services go through
+    /// its [authorize()](CompanionHandler::authorize) and [watchdog()](CompanionHandler::watchdog)
+    /// like the requests of the handler itself.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct BuiltinServices: u32 {
+        /// [open_as_root()]
+        const OPEN_AS_ROOT = (1 << 0);
+        /// [exec()]
+        const EXEC = (1 << 1);
+        /// [ConfigCache](super::ConfigCache), in the module directory
+        const CONFIG = (1 << 2);
+        /// `logging::forward_to_companion()`, in the module directory
+        const LOGS = (1 << 3);
+        /// [ring_log()](super::ring_log), in the module directory
+        const RING_LOG = (1 << 4);
+    }
+}
+
+/// The names accepted in `zygisk_companion!(handler, services = [...])`.
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub mod service_names {
+    use super::BuiltinServices;
+
+    pub const open_as_root: BuiltinServices = BuiltinServices::OPEN_AS_ROOT;
+    pub const exec: BuiltinServices = BuiltinServices::EXEC;
+    pub const config: BuiltinServices = BuiltinServices::CONFIG;
+    pub const logs: BuiltinServices = BuiltinServices::LOGS;
+    pub const ring_log: BuiltinServices = BuiltinServices::RING_LOG;
+}
+
+/// Open a file with root privileges in the companion process and receive the fd.
+///
+/// This is the most common reason to have a companion at all, so it is one of the
+/// [built-in services](super#built-in-services), enabled with
+/// `zygisk_companion!(handler, services = [open_as_root])`.
+///
+/// `flags` are passed to `open(2)`; `O_CLOEXEC` is always added. Files created with `O_CREAT`
+/// get mode `0600`.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::fs::File;
+/// use zygisk::{companion, libc, ZygiskApi};
+///
+/// fn read_config(api: &ZygiskApi) -> Option<File> {
+///     companion::open_as_root(api, "/data/adb/my-module/config.toml", libc::O_RDONLY)
+///         .ok()
+///         .map(File::from)
+/// }
+/// ```
+pub fn open_as_root(
+    api: &ZygiskApi,
+    path: impl AsRef<Path>,
+    flags: libc::c_int,
+) -> Result<OwnedFd, ZygiskError> {
+    let mut stream = api.connect_companion_service(Service::OpenAsRoot)?;
+    let path = path.as_ref().as_os_str().as_bytes();
+    let errno = stream
+        .send_bytes(path)
+        .and_then(|_| stream.send_i32(flags))
+        .and_then(|_| stream.recv_i32())
+        .map_err(ZygiskError::CompanionRequestFailed)?;
+    match errno {
+        0 => stream
+            .recv_fd()
+            .map_err(ZygiskError::CompanionRequestFailed),
+        errno => Err(ZygiskError::CompanionRequestFailed(
+            io::Error::from_raw_os_error(errno),
+        )),
+    }
+}
+
+// Frames sent back by the exec service, each tag followed by its payload.
+const FRAME_STDOUT: u8 = 1; // bytes
+const FRAME_STDERR: u8 = 2; // bytes
+const FRAME_EXIT: u8 = 3; // i32 wait status
+const FRAME_SPAWN_FAILED: u8 = 4; // i32 errno
+
+/// The most arguments [exec()] accepts.
+const MAX_EXEC_ARGS: u32 = 1024;
+/// The longest program or argument [exec()] accepts, like `MAX_ARG_STRLEN` of Linux.
+const MAX_EXEC_ARG_LEN: usize = 128 * 1024;
+/// How long a command run by [exec()] may take before it is killed.
+const EXEC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run a command in the companion process as root and collect its output.
+///
+/// This is one of the [built-in services](super#built-in-services), enabled with
+/// `services = [exec]`. The output of the command is streamed back over the socket as it is
+/// produced, so there is no limit on its size other than the memory of the calling process. The
+/// command inherits the environment of the companion process and gets `/dev/null` as its stdin.
+///
+/// The program and each argument are limited to 128 KiB, and there can be at most 1024
+/// arguments. Commands still running after 30 seconds are killed along with their process group,
+/// and reported as such in the returned status. Output written after that point, such as by
+/// background processes that left the group, is not forwarded.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{companion, ZygiskApi};
+///
+/// fn spoof_fingerprint(api: &ZygiskApi) {
+///     let output = companion::exec(api, "resetprop", ["ro.build.tags", "release-keys"]);
+///     if let Ok(output) = output {
+///         assert!(output.status.success());
+///     }
+/// }
+/// ```
+pub fn exec<I, S>(
+    api: &ZygiskApi,
+    program: impl AsRef<OsStr>,
+    args: I,
+) -> Result<Output, ZygiskError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut stream = api.connect_companion_service(Service::Exec)?;
+    let args: Vec<S> = args.into_iter().collect();
+    send_command(&mut stream, program.as_ref(), &args)
+        .and_then(|_| recv_output(&mut stream))
+        .map_err(ZygiskError::CompanionRequestFailed)?
+}
+
+fn send_command<S: AsRef<OsStr>>(
+    stream: &mut UnixStream,
+    program: &OsStr,
+    args: &[S],
+) -> io::Result<()> {
+    stream.send_bytes(program.as_bytes())?;
+    stream.send_u32(args.len() as u32)?;
+    for arg in args {
+        stream.send_bytes(arg.as_ref().as_bytes())?;
+    }
+    Ok(())
+}
+
+fn recv_output(stream: &mut UnixStream) -> io::Result<Result<Output, ZygiskError>> {
+    let mut output = Output {
+        status: ExitStatus::from_raw(0),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+    loop {
+        match stream.recv_u8()? {
+            FRAME_STDOUT => output.stdout.extend(stream.recv_bytes()?),
+            FRAME_STDERR => output.stderr.extend(stream.recv_bytes()?),
+            FRAME_EXIT => {
+                output.status = ExitStatus::from_raw(stream.recv_i32()?);
+                return Ok(Ok(output));
+            }
+            FRAME_SPAWN_FAILED => {
+                let errno = stream.recv_i32()?;
+                return Ok(Err(ZygiskError::CompanionRequestFailed(
+                    io::Error::from_raw_os_error(errno),
+                )));
+            }
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown exec frame {tag}"),
+                ))
+            }
+        }
+    }
+}
+
+pub(crate) fn serve_builtin(
+    service: Service,
+    mut stream: UnixStream,
+    enabled: BuiltinServices,
+    module_dir: Option<&str>,
+    handler: Option<&dyn CompanionHandler>,
+) {
+    let required = match service {
+        Service::OpenAsRoot => Some(BuiltinServices::OPEN_AS_ROOT),
+        Service::Exec => Some(BuiltinServices::EXEC),
+        Service::Config => Some(BuiltinServices::CONFIG),
+        Service::Logs => Some(BuiltinServices::LOGS),
+        Service::RingLog => Some(BuiltinServices::RING_LOG),
+        _ => None,
+    };
+    let mut _guard = None;
+    if let Some(required) = required {
+        if !enabled.contains(required) {
+            logcat::write(
+                logcat::Priority::Warn,
+                &format!("companion service {service:?} is not enabled"),
+            );
+            return;
+        }
+        if let Some(handler) = handler {
+            if !authorize(handler, &stream) {
+                return;
+            }
+            _guard = watch(handler, &stream);
+        }
+    }
+
+    let result = match service {
+        Service::Handler => unreachable!("handled by the registered companion handler"),
+        Service::OpenAsRoot => serve_open(&mut stream),
+        Service::Exec => serve_exec(&mut stream, EXEC_TIMEOUT),
+        Service::Logs => {
+            open_module_dir(module_dir).and_then(|dir| super::logs::serve_logs(&mut stream, &dir))
+        }
+        Service::RingLog => open_module_dir(module_dir)
+            .and_then(|dir| super::ring::serve_ring_log(&mut stream, &dir)),
+        Service::Config => open_module_dir(module_dir)
+            .and_then(|dir| super::config::serve_config(&mut stream, &dir)),
+        Service::Version => super::version::serve_version(&mut stream),
+    };
+    if let Err(e) = result {
+        logcat::write(
+            logcat::Priority::Error,
+            &format!("companion service {service:?} failed: {e}"),
+        );
+    }
+}
+
+/// Open the module directory given to `zygisk_companion!`, for the services working in it.
+fn open_module_dir(path: Option<&str>) -> io::Result<ModuleDir> {
+    let path = path.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "the module directory is not set with `zygisk_companion!(..., module_dir = \"...\")`",
+        )
+    })?;
+    ModuleDir::open_path(path)
+}
+
+/// Check the peer of `stream` with [CompanionHandler::authorize()], logging rejections.
+pub(crate) fn authorize(handler: &dyn CompanionHandler, stream: &UnixStream) -> bool {
+    let error = match PeerCredentials::of(stream) {
+        Ok(credentials) if handler.authorize(&credentials) => return true,
+        Ok(credentials) => format!("companion peer rejected: {credentials:?}"),
+        Err(e) => format!("failed to read the companion peer credentials: {e}"),
+    };
+    logcat::write(logcat::Priority::Warn, &error);
+    false
+}
+
+/// Start the [CompanionHandler::watchdog()] of `handler` on `stream`, if it has one.
+pub(crate) fn watch(handler: &dyn CompanionHandler, stream: &UnixStream) -> Option<WatchdogGuard> {
+    match handler.watchdog()?.watch(stream) {
+        Ok(guard) => Some(guard),
+        Err(e) => {
+            logcat::write(
+                logcat::Priority::Warn,
+                &format!("failed to start the companion watchdog: {e}"),
+            );
+            None
+        }
+    }
+}
+
+fn serve_open(stream: &mut UnixStream) -> io::Result<()> {
+    let path = stream.recv_bytes_max(libc::PATH_MAX as usize)?;
+    let flags = stream.recv_i32()?;
+    match open(path, flags) {
+        Ok(fd) => {
+            stream.send_i32(0)?;
+            stream.send_fd(fd.as_fd())
+        }
+        Err(e) => stream.send_i32(e.raw_os_error().unwrap_or(libc::EIO)),
+    }
+}
+
+fn serve_exec(stream: &mut UnixStream, timeout: Duration) -> io::Result<()> {
+    let program = OsString::from_vec(stream.recv_bytes_max(MAX_EXEC_ARG_LEN)?);
+    let argc = stream.recv_u32()?;
+    if argc > MAX_EXEC_ARGS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("too many exec arguments ({argc})"),
+        ));
+    }
+    let args = (0..argc)
+        .map(|_| {
+            stream
+                .recv_bytes_max(MAX_EXEC_ARG_LEN)
+                .map(OsString::from_vec)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let deadline = Instant::now() + timeout;
+    // In a process group of its own, so that its children are killed along with it.
+    let child = Command::new(program)
+        .args(args)
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            stream.send_u8(FRAME_SPAWN_FAILED)?;
+            return stream.send_i32(e.raw_os_error().unwrap_or(libc::EIO));
+        }
+    };
+
+    // Forward both pipes concurrently so that the child never blocks on a full pipe. Processes
+    // left behind by the command may keep the pipes open, so forwarding stops at the deadline.
+    let writer = Mutex::new(stream.try_clone()?);
+    let forward = |mut pipe: File, tag: u8| -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        loop {
+            if !poll_readable(&pipe, deadline)? {
+                return Ok(());
+            }
+            let len = pipe.read(&mut buf)?;
+            if len == 0 {
+                return Ok(());
+            }
+            let mut writer = writer.lock().unwrap();
+            writer.send_u8(tag)?;
+            writer.send_bytes(&buf[..len])?;
+        }
+    };
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let (status, stdout, stderr) = thread::scope(|scope| {
+        let stdout = scope.spawn(|| forward(OwnedFd::from(stdout).into(), FRAME_STDOUT));
+        let stderr = scope.spawn(|| forward(OwnedFd::from(stderr).into(), FRAME_STDERR));
+        // Reap the child even if the module went away in the meantime.
+        let status = wait_with_deadline(&mut child, deadline, timeout);
+        (status, stdout.join().unwrap(), stderr.join().unwrap())
+    });
+    let status = status?;
+    stdout?;
+    stderr?;
+    stream.send_u8(FRAME_EXIT)?;
+    stream.send_i32(status.into_raw())
+}
+
+/// Wait for `child` to exit, killing its process group at `deadline`.
+fn wait_with_deadline(
+    child: &mut Child,
+    deadline: Instant,
+    timeout: Duration,
+) -> io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            logcat::write(
+                logcat::Priority::Warn,
+                &format!("killing command exceeding its deadline of {timeout:?}"),
+            );
+            // The child has not been reaped, so its pid is still its process group.
+            if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            return child.wait();
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Wait for `pipe` to be readable or closed, returning `false` once `deadline` has passed.
+fn poll_readable(pipe: &File, deadline: Instant) -> io::Result<bool> {
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let mut pollfd = libc::pollfd {
+            fd: pipe.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+            0 if timeout.is_zero() => return Ok(false),
+            0 => continue,
+            n if n > 0 => return Ok(true),
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+fn open(path: Vec<u8>, flags: libc::c_int) -> io::Result<OwnedFd> {
+    let path = CString::new(path)?;
+    let fd = unsafe { libc::open(path.as_ptr(), flags | libc::O_CLOEXEC, 0o600) };
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+#[test]
+fn test_serve_open() {
+    use std::{fs::File, io::Read};
+
+    let root = crate::module_dir::TempDir::new("open");
+    let path = root.join("file");
+    std::fs::write(&path, "root").unwrap();
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    module.send_bytes(path.as_os_str().as_bytes()).unwrap();
+    module.send_i32(libc::O_RDONLY).unwrap();
+    serve_open(&mut companion).unwrap();
+    assert_eq!(module.recv_i32().unwrap(), 0);
+    let mut buf = String::new();
+    File::from(module.recv_fd().unwrap())
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "root");
+
+    std::fs::remove_file(&path).unwrap();
+    module.send_bytes(path.as_os_str().as_bytes()).unwrap();
+    module.send_i32(libc::O_RDONLY).unwrap();
+    serve_open(&mut companion).unwrap();
+    assert_eq!(module.recv_i32().unwrap(), libc::ENOENT);
+}
+
+#[test]
+fn test_serve_builtin_opt_in() {
+    struct Rejecting;
+    impl CompanionHandler for Rejecting {
+        fn authorize(&self, _credentials: &PeerCredentials) -> bool {
+            false
+        }
+        fn on_connect(&self, _stream: UnixStream) {}
+    }
+
+    let request = |enabled, handler: Option<&dyn CompanionHandler>| {
+        let (mut module, companion) = UnixStream::pair().unwrap();
+        module.send_bytes(b"/").unwrap();
+        module.send_i32(libc::O_RDONLY).unwrap();
+        serve_builtin(Service::OpenAsRoot, companion, enabled, None, handler);
+        // The connection is dropped without an answer unless the request is served.
+        module.recv_i32().ok()
+    };
+    assert_eq!(request(BuiltinServices::empty(), None), None);
+    assert_eq!(
+        request(BuiltinServices::OPEN_AS_ROOT, Some(&Rejecting)),
+        None
+    );
+    assert_eq!(request(BuiltinServices::OPEN_AS_ROOT, None), Some(0));
+
+    // Services in the module directory are neither served without it, nor without being enabled.
+    let root = crate::module_dir::TempDir::new("builtin-config");
+    std::fs::write(root.join("config.toml"), "").unwrap();
+    let config = |enabled, module_dir| {
+        let (mut module, companion) = UnixStream::pair().unwrap();
+        module.send_str("config.toml").unwrap();
+        module.send_u64(0).unwrap();
+        serve_builtin(Service::Config, companion, enabled, module_dir, None);
+        module.recv_u8().ok()
+    };
+    assert_eq!(config(BuiltinServices::empty(), root.to_str()), None);
+    assert_eq!(config(BuiltinServices::CONFIG, None), None);
+    assert_eq!(config(BuiltinServices::CONFIG, root.to_str()), Some(1));
+}
+
+#[test]
+fn test_serve_exec() {
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    send_command(
+        &mut module,
+        OsStr::new("sh"),
+        &["-c", "echo out; echo err >&2; exit 3"],
+    )
+    .unwrap();
+    serve_exec(&mut companion, EXEC_TIMEOUT).unwrap();
+    let output = recv_output(&mut module).unwrap().unwrap();
+    assert_eq!(output.stdout, b"out\n");
+    assert_eq!(output.stderr, b"err\n");
+    assert_eq!(output.status.code(), Some(3));
+
+    send_command::<&str>(&mut module, OsStr::new("/nonexistent"), &[]).unwrap();
+    serve_exec(&mut companion, EXEC_TIMEOUT).unwrap();
+    assert!(matches!(
+        recv_output(&mut module).unwrap(),
+        Err(ZygiskError::CompanionRequestFailed(_))
+    ));
+
+    send_command(&mut module, OsStr::new("sleep"), &["10"]).unwrap();
+    serve_exec(&mut companion, Duration::from_millis(100)).unwrap();
+    let output = recv_output(&mut module).unwrap().unwrap();
+    assert_eq!(output.status.signal(), Some(libc::SIGKILL));
+
+    // A background process keeping the pipes open past the deadline.
+    send_command(
+        &mut module,
+        OsStr::new("sh"),
+        &["-c", "sleep 10 & echo out"],
+    )
+    .unwrap();
+    let start = Instant::now();
+    serve_exec(&mut companion, Duration::from_millis(200)).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let output = recv_output(&mut module).unwrap().unwrap();
+    assert_eq!(output.stdout, b"out\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    module.send_bytes(b"true").unwrap();
+    module.send_u32(MAX_EXEC_ARGS + 1).unwrap();
+    let error = serve_exec(&mut companion, EXEC_TIMEOUT).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    module.send_u32(MAX_EXEC_ARG_LEN as u32 + 1).unwrap();
+    let error = serve_exec(&mut companion, EXEC_TIMEOUT).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,302 @@
+use std::{
+    io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    ptr::{self, NonNull},
+    slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::SocketExt;
+use crate::libc;
+
+/// A memory buffer shared between the companion and the module, backed by a memfd.
+///
+/// Large payloads (configs, dex files, ...) are better passed as a [SharedBuffer] than streamed
+/// through the socket: the data is written once by the companion and mapped by the module
+/// without any copy.
+///
+/// The size of the buffer is sealed on creation. Once [Self::seal()] is called, the contents can
+/// no longer be changed by anyone either, so the receiving side can trust that the data it
+/// validated stays the same. Only sealed buffers can be borrowed as a slice once they are
+/// shared: the contents of the others may change at any time, and are copied out with
+/// [Self::read_at()] instead.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::os::unix::net::UnixStream;
+/// use zygisk::companion::SharedBuffer;
+///
+/// // In the companion process:
+/// fn companion_main(mut stream: UnixStream) {
+///     let dex = std::fs::read("/data/adb/modules/example/classes.dex").unwrap();
+///     SharedBuffer::from_bytes(&dex).unwrap().send(&mut stream).unwrap();
+/// }
+///
+/// // In the module:
+/// fn load_dex(mut stream: UnixStream) {
+///     let dex = SharedBuffer::recv(&mut stream).unwrap();
+///     let _bytes: &[u8] = dex.as_slice().expect("the companion seals the dex");
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SharedBuffer {
+    fd: OwnedFd,
+    ptr: NonNull<u8>,
+    len: usize,
+    sealed: bool,
+    // Whether the fd was sent or received, so that the other side may write to the buffer.
+    shared: AtomicBool,
+}
+
+// SAFETY: the mapping is owned by the buffer and only accessed through `&self`/`&mut self`.
+unsafe impl Send for SharedBuffer {}
+unsafe impl Sync for SharedBuffer {}
+
+impl SharedBuffer {
+    /// Create a zero-filled, writable buffer of `len` bytes.
+    pub fn new(len: usize) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                c"zygisk-shared".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as _) };
+
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        add_seals(fd.as_fd(), libc::F_SEAL_SHRINK | libc::F_SEAL_GROW)?;
+        Self::map(fd, len, false)
+    }
+
+    /// Create a sealed buffer holding a copy of `data`.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut buffer = Self::new(data.len())?;
+        if let Some(contents) = buffer.as_mut_slice() {
+            contents.copy_from_slice(data);
+        }
+        buffer.seal()
+    }
+
+    /// Map a buffer received from the other side.
+    ///
+    /// The buffer is mapped read-only if its contents are sealed, and writable otherwise. Its
+    /// size has to be sealed, since accessing the mapping past the end of a shrunk file would
+    /// crash the process.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let size = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+        if seals & size != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the size of the buffer is not sealed",
+            ));
+        }
+        let len = std::fs::File::from(fd.try_clone()?).metadata()?.len() as usize;
+        let buffer = Self::map(fd, len, seals & libc::F_SEAL_WRITE != 0)?;
+        buffer.shared.store(true, Ordering::Relaxed);
+        Ok(buffer)
+    }
+
+    /// Forbid any further writes to the buffer, by anyone holding its fd.
+    pub fn seal(self) -> io::Result<Self> {
+        if self.sealed {
+            return Ok(self);
+        }
+        // A writable shared mapping would make `F_SEAL_WRITE` fail, so drop ours first.
+        let fd = self.fd.try_clone()?;
+        let len = self.len;
+        drop(self);
+        add_seals(fd.as_fd(), libc::F_SEAL_WRITE | libc::F_SEAL_SEAL)?;
+        Self::map(fd, len, true)
+    }
+
+    /// Send the buffer over a companion socket. The buffer stays usable on this side.
+    pub fn send(&self, socket: &mut impl SocketExt) -> io::Result<()> {
+        self.shared.store(true, Ordering::Relaxed);
+        socket.send_fd(self.fd.as_fd())
+    }
+
+    /// Receive a buffer sent with [Self::send()].
+    pub fn recv(socket: &mut impl SocketExt) -> io::Result<Self> {
+        Self::from_fd(socket.recv_fd()?)
+    }
+
+    /// Whether the contents of the buffer are sealed.
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// The size of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The contents of the buffer, or `None` if the buffer is not sealed.
+    pub fn as_slice(&self) -> Option<&[u8]> {
+        self.sealed
+            .then(|| unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) })
+    }
+
+    /// The contents of the buffer for writing, or `None` if the buffer is sealed or was already
+    /// sent or received, since the other side may then change them concurrently.
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        if self.sealed || self.shared.load(Ordering::Relaxed) {
+            None
+        } else {
+            Some(unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) })
+        }
+    }
+
+    /// Copy the contents of the buffer at `offset` into `buf`.
+    ///
+    /// If the buffer is not sealed, the other side may change the contents while they are
+    /// copied. Panics if the range is out of bounds.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        self.check_range(offset, buf.len());
+        unsafe { ptr::copy_nonoverlapping(self.as_ptr().add(offset), buf.as_mut_ptr(), buf.len()) }
+    }
+
+    /// Copy `data` into the buffer at `offset`.
+    ///
+    /// Fails with [io::ErrorKind::PermissionDenied] if the buffer is sealed. Panics if the range
+    /// is out of bounds.
+    pub fn write_at(&self, offset: usize, data: &[u8]) -> io::Result<()> {
+        if self.sealed {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        self.check_range(offset, data.len());
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.as_ptr().add(offset), data.len()) };
+        Ok(())
+    }
+
+    /// A pointer to the start of the mapping, valid for [Self::len()] bytes as long as the
+    /// buffer lives.
+    ///
+    /// It may only be written to if the buffer is not sealed. Unless the buffer is sealed, the
+    /// other side may access the same memory concurrently, so accesses have to be atomic or
+    /// volatile.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    fn check_range(&self, offset: usize, len: usize) {
+        assert!(
+            offset.checked_add(len).is_some_and(|end| end <= self.len),
+            "range out of bounds of the shared buffer"
+        );
+    }
+
+    fn map(fd: OwnedFd, len: usize, sealed: bool) -> io::Result<Self> {
+        if len == 0 {
+            // `mmap` refuses empty mappings.
+            return Ok(SharedBuffer {
+                fd,
+                ptr: NonNull::dangling(),
+                len,
+                sealed,
+                shared: AtomicBool::new(false),
+            });
+        }
+        let prot = if sealed {
+            libc::PROT_READ
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        };
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SharedBuffer {
+            fd,
+            ptr: NonNull::new(ptr.cast()).unwrap(),
+            len,
+            sealed,
+            shared: AtomicBool::new(false),
+        })
+    }
+}
+
+impl Drop for SharedBuffer {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+        }
+    }
+}
+
+impl AsFd for SharedBuffer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+fn add_seals(fd: BorrowedFd<'_>, seals: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_shared_buffer() {
+    use std::os::unix::net::UnixStream;
+
+    let (mut companion, mut module) = UnixStream::pair().unwrap();
+
+    let mut buffer = SharedBuffer::new(4).unwrap();
+    buffer.as_mut_slice().unwrap().copy_from_slice(b"ping");
+    buffer.send(&mut companion).unwrap();
+    assert!(buffer.as_mut_slice().is_none());
+    let mut received = SharedBuffer::recv(&mut module).unwrap();
+    assert!(!received.is_sealed());
+    assert!(received.as_slice().is_none() && received.as_mut_slice().is_none());
+    received.write_at(1, b"ong").unwrap();
+    let mut contents = [0; 4];
+    buffer.read_at(0, &mut contents);
+    assert_eq!(&contents, b"pong");
+
+    SharedBuffer::from_bytes(b"sealed")
+        .unwrap()
+        .send(&mut companion)
+        .unwrap();
+    let mut received = SharedBuffer::recv(&mut module).unwrap();
+    assert!(received.is_sealed());
+    assert!(received.as_mut_slice().is_none());
+    assert!(received.write_at(0, b"x").is_err());
+    assert_eq!(received.as_slice().unwrap(), b"sealed");
+
+    // A buffer that could shrink under the mapping is refused.
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, c"test".as_ptr(), libc::MFD_CLOEXEC) };
+    assert!(fd >= 0);
+    companion
+        .send_fd(unsafe { BorrowedFd::borrow_raw(fd as _) })
+        .unwrap();
+    unsafe { libc::close(fd as _) };
+    let error = SharedBuffer::recv(&mut module).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
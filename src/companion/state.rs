@@ -0,0 +1,82 @@
+use std::{os::unix::net::UnixStream, sync::OnceLock};
+
+use super::CompanionHandler;
+
+/// State shared by all connections of a companion, lazily initialized once per companion
+/// process and handed to the handler on every request.
+///
+/// The state is shared across concurrent requests, so use interior mutability (e.g. a
+/// [Mutex](std::sync::Mutex)) for anything that needs to change.
+///
+/// ## Example
+///
+/// ```
+/// use std::{collections::HashMap, os::unix::net::UnixStream, sync::Mutex};
+/// use zygisk::{companion::CompanionState, zygisk_companion};
+///
+/// #[derive(Default)]
+/// struct Cache {
+///     entries: Mutex<HashMap<String, Vec<u8>>>,
+/// }
+///
+/// fn handle(cache: &Cache, _stream: UnixStream) {
+///     let _entries = cache.entries.lock().unwrap();
+///     // ...
+/// }
+///
+/// static COMPANION: CompanionState<Cache> = CompanionState::new(Cache::default, handle);
+/// zygisk_companion!(&COMPANION);
+/// ```
+pub struct CompanionState<T> {
+    state: OnceLock<T>,
+    init: fn() -> T,
+    handler: fn(&T, UnixStream),
+}
+
+impl<T> CompanionState<T> {
+    pub const fn new(init: fn() -> T, handler: fn(&T, UnixStream)) -> Self {
+        CompanionState {
+            state: OnceLock::new(),
+            init,
+            handler,
+        }
+    }
+
+    /// Get the state, initializing it if needed.
+    pub fn get(&self) -> &T {
+        self.state.get_or_init(self.init)
+    }
+}
+
+impl<T: Send + Sync> CompanionHandler for CompanionState<T> {
+    fn on_daemon_load(&self) {
+        self.get();
+    }
+
+    fn on_connect(&self, stream: UnixStream) {
+        (self.handler)(self.get(), stream)
+    }
+}
+
+#[test]
+fn test_companion_state_shared() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static INITS: AtomicUsize = AtomicUsize::new(0);
+    fn init() -> AtomicUsize {
+        INITS.fetch_add(1, Ordering::SeqCst);
+        AtomicUsize::new(0)
+    }
+    fn handle(requests: &AtomicUsize, _stream: UnixStream) {
+        requests.fetch_add(1, Ordering::SeqCst);
+    }
+
+    static STATE: CompanionState<AtomicUsize> = CompanionState::new(init, handle);
+    let threads: Vec<_> = (0..4)
+        .map(|_| std::thread::spawn(|| STATE.on_connect(UnixStream::pair().unwrap().0)))
+        .collect();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+
+    assert_eq!(INITS.load(Ordering::SeqCst), 1);
+    assert_eq!(STATE.get().load(Ordering::SeqCst), 4);
+}
@@ -0,0 +1,179 @@
+//! Reporting the version of the root solution or Zygisk daemon to the module.
+//!
+//! The companion runs as root, so it can run `magisk` or read the `module.prop` of the loader
+//! module, which the module itself cannot do after zygote dropped its privileges.
+
+use std::{fs, io, os::unix::net::UnixStream, process::Command};
+
+use super::{handshake::Service, SocketExt};
+use crate::{
+    runtime::{self, Implementation},
+    ZygiskApi, ZygiskError,
+};
+
+/// The version of the Zygisk implementation, as returned by [daemon_version()].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DaemonVersion {
+    /// The implementation, as detected by the companion with
+    /// [runtime::implementation()](crate::runtime::implementation).
+    pub implementation: Implementation,
+
+    /// The version string, such as `27.0:MAGISK:R` for Magisk or the `version` of the loader
+    /// module. Empty if it could not be determined.
+    pub version: String,
+
+    /// The numeric version, such as `27000` for Magisk or the `versionCode` of the loader module.
+    pub version_code: Option<i64>,
+
+    /// The highest Zygisk API version supported by the daemon, when it is known from the version.
+    ///
+    /// This may be newer than [ApiVersion::LATEST](crate::ApiVersion::LATEST); the version
+    /// actually used by the module is [ZygiskApi::api_version()].
+    pub api_level: Option<u32>,
+}
+
+/// Ask the companion for the version of the Zygisk implementation.
+///
+/// This is one of the [built-in services](super#built-in-services).
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{companion, ZygiskApi};
+///
+/// fn daemon_supports_v4(api: &ZygiskApi) -> bool {
+///     companion::daemon_version(api)
+///         .is_ok_and(|version| version.api_level.is_some_and(|level| level >= 4))
+/// }
+/// ```
+pub fn daemon_version(api: &ZygiskApi) -> Result<DaemonVersion, ZygiskError> {
+    let mut stream = api.connect_companion_service(Service::Version)?;
+    recv_version(&mut stream).map_err(ZygiskError::CompanionConnectionFailed)
+}
+
+const IMPLEMENTATIONS: [Implementation; 5] = [
+    Implementation::Unknown,
+    Implementation::Magisk,
+    Implementation::ZygiskNext,
+    Implementation::ReZygisk,
+    Implementation::KernelSu,
+];
+
+fn send_version(stream: &mut UnixStream, version: &DaemonVersion) -> io::Result<()> {
+    let implementation = IMPLEMENTATIONS
+        .iter()
+        .position(|&i| i == version.implementation)
+        .unwrap_or(0);
+    stream.send_u8(implementation as u8)?;
+    stream.send_str(&version.version)?;
+    // Versions are never negative, and API versions start at 1.
+    stream.send_i64(version.version_code.unwrap_or(-1))?;
+    stream.send_u32(version.api_level.unwrap_or(0))
+}
+
+fn recv_version(stream: &mut UnixStream) -> io::Result<DaemonVersion> {
+    let implementation = IMPLEMENTATIONS
+        .get(stream.recv_u8()? as usize)
+        .copied()
+        .unwrap_or(Implementation::Unknown);
+    Ok(DaemonVersion {
+        implementation,
+        version: stream.recv_str()?,
+        version_code: Some(stream.recv_i64()?).filter(|&code| code >= 0),
+        api_level: Some(stream.recv_u32()?).filter(|&level| level != 0),
+    })
+}
+
+pub(crate) fn serve_version(stream: &mut UnixStream) -> io::Result<()> {
+    send_version(stream, &query())
+}
+
+fn query() -> DaemonVersion {
+    let implementation = runtime::implementation();
+    let (version, version_code) = match implementation {
+        Implementation::Magisk => (
+            magisk("-v").unwrap_or_default(),
+            magisk("-V").and_then(|code| code.parse().ok()),
+        ),
+        Implementation::ZygiskNext => module_prop("/data/adb/modules/zygisksu/module.prop"),
+        Implementation::ReZygisk => module_prop("/data/adb/modules/rezygisk/module.prop"),
+        _ => (String::new(), None),
+    };
+    let api_level = match implementation {
+        Implementation::Magisk => version_code.and_then(magisk_api_level),
+        _ => None,
+    };
+    DaemonVersion {
+        implementation,
+        version,
+        version_code,
+        api_level,
+    }
+}
+
+fn magisk(flag: &str) -> Option<String> {
+    let output = Command::new("magisk").arg(flag).output().ok()?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_owned()).filter(|output| !output.is_empty())
+}
+
+/// The `version` and `versionCode` of a module.
+fn module_prop(path: &str) -> (String, Option<i64>) {
+    let prop = fs::read_to_string(path).unwrap_or_default();
+    parse_module_prop(&prop)
+}
+
+fn parse_module_prop(prop: &str) -> (String, Option<i64>) {
+    let mut version = String::new();
+    let mut version_code = None;
+    for line in prop.lines() {
+        match line.split_once('=') {
+            Some(("version", value)) => version = value.trim().to_owned(),
+            Some(("versionCode", value)) => version_code = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+    (version, version_code)
+}
+
+/// The Zygisk API version introduced by each Magisk release.
+fn magisk_api_level(version_code: i64) -> Option<u32> {
+    match version_code {
+        28000.. => Some(5),
+        26000.. => Some(4),
+        24300.. => Some(3),
+        24100.. => Some(2),
+        24000.. => Some(1),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_version() {
+    let (mut module, mut companion) = UnixStream::pair().unwrap();
+    let version = DaemonVersion {
+        implementation: Implementation::ReZygisk,
+        version: "v1.0.0".into(),
+        version_code: Some(400),
+        api_level: None,
+    };
+    send_version(&mut companion, &version).unwrap();
+    assert_eq!(recv_version(&mut module).unwrap(), version);
+
+    serve_version(&mut companion).unwrap();
+    let version = recv_version(&mut module).unwrap();
+    assert_eq!(version.implementation, runtime::implementation());
+
+    let prop = "id=zygisksu\nname=Zygisk Next\nversion=1.2.3 (456-abc)\nversionCode=456\n";
+    assert_eq!(
+        parse_module_prop(prop),
+        ("1.2.3 (456-abc)".to_owned(), Some(456))
+    );
+    assert_eq!(parse_module_prop(""), (String::new(), None));
+
+    assert_eq!(magisk_api_level(28102), Some(5));
+    assert_eq!(magisk_api_level(28000), Some(5));
+    assert_eq!(magisk_api_level(27000), Some(4));
+    assert_eq!(magisk_api_level(24302), Some(3));
+    assert_eq!(magisk_api_level(23000), None);
+}
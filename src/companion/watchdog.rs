@@ -0,0 +1,94 @@
+use std::{
+    io,
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use crate::logcat;
+
+/// What to do when a companion request exceeds its deadline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutAction {
+    /// Abort the whole companion process.
+    Abort,
+    /// Shut the connection down, so that any pending I/O of the handler fails and the module
+    /// sees the connection closed. The handler thread itself keeps running until it returns.
+    DropConnection,
+}
+
+/// A per-request deadline for companion handlers.
+///
+/// Return one from [CompanionHandler::watchdog()](super::CompanionHandler::watchdog) to have the
+/// glue watch every request, or call [Self::watch()] from a plain handler function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchdog {
+    pub timeout: Duration,
+    pub action: TimeoutAction,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, action: TimeoutAction) -> Self {
+        Watchdog { timeout, action }
+    }
+
+    /// Start watching a request served over `stream`. The request is considered done when the
+    /// returned guard is dropped.
+    pub fn watch(&self, stream: &UnixStream) -> io::Result<WatchdogGuard> {
+        let stream = stream.try_clone()?;
+        let (done, timer) = mpsc::channel::<()>();
+        let Watchdog { timeout, action } = *self;
+
+        thread::Builder::new()
+            .name("zygisk-watchdog".into())
+            .spawn(move || {
+                if timer.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                    return;
+                }
+                logcat::write(
+                    logcat::Priority::Error,
+                    &format!("companion request exceeded its deadline of {timeout:?}"),
+                );
+                match action {
+                    TimeoutAction::Abort => std::process::abort(),
+                    TimeoutAction::DropConnection => {
+                        let _ = stream.shutdown(Shutdown::Both);
+                    }
+                }
+            })?;
+
+        Ok(WatchdogGuard { _done: done })
+    }
+}
+
+/// Marks the end of a request watched by [Watchdog::watch()] when dropped.
+pub struct WatchdogGuard {
+    _done: mpsc::Sender<()>,
+}
+
+#[test]
+fn test_watchdog_drops_connection() {
+    use std::io::Read;
+
+    let (mut module, companion) = UnixStream::pair().unwrap();
+    let watchdog = Watchdog::new(Duration::from_millis(50), TimeoutAction::DropConnection);
+
+    let _guard = watchdog.watch(&companion).unwrap();
+    // A handler stuck waiting for data gets unblocked by the shutdown.
+    assert_eq!((&companion).read(&mut [0u8; 1]).unwrap(), 0);
+    assert_eq!(module.read(&mut [0u8; 1]).unwrap(), 0);
+}
+
+#[test]
+fn test_watchdog_finished_in_time() {
+    use std::io::Write;
+
+    let (mut module, companion) = UnixStream::pair().unwrap();
+    let watchdog = Watchdog::new(Duration::from_millis(50), TimeoutAction::DropConnection);
+
+    drop(watchdog.watch(&companion).unwrap());
+    thread::sleep(Duration::from_millis(100));
+    module.write_all(b"still open").unwrap();
+}
@@ -0,0 +1,187 @@
+//! Loading the configuration of a module from a file in its [ModuleDir].
+//!
+//! The format is picked from the extension of the file: `.toml` with the `config-toml` feature,
+//! and `.json` with the `config-json` feature.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use serde::Deserialize;
+//! use zygisk::{config, ZygiskApi};
+//!
+//! #[derive(Deserialize, Default)]
+//! struct Config {
+//!     packages: Vec<String>,
+//! }
+//!
+//! fn load_config(api: &ZygiskApi) -> Config {
+//!     let Ok(dir) = api.module_dir() else {
+//!         return Config::default();
+//!     };
+//!     config::load(&dir, "config.toml").unwrap_or_else(|e| {
+//!         eprintln!("{e}");
+//!         Config::default()
+//!     })
+//! }
+//! ```
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::ModuleDir;
+
+/// An error returned by [load()].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The file does not exist.
+    Missing(PathBuf),
+
+    /// The file could not be read.
+    Io(PathBuf, io::Error),
+
+    /// The file is not valid for the configuration type.
+    Malformed { path: PathBuf, message: String },
+
+    /// The extension of the file is not one of the enabled formats.
+    UnsupportedFormat(PathBuf),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Missing(path) => {
+                write!(f, "{} not found in the module directory", path.display())
+            }
+            ConfigError::Io(path, e) => write!(f, "failed to read {}: {e}", path.display()),
+            ConfigError::Malformed { path, message } => {
+                write!(f, "invalid config in {}: {message}", path.display())
+            }
+            ConfigError::UnsupportedFormat(path) => {
+                write!(f, "unsupported config format for {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Read and parse the configuration file at `path`, relative to the module directory.
+///
+/// Like the module directory itself, this only works in the `pre[XXX]Specialize` callbacks or in
+/// the companion.
+pub fn load<T: DeserializeOwned>(
+    dir: &ModuleDir,
+    path: impl AsRef<Path>,
+) -> Result<T, ConfigError> {
+    let path = path.as_ref();
+    parser::<T>(path).ok_or_else(|| ConfigError::UnsupportedFormat(path.into()))?;
+    let data = dir.read(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => ConfigError::Missing(path.into()),
+        _ => ConfigError::Io(path.into(), e),
+    })?;
+    parse(path, &data)
+}
+
+/// Parse the contents of a configuration file obtained otherwise, such as from a
+/// [ConfigCache](crate::companion::ConfigCache). The format is picked from the extension of
+/// `path`, as for [load()].
+pub fn parse<T: DeserializeOwned>(path: impl AsRef<Path>, data: &[u8]) -> Result<T, ConfigError> {
+    let path = path.as_ref();
+    let parse = parser(path).ok_or_else(|| ConfigError::UnsupportedFormat(path.into()))?;
+    let text = std::str::from_utf8(data).map_err(|e| ConfigError::Malformed {
+        path: path.into(),
+        message: e.to_string(),
+    })?;
+    parse(text).map_err(|message| ConfigError::Malformed {
+        path: path.into(),
+        message,
+    })
+}
+
+/// Like [load()], but returns `T::default()` if the file does not exist.
+pub fn load_or_default<T: DeserializeOwned + Default>(
+    dir: &ModuleDir,
+    path: impl AsRef<Path>,
+) -> Result<T, ConfigError> {
+    match load(dir, path) {
+        Err(ConfigError::Missing(_)) => Ok(T::default()),
+        result => result,
+    }
+}
+
+type Parser<T> = fn(&str) -> Result<T, String>;
+
+fn parser<T: DeserializeOwned>(path: &Path) -> Option<Parser<T>> {
+    match path.extension()?.to_str()? {
+        #[cfg(feature = "config-toml")]
+        "toml" => Some(|text| toml::from_str(text).map_err(|e| e.to_string())),
+        #[cfg(feature = "config-json")]
+        "json" => Some(|text| serde_json::from_str(text).map_err(|e| e.to_string())),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_load() {
+    use std::{fs::File, os::fd::OwnedFd};
+
+    #[derive(serde::Deserialize, Debug, Default, PartialEq)]
+    struct Config {
+        packages: Vec<String>,
+        #[serde(default)]
+        verbose: bool,
+    }
+
+    let root = crate::module_dir::TempDir::new("config");
+    std::fs::write(root.join("config.toml"), "packages = [\"com.example\"]\n").unwrap();
+    std::fs::write(
+        root.join("config.json"),
+        r#"{"packages": [], "verbose": true}"#,
+    )
+    .unwrap();
+    std::fs::write(root.join("bad.toml"), "packages = 1\n").unwrap();
+    let dir = ModuleDir::from(OwnedFd::from(File::open(&root).unwrap()));
+
+    #[cfg(feature = "config-toml")]
+    {
+        let config: Config = load(&dir, "config.toml").unwrap();
+        assert_eq!(config.packages, ["com.example"]);
+        let error = load::<Config>(&dir, "bad.toml").unwrap_err();
+        assert!(matches!(error, ConfigError::Malformed { .. }), "{error}");
+        assert!(error
+            .to_string()
+            .starts_with("invalid config in bad.toml: "));
+    }
+    #[cfg(feature = "config-json")]
+    {
+        assert!(load::<Config>(&dir, "config.json").unwrap().verbose);
+        let config: Config = parse("cached.json", br#"{"packages": ["a"]}"#).unwrap();
+        assert_eq!(config.packages, ["a"]);
+    }
+
+    assert!(matches!(
+        load::<Config>(&dir, "missing.toml"),
+        Err(ConfigError::Missing(_) | ConfigError::UnsupportedFormat(_))
+    ));
+    assert!(matches!(
+        load::<Config>(&dir, "config.ini"),
+        Err(ConfigError::UnsupportedFormat(_))
+    ));
+    #[cfg(feature = "config-toml")]
+    assert_eq!(
+        load_or_default::<Config>(&dir, "missing.toml").unwrap(),
+        Config::default()
+    );
+}
@@ -1,34 +1,207 @@
-/// An opaque type that represents an error originated from Zygisk.
+use std::{io, os::unix::prelude::RawFd};
+
+use crate::{HookFailure, StateFlags};
+
+/// An error originated from Zygisk.
 ///
-/// Since Zygisk does not make use of `errno`, it is not really possible for us
-/// to know the actual cause without using `logcat`. This type only acts as a
-/// reminder to inform the user that `logcat` MAY contain useful information
-/// for diagnostics.
-#[derive(Clone)]
-pub struct ZygiskError;
-
-impl std::fmt::Debug for ZygiskError {
+/// Since Zygisk does not make use of `errno`, it is often not possible for us to know the actual
+/// cause of a failure without using `logcat`. Variants that cannot carry more details act as a
+/// reminder to inform the user that `logcat` MAY contain useful information for diagnostics.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ZygiskError {
+    /// Zygisk failed to connect to the root companion process.
+    CompanionConnectionFailed(io::Error),
+
+    /// The loading Zygisk implementation does not provide this API function, either because it
+    /// is too old or because the function does not exist in the negotiated API version.
+    ApiFunctionUnavailable(&'static str),
+
+    /// Zygisk returned state flags that this crate does not know about, as reported by
+    /// [ZygiskApi::try_get_flags()](crate::ZygiskApi::try_get_flags).
+    UnknownFlags(UnknownFlags),
+
+    /// Zygisk refused to register the module with any of the supported API versions.
+    RegisterModuleRejected,
+
+    /// Zygisk failed to open the root folder of the current module.
+    ModuleDirUnavailable,
+
+    /// Zygisk refused to exempt the file descriptor; it will be closed by zygote.
+    FdNotExempted(RawFd),
+
+    /// Zygisk failed to commit the registered PLT hooks.
+    PltHookCommitFailed,
+
+    /// Some of the registered PLT hooks were not applied by the commit.
+    PltHookFailed(Vec<HookFailure>),
+
+    /// The companion process was built from a different version of the module, usually because
+    /// the module was updated while the daemon kept running the old companion. `companion` is `0`
+    /// if the companion did not answer the handshake in time.
+    CompanionProtocolMismatch { module: u32, companion: u32 },
+
+    /// The companion process failed to carry out a request on behalf of the module.
+    CompanionRequestFailed(io::Error),
+
+    /// The daemon did not accept the connection in time; retrying later may succeed.
+    CompanionBusy(io::Error),
+
+    /// Connecting to the daemon was denied, usually by SELinux.
+    CompanionDenied(io::Error),
+
+    /// An inline hook could not be applied or restored.
+    InlineHookFailed(HookFailure),
+
+    /// The library to hook is not loaded in the current process.
+    LibraryNotFound(String),
+
+    /// The API functions were unloaded by Zygisk after `post[XXX]Specialize`.
+    ApiUnloaded,
+
+    /// The module library cannot be unloaded, since this many hooks are installed and would
+    /// jump into unmapped code.
+    HooksInstalled(usize),
+
+    /// A different callback is already installed for the [libc preset](crate::hooks::libc) of
+    /// this function.
+    HookCallbackInstalled(&'static str),
+}
+
+impl std::fmt::Display for ZygiskError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ZygiskError")
-            .field("_note", &"see logcat for details")
-            .finish()
+        match self {
+            ZygiskError::CompanionConnectionFailed(e) => {
+                write!(f, "failed to connect to the companion process: {e}")
+            }
+            ZygiskError::ApiFunctionUnavailable(name) => {
+                write!(f, "Zygisk API function `{name}` is not available")
+            }
+            ZygiskError::UnknownFlags(flags) => flags.fmt(f),
+            ZygiskError::RegisterModuleRejected => {
+                f.write_str("Zygisk rejected the module registration (see logcat for details)")
+            }
+            ZygiskError::ModuleDirUnavailable => {
+                f.write_str("failed to get the module directory (see logcat for details)")
+            }
+            ZygiskError::FdNotExempted(fd) => {
+                write!(f, "failed to exempt fd {fd} (see logcat for details)")
+            }
+            ZygiskError::PltHookCommitFailed => {
+                f.write_str("failed to commit PLT hooks (see logcat for details)")
+            }
+            ZygiskError::PltHookFailed(failures) => {
+                f.write_str("failed to hook")?;
+                for (i, failure) in failures.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{separator}`{}` in {}", failure.symbol, failure.library)?;
+                }
+                Ok(())
+            }
+            ZygiskError::CompanionProtocolMismatch { module, companion } => write!(
+                f,
+                "companion protocol mismatch: module {module:#010x}, companion {companion:#010x}"
+            ),
+            ZygiskError::CompanionRequestFailed(e) => {
+                write!(f, "the companion process failed to handle the request: {e}")
+            }
+            ZygiskError::CompanionBusy(e) => {
+                write!(f, "the companion process is busy: {e}")
+            }
+            ZygiskError::CompanionDenied(e) => {
+                write!(f, "connecting to the companion process was denied: {e}")
+            }
+            ZygiskError::InlineHookFailed(failure) => write!(
+                f,
+                "failed to inline hook `{}` in {}",
+                failure.symbol, failure.library
+            ),
+            ZygiskError::LibraryNotFound(name) => {
+                write!(f, "library `{name}` is not loaded in the current process")
+            }
+            ZygiskError::ApiUnloaded => {
+                f.write_str("the Zygisk API was unloaded after specialization")
+            }
+            ZygiskError::HooksInstalled(count) => {
+                write!(
+                    f,
+                    "the module cannot be unloaded with {count} hooks installed"
+                )
+            }
+            ZygiskError::HookCallbackInstalled(name) => {
+                write!(f, "a different callback is already installed for `{name}`")
+            }
+        }
     }
 }
 
-impl std::fmt::Display for ZygiskError {
+/// State flags returned by Zygisk that this crate does not know about, usually because Zygisk is
+/// newer than the crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownFlags {
+    /// The known flags among those returned.
+    pub known: StateFlags,
+    /// The bits that do not match any known flag.
+    pub unknown: u32,
+}
+
+impl std::fmt::Display for UnknownFlags {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        const ERROR_MSG: &str = "an error occurred within Zygisk (see logcat for details)";
+        write!(
+            f,
+            "unknown state flags returned by Zygisk: {:#x}",
+            self.unknown
+        )
+    }
+}
+
+impl std::error::Error for UnknownFlags {}
 
-        f.write_str(ERROR_MSG)
+impl From<UnknownFlags> for ZygiskError {
+    fn from(e: UnknownFlags) -> Self {
+        ZygiskError::UnknownFlags(e)
     }
 }
 
-impl std::error::Error for ZygiskError {}
+impl std::error::Error for ZygiskError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ZygiskError::CompanionConnectionFailed(e)
+            | ZygiskError::CompanionRequestFailed(e)
+            | ZygiskError::CompanionBusy(e)
+            | ZygiskError::CompanionDenied(e) => Some(e),
+            ZygiskError::UnknownFlags(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 #[test]
-fn test_debug_fmt() {
+fn test_display_fmt() {
+    assert_eq!(
+        ZygiskError::ApiFunctionUnavailable("exempt_fd").to_string(),
+        "Zygisk API function `exempt_fd` is not available",
+    );
+    assert_eq!(
+        ZygiskError::UnknownFlags(UnknownFlags {
+            known: StateFlags::PROCESS_ON_DENYLIST,
+            unknown: 0x8000_0000,
+        })
+        .to_string(),
+        "unknown state flags returned by Zygisk: 0x80000000",
+    );
     assert_eq!(
-        format!("{:?}", ZygiskError),
-        "ZygiskError { _note: \"see logcat for details\" }",
+        ZygiskError::PltHookFailed(vec![
+            HookFailure {
+                library: "/system/lib64/libc.so".into(),
+                symbol: "open".into(),
+            },
+            HookFailure {
+                library: ".*libart.so$".into(),
+                symbol: "stat".into(),
+            },
+        ])
+        .to_string(),
+        "failed to hook `open` in /system/lib64/libc.so, `stat` in .*libart.so$",
     );
 }
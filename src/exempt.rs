@@ -0,0 +1,155 @@
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    ops::{Deref, DerefMut},
+    os::fd::{AsFd, BorrowedFd, RawFd},
+};
+
+use crate::{libc, ZygiskApi};
+
+/// A file descriptor exempted with [ZygiskApi::exempt_fd()] when wrapped, so that it can be
+/// carried into the specialized app process.
+///
+/// Whether the exemption succeeded is kept in [Self::is_exempted()]; if it did not, zygote will
+/// close the file descriptor during specialization.
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::os::unix::net::UnixStream;
+/// use zygisk::{ExemptedFd, ZygiskApi, ZygiskError};
+///
+/// fn connect(api: &ZygiskApi) -> Result<ExemptedFd<UnixStream>, ZygiskError> {
+///     let stream = ExemptedFd::new(api, api.connect_companion()?);
+///     if !stream.is_exempted() {
+///         // Fall back to reconnecting later, or give up on this process.
+///     }
+///     Ok(stream)
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ExemptedFd<T: AsFd> {
+    inner: T,
+    exempted: bool,
+}
+
+impl<T: AsFd> ExemptedFd<T> {
+    /// Exempt `inner` from being closed by zygote.
+    pub fn new(api: &ZygiskApi, inner: T) -> Self {
+        let exempted = api.exempt_fd(&inner).is_ok();
+        ExemptedFd { inner, exempted }
+    }
+
+    /// Whether the file descriptor survives specialization.
+    pub fn is_exempted(&self) -> bool {
+        self.exempted
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsFd> Deref for ExemptedFd<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: AsFd> DerefMut for ExemptedFd<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: AsFd> AsFd for ExemptedFd<T> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+/// The file descriptors currently open in the process.
+pub(crate) fn open_fds() -> io::Result<BTreeSet<RawFd>> {
+    let mut fds = BTreeSet::new();
+    for entry in fs::read_dir("/proc/self/fd")? {
+        if let Some(fd) = entry?
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            fds.insert(fd);
+        }
+    }
+    // Drop the fd used to read the directory, which is closed by now.
+    fds.retain(|&fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1);
+    Ok(fds)
+}
+
+#[cfg(feature = "debug-fd-audit")]
+static EXEMPTED: std::sync::Mutex<BTreeSet<RawFd>> = std::sync::Mutex::new(BTreeSet::new());
+
+/// Remember a file descriptor exempted with [ZygiskApi::exempt_fd()], for [audit()].
+#[cfg(feature = "debug-fd-audit")]
+pub(crate) fn record_exempted(fd: RawFd) {
+    EXEMPTED.lock().unwrap().insert(fd);
+}
+
+/// Run `f`, and log the file descriptors it opened without exempting them, which zygote is going
+/// to close.
+#[cfg(feature = "debug-fd-audit")]
+pub(crate) fn audit<R>(callback: &str, f: impl FnOnce() -> R) -> R {
+    use crate::logcat;
+
+    let before = open_fds();
+    let result = f();
+    let (Ok(before), Ok(after)) = (before, open_fds()) else {
+        logcat::write(
+            logcat::Priority::Warn,
+            "fd audit: failed to list open file descriptors",
+        );
+        return result;
+    };
+
+    let exempted = EXEMPTED.lock().unwrap();
+    for fd in after
+        .difference(&before)
+        .filter(|fd| !exempted.contains(fd))
+    {
+        let target = fs::read_link(format!("/proc/self/fd/{fd}"))
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| "?".into());
+        logcat::write(
+            logcat::Priority::Warn,
+            &format!(
+                "fd audit: fd {fd} ({target}) opened in {callback} is not exempted, zygote will \
+                 close it"
+            ),
+        );
+    }
+    result
+}
+
+#[test]
+fn test_exempted_fd() {
+    use crate::ApiVersion;
+    use std::os::fd::AsRawFd;
+
+    extern "C" fn exempt_fd(fd: std::os::raw::c_int) -> bool {
+        fd != 2
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::LATEST, |table| {
+        table.exempt_fd = Some(exempt_fd);
+    });
+    assert!(ExemptedFd::new(&api, io::stdout()).is_exempted());
+    assert!(!ExemptedFd::new(&api, io::stderr()).is_exempted());
+
+    let fds = open_fds().unwrap();
+    assert!(fds.contains(&io::stdin().as_raw_fd()));
+    let (file, failed) = api.exempt_scope(|| fs::File::open("/proc/self/maps").unwrap());
+    assert!(failed.is_empty());
+    assert!(!fds.contains(&file.as_raw_fd()));
+    assert!(open_fds().unwrap().contains(&file.as_raw_fd()));
+}
@@ -0,0 +1,246 @@
+//! Checking which symbols a built module exports.
+//!
+//! Zygisk only looks up `zygisk_module_entry` and `zygisk_companion_entry`, which the
+//! [zygisk_module!](crate::zygisk_module) and [zygisk_companion!](crate::zygisk_companion) macros
+//! generate in an anonymous scope. Rust functions are not exported from a `cdylib`, but every
+//! `#[no_mangle]` item of the module and its dependencies is, and shows up in the dynamic symbol
+//! table of the library that is mapped into every app. Such symbols are an easy way to detect
+//! the module, and make the symbol table larger.
+//!
+//! [unexpected_exports()] reads the dynamic symbol table of the built library, so that the
+//! module can check it in a test, without a build script. Modules exporting their entry point
+//! under other names with `aliases = [...]` pass these names along.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use zygisk::exports;
+//!
+//! #[test]
+//! fn only_entry_points_are_exported() {
+//!     let elf = std::fs::read("target/aarch64-linux-android/release/libmymodule.so").unwrap();
+//!     assert_eq!(exports::unexpected_exports(&elf, &[]).unwrap(), Vec::<String>::new());
+//! }
+//! ```
+
+use std::io;
+
+/// The symbols Zygisk looks up in modules.
+pub const ENTRY_POINTS: &[&str] = &["zygisk_module_entry", "zygisk_companion_entry"];
+
+#[cfg(test)]
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+const STB_GNU_UNIQUE: u8 = 10;
+const STV_DEFAULT: u8 = 0;
+const STV_PROTECTED: u8 = 3;
+
+/// List the symbols defined and exported by an ELF shared library, from its `.dynsym` section.
+///
+/// Both 32 and 64-bit little-endian ELF files are supported, which covers every Android ABI.
+pub fn exported_symbols(elf: &[u8]) -> io::Result<Vec<String>> {
+    global_symbols(elf, SHT_DYNSYM)
+}
+
+/// List the symbols exported by a module besides [ENTRY_POINTS] and the `allowed` ones, such as
+/// the `aliases` of [zygisk_module!](crate::zygisk_module).
+pub fn unexpected_exports(elf: &[u8], allowed: &[&str]) -> io::Result<Vec<String>> {
+    let mut exported = exported_symbols(elf)?;
+    exported.retain(|symbol| {
+        !ENTRY_POINTS.contains(&symbol.as_str()) && !allowed.contains(&symbol.as_str())
+    });
+    Ok(exported)
+}
+
+/// List the defined global symbols of the symbol table of type `kind`.
+fn global_symbols(elf: &[u8], kind: u32) -> io::Result<Vec<String>> {
+    let elf = Elf::parse(elf)?;
+    let mut table = None;
+    for index in 0..elf.section_count {
+        let section = elf.section(index)?;
+        if section.kind == kind {
+            table = Some(section);
+            break;
+        }
+    }
+    let table = table.ok_or_else(|| invalid("no symbol table"))?;
+    let strtab = elf.section(table.link as usize)?;
+    let strings = elf.slice(strtab.offset, strtab.size)?;
+    let symbols = elf.slice(table.offset, table.size)?;
+
+    let entry_size = if elf.is_64 { 24 } else { 16 };
+    let mut exported = Vec::new();
+    // The first symbol is always the undefined null symbol.
+    for symbol in symbols.chunks_exact(entry_size).skip(1) {
+        let name = read_u32(symbol, 0)? as usize;
+        let (info, other, shndx) = if elf.is_64 {
+            (symbol[4], symbol[5], read_u16(symbol, 6)?)
+        } else {
+            (symbol[12], symbol[13], read_u16(symbol, 14)?)
+        };
+        let binding = info >> 4;
+        let visibility = other & 0x3;
+        if shndx == 0
+            || !matches!(binding, STB_GLOBAL | STB_WEAK | STB_GNU_UNIQUE)
+            || !matches!(visibility, STV_DEFAULT | STV_PROTECTED)
+        {
+            continue;
+        }
+        let name = strings
+            .get(name..)
+            .and_then(|rest| rest.split(|&b| b == 0).next())
+            .ok_or_else(|| invalid("truncated ELF file"))?;
+        exported.push(String::from_utf8_lossy(name).into_owned());
+    }
+    Ok(exported)
+}
+
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    section_offset: usize,
+    section_size: usize,
+    section_count: usize,
+}
+
+struct Section {
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> io::Result<Elf<'a>> {
+        if data.get(..4) != Some(b"\x7fELF") {
+            return Err(invalid("not an ELF file"));
+        }
+        let is_64 = match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(invalid("unknown ELF class")),
+        };
+        if data.get(5) != Some(&1) {
+            return Err(invalid("big-endian ELF files are not supported"));
+        }
+        let (section_offset, section_size, section_count) = if is_64 {
+            (
+                read_u64(data, 0x28)?,
+                read_u16(data, 0x3a)?,
+                read_u16(data, 0x3c)?,
+            )
+        } else {
+            (
+                read_u32(data, 0x20)? as u64,
+                read_u16(data, 0x2e)?,
+                read_u16(data, 0x30)?,
+            )
+        };
+        Ok(Elf {
+            data,
+            is_64,
+            section_offset: section_offset as usize,
+            section_size: section_size as usize,
+            section_count: section_count as usize,
+        })
+    }
+
+    fn section(&self, index: usize) -> io::Result<Section> {
+        let header = self.slice(
+            self.section_offset + index * self.section_size,
+            self.section_size,
+        )?;
+        let (offset, size, link) = if self.is_64 {
+            (read_u64(header, 0x18)?, read_u64(header, 0x20)?, 0x28)
+        } else {
+            (
+                read_u32(header, 0x10)? as u64,
+                read_u32(header, 0x14)? as u64,
+                0x18,
+            )
+        };
+        Ok(Section {
+            kind: read_u32(header, 0x4)?,
+            offset: offset as usize,
+            size: size as usize,
+            link: read_u32(header, link)?,
+        })
+    }
+
+    fn slice(&self, offset: usize, size: usize) -> io::Result<&'a [u8]> {
+        offset
+            .checked_add(size)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| invalid("truncated ELF file"))
+    }
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> io::Result<[u8; N]> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| invalid("truncated ELF file"))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> io::Result<u16> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> io::Result<u64> {
+    read(data, offset).map(u64::from_le_bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[test]
+fn test_exported_symbols() {
+    let libc = crate::maps::entries()
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.pathname.contains("/libc.so"))
+        .unwrap();
+    let elf = std::fs::read(libc.pathname).unwrap();
+    let exported = exported_symbols(&elf).unwrap();
+    assert!(exported.iter().any(|symbol| symbol == "malloc"));
+    assert_eq!(unexpected_exports(&elf, &[]).unwrap(), exported);
+    assert!(!unexpected_exports(&elf, &["malloc"])
+        .unwrap()
+        .iter()
+        .any(|symbol| symbol == "malloc"));
+
+    let error = exported_symbols(b"not an ELF").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(exported_symbols(&elf[..0x40]).is_err());
+}
+
+#[test]
+fn test_entry_aliases() {
+    use crate::ZygiskModule;
+
+    struct Module;
+    impl ZygiskModule for Module {}
+    static MODULE: Module = Module;
+    crate::zygisk_module!(&MODULE, aliases = ["zygisk_test_entry_alias"]);
+
+    // Test binaries do not export anything, and only keep the symbols that are referenced.
+    extern "C" {
+        fn zygisk_module_entry(table: *const (), env: *mut ());
+        fn zygisk_test_entry_alias(table: *const (), env: *mut ());
+    }
+    std::hint::black_box([
+        zygisk_module_entry as *const (),
+        zygisk_test_entry_alias as *const (),
+    ]);
+    let elf = std::fs::read("/proc/self/exe").unwrap();
+    let symbols = global_symbols(&elf, SHT_SYMTAB).unwrap();
+    for name in ["zygisk_module_entry", "zygisk_test_entry_alias"] {
+        assert!(symbols.iter().any(|symbol| symbol == name), "{name}");
+    }
+}
@@ -0,0 +1,171 @@
+use std::ops::RangeInclusive;
+
+/// The processes a module wants to run in, returned by
+/// [ZygiskModule::targets()](crate::ZygiskModule::targets).
+///
+/// In processes that do not match, none of the `pre`/`post` specialize callbacks of the module
+/// are called, and [ZygiskOption::DlcloseModuleLibrary](crate::ZygiskOption::DlcloseModuleLibrary)
+/// is set so that the module is unloaded after specialization.
+///
+/// An app matches if its process name matches one of the package patterns and its uid is in one
+/// of the uid ranges. No patterns (or no ranges) means any name (or any uid).
+///
+/// ## Example
+///
+/// ```
+/// use zygisk::ProcessFilter;
+///
+/// // All processes of two apps, in any user, but not `system_server`.
+/// let filter = ProcessFilter::apps()
+///     .package("com.android.vending*")
+///     .package("com.google.android.gms*");
+/// assert!(filter.matches_app("com.android.vending:background", 10_123));
+/// assert!(!filter.matches_system_server());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessFilter {
+    apps: bool,
+    packages: Vec<String>,
+    uids: Vec<RangeInclusive<i32>>,
+    system_server: bool,
+}
+
+impl ProcessFilter {
+    /// Match every app and `system_server`.
+    pub fn all() -> Self {
+        ProcessFilter {
+            apps: true,
+            packages: Vec::new(),
+            uids: Vec::new(),
+            system_server: true,
+        }
+    }
+
+    /// Match every app, but not `system_server`.
+    pub fn apps() -> Self {
+        ProcessFilter {
+            system_server: false,
+            ..Self::all()
+        }
+    }
+
+    /// Match `system_server` only.
+    pub fn system_server_only() -> Self {
+        ProcessFilter {
+            apps: false,
+            ..Self::all()
+        }
+    }
+
+    /// Only match apps whose process name matches `pattern`, in addition to the patterns already
+    /// added. `*` matches any sequence of characters and `?` matches a single one, so
+    /// `com.example*` also matches the `com.example:remote` process.
+    pub fn package(mut self, pattern: impl Into<String>) -> Self {
+        self.packages.push(pattern.into());
+        self
+    }
+
+    /// Only match apps whose uid is in `uids`, in addition to the ranges already added. Uids of
+    /// secondary users are offset by [Uid::PER_USER_RANGE](crate::Uid::PER_USER_RANGE) per user.
+    pub fn uids(mut self, uids: RangeInclusive<i32>) -> Self {
+        self.uids.push(uids);
+        self
+    }
+
+    /// Whether to match `system_server`.
+    pub fn system_server(mut self, system_server: bool) -> Self {
+        self.system_server = system_server;
+        self
+    }
+
+    /// Whether an app process with the given name and uid matches.
+    pub fn matches_app(&self, name: &str, uid: i32) -> bool {
+        self.apps
+            && (self.packages.is_empty() || self.packages.iter().any(|p| glob_match(p, name)))
+            && (self.uids.is_empty() || self.uids.iter().any(|range| range.contains(&uid)))
+    }
+
+    /// Whether `system_server` matches.
+    pub fn matches_system_server(&self) -> bool {
+        self.system_server
+    }
+}
+
+impl Default for ProcessFilter {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, skipped)) => {
+                    p = star + 1;
+                    n = skipped + 1;
+                    backtrack = Some((star, skipped + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// What to do with an app process, returned by
+/// [ZygiskModule::pre_app_specialize()](crate::ZygiskModule::pre_app_specialize).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProcessDecision {
+    /// Keep the module loaded and call
+    /// [post_app_specialize](crate::ZygiskModule::post_app_specialize).
+    #[default]
+    Continue,
+
+    /// Do not call `post_app_specialize`, and unload the module after specialization (see
+    /// [ZygiskOption::DlcloseModuleLibrary](crate::ZygiskOption::DlcloseModuleLibrary)).
+    ///
+    /// If hooks were registered through this crate, the module library stays loaded and an error
+    /// is logged instead (see [ZygiskApi::skip_and_unload()](crate::ZygiskApi::skip_and_unload)).
+    SkipAndUnload,
+
+    /// Unmount Magisk and module files from the process like for denylisted apps (see
+    /// [ZygiskOption::ForceDenylistUnmount](crate::ZygiskOption::ForceDenylistUnmount)), and
+    /// keep the module loaded.
+    ForceDenylistUnmount,
+}
+
+#[test]
+fn test_process_filter() {
+    assert!(glob_match("com.example", "com.example"));
+    assert!(!glob_match("com.example", "com.example:remote"));
+    assert!(glob_match("com.example*", "com.example:remote"));
+    assert!(glob_match("*.gms*", "com.google.android.gms.persistent"));
+    assert!(glob_match("com.?xample", "com.example"));
+    assert!(!glob_match("*.gms", "com.google.android.gms.ui"));
+
+    let filter = ProcessFilter::apps()
+        .package("com.example*")
+        .uids(10_000..=19_999);
+    assert!(filter.matches_app("com.example", 10_001));
+    assert!(!filter.matches_app("com.example", 1_000));
+    assert!(!filter.matches_app("com.other", 10_001));
+    assert!(!filter.matches_system_server());
+
+    let filter = ProcessFilter::system_server_only();
+    assert!(!filter.matches_app("com.example", 10_001));
+    assert!(filter.matches_system_server());
+    assert!(ProcessFilter::default().matches_app("anything", 0));
+}
@@ -0,0 +1,991 @@
+//! Higher-level helpers for PLT and JNI hooks.
+//!
+//! ## Inline hooks
+//!
+//! PLT hooks only catch calls that go through the PLT of the hooked library. With the
+//! `inline-hook` feature, [PltHookSession::hook_inline()] patches the target function itself
+//! through [Dobby](https://github.com/jmpews/Dobby), which also catches calls from within the
+//! same library and through pointers that were already resolved. The crate only declares the
+//! functions of Dobby: the module has to link it, usually with
+//! `cargo:rustc-link-lib=static=dobby` in its build script.
+
+use std::ffi::{CStr, CString};
+
+mod backend;
+#[cfg(feature = "inline-hook")]
+mod inline;
+pub mod jni;
+#[cfg(feature = "api-v4")]
+pub mod lazy;
+#[cfg(feature = "api-v4")]
+pub mod libc;
+mod registry;
+
+#[cfg(feature = "api-v4")]
+use backend::custom_backend;
+pub use backend::{backend, set_backend, HookBackend, ZygiskBackend};
+#[cfg(feature = "api-v4")]
+pub(crate) use registry::record_elf;
+pub(crate) use registry::{commit_plt, forget, record, snapshot};
+pub use registry::{HookFailure, HookKind, InstalledHook};
+
+#[cfg(feature = "api-v4")]
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+#[cfg(feature = "api-v4")]
+use crate::{
+    libc::{dev_t, ino_t},
+    maps,
+};
+use crate::{logcat, ZygiskApi, ZygiskError};
+
+/// Look up the `dev` and `inode` pair of a loaded library, as expected by
+/// [PltHookSession::hook()]. See [maps::find_library()] for how `name` is matched.
+#[cfg(feature = "api-v4")]
+pub fn library_id(name: &str) -> Result<(dev_t, ino_t), ZygiskError> {
+    maps::find_library(name)
+        .ok()
+        .and_then(|entries| entries.first().map(|entry| (entry.dev, entry.inode)))
+        .ok_or_else(|| ZygiskError::LibraryNotFound(name.to_owned()))
+}
+
+/// Declare PLT hooks together with typed access to the original functions.
+///
+/// For every hooked symbol, this generates a function to call the original implementation
+/// (named after the `=>` and the hook function), and a function that queues all the hooks on a
+/// [PltHookSession], looking up the libraries in `/proc/self/maps`. Requires the `api-v4`
+/// feature, and fails to compile without it.
+///
+/// The original functions are only available once the session has been committed; calling
+/// them before that panics.
+///
+/// ## Example
+///
+/// ```no_run
+/// # #[cfg(feature = "api-v4")]
+/// # mod example {
+/// use zygisk::{libc::{c_char, c_int}, plt_hook, ZygiskApi};
+///
+/// extern "C" fn my_open(path: *const c_char, flags: c_int) -> c_int {
+///     unsafe { orig_open(path, flags) }
+/// }
+///
+/// plt_hook! {
+///     fn install_hooks;
+///
+///     "libc.so" {
+///         fn open(path: *const c_char, flags: c_int) -> c_int => my_open, orig_open;
+///     }
+/// }
+///
+/// fn pre_app_specialize(api: &ZygiskApi) -> Result<(), zygisk::ZygiskError> {
+///     let mut session = api.plt_hook_session();
+///     install_hooks(&mut session)?;
+///     session.commit()
+/// }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! plt_hook {
+    ($($tokens: tt)*) => {
+        $crate::__plt_hook! { $($tokens)* }
+    };
+}
+
+// The expansion depends on the features of this crate, not of the one invoking `plt_hook!`, so
+// the check for `api-v4` happens by picking one of two definitions.
+#[cfg(not(feature = "api-v4"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plt_hook {
+    ($($tokens: tt)*) => {
+        ::core::compile_error!("`plt_hook!` requires the `api-v4` feature of `zygisk`");
+    };
+}
+
+#[cfg(feature = "api-v4")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plt_hook {
+    (
+        $install_vis: vis fn $install: ident;
+
+        $(
+            $lib: literal {
+                $(
+                    $vis: vis fn $symbol: ident ($($arg: ident : $ty: ty),* $(,)?) $(-> $ret: ty)?
+                        => $hook: path, $orig: ident;
+                )*
+            }
+        )*
+    ) => {
+        $($(
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            mod $orig {
+                pub(super) static ORIGINAL: ::std::sync::atomic::AtomicPtr<()> =
+                    ::std::sync::atomic::AtomicPtr::new(::std::ptr::null_mut());
+            }
+
+            /// Call the original implementation of the hooked function.
+            ///
+            /// ## Safety
+            ///
+            /// Same as the original function.
+            $vis unsafe fn $orig($($arg: $ty),*) $(-> $ret)? {
+                let original = $orig::ORIGINAL.load(::std::sync::atomic::Ordering::Acquire);
+                assert!(
+                    !original.is_null(),
+                    concat!("`", stringify!($symbol), "` is not hooked"),
+                );
+                let original: unsafe extern "C" fn($($ty),*) $(-> $ret)? =
+                    ::std::mem::transmute(original);
+                original($($arg),*)
+            }
+        )*)*
+
+        $install_vis fn $install<'h>(
+            session: &mut $crate::PltHookSession<'_, 'h>,
+        ) -> ::std::result::Result<(), $crate::ZygiskError> {
+            $(
+                let (device, inode) = $crate::hooks::library_id($lib)?;
+                $(
+                    let hook: extern "C" fn($($ty),*) $(-> $ret)? = $hook;
+                    let symbol = concat!(stringify!($symbol), "\0");
+                    // SAFETY: the symbol has no interior NUL, and the signature of the hook has
+                    // been checked above to match the one of the original function.
+                    unsafe {
+                        session.hook_atomic(
+                            device,
+                            inode,
+                            ::std::ffi::CStr::from_bytes_with_nul_unchecked(symbol.as_bytes()),
+                            hook as *mut (),
+                            &$orig::ORIGINAL,
+                        )?;
+                    }
+                )*
+            )*
+            Ok(())
+        }
+    };
+}
+
+/// Where Zygisk saves the original function of a hook.
+enum OriginalSlot<'h> {
+    Borrowed(&'h mut *mut ()),
+    // Used when the caller did not ask for the original function, which is still needed to
+    // restore the hook.
+    Owned(Box<*mut ()>),
+    // Zygisk writes the original function to a plain pointer, which may race with the hook
+    // loading it on another thread, so it is only published to `target` after the commit.
+    #[cfg(feature = "api-v4")]
+    Atomic {
+        slot: Box<*mut ()>,
+        target: &'h AtomicPtr<()>,
+    },
+}
+
+impl OriginalSlot<'_> {
+    fn new(old_func: Option<&mut *mut ()>) -> OriginalSlot<'_> {
+        match old_func {
+            Some(slot) => OriginalSlot::Borrowed(slot),
+            None => OriginalSlot::Owned(Box::new(std::ptr::null_mut())),
+        }
+    }
+
+    fn as_mut(&mut self) -> &mut *mut () {
+        match self {
+            OriginalSlot::Borrowed(slot) => slot,
+            OriginalSlot::Owned(slot) => slot,
+            #[cfg(feature = "api-v4")]
+            OriginalSlot::Atomic { slot, .. } => slot,
+        }
+    }
+
+    fn get(&self) -> *mut () {
+        match self {
+            OriginalSlot::Borrowed(slot) => **slot,
+            OriginalSlot::Owned(slot) => **slot,
+            #[cfg(feature = "api-v4")]
+            OriginalSlot::Atomic { slot, .. } => **slot,
+        }
+    }
+
+    /// Store the original function saved by the commit to the atomic target, if any.
+    fn publish(&self) {
+        #[cfg(feature = "api-v4")]
+        if let OriginalSlot::Atomic { slot, target } = self {
+            if !slot.is_null() {
+                target.store(**slot, Ordering::Release);
+            }
+        }
+    }
+}
+
+enum PendingHook<'h> {
+    #[cfg(feature = "api-v4")]
+    Inode {
+        device: dev_t,
+        inode: ino_t,
+        symbol: CString,
+        new_func: *mut (),
+        old_func: OriginalSlot<'h>,
+    },
+    Regex {
+        regex: CString,
+        symbol: CString,
+        new_func: *mut (),
+        old_func: OriginalSlot<'h>,
+    },
+    Exclude {
+        regex: CString,
+        symbol: CString,
+    },
+    #[cfg(feature = "inline-hook")]
+    Inline {
+        target: *mut (),
+        new_func: *mut (),
+        old_func: OriginalSlot<'h>,
+    },
+}
+
+impl PendingHook<'_> {
+    /// Register the hook, or with `restore`, register the original function back.
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register()].
+    unsafe fn register(
+        &mut self,
+        api: &ZygiskApi,
+        backend: &dyn HookBackend,
+        restore: bool,
+    ) -> Result<(), ZygiskError> {
+        match self {
+            #[cfg(feature = "api-v4")]
+            PendingHook::Inode {
+                device,
+                inode,
+                symbol,
+                new_func,
+                old_func,
+            } => match restore {
+                false => backend.register(
+                    api,
+                    *device,
+                    *inode,
+                    symbol,
+                    *new_func,
+                    Some(old_func.as_mut()),
+                ),
+                true if old_func.get().is_null() => Ok(()),
+                true => backend.register(api, *device, *inode, symbol, old_func.get(), None),
+            },
+            PendingHook::Regex {
+                regex,
+                symbol,
+                new_func,
+                old_func,
+            } => match restore {
+                false => {
+                    backend.register_regex(api, regex, symbol, *new_func, Some(old_func.as_mut()))
+                }
+                true if old_func.get().is_null() => Ok(()),
+                true => backend.register_regex(api, regex, symbol, old_func.get(), None),
+            },
+            PendingHook::Exclude { regex, symbol } => match restore {
+                false => backend.exclude(api, regex, symbol),
+                true => Ok(()),
+            },
+            #[cfg(feature = "inline-hook")]
+            PendingHook::Inline {
+                target,
+                new_func,
+                old_func,
+            } => match restore {
+                false if inline::hook(*target, *new_func, old_func.as_mut()) => {
+                    let described = inline::describe(*target);
+                    record(
+                        InstalledHook {
+                            kind: HookKind::Inline,
+                            library: described.library,
+                            symbol: described.symbol,
+                            address: *new_func as usize,
+                            committed: true,
+                        },
+                        None,
+                    );
+                    Ok(())
+                }
+                true if old_func.get().is_null() || inline::unhook(*target) => Ok(()),
+                _ => Err(ZygiskError::InlineHookFailed(inline::describe(*target))),
+            },
+        }
+    }
+
+    fn original(&self) -> Option<&OriginalSlot<'_>> {
+        match self {
+            #[cfg(feature = "api-v4")]
+            PendingHook::Inode { old_func, .. } => Some(old_func),
+            PendingHook::Regex { old_func, .. } => Some(old_func),
+            #[cfg(feature = "inline-hook")]
+            PendingHook::Inline { old_func, .. } => Some(old_func),
+            PendingHook::Exclude { .. } => None,
+        }
+    }
+
+    fn restored(&self, hook: &InstalledHook) -> bool {
+        let (symbol, new_func, old_func) = match self {
+            #[cfg(feature = "inline-hook")]
+            PendingHook::Inline { new_func, .. } => {
+                return hook.kind == HookKind::Inline && hook.address == *new_func as usize;
+            }
+            #[cfg(feature = "api-v4")]
+            PendingHook::Inode {
+                symbol,
+                new_func,
+                old_func,
+                ..
+            } => (symbol, new_func, old_func),
+            PendingHook::Regex {
+                symbol,
+                new_func,
+                old_func,
+                ..
+            } => (symbol, new_func, old_func),
+            PendingHook::Exclude { .. } => return false,
+        };
+        hook.kind == HookKind::Plt
+            && hook.symbol.as_bytes() == symbol.as_bytes()
+            && (hook.address == *new_func as usize || hook.address == old_func.get() as usize)
+    }
+}
+
+/// A group of PLT hooks that are registered and committed together, created with
+/// [ZygiskApi::plt_hook_session()].
+///
+/// The hooks are registered through a [HookBackend]: the one set with [set_backend()], which
+/// defaults to [ZygiskBackend], or the one passed to [ZygiskApi::plt_hook_session_with()].
+///
+/// Hooks are only handed to the backend by [Self::commit()]. Dropping a session without committing
+/// discards its hooks, so hooks of different logical groups never end up in the same commit.
+///
+/// Committed hooks can be removed again with [Self::restore()], which registers the original
+/// functions back. [Self::commit_guard()] does so automatically once the returned [HookGuard]
+/// is dropped. Both have to happen before Zygisk is unloaded, i.e. within the
+/// `pre[XXX]Specialize`/`post[XXX]Specialize` functions.
+///
+/// ## Example
+///
+/// ```no_run
+/// # #[cfg(feature = "api-v4")]
+/// # mod example {
+/// use zygisk::{libc, ZygiskApi};
+///
+/// static mut OLD_OPEN: *mut () = std::ptr::null_mut();
+///
+/// extern "C" fn my_open(path: *const libc::c_char, flags: libc::c_int) -> libc::c_int {
+///     let orig: extern "C" fn(*const libc::c_char, libc::c_int) -> libc::c_int =
+///         unsafe { std::mem::transmute(OLD_OPEN) };
+///     orig(path, flags)
+/// }
+///
+/// fn install(api: &ZygiskApi, dev: libc::dev_t, inode: libc::ino_t) -> Result<(), zygisk::ZygiskError> {
+///     let mut session = api.plt_hook_session();
+///     unsafe {
+///         session.hook(
+///             dev,
+///             inode,
+///             c"open",
+///             my_open as *mut (),
+///             Some(&mut *std::ptr::addr_of_mut!(OLD_OPEN)),
+///         )?;
+///     }
+///     session.commit()
+/// }
+/// # }
+/// ```
+pub struct PltHookSession<'a, 'h> {
+    api: &'h ZygiskApi<'a>,
+    backend: &'h dyn HookBackend,
+    pending: Vec<PendingHook<'h>>,
+    committed: Vec<PendingHook<'h>>,
+}
+
+impl<'a, 'h> PltHookSession<'a, 'h> {
+    pub(crate) fn new(api: &'h ZygiskApi<'a>, backend: &'h dyn HookBackend) -> Self {
+        PltHookSession {
+            api,
+            backend,
+            pending: Vec::new(),
+            committed: Vec::new(),
+        }
+    }
+
+    /// Queue a hook like [ZygiskApi::plt_hook_register()]. `old_func` is written when the
+    /// session is committed.
+    ///
+    /// Returns `Err` right away if the backend does not support it.
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register()].
+    #[cfg(feature = "api-v4")]
+    pub unsafe fn hook(
+        &mut self,
+        device: dev_t,
+        inode: ino_t,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&'h mut *mut ()>,
+    ) -> Result<&mut Self, ZygiskError> {
+        if !self.backend.supports_register(self.api) {
+            return Err(ZygiskError::ApiFunctionUnavailable("plt_hook_register"));
+        }
+        self.pending.push(PendingHook::Inode {
+            device,
+            inode,
+            symbol: symbol.to_owned(),
+            new_func,
+            old_func: OriginalSlot::new(old_func),
+        });
+        Ok(self)
+    }
+
+    /// Queue a hook like [Self::hook()], storing the original function to `old_func` once the
+    /// session has been committed.
+    ///
+    /// Unlike a `&mut` slot, `old_func` can be loaded by the hook on other threads while it is
+    /// written, and queued again by later sessions. It is left untouched if the hook was not
+    /// applied.
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register()].
+    #[cfg(feature = "api-v4")]
+    pub unsafe fn hook_atomic(
+        &mut self,
+        device: dev_t,
+        inode: ino_t,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: &'h AtomicPtr<()>,
+    ) -> Result<&mut Self, ZygiskError> {
+        if !self.backend.supports_register(self.api) {
+            return Err(ZygiskError::ApiFunctionUnavailable("plt_hook_register"));
+        }
+        self.pending.push(PendingHook::Inode {
+            device,
+            inode,
+            symbol: symbol.to_owned(),
+            new_func,
+            old_func: OriginalSlot::Atomic {
+                slot: Box::new(std::ptr::null_mut()),
+                target: old_func,
+            },
+        });
+        Ok(self)
+    }
+
+    /// Queue a hook like [ZygiskApi::plt_hook_register_regex()]. `old_func` is written when the
+    /// session is committed.
+    ///
+    /// Returns `Err` right away if the backend does not support it.
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register_regex()].
+    pub unsafe fn hook_regex(
+        &mut self,
+        regex: &CStr,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&'h mut *mut ()>,
+    ) -> Result<&mut Self, ZygiskError> {
+        if !self.backend.supports_regex(self.api) {
+            return Err(ZygiskError::ApiFunctionUnavailable(
+                "plt_hook_register_regex",
+            ));
+        }
+        self.pending.push(PendingHook::Regex {
+            regex: regex.to_owned(),
+            symbol: symbol.to_owned(),
+            new_func,
+            old_func: OriginalSlot::new(old_func),
+        });
+        Ok(self)
+    }
+
+    /// Queue an exclusion like [ZygiskApi::plt_hook_exclude()].
+    pub fn exclude(&mut self, regex: &CStr, symbol: &CStr) -> Result<&mut Self, ZygiskError> {
+        if !self.backend.supports_regex(self.api) {
+            return Err(ZygiskError::ApiFunctionUnavailable("plt_hook_exclude"));
+        }
+        self.pending.push(PendingHook::Exclude {
+            regex: regex.to_owned(),
+            symbol: symbol.to_owned(),
+        });
+        Ok(self)
+    }
+
+    /// Queue an inline hook of the function at `target`, through Dobby. `old_func` receives a
+    /// trampoline to the original function when the session is committed.
+    ///
+    /// Inline hooks are applied one by one by [Self::commit()], rather than by the commit of
+    /// Zygisk, and are in effect right away. They are restored along with the PLT hooks of the
+    /// session. Requires the `inline-hook` feature; see [the backend](self#inline-hooks) for
+    /// how to link Dobby.
+    ///
+    /// ## Safety
+    ///
+    /// `target` must be the start of a function with the same signature as `new_func`, and the
+    /// function must not be running while it is patched.
+    #[cfg(feature = "inline-hook")]
+    pub unsafe fn hook_inline(
+        &mut self,
+        target: *mut (),
+        new_func: *mut (),
+        old_func: Option<&'h mut *mut ()>,
+    ) -> Result<&mut Self, ZygiskError> {
+        self.pending.push(PendingHook::Inline {
+            target,
+            new_func,
+            old_func: OriginalSlot::new(old_func),
+        });
+        Ok(self)
+    }
+
+    /// The number of hooks and exclusions queued so far.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether nothing has been queued yet.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Register all queued hooks with the backend and commit them.
+    ///
+    /// With [ZygiskBackend], since the session always knows where the original functions are
+    /// saved, hooks that were not applied are reported individually with
+    /// [ZygiskError::PltHookFailed]. A hook that fails to register does not stop the others from
+    /// being registered and committed; the first such error is returned.
+    ///
+    /// The session can be reused afterwards; hooks queued later go into a new commit.
+    pub fn commit(&mut self) -> Result<(), ZygiskError> {
+        // The backend may hold on to the slots of the registered hooks until it commits, so
+        // everything is registered and committed even if a hook fails, and the registered hooks
+        // are kept along with their slots.
+        let mut result = Ok(());
+        let first = self.committed.len();
+        for mut hook in std::mem::take(&mut self.pending) {
+            // SAFETY: the caller upheld the requirements when queueing the hook.
+            match unsafe { hook.register(self.api, self.backend, false) } {
+                Ok(()) => self.committed.push(hook),
+                Err(e) => result = result.and(Err(e)),
+            }
+        }
+        // Even if some hooks failed, the others are in effect and may have to be restored.
+        let result = result.and(self.backend.commit(self.api));
+        for hook in &self.committed[first..] {
+            if let Some(original) = hook.original() {
+                original.publish();
+            }
+        }
+        result
+    }
+
+    /// Register all queued hooks and commit them, restoring the original functions once the
+    /// returned guard is dropped.
+    pub fn commit_guard(mut self) -> Result<HookGuard<'a, 'h>, ZygiskError> {
+        self.commit()?;
+        Ok(HookGuard {
+            session: Some(self),
+        })
+    }
+
+    /// Undo all hooks committed by this session, by registering the saved original functions
+    /// back and committing.
+    ///
+    /// Hooks whose symbol was not found when they were committed are skipped.
+    pub fn restore(&mut self) -> Result<(), ZygiskError> {
+        if self.committed.is_empty() {
+            return Ok(());
+        }
+        for hook in &mut self.committed {
+            // SAFETY: the original function has the same signature as the hook.
+            unsafe { hook.register(self.api, self.backend, true)? };
+        }
+        self.backend.commit(self.api)?;
+        let committed = std::mem::take(&mut self.committed);
+        registry::forget(|installed| committed.iter().any(|hook| hook.restored(installed)));
+        Ok(())
+    }
+}
+
+/// Committed PLT hooks that are restored when dropped, returned by
+/// [PltHookSession::commit_guard()].
+pub struct HookGuard<'a, 'h> {
+    session: Option<PltHookSession<'a, 'h>>,
+}
+
+impl HookGuard<'_, '_> {
+    /// Restore the original functions now, reporting failures instead of logging them.
+    pub fn restore(mut self) -> Result<(), ZygiskError> {
+        match self.session.take() {
+            Some(mut session) => session.restore(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for HookGuard<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            if let Err(e) = session.restore() {
+                logcat::write(
+                    logcat::Priority::Error,
+                    &format!("failed to restore PLT hooks: {e}"),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "api-v4")]
+#[test]
+fn test_plt_hook_session() {
+    use crate::ApiVersion;
+    use std::{
+        os::raw::c_char,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    static REGISTERED: AtomicUsize = AtomicUsize::new(0);
+    static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+    extern "C" fn register(
+        _dev: dev_t,
+        _inode: ino_t,
+        symbol: *const c_char,
+        new_func: *mut (),
+        old_func: *mut *mut (),
+    ) {
+        REGISTERED.fetch_add(1, Ordering::SeqCst);
+        // Pretend that the original function lives at 0x5678, and that `missing` is not found.
+        let missing = unsafe { CStr::from_ptr(symbol) } == c"missing";
+        if !old_func.is_null() && new_func != 0x5678 as *mut () && !missing {
+            unsafe { *old_func = 0x5678 as *mut () };
+        }
+    }
+    extern "C" fn commit() -> bool {
+        COMMITTED.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V5, |table| {
+        table.plt_hook_register = Some(register);
+        table.plt_hook_commit = Some(commit);
+    });
+    let installed = |api: &ZygiskApi| {
+        api.installed_hooks()
+            .into_iter()
+            .find(|hook| hook.symbol == "zygisk_test_session")
+    };
+
+    let mut old = std::ptr::null_mut();
+    let mut session = api.plt_hook_session();
+    unsafe { session.hook(1, 2, c"zygisk_test_session", 0x1234 as *mut (), None) }.unwrap();
+    assert!(session.exclude(c".*", c"zygisk_test_session").is_err());
+    drop(session);
+    assert_eq!(REGISTERED.load(Ordering::SeqCst), 0);
+
+    let mut session = api.plt_hook_session();
+    unsafe {
+        session.hook(
+            1,
+            2,
+            c"zygisk_test_session",
+            0x1234 as *mut (),
+            Some(&mut old),
+        )
+    }
+    .unwrap();
+    session.commit().unwrap();
+    assert_eq!(REGISTERED.load(Ordering::SeqCst), 1);
+    assert_eq!(COMMITTED.load(Ordering::SeqCst), 1);
+
+    let hook = installed(&api).unwrap();
+    assert_eq!(hook.kind, HookKind::Plt);
+    assert_eq!(hook.library, "1:2");
+    assert_eq!(hook.address, 0x1234);
+    assert!(hook.committed);
+
+    session.restore().unwrap();
+    drop(session);
+    assert_eq!(old, 0x5678 as *mut ());
+    assert_eq!(REGISTERED.load(Ordering::SeqCst), 2);
+    assert_eq!(COMMITTED.load(Ordering::SeqCst), 2);
+    assert!(installed(&api).is_none());
+
+    let mut session = api.plt_hook_session();
+    unsafe { session.hook(1, 2, c"zygisk_test_session", 0x1234 as *mut (), None) }.unwrap();
+    let guard = session.commit_guard().unwrap();
+    assert!(installed(&api).is_some());
+    drop(guard);
+    assert_eq!(REGISTERED.load(Ordering::SeqCst), 4);
+    assert_eq!(COMMITTED.load(Ordering::SeqCst), 4);
+    assert!(installed(&api).is_none());
+
+    static ORIGINAL: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+    let mut session = api.plt_hook_session();
+    unsafe { session.hook_atomic(1, 2, c"zygisk_test_session", 0x1234 as *mut (), &ORIGINAL) }
+        .unwrap();
+    assert!(ORIGINAL.load(Ordering::SeqCst).is_null());
+    session.commit().unwrap();
+    assert_eq!(ORIGINAL.load(Ordering::SeqCst), 0x5678 as *mut ());
+    session.restore().unwrap();
+    drop(session);
+
+    let mut session = api.plt_hook_session();
+    unsafe { session.hook(1, 2, c"missing", 0x1234 as *mut (), None) }.unwrap();
+    match session.commit() {
+        Err(ZygiskError::PltHookFailed(failures)) => {
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].symbol, "missing");
+            assert_eq!(failures[0].library, "1:2");
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+
+    // ELFs that are still mapped are reported by their path.
+    let exe = std::env::current_exe().unwrap();
+    let elf = maps::entries()
+        .unwrap()
+        .into_iter()
+        .find(|entry| exe.to_str() == Some(&entry.pathname))
+        .unwrap();
+    let mut session = api.plt_hook_session();
+    unsafe {
+        session.hook(
+            elf.dev,
+            elf.inode,
+            c"zygisk_test_session",
+            0x1234 as *mut (),
+            None,
+        )
+    }
+    .unwrap();
+    session.commit().unwrap();
+    assert_eq!(installed(&api).unwrap().library, elf.pathname);
+    session.restore().unwrap();
+}
+
+#[cfg(feature = "api-v4")]
+#[test]
+fn test_plt_hook_macro() {
+    use crate::{libc::c_int, ApiVersion};
+
+    extern "C" fn my_abs(value: c_int) -> c_int {
+        unsafe { orig_abs(value) }
+    }
+
+    crate::plt_hook! {
+        fn install_hooks;
+
+        "libdoesnotexist.so" {
+            fn abs(value: c_int) -> c_int => my_abs, orig_abs;
+        }
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V5, |_| {});
+    let mut session = api.plt_hook_session();
+    assert!(matches!(
+        install_hooks(&mut session),
+        Err(ZygiskError::LibraryNotFound(name)) if name == "libdoesnotexist.so"
+    ));
+    assert!(std::panic::catch_unwind(|| unsafe { orig_abs(-1) }).is_err());
+
+    orig_abs::ORIGINAL.store(
+        crate::libc::abs as *mut (),
+        std::sync::atomic::Ordering::Release,
+    );
+    assert_eq!(my_abs(-1), 1);
+}
+
+#[cfg(feature = "inline-hook")]
+#[test]
+fn test_hook_inline() {
+    use crate::ApiVersion;
+
+    extern "C" fn commit() -> bool {
+        true
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V5, |table| {
+        table.plt_hook_commit = Some(commit);
+    });
+    let mut old = std::ptr::null_mut();
+    let mut session = api.plt_hook_session();
+    unsafe {
+        session.hook_inline(
+            crate::libc::abs as *mut (),
+            0x1234 as *mut (),
+            Some(&mut old),
+        )
+    }
+    .unwrap();
+    assert_eq!(session.len(), 1);
+
+    // Host builds have no inline hooking backend.
+    match session.commit() {
+        Err(e @ ZygiskError::InlineHookFailed(_)) => {
+            assert!(
+                e.to_string().starts_with("failed to inline hook `abs` in "),
+                "{e}"
+            );
+        }
+        result => panic!("unexpected result {result:?}"),
+    }
+    drop(session);
+    assert!(old.is_null());
+}
+
+#[cfg(all(feature = "api-v4", feature = "inline-hook"))]
+#[test]
+fn test_hook_inline_with_plt() {
+    use crate::ApiVersion;
+    use std::sync::Mutex;
+
+    // Keeps the slots it was given until the commit, like Zygisk does.
+    #[derive(Default)]
+    struct DeferredBackend {
+        slots: Mutex<Vec<usize>>,
+        restored: Mutex<Vec<usize>>,
+    }
+
+    impl HookBackend for DeferredBackend {
+        unsafe fn register(
+            &self,
+            _api: &ZygiskApi,
+            _device: dev_t,
+            _inode: ino_t,
+            _symbol: &CStr,
+            new_func: *mut (),
+            old_func: Option<&mut *mut ()>,
+        ) -> Result<(), ZygiskError> {
+            match old_func {
+                Some(slot) => self.slots.lock().unwrap().push(slot as *mut _ as usize),
+                None => self.restored.lock().unwrap().push(new_func as usize),
+            }
+            Ok(())
+        }
+
+        fn commit(&self, _api: &ZygiskApi) -> Result<(), ZygiskError> {
+            for slot in self.slots.lock().unwrap().drain(..) {
+                unsafe { *(slot as *mut *mut ()) = 0x5678 as *mut () };
+            }
+            Ok(())
+        }
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V5, |_| {});
+    let backend = DeferredBackend::default();
+    let mut session = api.plt_hook_session_with(&backend);
+    unsafe {
+        session
+            .hook_inline(crate::libc::abs as *mut (), 0x1234 as *mut (), None)
+            .unwrap()
+            .hook(1, 2, c"zygisk_test_mixed", 0x1234 as *mut (), None)
+            .unwrap();
+    }
+
+    // Host builds have no inline hooking backend, but the PLT hook is still committed.
+    assert!(matches!(
+        session.commit(),
+        Err(ZygiskError::InlineHookFailed(_))
+    ));
+    assert!(backend.slots.lock().unwrap().is_empty());
+    session.restore().unwrap();
+    assert_eq!(*backend.restored.lock().unwrap(), [0x5678]);
+}
+
+#[cfg(feature = "api-v4")]
+#[test]
+fn test_hook_backend() {
+    use crate::ApiVersion;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingBackend {
+        registered: Mutex<Vec<(String, usize)>>,
+        commits: Mutex<usize>,
+    }
+
+    impl HookBackend for RecordingBackend {
+        unsafe fn register(
+            &self,
+            _api: &ZygiskApi,
+            _device: dev_t,
+            _inode: ino_t,
+            symbol: &CStr,
+            new_func: *mut (),
+            old_func: Option<&mut *mut ()>,
+        ) -> Result<(), ZygiskError> {
+            let symbol = symbol.to_string_lossy().into_owned();
+            self.registered
+                .lock()
+                .unwrap()
+                .push((symbol, new_func as usize));
+            if let Some(old_func) = old_func {
+                *old_func = 0x5678 as *mut ();
+            }
+            Ok(())
+        }
+
+        fn commit(&self, _api: &ZygiskApi) -> Result<(), ZygiskError> {
+            *self.commits.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    // The table has no PLT hook functions at all.
+    let api = ZygiskApi::mock(ApiVersion::V5, |_| {});
+    assert!(unsafe {
+        api.plt_hook_session()
+            .hook(1, 2, c"zygisk_test_backend", 0x1234 as *mut (), None)
+    }
+    .is_err());
+
+    let backend = RecordingBackend::default();
+    let mut old = std::ptr::null_mut();
+    let mut session = api.plt_hook_session_with(&backend);
+    unsafe {
+        session.hook(
+            1,
+            2,
+            c"zygisk_test_backend",
+            0x1234 as *mut (),
+            Some(&mut old),
+        )
+    }
+    .unwrap();
+    assert!(session.exclude(c".*", c"zygisk_test_backend").is_err());
+    session.commit().unwrap();
+    session.restore().unwrap();
+    drop(session);
+
+    assert_eq!(old, 0x5678 as *mut ());
+    assert_eq!(
+        *backend.registered.lock().unwrap(),
+        [
+            ("zygisk_test_backend".to_owned(), 0x1234),
+            ("zygisk_test_backend".to_owned(), 0x5678)
+        ]
+    );
+    assert_eq!(*backend.commits.lock().unwrap(), 2);
+    assert!(api
+        .installed_hooks()
+        .iter()
+        .all(|hook| hook.symbol != "zygisk_test_backend"));
+}
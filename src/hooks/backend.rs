@@ -0,0 +1,194 @@
+use std::{ffi::CStr, sync::OnceLock};
+
+use crate::{
+    libc::{dev_t, ino_t},
+    ZygiskApi, ZygiskError,
+};
+
+/// The implementation of the PLT hooks of a [PltHookSession](super::PltHookSession) and of
+/// [lazy](super::lazy) hooks.
+///
+/// [ZygiskBackend] forwards to the Zygisk API and is used unless another backend was installed
+/// with [set_backend()]. Other backends, such as bytehook or xhook, can be plugged in to hook
+/// with Zygisk implementations that lack `plt_hook_register`, or to get their own features,
+/// without changing the code that queues the hooks.
+///
+/// Only hooks of [ZygiskBackend] are listed by
+/// [ZygiskApi::installed_hooks()](crate::ZygiskApi::installed_hooks).
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::ffi::CStr;
+/// use zygisk::{hooks::{self, HookBackend}, libc::{dev_t, ino_t}, ZygiskApi, ZygiskError};
+///
+/// struct ByteHook;
+///
+/// impl HookBackend for ByteHook {
+///     unsafe fn register(
+///         &self,
+///         _api: &ZygiskApi,
+///         device: dev_t,
+///         inode: ino_t,
+///         symbol: &CStr,
+///         new_func: *mut (),
+///         old_func: Option<&mut *mut ()>,
+///     ) -> Result<(), ZygiskError> {
+///         // Call into bytehook here.
+///         Ok(())
+///     }
+/// }
+///
+/// static BYTEHOOK: ByteHook = ByteHook;
+///
+/// fn on_load() {
+///     let _ = hooks::set_backend(&BYTEHOOK);
+/// }
+/// ```
+pub trait HookBackend: Send + Sync {
+    /// Whether [Self::register()] is usable.
+    fn supports_register(&self, api: &ZygiskApi) -> bool {
+        let _ = api;
+        true
+    }
+
+    /// Whether [Self::register_regex()] and [Self::exclude()] are usable.
+    fn supports_regex(&self, api: &ZygiskApi) -> bool {
+        let _ = api;
+        false
+    }
+
+    /// Hook `symbol` in the ELF identified by `device` and `inode`, like
+    /// [ZygiskApi::plt_hook_register()](crate::ZygiskApi::plt_hook_register).
+    ///
+    /// The hook may only take effect in [Self::commit()], but `old_func` must be written by then.
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register()](crate::ZygiskApi::plt_hook_register).
+    unsafe fn register(
+        &self,
+        api: &ZygiskApi,
+        device: dev_t,
+        inode: ino_t,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&mut *mut ()>,
+    ) -> Result<(), ZygiskError>;
+
+    /// Hook `symbol` in the ELFs whose path matches `regex`, like
+    /// [ZygiskApi::plt_hook_register_regex()].
+    ///
+    /// ## Safety
+    ///
+    /// See [ZygiskApi::plt_hook_register_regex()].
+    unsafe fn register_regex(
+        &self,
+        api: &ZygiskApi,
+        regex: &CStr,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&mut *mut ()>,
+    ) -> Result<(), ZygiskError> {
+        let _ = (api, regex, symbol, new_func, old_func);
+        Err(ZygiskError::ApiFunctionUnavailable(
+            "plt_hook_register_regex",
+        ))
+    }
+
+    /// Exclude the ELFs whose path matches `regex` from the hooks of `symbol`, like
+    /// [ZygiskApi::plt_hook_exclude()].
+    fn exclude(&self, api: &ZygiskApi, regex: &CStr, symbol: &CStr) -> Result<(), ZygiskError> {
+        let _ = (api, regex, symbol);
+        Err(ZygiskError::ApiFunctionUnavailable("plt_hook_exclude"))
+    }
+
+    /// Apply the hooks registered so far.
+    fn commit(&self, api: &ZygiskApi) -> Result<(), ZygiskError> {
+        let _ = api;
+        Ok(())
+    }
+}
+
+/// The PLT hooks of Zygisk itself, the default [HookBackend].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZygiskBackend;
+
+impl HookBackend for ZygiskBackend {
+    fn supports_register(&self, api: &ZygiskApi) -> bool {
+        #[cfg(feature = "api-v4")]
+        return api.supports_plt_hook_register();
+        #[cfg(not(feature = "api-v4"))]
+        {
+            let _ = api;
+            false
+        }
+    }
+
+    fn supports_regex(&self, api: &ZygiskApi) -> bool {
+        api.supports_plt_hook_regex()
+    }
+
+    unsafe fn register(
+        &self,
+        api: &ZygiskApi,
+        device: dev_t,
+        inode: ino_t,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&mut *mut ()>,
+    ) -> Result<(), ZygiskError> {
+        #[cfg(feature = "api-v4")]
+        return api.plt_hook_register(device, inode, symbol, new_func, old_func);
+        #[cfg(not(feature = "api-v4"))]
+        {
+            let _ = (api, device, inode, symbol, new_func, old_func);
+            Err(ZygiskError::ApiFunctionUnavailable("plt_hook_register"))
+        }
+    }
+
+    unsafe fn register_regex(
+        &self,
+        api: &ZygiskApi,
+        regex: &CStr,
+        symbol: &CStr,
+        new_func: *mut (),
+        old_func: Option<&mut *mut ()>,
+    ) -> Result<(), ZygiskError> {
+        api.plt_hook_register_regex(regex, symbol, new_func, old_func)
+    }
+
+    fn exclude(&self, api: &ZygiskApi, regex: &CStr, symbol: &CStr) -> Result<(), ZygiskError> {
+        api.plt_hook_exclude(regex, symbol)
+    }
+
+    fn commit(&self, api: &ZygiskApi) -> Result<(), ZygiskError> {
+        api.plt_hook_commit()
+    }
+}
+
+static BACKEND: OnceLock<&'static dyn HookBackend> = OnceLock::new();
+
+/// Use `backend` for all PLT hooks from now on, instead of [ZygiskBackend].
+///
+/// The backend can only be set once, usually in `on_load`. If one was already set, it is
+/// returned as the error.
+pub fn set_backend(backend: &'static dyn HookBackend) -> Result<(), &'static dyn HookBackend> {
+    let mut installed = Some(backend);
+    let current = *BACKEND.get_or_init(|| installed.take().unwrap());
+    match installed {
+        None => Ok(()),
+        Some(_) => Err(current),
+    }
+}
+
+/// The backend set with [set_backend()], if any.
+#[cfg(feature = "api-v4")]
+pub(crate) fn custom_backend() -> Option<&'static dyn HookBackend> {
+    BACKEND.get().copied()
+}
+
+/// The backend used for PLT hooks: the one set with [set_backend()], or [ZygiskBackend].
+pub fn backend() -> &'static dyn HookBackend {
+    BACKEND.get().copied().unwrap_or(&ZygiskBackend)
+}
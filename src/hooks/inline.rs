@@ -0,0 +1,86 @@
+//! Inline hooks through Dobby, see [the parent module](super#inline-hooks).
+
+use std::ffi::CStr;
+
+use crate::{libc, maps, HookFailure};
+
+#[cfg(target_os = "android")]
+mod dobby {
+    use std::os::raw::{c_int, c_void};
+
+    extern "C" {
+        pub fn DobbyHook(
+            address: *mut c_void,
+            replace: *mut c_void,
+            origin: *mut *mut c_void,
+        ) -> c_int;
+        pub fn DobbyDestroy(address: *mut c_void) -> c_int;
+    }
+}
+
+/// Patch `target` to jump to `new_func`, saving a trampoline to the original in `old_func`.
+///
+/// ## Safety
+///
+/// `target` must be the start of a function with the same signature as `new_func`.
+#[cfg(target_os = "android")]
+pub(crate) unsafe fn hook(target: *mut (), new_func: *mut (), old_func: &mut *mut ()) -> bool {
+    let old_func = (old_func as *mut *mut ()).cast();
+    dobby::DobbyHook(target.cast(), new_func.cast(), old_func) == 0
+}
+
+/// Undo [hook()].
+#[cfg(target_os = "android")]
+pub(crate) unsafe fn unhook(target: *mut ()) -> bool {
+    dobby::DobbyDestroy(target.cast()) == 0
+}
+
+// Dobby is only linked into Android modules, so host builds cannot hook.
+#[cfg(not(target_os = "android"))]
+pub(crate) unsafe fn hook(_target: *mut (), _new_func: *mut (), _old_func: &mut *mut ()) -> bool {
+    false
+}
+
+#[cfg(not(target_os = "android"))]
+pub(crate) unsafe fn unhook(_target: *mut ()) -> bool {
+    false
+}
+
+/// The library and symbol of a hooked address, for [InstalledHook](crate::InstalledHook) and
+/// [HookFailure].
+pub(crate) fn describe(target: *mut ()) -> HookFailure {
+    let address = target as usize;
+    let library = maps::entries()
+        .ok()
+        .and_then(|entries| {
+            entries
+                .into_iter()
+                .find(|entry| (entry.start..entry.end).contains(&address))
+        })
+        .map(|entry| entry.pathname)
+        .filter(|pathname| !pathname.is_empty())
+        .unwrap_or_else(|| "[anonymous]".into());
+
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    let found = unsafe { libc::dladdr(target.cast_const().cast(), &mut info) } != 0;
+    let symbol = if found && !info.dli_sname.is_null() && info.dli_saddr as usize == address {
+        unsafe { CStr::from_ptr(info.dli_sname) }
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        format!("{address:#x}")
+    };
+    HookFailure { library, symbol }
+}
+
+#[test]
+fn test_describe() {
+    let target = crate::libc::abs as *mut ();
+    let described = describe(target);
+    assert_eq!(described.symbol, "abs");
+    assert!(described.library.contains("libc"), "{described:?}");
+
+    let anonymous = describe(0x10 as *mut ());
+    assert_eq!(anonymous.library, "[anonymous]");
+    assert_eq!(anonymous.symbol, "0x10");
+}
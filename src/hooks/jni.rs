@@ -0,0 +1,268 @@
+//! Helpers for hooking JNI native methods.
+
+use std::{
+    ffi::{c_void, CStr},
+    sync::Mutex,
+};
+
+use crate::{
+    jni::{strings::JNIStr, sys::JNINativeMethod},
+    ZygiskError,
+};
+
+/// A method hooked by
+/// [ZygiskApi::hook_jni_native_methods_checked()](crate::ZygiskApi::hook_jni_native_methods_checked).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OriginalMethod {
+    pub name: String,
+    pub signature: String,
+    /// The original function, or null if no method matched the name and signature.
+    pub original: *mut c_void,
+}
+
+impl OriginalMethod {
+    /// Whether the method was found and hooked.
+    pub fn is_hooked(&self) -> bool {
+        !self.original.is_null()
+    }
+}
+
+/// An error returned by
+/// [ZygiskApi::hook_jni_native_methods_checked()](crate::ZygiskApi::hook_jni_native_methods_checked).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum JniHookError {
+    /// The hook could not be attempted.
+    Zygisk(ZygiskError),
+
+    /// Some methods did not match any native method of the class. All methods are listed; the
+    /// ones that matched are hooked nevertheless.
+    Unmatched(Vec<OriginalMethod>),
+}
+
+impl std::fmt::Display for JniHookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JniHookError::Zygisk(e) => e.fmt(f),
+            JniHookError::Unmatched(methods) => {
+                f.write_str("no native method found for")?;
+                let unmatched = methods.iter().filter(|method| !method.is_hooked());
+                for (i, method) in unmatched.enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{separator}`{}{}`", method.name, method.signature)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for JniHookError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JniHookError::Zygisk(e) => Some(e),
+            JniHookError::Unmatched(_) => None,
+        }
+    }
+}
+
+impl From<ZygiskError> for JniHookError {
+    fn from(e: ZygiskError) -> Self {
+        JniHookError::Zygisk(e)
+    }
+}
+
+struct SavedMethod {
+    class: String,
+    name: String,
+    signature: String,
+    original: usize,
+}
+
+// The original functions of all hooked methods, for
+// [ZygiskApi::restore_jni_native_methods()](crate::ZygiskApi::restore_jni_native_methods).
+static SAVED: Mutex<Vec<SavedMethod>> = Mutex::new(Vec::new());
+
+/// Remember the original function of a hooked method. Hooking a method again keeps the first
+/// original, which is the one that has to be restored.
+pub(crate) fn save_original(class: &str, name: &str, signature: &str, original: *mut c_void) {
+    let mut saved = SAVED.lock().unwrap();
+    let known = saved
+        .iter()
+        .any(|m| m.class == class && m.name == name && m.signature == signature);
+    if !known {
+        saved.push(SavedMethod {
+            class: class.to_owned(),
+            name: name.to_owned(),
+            signature: signature.to_owned(),
+            original: original as usize,
+        });
+    }
+}
+
+/// Forget the original function of a hooked method and return it.
+pub(crate) fn take_original(class: &str, name: &str, signature: &str) -> Option<*mut c_void> {
+    let mut saved = SAVED.lock().unwrap();
+    let index = saved
+        .iter()
+        .position(|m| m.class == class && m.name == name && m.signature == signature)?;
+    Some(saved.remove(index).original as *mut c_void)
+}
+
+/// A Java class and the native methods to hook in it, built with [jni_method_table!].
+///
+/// After [ZygiskApi::hook_jni_method_table()](crate::ZygiskApi::hook_jni_method_table), each
+/// method holds the original function, or null if the method was not found.
+pub struct JniMethodTable<const N: usize> {
+    class: &'static CStr,
+    methods: [JNINativeMethod; N],
+}
+
+impl<const N: usize> JniMethodTable<N> {
+    #[doc(hidden)]
+    pub const fn new(class: &'static CStr, methods: [JNINativeMethod; N]) -> Self {
+        JniMethodTable { class, methods }
+    }
+
+    /// The class name, such as `com/android/internal/os/Zygote`.
+    pub fn class(&self) -> &'static JNIStr {
+        // SAFETY: the pointer comes from a valid C string.
+        unsafe { JNIStr::from_ptr(self.class.as_ptr()) }
+    }
+
+    /// The methods, ready for [ZygiskApi::hook_jni_native_methods()](crate::ZygiskApi::hook_jni_native_methods).
+    pub fn methods_mut(&mut self) -> &mut [JNINativeMethod] {
+        &mut self.methods
+    }
+
+    /// The methods of the table.
+    pub fn methods(&self) -> &[JNINativeMethod] {
+        &self.methods
+    }
+}
+
+/// Check the shape of a JNI method signature, such as `(ILjava/lang/String;)V`, and return the
+/// number of parameters. Panics on malformed signatures, which fails the build when evaluated
+/// in a constant.
+pub const fn signature_params(signature: &str) -> usize {
+    let bytes = signature.as_bytes();
+    if bytes.is_empty() || bytes[0] != b'(' {
+        panic!("JNI signatures must start with `(`");
+    }
+    let mut i = 1;
+    let mut params = 0;
+    while i < bytes.len() && bytes[i] != b')' {
+        i = skip_type(bytes, i);
+        params += 1;
+    }
+    if i >= bytes.len() {
+        panic!("JNI signatures must close the parameter list with `)`");
+    }
+    i += 1;
+    i = if i < bytes.len() && bytes[i] == b'V' {
+        i + 1
+    } else {
+        skip_type(bytes, i)
+    };
+    if i != bytes.len() {
+        panic!("trailing characters after the return type of a JNI signature");
+    }
+    params
+}
+
+/// Skip one type descriptor starting at `i`, returning the index after it.
+const fn skip_type(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && bytes[i] == b'[' {
+        i += 1;
+    }
+    if i >= bytes.len() {
+        panic!("missing type in JNI signature");
+    }
+    match bytes[i] {
+        b'Z' | b'B' | b'C' | b'S' | b'I' | b'J' | b'F' | b'D' => i + 1,
+        b'L' => {
+            let start = i + 1;
+            i = start;
+            while i < bytes.len() && bytes[i] != b';' {
+                i += 1;
+            }
+            if i >= bytes.len() || i == start {
+                panic!("class types in JNI signatures must look like `Lpackage/Class;`");
+            }
+            i + 1
+        }
+        _ => panic!("invalid type in JNI signature"),
+    }
+}
+
+/// Build a [JniMethodTable](crate::hooks::jni::JniMethodTable) of native methods to hook.
+///
+/// Each entry maps a method name and its JNI signature to a replacement function, which has to
+/// be an `extern "system"` (or `extern "C"`) function taking a `JNIEnv` pointer and the object
+/// or class, followed by the parameters of the method. The syntax of the signatures is checked at
+/// compile time, but not whether the replacement function takes the parameters they describe.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{jni::{sys::{jclass, jint}, JNIEnv}, jni_method_table, ZygiskApi};
+///
+/// extern "system" fn my_get_pid(env: *mut zygisk::jni::sys::JNIEnv, class: jclass) -> jint {
+///     0
+/// }
+///
+/// fn hook(api: &ZygiskApi, env: JNIEnv) -> Result<(), zygisk::ZygiskError> {
+///     let mut table = jni_method_table! {
+///         "android/os/Process" {
+///             "myPid", "()I" => my_get_pid;
+///         }
+///     };
+///     unsafe { api.hook_jni_method_table(env, &mut table) }
+/// }
+/// ```
+///
+/// Malformed signatures are rejected:
+///
+/// ```compile_fail
+/// # use zygisk::{jni::sys::{jclass, jint}, jni_method_table};
+/// # extern "system" fn my_get_pid(env: *mut zygisk::jni::sys::JNIEnv, class: jclass) -> jint { 0 }
+/// let table = jni_method_table! {
+///     "android/os/Process" {
+///         "myPid", "()Ljava/lang/String" => my_get_pid;
+///     }
+/// };
+/// ```
+#[macro_export]
+macro_rules! jni_method_table {
+    ($class: literal { $($name: literal, $signature: literal => $func: path;)* }) => {
+        $crate::hooks::jni::JniMethodTable::new(
+            match ::std::ffi::CStr::from_bytes_with_nul(concat!($class, "\0").as_bytes()) {
+                Ok(class) => class,
+                Err(_) => panic!("class names cannot contain NUL"),
+            },
+            [$({
+                const _: usize = $crate::hooks::jni::signature_params($signature);
+                $crate::jni::sys::JNINativeMethod {
+                    name: concat!($name, "\0").as_ptr() as *mut _,
+                    signature: concat!($signature, "\0").as_ptr() as *mut _,
+                    fnPtr: $func as *mut ::std::ffi::c_void,
+                }
+            }),*],
+        )
+    };
+}
+
+#[test]
+fn test_signature_params() {
+    assert_eq!(signature_params("()V"), 0);
+    assert_eq!(signature_params("(I)I"), 1);
+    assert_eq!(
+        signature_params("(II[II[[IILjava/lang/String;Ljava/lang/String;[IZ)I"),
+        10
+    );
+    assert_eq!(signature_params("([Ljava/lang/String;J)[B"), 2);
+    assert!(std::panic::catch_unwind(|| signature_params("(I")).is_err());
+    assert!(std::panic::catch_unwind(|| signature_params("(L;)V")).is_err());
+    assert!(std::panic::catch_unwind(|| signature_params("(I)")).is_err());
+    assert!(std::panic::catch_unwind(|| signature_params("(Q)V")).is_err());
+}
@@ -0,0 +1,631 @@
+//! Hooks for libraries that are loaded after specialization.
+//!
+//! Apps, and games in particular, often load their native libraries long after
+//! `post_app_specialize`, so the libraries to hook are not mapped yet when the module runs.
+//! Hooks queued with [register()] are kept pending until [install()] finds the library mapped,
+//! either right away or after a later call to `dlopen` or `android_dlopen_ext`.
+//!
+//! Zygisk unloads its API table after `post[XXX]Specialize`, so from then on, hooks can only be
+//! registered through a backend set with [set_backend()](super::set_backend). Without one, the
+//! hooks still pending at that point are logged and dropped.
+
+use std::{
+    collections::BTreeSet,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int, c_void},
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use super::HookBackend;
+use crate::{
+    binding::RawApiTable,
+    libc::{dev_t, ino_t},
+    logcat,
+    maps::{self, MapEntry},
+    RetainedApi, ZygiskApi, ZygiskError,
+};
+
+struct PendingHook {
+    library: String,
+    symbol: CString,
+    new_func: *mut (),
+    old_func: &'static AtomicPtr<()>,
+}
+
+struct State {
+    pending: Vec<PendingHook>,
+    // ELFs whose `dlopen` calls are already intercepted. Hooking them again would make Zygisk
+    // save our own wrapper as the original function.
+    watched: BTreeSet<(dev_t, ino_t)>,
+}
+
+// SAFETY: the function pointers are only handed to Zygisk, never dereferenced by us.
+unsafe impl Send for State {}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    pending: Vec::new(),
+    watched: BTreeSet::new(),
+});
+
+static API: OnceLock<RetainedApi> = OnceLock::new();
+
+static ORIG_DLOPEN: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+static ORIG_ANDROID_DLOPEN_EXT: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Queue a PLT hook of `symbol` in the library `name`, to be registered as soon as the library
+/// is mapped. See [maps::find_library()] for how `name` is matched.
+///
+/// `old_func` receives the original function once the hook is committed.
+///
+/// ## Safety
+///
+/// See [ZygiskApi::plt_hook_register()].
+pub unsafe fn register(
+    name: &str,
+    symbol: &CStr,
+    new_func: *mut (),
+    old_func: &'static AtomicPtr<()>,
+) {
+    STATE.lock().unwrap().pending.push(PendingHook {
+        library: name.to_owned(),
+        symbol: symbol.to_owned(),
+        new_func,
+        old_func,
+    });
+    mapped();
+}
+
+/// Start intercepting `dlopen` and `android_dlopen_ext`, and register the pending hooks of
+/// libraries that are already mapped. The hooks are registered through [super::backend()].
+///
+/// Call this once, usually from `post_app_specialize`. Hooks are registered through `api` for
+/// as long as it is loaded; see [the module documentation](self) for what happens afterwards.
+///
+/// ## Limitations
+///
+/// The linker picks the namespace to search for a library from the address `dlopen` was called
+/// from. The hooks pass on the address of their own caller through `__loader_dlopen` and
+/// `__loader_android_dlopen_ext`, so libraries are looked up as if `dlopen` was not intercepted.
+/// Before Android 8, which lacks these functions, and on architectures other than ARM and x86,
+/// calls are forwarded to the original functions instead, and plain `dlopen` calls are looked
+/// up in the namespace of the module rather than the one of the calling library.
+pub fn install(api: RetainedApi) -> Result<(), ZygiskError> {
+    if !api.with(|api| super::backend().supports_register(api))? {
+        return Err(ZygiskError::ApiFunctionUnavailable("plt_hook_register"));
+    }
+    API.get_or_init(|| api);
+    mapped();
+    Ok(())
+}
+
+// The linker picks the namespace of a library from the address `dlopen` is called from, which
+// has to be the caller of the hooks rather than the hooks themselves. Only assembly can read the
+// return address, so the hooks are shims adding it as an extra argument to the functions below.
+mod shim {
+    #[cfg(any(
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    ))]
+    use std::arch::naked_asm;
+    use std::os::raw::{c_char, c_int, c_void};
+
+    use super::{android_dlopen_ext_from, dlopen_from};
+
+    #[cfg(target_arch = "aarch64")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn dlopen_hook(_filename: *const c_char, _flags: c_int) -> *mut c_void {
+        naked_asm!("mov x2, x30", "b {from}", from = sym dlopen_from)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn android_dlopen_ext_hook(
+        _filename: *const c_char,
+        _flags: c_int,
+        _extinfo: *const c_void,
+    ) -> *mut c_void {
+        naked_asm!("mov x3, x30", "b {from}", from = sym android_dlopen_ext_from)
+    }
+
+    #[cfg(target_arch = "arm")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn dlopen_hook(_filename: *const c_char, _flags: c_int) -> *mut c_void {
+        naked_asm!("mov r2, lr", "b {from}", from = sym dlopen_from)
+    }
+
+    #[cfg(target_arch = "arm")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn android_dlopen_ext_hook(
+        _filename: *const c_char,
+        _flags: c_int,
+        _extinfo: *const c_void,
+    ) -> *mut c_void {
+        naked_asm!("mov r3, lr", "b {from}", from = sym android_dlopen_ext_from)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn dlopen_hook(_filename: *const c_char, _flags: c_int) -> *mut c_void {
+        naked_asm!("mov rdx, [rsp]", "jmp {from}", from = sym dlopen_from)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn android_dlopen_ext_hook(
+        _filename: *const c_char,
+        _flags: c_int,
+        _extinfo: *const c_void,
+    ) -> *mut c_void {
+        naked_asm!("mov rcx, [rsp]", "jmp {from}", from = sym android_dlopen_ext_from)
+    }
+
+    // The arguments are on the stack, so they are pushed again below the return address, keeping
+    // the stack 16-byte aligned.
+    #[cfg(target_arch = "x86")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn dlopen_hook(_filename: *const c_char, _flags: c_int) -> *mut c_void {
+        naked_asm!(
+            "push dword ptr [esp]",
+            "push dword ptr [esp + 12]",
+            "push dword ptr [esp + 12]",
+            "call {from}",
+            "add esp, 12",
+            "ret",
+            from = sym dlopen_from,
+        )
+    }
+
+    #[cfg(target_arch = "x86")]
+    #[unsafe(naked)]
+    pub(super) extern "C" fn android_dlopen_ext_hook(
+        _filename: *const c_char,
+        _flags: c_int,
+        _extinfo: *const c_void,
+    ) -> *mut c_void {
+        naked_asm!(
+            "sub esp, 12",
+            "push dword ptr [esp + 12]",
+            "push dword ptr [esp + 28]",
+            "push dword ptr [esp + 28]",
+            "push dword ptr [esp + 28]",
+            "call {from}",
+            "add esp, 28",
+            "ret",
+            from = sym android_dlopen_ext_from,
+        )
+    }
+
+    #[cfg(not(any(
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    )))]
+    pub(super) extern "C" fn dlopen_hook(filename: *const c_char, flags: c_int) -> *mut c_void {
+        dlopen_from(filename, flags, std::ptr::null())
+    }
+
+    #[cfg(not(any(
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "x86",
+        target_arch = "x86_64"
+    )))]
+    pub(super) extern "C" fn android_dlopen_ext_hook(
+        filename: *const c_char,
+        flags: c_int,
+        extinfo: *const c_void,
+    ) -> *mut c_void {
+        android_dlopen_ext_from(filename, flags, extinfo, std::ptr::null())
+    }
+}
+
+use shim::{android_dlopen_ext_hook, dlopen_hook};
+
+type LoaderDlopen = unsafe extern "C" fn(*const c_char, c_int, *const c_void) -> *mut c_void;
+type LoaderAndroidDlopenExt =
+    unsafe extern "C" fn(*const c_char, c_int, *const c_void, *const c_void) -> *mut c_void;
+
+/// The functions of the linker behind `dlopen` and `android_dlopen_ext`, which take the address
+/// of the caller explicitly. They exist since Android 8.
+struct Loader {
+    dlopen: Option<LoaderDlopen>,
+    android_dlopen_ext: Option<LoaderAndroidDlopenExt>,
+}
+
+static LOADER: OnceLock<Loader> = OnceLock::new();
+
+fn loader() -> &'static Loader {
+    LOADER.get_or_init(|| {
+        let lookup =
+            |name: &CStr| unsafe { crate::libc::dlsym(crate::libc::RTLD_DEFAULT, name.as_ptr()) };
+        let dlopen = lookup(c"__loader_dlopen");
+        let android_dlopen_ext = lookup(c"__loader_android_dlopen_ext");
+        // SAFETY: the linker exports these functions with the signatures above.
+        unsafe {
+            Loader {
+                dlopen: (!dlopen.is_null()).then(|| std::mem::transmute(dlopen)),
+                android_dlopen_ext: (!android_dlopen_ext.is_null())
+                    .then(|| std::mem::transmute(android_dlopen_ext)),
+            }
+        }
+    })
+}
+
+/// The hook of `dlopen`, called by [shim::dlopen_hook] along with the address it returns to.
+extern "C" fn dlopen_from(
+    filename: *const c_char,
+    flags: c_int,
+    caller: *const c_void,
+) -> *mut c_void {
+    let handle = match loader().dlopen {
+        Some(dlopen) if !caller.is_null() => unsafe { dlopen(filename, flags, caller) },
+        _ => {
+            let original: extern "C" fn(*const c_char, c_int) -> *mut c_void =
+                unsafe { std::mem::transmute(ORIG_DLOPEN.load(Ordering::Acquire)) };
+            original(filename, flags)
+        }
+    };
+    if !handle.is_null() {
+        mapped();
+    }
+    handle
+}
+
+/// The hook of `android_dlopen_ext`, called by [shim::android_dlopen_ext_hook] along with the
+/// address it returns to.
+extern "C" fn android_dlopen_ext_from(
+    filename: *const c_char,
+    flags: c_int,
+    extinfo: *const c_void,
+    caller: *const c_void,
+) -> *mut c_void {
+    let handle = match loader().android_dlopen_ext {
+        Some(android_dlopen_ext) if !caller.is_null() => unsafe {
+            android_dlopen_ext(filename, flags, extinfo, caller)
+        },
+        _ => {
+            let original: extern "C" fn(*const c_char, c_int, *const c_void) -> *mut c_void =
+                unsafe { std::mem::transmute(ORIG_ANDROID_DLOPEN_EXT.load(Ordering::Acquire)) };
+            original(filename, flags, extinfo)
+        }
+    };
+    if !handle.is_null() {
+        mapped();
+    }
+    handle
+}
+
+/// Register the hooks of newly mapped libraries, through the Zygisk API while it is loaded, and
+/// through the backend set with [set_backend()](super::set_backend) afterwards.
+fn mapped() {
+    let Some(api) = API.get() else {
+        return;
+    };
+    if api.with(|api| resolve(api, super::backend())).is_ok() {
+        return;
+    }
+    match super::custom_backend() {
+        Some(backend) => {
+            // The backend must not need the API table, which is gone.
+            let table = RawApiTable::empty();
+            resolve(&ZygiskApi::from_raw(&table, api.version()), backend);
+        }
+        None => drop_pending(),
+    }
+}
+
+/// Drop the pending hooks, which cannot be registered without the API table.
+fn drop_pending() {
+    let pending = std::mem::take(&mut STATE.lock().unwrap().pending);
+    for hook in pending {
+        logcat::write(
+            logcat::Priority::Warn,
+            &format!(
+                "dropping the lazy hook of `{}` in {}: the Zygisk API was unloaded",
+                hook.symbol.to_string_lossy(),
+                hook.library
+            ),
+        );
+    }
+}
+
+/// Register the pending hooks of mapped libraries, watch `dlopen` calls of newly mapped ELFs,
+/// and commit. Hooks that could not be applied are kept pending for the next `dlopen`.
+fn resolve(api: &ZygiskApi, backend: &dyn HookBackend) {
+    let entries = match maps::entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            logcat::write(
+                logcat::Priority::Error,
+                &format!("failed to read /proc/self/maps: {e}"),
+            );
+            return;
+        }
+    };
+
+    let mut state = STATE.lock().unwrap();
+    let ready = take_loaded(&mut state.pending, &entries);
+    let unwatched = take_unwatched(&mut state.watched, &entries);
+    if ready.is_empty() && unwatched.is_empty() {
+        return;
+    }
+
+    let mut errors = Vec::new();
+    // The backend writes the original functions to plain pointers until it commits, while the
+    // hooks may already be loading them from other threads, so they are only stored to the
+    // atomics afterwards. The slots are boxed to stay in place until then.
+    let mut registered: Vec<(PendingHook, Box<*mut ()>)> = Vec::new();
+    let mut dlopen_slots: Vec<(Box<*mut ()>, &AtomicPtr<()>)> = Vec::new();
+    // SAFETY: the requirements were upheld by the callers of `register()`, and the hooks of
+    // `dlopen` have the right signatures.
+    for (hook, device, inode) in ready {
+        let mut slot = Box::new(std::ptr::null_mut());
+        let result = unsafe {
+            backend.register(
+                api,
+                device,
+                inode,
+                &hook.symbol,
+                hook.new_func,
+                Some(&mut *slot),
+            )
+        };
+        match result {
+            Ok(()) => registered.push((hook, slot)),
+            Err(e) => {
+                errors.push(e);
+                state.pending.push(hook);
+            }
+        }
+    }
+    for (device, inode) in unwatched {
+        let mut slot = Box::new(std::ptr::null_mut());
+        let result = unsafe {
+            backend.register(
+                api,
+                device,
+                inode,
+                c"dlopen",
+                dlopen_hook as *mut (),
+                Some(&mut *slot),
+            )
+        };
+        if let Err(e) = result {
+            // Nothing was hooked in this ELF, so it is safe to try again later.
+            errors.push(e);
+            state.watched.remove(&(device, inode));
+            continue;
+        }
+        dlopen_slots.push((slot, &ORIG_DLOPEN));
+        let mut slot = Box::new(std::ptr::null_mut());
+        let result = unsafe {
+            backend.register(
+                api,
+                device,
+                inode,
+                c"android_dlopen_ext",
+                android_dlopen_ext_hook as *mut (),
+                Some(&mut *slot),
+            )
+        };
+        match result {
+            Ok(()) => dlopen_slots.push((slot, &ORIG_ANDROID_DLOPEN_EXT)),
+            Err(e) => errors.push(e),
+        }
+    }
+    let committed = backend.commit(api);
+    for (slot, target) in dlopen_slots {
+        if !slot.is_null() {
+            target.store(*slot, Ordering::Release);
+        }
+    }
+    for (hook, slot) in registered {
+        if !slot.is_null() {
+            hook.old_func.store(*slot, Ordering::Release);
+        } else if committed.is_err() {
+            // Zygisk saves the original function of the hooks it applied, so the others can be
+            // registered again by the next `dlopen`.
+            state.pending.push(hook);
+        }
+    }
+    if let Err(e) = committed {
+        errors.push(e);
+    }
+    for e in errors {
+        logcat::write(
+            logcat::Priority::Error,
+            &format!("failed to install lazy hooks: {e}"),
+        );
+    }
+}
+
+/// Remove the pending hooks whose library is mapped, along with the `dev` and `inode` pair of
+/// the library.
+fn take_loaded(
+    pending: &mut Vec<PendingHook>,
+    entries: &[MapEntry],
+) -> Vec<(PendingHook, dev_t, ino_t)> {
+    let mut ready = Vec::new();
+    let mut i = 0;
+    while i < pending.len() {
+        let library = &pending[i].library;
+        match entries
+            .iter()
+            .find(|entry| maps::library_matches(entry, library))
+        {
+            Some(entry) => {
+                let (device, inode) = (entry.dev, entry.inode);
+                ready.push((pending.remove(i), device, inode));
+            }
+            None => i += 1,
+        }
+    }
+    ready
+}
+
+/// Collect the executable ELFs whose `dlopen` calls are not intercepted yet.
+fn take_unwatched(
+    watched: &mut BTreeSet<(dev_t, ino_t)>,
+    entries: &[MapEntry],
+) -> Vec<(dev_t, ino_t)> {
+    entries
+        .iter()
+        .filter(|entry| entry.inode != 0 && entry.is_executable())
+        .map(|entry| (entry.dev, entry.inode))
+        .filter(|&id| watched.insert(id))
+        .collect()
+}
+
+#[test]
+fn test_take_loaded() {
+    static OLD: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+
+    let entry = |path: &str, inode, perms: &str| MapEntry {
+        start: 0,
+        end: 0x1000,
+        perms: perms.to_owned(),
+        offset: 0,
+        dev: 1,
+        inode,
+        pathname: path.to_owned(),
+    };
+    let hook = |library: &str| PendingHook {
+        library: library.to_owned(),
+        symbol: c"open".to_owned(),
+        new_func: std::ptr::null_mut(),
+        old_func: &OLD,
+    };
+
+    let mut pending = vec![hook("libgame.so"), hook("libengine.so")];
+    let entries = [
+        entry("/data/app/lib/arm64/libgame.so", 7, "r--p"),
+        entry("/data/app/lib/arm64/libgame.so", 7, "r-xp"),
+    ];
+    let ready = take_loaded(&mut pending, &entries);
+    assert_eq!(ready.len(), 1);
+    assert_eq!(ready[0].0.library, "libgame.so");
+    assert_eq!(ready[0].2, 7);
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].library, "libengine.so");
+
+    let mut watched = BTreeSet::new();
+    assert_eq!(take_unwatched(&mut watched, &entries), [(1, 7)]);
+    assert!(take_unwatched(&mut watched, &entries).is_empty());
+}
+
+#[test]
+fn test_dlopen_after_unload() {
+    use crate::{libc, ApiVersion};
+    use std::sync::atomic::AtomicUsize;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static OLD: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+    extern "C" fn plt_hook_register(
+        _dev: dev_t,
+        _inode: ino_t,
+        symbol: *const c_char,
+        _new_func: *mut (),
+        old_func: *mut *mut (),
+    ) {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        if unsafe { CStr::from_ptr(symbol) } == c"dlopen" {
+            unsafe { *old_func = libc::dlopen as *mut () };
+        }
+    }
+    extern "C" fn commit() -> bool {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    test_loader();
+    let api = ZygiskApi::mock(ApiVersion::V5, |table| {
+        table.plt_hook_register = Some(plt_hook_register);
+        table.plt_hook_commit = Some(commit);
+    });
+    install(api.retained()).unwrap();
+    assert!(CALLS.load(Ordering::SeqCst) > 0);
+
+    // Zygisk unloads the table after `post[XXX]Specialize`, leaving the hooks of `dlopen`.
+    crate::api::mark_unloaded();
+    let calls = CALLS.load(Ordering::SeqCst);
+    let exe = std::env::current_exe().unwrap();
+    unsafe { register(exe.to_str().unwrap(), c"open", std::ptr::null_mut(), &OLD) };
+    let handle = dlopen_hook(c"libc.so.6".as_ptr(), libc::RTLD_NOW | libc::RTLD_NOLOAD);
+    assert!(!handle.is_null());
+    unsafe { libc::dlclose(handle) };
+
+    assert_eq!(CALLS.load(Ordering::SeqCst), calls);
+    assert!(STATE.lock().unwrap().pending.is_empty());
+    assert!(OLD.load(Ordering::SeqCst).is_null());
+}
+
+// Glibc has no `__loader_dlopen`, so tests stand in for the linker, recording the callers.
+#[cfg(test)]
+static CALLER: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+
+#[cfg(test)]
+fn test_loader() {
+    unsafe extern "C" fn dlopen(
+        filename: *const c_char,
+        flags: c_int,
+        caller: *const c_void,
+    ) -> *mut c_void {
+        CALLER.store(caller.cast_mut(), Ordering::SeqCst);
+        unsafe { crate::libc::dlopen(filename, flags) }
+    }
+    unsafe extern "C" fn android_dlopen_ext(
+        filename: *const c_char,
+        flags: c_int,
+        _extinfo: *const c_void,
+        caller: *const c_void,
+    ) -> *mut c_void {
+        unsafe { dlopen(filename, flags, caller) }
+    }
+
+    LOADER.get_or_init(|| Loader {
+        dlopen: Some(dlopen),
+        android_dlopen_ext: Some(android_dlopen_ext),
+    });
+}
+
+#[cfg(any(
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+#[test]
+fn test_dlopen_caller() {
+    use crate::libc;
+
+    // Closing the handle here keeps the hooks from being tail-called, returning past `open`.
+    #[inline(never)]
+    fn open(ext: bool) {
+        let filename = c"libc.so.6".as_ptr();
+        let flags = libc::RTLD_NOW | libc::RTLD_NOLOAD;
+        let handle = if ext {
+            std::hint::black_box(android_dlopen_ext_hook)(filename, flags, std::ptr::null())
+        } else {
+            std::hint::black_box(dlopen_hook)(filename, flags)
+        };
+        assert!(!handle.is_null());
+        unsafe { libc::dlclose(handle) };
+    }
+
+    test_loader();
+    for ext in [false, true] {
+        CALLER.store(std::ptr::null_mut(), Ordering::SeqCst);
+        std::hint::black_box(open)(ext);
+
+        // The linker is given the address the hook returns to, within `open`.
+        let caller = CALLER.load(Ordering::SeqCst) as usize;
+        let start = open as *const () as usize;
+        assert!((start..start + 0x400).contains(&caller), "{caller:#x}");
+    }
+}
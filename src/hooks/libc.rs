@@ -0,0 +1,184 @@
+//! Ready-made PLT hooks for commonly hooked libc functions.
+//!
+//! Each function queues a hook of the libc function of the same name on a [PltHookSession], for
+//! calls made by `library`. The crate provides the `extern "C"` shim; `callback` receives the
+//! original function followed by the arguments of the call, and its return value is returned
+//! to the caller.
+//!
+//! Every libc function can only have one callback, since the shim cannot tell which library
+//! called it. To hook more libraries, pass the same callback again, as a function or a closure
+//! that captures nothing; other callbacks fail with [ZygiskError::HookCallbackInstalled].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::ffi::CStr;
+//! use zygisk::{hooks, PltHookSession};
+//!
+//! fn hide_su(session: &mut PltHookSession) -> Result<(), zygisk::ZygiskError> {
+//!     hooks::libc::access(session, "libapp.so", |access, path, mode| {
+//!         if !path.is_null() && unsafe { CStr::from_ptr(path) } == c"/system/bin/su" {
+//!             return -1;
+//!         }
+//!         unsafe { access(path, mode) }
+//!     })
+//! }
+//! ```
+
+use std::{
+    any::TypeId,
+    os::raw::{c_char, c_int},
+};
+
+use crate::{
+    libc::{self, mode_t, FILE},
+    PltHookSession, ZygiskError,
+};
+
+macro_rules! preset {
+    ($(
+        $(#[$doc: meta])*
+        fn $name: ident ($($arg: ident : $ty: ty),*) -> $ret: ty;
+    )*) => {$(
+        $(#[$doc])*
+        pub fn $name<F>(
+            session: &mut PltHookSession<'_, '_>,
+            library: &str,
+            callback: F,
+        ) -> Result<(), ZygiskError>
+        where
+            F: Fn(unsafe extern "C" fn($($ty),*) -> $ret, $($ty),*) -> $ret + Send + Sync + 'static,
+        {
+            type Original = unsafe extern "C" fn($($ty),*) -> $ret;
+            type Callback = Box<dyn Fn(Original, $($ty),*) -> $ret + Send + Sync>;
+
+            static CALLBACK: ::std::sync::OnceLock<(TypeId, Callback)> =
+                ::std::sync::OnceLock::new();
+            static ORIGINAL: ::std::sync::atomic::AtomicPtr<()> =
+                ::std::sync::atomic::AtomicPtr::new(::std::ptr::null_mut());
+
+            extern "C" fn shim($($arg: $ty),*) -> $ret {
+                // SAFETY: Zygisk saved the original function before redirecting calls here.
+                let original: Original = unsafe {
+                    ::std::mem::transmute(ORIGINAL.load(::std::sync::atomic::Ordering::Acquire))
+                };
+                match CALLBACK.get() {
+                    Some((_, callback)) => callback(original, $($arg),*),
+                    None => unsafe { original($($arg),*) },
+                }
+            }
+
+            // Callbacks of the same type that capture nothing behave the same, so they can be
+            // passed again for other libraries.
+            let mut callback = Some(callback);
+            let (installed, _) = CALLBACK.get_or_init(|| {
+                (TypeId::of::<F>(), Box::new(callback.take().unwrap()))
+            });
+            let reused = *installed == TypeId::of::<F>() && ::std::mem::size_of::<F>() == 0;
+            if callback.is_some() && !reused {
+                return Err(ZygiskError::HookCallbackInstalled(stringify!($name)));
+            }
+            let (device, inode) = super::library_id(library)?;
+            let symbol = concat!(stringify!($name), "\0");
+            // SAFETY: the symbol has no interior NUL, and the shim has the signature of the
+            // libc function.
+            unsafe {
+                session.hook_atomic(
+                    device,
+                    inode,
+                    ::std::ffi::CStr::from_bytes_with_nul_unchecked(symbol.as_bytes()),
+                    shim as *mut (),
+                    &ORIGINAL,
+                )?;
+            }
+            Ok(())
+        }
+    )*};
+}
+
+preset! {
+    /// Hook `open(2)`. `mode` is only meaningful when `flags` contains `O_CREAT` or
+    /// `O_TMPFILE`.
+    fn open(path: *const c_char, flags: c_int, mode: mode_t) -> c_int;
+
+    /// Hook `openat(2)`. `mode` is only meaningful when `flags` contains `O_CREAT` or
+    /// `O_TMPFILE`.
+    fn openat(dirfd: c_int, path: *const c_char, flags: c_int, mode: mode_t) -> c_int;
+
+    /// Hook `stat(2)`.
+    fn stat(path: *const c_char, buf: *mut libc::stat) -> c_int;
+
+    /// Hook `access(2)`.
+    fn access(path: *const c_char, mode: c_int) -> c_int;
+
+    /// Hook `fopen(3)`.
+    fn fopen(path: *const c_char, mode: *const c_char) -> *mut FILE;
+
+    /// Hook `execve(2)`.
+    fn execve(
+        path: *const c_char,
+        argv: *const *const c_char,
+        envp: *const *const c_char
+    ) -> c_int;
+
+    /// Hook `__system_property_get`, which reads system properties into a buffer of at least
+    /// `PROP_VALUE_MAX` bytes and returns the length of the value.
+    fn __system_property_get(name: *const c_char, value: *mut c_char) -> c_int;
+}
+
+#[test]
+fn test_access_preset() {
+    use crate::{ApiVersion, ZygiskApi};
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    static SHIM: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+    extern "C" fn register(
+        _dev: libc::dev_t,
+        _inode: libc::ino_t,
+        _symbol: *const c_char,
+        new_func: *mut (),
+        old_func: *mut *mut (),
+    ) {
+        SHIM.store(new_func, Ordering::SeqCst);
+        unsafe { *old_func = libc::access as *mut () };
+    }
+    extern "C" fn commit() -> bool {
+        true
+    }
+
+    let api = ZygiskApi::mock(ApiVersion::V5, |table| {
+        table.plt_hook_register = Some(register);
+        table.plt_hook_commit = Some(commit);
+    });
+
+    fn hide(
+        access: unsafe extern "C" fn(*const c_char, c_int) -> c_int,
+        path: *const c_char,
+        mode: c_int,
+    ) -> c_int {
+        if unsafe { std::ffi::CStr::from_ptr(path) } == c"/hidden" {
+            -1
+        } else {
+            unsafe { access(path, mode) }
+        }
+    }
+
+    let exe = std::env::current_exe().unwrap();
+    let exe = exe.to_str().unwrap();
+    let mut session = api.plt_hook_session();
+    access(&mut session, exe, hide).unwrap();
+    // The same callback again, as for another library.
+    access(&mut session, exe, hide).unwrap();
+    assert!(matches!(
+        access(&mut session, exe, |access, path, mode| unsafe {
+            access(path, mode)
+        }),
+        Err(ZygiskError::HookCallbackInstalled("access"))
+    ));
+    session.commit().unwrap();
+
+    let shim: extern "C" fn(*const c_char, c_int) -> c_int =
+        unsafe { std::mem::transmute(SHIM.load(Ordering::SeqCst)) };
+    assert_eq!(shim(c"/hidden".as_ptr(), libc::F_OK), -1);
+    assert_eq!(shim(c"/".as_ptr(), libc::F_OK), 0);
+}
@@ -0,0 +1,149 @@
+use std::sync::Mutex;
+
+use crate::libc::{dev_t, ino_t};
+
+/// The kind of an [InstalledHook].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookKind {
+    /// Registered with [ZygiskApi::plt_hook_register()](crate::ZygiskApi::plt_hook_register) or
+    /// [ZygiskApi::plt_hook_register_regex()](crate::ZygiskApi::plt_hook_register_regex).
+    Plt,
+    /// Registered with
+    /// [ZygiskApi::hook_jni_native_methods()](crate::ZygiskApi::hook_jni_native_methods).
+    Jni,
+    /// Registered with `PltHookSession::hook_inline()`, with the `inline-hook` feature.
+    Inline,
+}
+
+/// A hook registered through this crate, as returned by
+/// [ZygiskApi::installed_hooks()](crate::ZygiskApi::installed_hooks).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstalledHook {
+    pub kind: HookKind,
+    /// The hooked ELF (its path, or `dev:inode` if it was unmapped before the hook was reported),
+    /// the regex matching the hooked ELFs, or the Java class of a JNI hook.
+    pub library: String,
+    /// The hooked symbol, or the name and signature of a JNI method.
+    pub symbol: String,
+    /// The address of the replacement function.
+    pub address: usize,
+    /// Whether the hook is in effect. PLT hooks only are after a successful commit; JNI hooks
+    /// are as soon as they are registered.
+    pub committed: bool,
+}
+
+/// A PLT hook that failed to be committed, as reported by
+/// [ZygiskError::PltHookFailed](crate::ZygiskError::PltHookFailed).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookFailure {
+    /// The hooked ELF or the regex matching the hooked ELFs, as in [InstalledHook::library].
+    pub library: String,
+    /// The hooked symbol.
+    pub symbol: String,
+}
+
+struct Entry {
+    hook: InstalledHook,
+    // Where Zygisk saves the original function of an uncommitted PLT hook, if known. The slot
+    // stays null if the hook could not be applied.
+    original: Option<usize>,
+    // The ELF of a PLT hook registered by device and inode, whose path is only looked up when
+    // the hook is reported.
+    elf: Option<(dev_t, ino_t)>,
+}
+
+static REGISTRY: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+pub(crate) fn record(hook: InstalledHook, original: Option<*mut *mut ()>) {
+    REGISTRY.lock().unwrap().push(Entry {
+        hook,
+        original: original.map(|slot| slot as usize),
+        elf: None,
+    });
+}
+
+/// Record a hook of the ELF `device:inode`, labelled with its path once it is reported.
+#[cfg(feature = "api-v4")]
+pub(crate) fn record_elf(
+    mut hook: InstalledHook,
+    original: Option<*mut *mut ()>,
+    device: dev_t,
+    inode: ino_t,
+) {
+    hook.library = format!("{device}:{inode}");
+    REGISTRY.lock().unwrap().push(Entry {
+        hook,
+        original: original.map(|slot| slot as usize),
+        elf: Some((device, inode)),
+    });
+}
+
+/// Label the hooked ELFs of `entries` with their path, reading the memory maps once. ELFs that
+/// are not mapped anymore keep their `device:inode` label.
+fn resolve_paths<'e>(entries: impl IntoIterator<Item = &'e mut Entry>) {
+    let mut entries = entries
+        .into_iter()
+        .filter(|entry| entry.elf.is_some())
+        .peekable();
+    if entries.peek().is_none() {
+        return;
+    }
+    let maps = crate::maps::entries().unwrap_or_default();
+    for entry in entries {
+        let (device, inode) = entry.elf.take().unwrap();
+        if let Some(map) = maps
+            .iter()
+            .find(|map| map.dev == device && map.inode == inode)
+        {
+            entry.hook.library.clone_from(&map.pathname);
+        }
+    }
+}
+
+/// Settle all pending PLT hooks after a commit, and return those that were not applied.
+///
+/// Hooks are known to have failed when Zygisk did not save their original function. Hooks
+/// registered without a place to save it are only considered failed if the whole commit failed.
+///
+/// ## Safety
+///
+/// The slots passed to [record()] have to be valid until this is called.
+pub(crate) unsafe fn commit_plt(success: bool) -> Vec<HookFailure> {
+    let mut failed: Vec<_> = REGISTRY
+        .lock()
+        .unwrap()
+        .extract_if(.., |entry| {
+            if entry.hook.kind != HookKind::Plt || entry.hook.committed {
+                return false;
+            }
+            let applied = match entry.original.take() {
+                Some(slot) => !(*(slot as *const *mut ())).is_null(),
+                None => success,
+            };
+            entry.hook.committed = true;
+            !applied
+        })
+        .collect();
+    resolve_paths(&mut failed);
+    failed
+        .into_iter()
+        .map(|entry| HookFailure {
+            library: entry.hook.library,
+            symbol: entry.hook.symbol,
+        })
+        .collect()
+}
+
+/// Drop the hooks matching `restored` from the registry.
+pub(crate) fn forget(restored: impl Fn(&InstalledHook) -> bool) {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .retain(|entry| !restored(&entry.hook));
+}
+
+pub(crate) fn snapshot() -> Vec<InstalledHook> {
+    let mut registry = REGISTRY.lock().unwrap();
+    resolve_paths(registry.iter_mut());
+    registry.iter().map(|entry| entry.hook.clone()).collect()
+}
@@ -0,0 +1,23 @@
+//! Helpers for running Java code from a module, once the process has been specialized.
+
+use crate::jni::{
+    errors::{Error as JniError, Result as JniResult},
+    JNIEnv,
+};
+
+mod class_loader;
+mod dex;
+mod hidden_api;
+
+pub use class_loader::app_class_loader;
+pub use dex::{DexInjector, InjectedDex};
+pub use hidden_api::exempt_hidden_apis;
+
+/// Print and clear the pending exception, if the call threw one.
+fn clear_exception<T>(env: &mut JNIEnv, result: JniResult<T>) -> JniResult<T> {
+    if let Err(JniError::JavaException) = result {
+        let _ = env.exception_describe();
+        let _ = env.exception_clear();
+    }
+    result
+}
@@ -0,0 +1,159 @@
+use crate::jni::{
+    errors::{Error as JniError, Result as JniResult},
+    objects::{JObject, JValue},
+    JNIEnv,
+};
+
+/// Get the class loader of the app, which is needed to find the classes of the app rather than
+/// only the ones of the framework.
+///
+/// The loader is looked up through `ActivityThread`, from the first of these that exists:
+///
+/// - the `Application`, once it has been created;
+/// - the `LoadedApk` of the `AppBindData` the app was bound with;
+/// - the `LoadedApk` of the package in `ActivityThread.mPackages`, looked up by
+///   [process_name()](crate::process_name).
+///
+/// The app is only bound after the Zygisk callbacks returned, so in `post_app_specialize` this
+/// usually fails with [NullPtr](JniError::NullPtr). Call it later instead, such as from a hook on
+/// a method of the framework that the app calls early on. Java exceptions thrown along the way
+/// are cleared.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{java, jni::JNIEnv};
+///
+/// fn find_main_activity(env: &mut JNIEnv) -> bool {
+///     let Ok(loader) = java::app_class_loader(env) else {
+///         return false;
+///     };
+///     let name = env.new_string("com.example.MainActivity").unwrap();
+///     env.call_method(
+///         loader,
+///         "loadClass",
+///         "(Ljava/lang/String;)Ljava/lang/Class;",
+///         &[(&name).into()],
+///     )
+///     .is_ok()
+/// }
+/// ```
+pub fn app_class_loader<'local>(env: &mut JNIEnv<'local>) -> JniResult<JObject<'local>> {
+    let attempts: [fn(&mut JNIEnv<'local>) -> JniResult<JObject<'local>>; 3] = [
+        |env| from_application(env),
+        |env| from_bound_application(env),
+        |env| from_packages(env),
+    ];
+    for attempt in attempts {
+        match attempt(env) {
+            Ok(loader) if !loader.is_null() => return Ok(loader),
+            Ok(_) => {}
+            // Fields that do not exist on this version of Android.
+            Err(JniError::JavaException) => {
+                let _ = env.exception_clear();
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(JniError::NullPtr("application class loader"))
+}
+
+const ACTIVITY_THREAD: &str = "android/app/ActivityThread";
+
+/// `ActivityThread.currentApplication().getClassLoader()`.
+fn from_application<'local>(env: &mut JNIEnv<'local>) -> JniResult<JObject<'local>> {
+    let application = env
+        .call_static_method(
+            ACTIVITY_THREAD,
+            "currentApplication",
+            "()Landroid/app/Application;",
+            &[],
+        )?
+        .l()?;
+    get_class_loader(env, &application)
+}
+
+/// `ActivityThread.currentActivityThread().mBoundApplication.info.getClassLoader()`.
+fn from_bound_application<'local>(env: &mut JNIEnv<'local>) -> JniResult<JObject<'local>> {
+    let thread = current_activity_thread(env)?;
+    if thread.is_null() {
+        return Ok(thread);
+    }
+    let data = env
+        .get_field(
+            &thread,
+            "mBoundApplication",
+            "Landroid/app/ActivityThread$AppBindData;",
+        )?
+        .l()?;
+    if data.is_null() {
+        return Ok(data);
+    }
+    let apk = env
+        .get_field(&data, "info", "Landroid/app/LoadedApk;")?
+        .l()?;
+    get_class_loader(env, &apk)
+}
+
+/// `ActivityThread.currentActivityThread().mPackages.get(package).get().getClassLoader()`.
+fn from_packages<'local>(env: &mut JNIEnv<'local>) -> JniResult<JObject<'local>> {
+    let thread = current_activity_thread(env)?;
+    if thread.is_null() {
+        return Ok(thread);
+    }
+    let packages = env
+        .get_field(&thread, "mPackages", "Landroid/util/ArrayMap;")?
+        .l()?;
+    let process = crate::process_name();
+    // Processes such as `com.example:remote` run the code of their package.
+    let package = process.split(':').next().unwrap_or_default();
+    let package = env.new_string(package)?;
+    let reference = env
+        .call_method(
+            &packages,
+            "get",
+            "(Ljava/lang/Object;)Ljava/lang/Object;",
+            &[JValue::Object(&package)],
+        )?
+        .l()?;
+    if reference.is_null() {
+        return Ok(reference);
+    }
+    let apk = env
+        .call_method(&reference, "get", "()Ljava/lang/Object;", &[])?
+        .l()?;
+    get_class_loader(env, &apk)
+}
+
+fn current_activity_thread<'local>(env: &mut JNIEnv<'local>) -> JniResult<JObject<'local>> {
+    env.call_static_method(
+        ACTIVITY_THREAD,
+        "currentActivityThread",
+        "()Landroid/app/ActivityThread;",
+        &[],
+    )?
+    .l()
+}
+
+/// `getClassLoader()` of an `Application` or a `LoadedApk`, or null if the object is null.
+fn get_class_loader<'local>(
+    env: &mut JNIEnv<'local>,
+    object: &JObject,
+) -> JniResult<JObject<'local>> {
+    if object.is_null() {
+        return Ok(JObject::null());
+    }
+    env.call_method(object, "getClassLoader", "()Ljava/lang/ClassLoader;", &[])?
+        .l()
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_app_class_loader() {
+    let stub = crate::testing::StubEnv::new();
+    // The stub knows no framework classes.
+    assert!(matches!(
+        app_class_loader(&mut stub.env()),
+        Err(JniError::NullPtr(_))
+    ));
+}
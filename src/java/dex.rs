@@ -0,0 +1,174 @@
+use std::{borrow::Cow, io, path::Path};
+
+use super::clear_exception;
+use crate::{
+    jni::{
+        errors::Result as JniResult,
+        objects::{GlobalRef, JClass, JObject, JValue, JValueOwned},
+        JNIEnv,
+    },
+    ModuleDir,
+};
+
+/// A DEX file to load into the process with an `InMemoryDexClassLoader`, so that part of the
+/// module can be written in Java or Kotlin.
+///
+/// The DEX is either embedded in the module library with `include_bytes!`, or read from the
+/// module directory in `pre_app_specialize` with [Self::from_module_dir()], since the directory
+/// is not accessible anymore afterwards. It is loaded in `post_app_specialize`, once the process
+/// runs as the app. `InMemoryDexClassLoader` exists since Android 8.0.
+///
+/// Java exceptions thrown while loading or calling into the DEX are printed to logcat and
+/// cleared before the error is returned, since a pending exception would crash the app as soon
+/// as control returns to zygote.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{java::DexInjector, jni::JNIEnv};
+///
+/// // Usually `include_bytes!(concat!(env!("OUT_DIR"), "/classes.dex"))`.
+/// static DEX: &[u8] = b"dex\n035\0";
+///
+/// fn post_app_specialize(env: &mut JNIEnv) {
+///     let injector = DexInjector::new(DEX);
+///     if let Err(e) = injector.inject(env, "com.example.module.Entry", "main") {
+///         eprintln!("failed to inject the DEX: {e}");
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct DexInjector {
+    dex: Cow<'static, [u8]>,
+}
+
+impl DexInjector {
+    /// Inject the given DEX, such as one embedded with `include_bytes!`.
+    pub fn new(dex: impl Into<Cow<'static, [u8]>>) -> Self {
+        DexInjector { dex: dex.into() }
+    }
+
+    /// Read the DEX from a file in the module directory.
+    pub fn from_module_dir(dir: &ModuleDir, path: impl AsRef<Path>) -> io::Result<Self> {
+        dir.read(path).map(Self::new)
+    }
+
+    /// The contents of the DEX.
+    pub fn dex(&self) -> &[u8] {
+        &self.dex
+    }
+
+    /// Load the DEX with the system class loader as its parent.
+    ///
+    /// Classes of the DEX can then use the framework, but not the classes of the app.
+    pub fn load(&self, env: &mut JNIEnv) -> JniResult<InjectedDex> {
+        let result = env
+            .call_static_method(
+                "java/lang/ClassLoader",
+                "getSystemClassLoader",
+                "()Ljava/lang/ClassLoader;",
+                &[],
+            )
+            .and_then(|parent| parent.l());
+        let parent = clear_exception(env, result)?;
+        self.load_with_parent(env, &parent)
+    }
+
+    /// Load the DEX with the given class loader as its parent.
+    pub fn load_with_parent(&self, env: &mut JNIEnv, parent: &JObject) -> JniResult<InjectedDex> {
+        let result = env.with_local_frame(4, |env| {
+            let bytes = env.byte_array_from_slice(&self.dex)?;
+            // A heap buffer, which ART copies, since the DEX may not outlive the loader.
+            let buffer = env
+                .call_static_method(
+                    "java/nio/ByteBuffer",
+                    "wrap",
+                    "([B)Ljava/nio/ByteBuffer;",
+                    &[JValue::Object(&bytes)],
+                )?
+                .l()?;
+            let loader = env.new_object(
+                "dalvik/system/InMemoryDexClassLoader",
+                "(Ljava/nio/ByteBuffer;Ljava/lang/ClassLoader;)V",
+                &[JValue::Object(&buffer), JValue::Object(parent)],
+            )?;
+            env.new_global_ref(loader)
+        });
+        let loader = clear_exception(env, result)?;
+        Ok(InjectedDex { loader })
+    }
+
+    /// Load the DEX and call the `static void <method>()` of the entry class.
+    ///
+    /// `class` is a binary name such as `com.example.Entry`. The loaded DEX is returned so that
+    /// more of its methods can be called later.
+    pub fn inject(&self, env: &mut JNIEnv, class: &str, method: &str) -> JniResult<InjectedDex> {
+        let dex = self.load(env)?;
+        let class = dex.load_class(env, class)?;
+        dex.call_static(env, &class, method, "()V", &[])?;
+        Ok(dex)
+    }
+}
+
+/// A DEX loaded by [DexInjector]. The class loader is kept alive as long as this is.
+#[derive(Clone, Debug)]
+pub struct InjectedDex {
+    loader: GlobalRef,
+}
+
+impl InjectedDex {
+    /// The `InMemoryDexClassLoader` of the DEX.
+    pub fn class_loader(&self) -> &JObject<'static> {
+        self.loader.as_obj()
+    }
+
+    /// Load a class of the DEX by its binary name, such as `com.example.Entry`.
+    pub fn load_class<'local>(
+        &self,
+        env: &mut JNIEnv<'local>,
+        name: &str,
+    ) -> JniResult<JClass<'local>> {
+        let result = env.new_string(name).and_then(|name| {
+            env.call_method(
+                self.loader.as_obj(),
+                "loadClass",
+                "(Ljava/lang/String;)Ljava/lang/Class;",
+                &[JValue::Object(&name)],
+            )
+        });
+        let class = clear_exception(env, result.and_then(|class| class.l()))?;
+        Ok(JClass::from(class))
+    }
+
+    /// Call a static method of a class loaded with [Self::load_class()].
+    pub fn call_static<'local>(
+        &self,
+        env: &mut JNIEnv<'local>,
+        class: &JClass,
+        method: &str,
+        signature: &str,
+        args: &[JValue],
+    ) -> JniResult<JValueOwned<'local>> {
+        let result = env.call_static_method(class, method, signature, args);
+        clear_exception(env, result)
+    }
+}
+
+#[test]
+fn test_from_module_dir() {
+    use std::{fs::File, os::fd::OwnedFd};
+
+    let root = crate::module_dir::TempDir::new("dex");
+    std::fs::write(root.join("classes.dex"), b"dex\n035\0").unwrap();
+    let dir = ModuleDir::from(OwnedFd::from(File::open(&root).unwrap()));
+
+    let injector = DexInjector::from_module_dir(&dir, "classes.dex").unwrap();
+    assert_eq!(injector.dex(), b"dex\n035\0");
+    assert!(DexInjector::from_module_dir(&dir, "missing.dex").is_err());
+
+    #[cfg(feature = "testing")]
+    {
+        let stub = crate::testing::StubEnv::new();
+        assert!(injector.load(&mut stub.env()).is_err());
+    }
+}
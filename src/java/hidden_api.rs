@@ -0,0 +1,160 @@
+use super::clear_exception;
+use crate::jni::{
+    errors::{Error as JniError, Result as JniResult},
+    objects::{JObject, JObjectArray, JValue},
+    JNIEnv,
+};
+
+const VM_RUNTIME: &str = "dalvik/system/VMRuntime";
+const INVOKE: &str = "(Ljava/lang/Object;[Ljava/lang/Object;)Ljava/lang/Object;";
+
+/// Exempt the members of the framework that start with the given type descriptor prefixes, such
+/// as `Landroid/app/ActivityThread;`, from the hidden API restrictions of Android 9 and later.
+/// `"L"` exempts all of them.
+///
+/// This calls `VMRuntime.setHiddenApiExemptions()`, which is itself hidden. It is called through
+/// JNI if possible, and otherwise through "meta-reflection": looking the methods up with a
+/// `getDeclaredMethod` obtained by reflection, so that the caller appears to be the framework.
+/// Java exceptions are printed to logcat and cleared.
+///
+/// The exemptions apply to the whole process, including the app, so they should be as narrow as
+/// the module allows.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{java, jni::JNIEnv};
+///
+/// fn post_app_specialize(env: &mut JNIEnv) {
+///     if let Err(e) = java::exempt_hidden_apis(env, &["Landroid/app/ActivityThread;"]) {
+///         eprintln!("failed to exempt hidden APIs: {e}");
+///     }
+/// }
+/// ```
+pub fn exempt_hidden_apis(env: &mut JNIEnv, prefixes: &[&str]) -> JniResult<()> {
+    // There are no restrictions before Android 9.
+    if crate::sysprop::get_int("ro.build.version.sdk").is_some_and(|sdk| sdk < 28) {
+        return Ok(());
+    }
+    let result = env.with_local_frame(16, |env| {
+        let prefixes = string_array(env, prefixes)?;
+        match set_directly(env, &prefixes) {
+            Err(JniError::JavaException) => {
+                env.exception_clear()?;
+                set_with_meta_reflection(env, &prefixes)
+            }
+            result => result,
+        }
+    });
+    clear_exception(env, result)
+}
+
+/// `VMRuntime.getRuntime().setHiddenApiExemptions(prefixes)`.
+fn set_directly(env: &mut JNIEnv, prefixes: &JObjectArray) -> JniResult<()> {
+    let runtime = env
+        .call_static_method(VM_RUNTIME, "getRuntime", "()Ldalvik/system/VMRuntime;", &[])?
+        .l()?;
+    env.call_method(
+        runtime,
+        "setHiddenApiExemptions",
+        "([Ljava/lang/String;)V",
+        &[JValue::Object(prefixes)],
+    )?;
+    Ok(())
+}
+
+/// The same calls, with methods looked up by a `Class.getDeclaredMethod` obtained by reflection.
+fn set_with_meta_reflection(env: &mut JNIEnv, prefixes: &JObjectArray) -> JniResult<()> {
+    let string_class = env.find_class("java/lang/String")?;
+    let class_array_class = env.find_class("[Ljava/lang/Class;")?;
+    let string_array_class = env.find_class("[Ljava/lang/String;")?;
+    let runtime_class = env.find_class(VM_RUNTIME)?;
+    let class_class = env.find_class("java/lang/Class")?;
+
+    let parameters = object_array(env, "java/lang/Class", &[&string_class, &class_array_class])?;
+    let name = env.new_string("getDeclaredMethod")?;
+    let get_declared_method = env
+        .call_method(
+            class_class,
+            "getDeclaredMethod",
+            "(Ljava/lang/String;[Ljava/lang/Class;)Ljava/lang/reflect/Method;",
+            &[JValue::Object(&name), JValue::Object(&parameters)],
+        )?
+        .l()?;
+
+    let get_runtime = find_method(env, &get_declared_method, &runtime_class, "getRuntime", &[])?;
+    let set_exemptions = find_method(
+        env,
+        &get_declared_method,
+        &runtime_class,
+        "setHiddenApiExemptions",
+        &[&string_array_class],
+    )?;
+
+    let no_args = object_array(env, "java/lang/Object", &[])?;
+    let runtime = invoke(env, &get_runtime, &JObject::null(), &no_args)?;
+    let args = object_array(env, "java/lang/Object", &[prefixes])?;
+    invoke(env, &set_exemptions, &runtime, &args)?;
+    Ok(())
+}
+
+/// `get_declared_method.invoke(class, name, parameters)`.
+fn find_method<'local>(
+    env: &mut JNIEnv<'local>,
+    get_declared_method: &JObject,
+    class: &JObject,
+    name: &str,
+    parameters: &[&JObject],
+) -> JniResult<JObject<'local>> {
+    let name = env.new_string(name)?;
+    let parameters = object_array(env, "java/lang/Class", parameters)?;
+    let args = object_array(env, "java/lang/Object", &[&name, &parameters])?;
+    invoke(env, get_declared_method, class, &args)
+}
+
+/// `method.invoke(receiver, args)`.
+fn invoke<'local>(
+    env: &mut JNIEnv<'local>,
+    method: &JObject,
+    receiver: &JObject,
+    args: &JObjectArray,
+) -> JniResult<JObject<'local>> {
+    env.call_method(
+        method,
+        "invoke",
+        INVOKE,
+        &[JValue::Object(receiver), JValue::Object(args)],
+    )?
+    .l()
+}
+
+fn object_array<'local>(
+    env: &mut JNIEnv<'local>,
+    class: &str,
+    elements: &[&JObject],
+) -> JniResult<JObjectArray<'local>> {
+    let array = env.new_object_array(elements.len() as _, class, JObject::null())?;
+    for (index, element) in elements.iter().enumerate() {
+        env.set_object_array_element(&array, index as _, element)?;
+    }
+    Ok(array)
+}
+
+fn string_array<'local>(
+    env: &mut JNIEnv<'local>,
+    strings: &[&str],
+) -> JniResult<JObjectArray<'local>> {
+    let array = env.new_object_array(strings.len() as _, "java/lang/String", JObject::null())?;
+    for (index, string) in strings.iter().enumerate() {
+        let string = crate::jni_util::string_to_jstring(env, string)?;
+        env.set_object_array_element(&array, index as _, string)?;
+    }
+    Ok(array)
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_exempt_hidden_apis() {
+    let stub = crate::testing::StubEnv::new();
+    assert!(exempt_hidden_apis(&mut stub.env(), &["L"]).is_err());
+}
@@ -0,0 +1,153 @@
+//! Conversions between Java and Rust strings that report failures instead of panicking.
+//!
+//! A panic in zygote takes down the app that is being launched, so these check for everything
+//! the JNI string functions do not: null strings, pending exceptions, and strings that are not
+//! valid modified UTF-8.
+
+use crate::jni::{
+    errors::{Error as JniError, Result as JniResult},
+    objects::JString,
+    JNIEnv,
+};
+
+/// Convert a Java string to a Rust string.
+///
+/// Fails with [NullPtr](JniError::NullPtr) if the string is null, and with
+/// [JavaException](JniError::JavaException) if an exception is pending, since calling into JNI
+/// is not allowed then. Invalid sequences in the modified UTF-8 returned by the VM, such as
+/// unpaired surrogates, are replaced with `U+FFFD`.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{jni::{objects::JString, JNIEnv}, jni_util};
+///
+/// fn log_name(env: &mut JNIEnv, name: &JString) {
+///     match jni_util::jstring_to_string(env, name) {
+///         Ok(name) => eprintln!("name: {name}"),
+///         Err(e) => eprintln!("invalid name: {e}"),
+///     }
+/// }
+/// ```
+pub fn jstring_to_string(env: &mut JNIEnv, string: &JString) -> JniResult<String> {
+    check_exception(env)?;
+    if string.is_null() {
+        return Err(JniError::NullPtr("jstring"));
+    }
+    let chars = env.get_string(string)?;
+    Ok(decode_modified_utf8(chars.to_bytes()))
+}
+
+/// Like [jstring_to_string()], but returns `None` for a null string.
+pub fn jstring_to_option(env: &mut JNIEnv, string: &JString) -> JniResult<Option<String>> {
+    if string.is_null() {
+        return Ok(None);
+    }
+    jstring_to_string(env, string).map(Some)
+}
+
+/// Convert a Rust string to a new Java string.
+///
+/// Fails with [JavaException](JniError::JavaException) if an exception is pending, or if the VM
+/// could not allocate the string. NUL characters are encoded as modified UTF-8 requires, so they
+/// are kept in the Java string.
+pub fn string_to_jstring<'local>(
+    env: &mut JNIEnv<'local>,
+    value: &str,
+) -> JniResult<JString<'local>> {
+    check_exception(env)?;
+    env.new_string(value)
+}
+
+fn check_exception(env: &mut JNIEnv) -> JniResult<()> {
+    if env.exception_check()? {
+        Err(JniError::JavaException)
+    } else {
+        Ok(())
+    }
+}
+
+/// Decode modified UTF-8, where NUL is encoded on two bytes and characters outside the BMP as
+/// surrogate pairs of three bytes each. Regular four byte sequences are accepted too.
+fn decode_modified_utf8(bytes: &[u8]) -> String {
+    if let Ok(string) = std::str::from_utf8(bytes) {
+        // Valid UTF-8 only differs from modified UTF-8 in the encodings above.
+        if !bytes.iter().any(|&b| b == 0xc0 || b == 0xed) {
+            return string.to_owned();
+        }
+    }
+
+    let mut string = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (code, len) = match decode_one(rest) {
+            Some((high @ 0xd800..=0xdbff, len)) => match decode_one(&rest[len..]) {
+                Some((low @ 0xdc00..=0xdfff, low_len)) => (
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    len + low_len,
+                ),
+                _ => (high, len),
+            },
+            Some(decoded) => decoded,
+            None => (0xfffd, 1),
+        };
+        string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+        rest = &rest[len..];
+    }
+    string
+}
+
+/// Decode a single sequence, without checking that the code point is a valid `char`.
+fn decode_one(bytes: &[u8]) -> Option<(u32, usize)> {
+    let len = match bytes[0] {
+        0x00..=0x7f => return Some((bytes[0] as u32, 1)),
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return None,
+    };
+    let sequence = bytes.get(..len)?;
+    if !sequence[1..].iter().all(|&b| b & 0xc0 == 0x80) {
+        return None;
+    }
+    let lead = (sequence[0] as u32) & (0x7f >> len);
+    let code = sequence[1..]
+        .iter()
+        .fold(lead, |code, &b| (code << 6) | (b & 0x3f) as u32);
+    Some((code, len))
+}
+
+#[test]
+fn test_decode_modified_utf8() {
+    assert_eq!(decode_modified_utf8(b"com.example"), "com.example");
+    assert_eq!(decode_modified_utf8("é✓".as_bytes()), "é✓");
+    assert_eq!(decode_modified_utf8(b"a\xc0\x80b"), "a\0b");
+    // U+1F600 as a surrogate pair, and as regular UTF-8.
+    assert_eq!(
+        decode_modified_utf8(b"\xed\xa0\xbd\xed\xb8\x80"),
+        "\u{1f600}"
+    );
+    assert_eq!(decode_modified_utf8("\u{1f600}".as_bytes()), "\u{1f600}");
+    // An unpaired surrogate, and truncated sequences.
+    assert_eq!(decode_modified_utf8(b"\xed\xa0\xbdx"), "\u{fffd}x");
+    assert_eq!(decode_modified_utf8(b"\xe2\x9c"), "\u{fffd}\u{fffd}");
+    assert_eq!(decode_modified_utf8(b"\xff"), "\u{fffd}");
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_jstring_conversions() {
+    use crate::jni::objects::JObject;
+
+    let stub = crate::testing::StubEnv::new();
+    let mut env = stub.env();
+    let string = string_to_jstring(&mut env, "a\0b").unwrap();
+    assert_eq!(jstring_to_string(&mut env, &string).unwrap(), "a\0b");
+
+    let null = JString::from(JObject::null());
+    assert!(matches!(
+        jstring_to_string(&mut env, &null),
+        Err(JniError::NullPtr(_))
+    ));
+    assert_eq!(jstring_to_option(&mut env, &null).unwrap(), None);
+}
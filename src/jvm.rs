@@ -0,0 +1,60 @@
+use std::sync::OnceLock;
+
+use crate::jni::{
+    errors::{Error as JniError, Result as JniResult},
+    JNIEnv, JavaVM,
+};
+
+pub use crate::jni::AttachGuard;
+
+/// The Java VM of the process, captured by the module glue before `on_load`.
+static JVM: OnceLock<JavaVM> = OnceLock::new();
+
+pub(crate) fn capture(env: &JNIEnv) {
+    if let Ok(vm) = env.get_java_vm() {
+        let _ = JVM.set(vm);
+    }
+}
+
+/// Get the Java VM of the process.
+///
+/// The `JNIEnv` passed to the module callbacks is only valid on the thread and for the duration
+/// of the callback. Hooks run later and on arbitrary threads, so they have to get an
+/// environment from the VM instead, usually with [attach_current_thread()].
+///
+/// Returns `None` before the module was loaded by `zygisk_module!`, such as in the companion.
+pub fn jvm() -> Option<&'static JavaVM> {
+    JVM.get()
+}
+
+/// Attach the current thread to the Java VM, and detach it when the guard is dropped.
+///
+/// Threads that are already attached, such as the ones created by the app in Java, are left
+/// attached. The guard dereferences to the [JNIEnv] of the thread.
+///
+/// ## Example
+///
+/// ```no_run
+/// // Started by a hook with `pthread_create`, after the module callbacks have returned.
+/// extern "C" fn hook_thread(_arg: *mut std::ffi::c_void) -> *mut std::ffi::c_void {
+///     if let Ok(mut env) = zygisk::attach_current_thread() {
+///         let _ = env.find_class("android/app/ActivityThread");
+///     }
+///     std::ptr::null_mut()
+/// }
+/// ```
+pub fn attach_current_thread() -> JniResult<AttachGuard<'static>> {
+    jvm()
+        .ok_or(JniError::NullPtr("JavaVM"))?
+        .attach_current_thread()
+}
+
+#[test]
+fn test_jvm() {
+    // Host tests do not run in a Java VM.
+    assert!(jvm().is_none());
+    assert!(matches!(
+        attach_current_thread(),
+        Err(JniError::NullPtr("JavaVM"))
+    ));
+}
@@ -1,14 +1,63 @@
 mod api;
+mod args;
+#[cfg(feature = "macros")]
+pub mod attr;
 mod binding;
+pub mod companion;
+#[cfg(any(feature = "config-toml", feature = "config-json"))]
+pub mod config;
 mod error;
+#[cfg(feature = "api-v4")]
+mod exempt;
+pub mod exports;
+mod filter;
+pub mod hooks;
+pub mod java;
+pub mod jni_util;
+mod jvm;
+mod logcat;
+#[cfg(feature = "logging")]
+pub mod logging;
 #[doc(hidden)]
 pub mod macros;
+pub mod maps;
 mod module;
+mod module_dir;
+pub mod native;
+mod process;
+pub mod raw_log;
+pub mod runtime;
+pub mod selinux;
+pub mod sysprop;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod uid;
 
 mod aux;
 pub use aux::*;
 
-pub use api::ZygiskApi;
-pub use binding::{AppSpecializeArgs, ServerSpecializeArgs, StateFlags, ZygiskOption, API_VERSION};
-pub use error::ZygiskError;
+pub use api::{PostSpecializeApi, PreSpecializeApi, RetainedApi, ZygiskApi};
+pub use args::{
+    AppSpecializeInfo, ArgsError, Capabilities, Gids, MountExternal, RuntimeFlags,
+    ServerSpecializeInfo,
+};
+pub use binding::{
+    ApiCapabilities, ApiVersion, AppSpecializeArgs, ServerSpecializeArgs, StateFlags, ZygiskOption,
+    API_VERSION,
+};
+#[cfg(feature = "raw")]
+pub use binding::{LegacyApiTable, ModuleAbi, RawApiTable};
+pub use companion::{CompanionHandler, SocketExt};
+pub use error::{UnknownFlags, ZygiskError};
+#[cfg(feature = "api-v4")]
+pub use exempt::ExemptedFd;
+pub use filter::{ProcessDecision, ProcessFilter};
+pub use hooks::{HookFailure, HookGuard, HookKind, InstalledHook, PltHookSession};
+pub use jvm::{attach_current_thread, jvm, AttachGuard};
+pub use maps::self_map;
+#[cfg(feature = "raw")]
+pub use module::RawModule;
 pub use module::ZygiskModule;
+pub use module_dir::{DirEntry, ModuleDir, ReadDir};
+pub use process::process_name;
+pub use uid::Uid;
@@ -0,0 +1,50 @@
+//! Minimal logcat output used by the crate itself.
+
+use std::ffi::CString;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+#[repr(i32)]
+pub(crate) enum Priority {
+    Verbose = 2,
+    Debug = 3,
+    Info = 4,
+    Warn = 5,
+    Error = 6,
+}
+
+pub(crate) const TAG: &std::ffi::CStr = c"zygisk-rs";
+
+#[cfg(target_os = "android")]
+extern "C" {
+    fn __android_log_write(
+        prio: std::os::raw::c_int,
+        tag: *const std::os::raw::c_char,
+        text: *const std::os::raw::c_char,
+    ) -> std::os::raw::c_int;
+}
+
+/// Write a single line to logcat. On non-Android targets (i.e. host tests), the message goes
+/// to stderr instead.
+pub(crate) fn write(priority: Priority, msg: &str) {
+    write_tagged(priority, TAG, msg);
+}
+
+/// Like [write()], with the given tag.
+pub(crate) fn write_tagged(priority: Priority, tag: &std::ffi::CStr, msg: &str) {
+    // Interior NULs would truncate the message; replace them rather than dropping it.
+    let msg = CString::new(msg.replace('\0', "\u{FFFD}")).unwrap_or_default();
+
+    #[cfg(target_os = "android")]
+    unsafe {
+        __android_log_write(priority as _, tag.as_ptr(), msg.as_ptr());
+    }
+
+    #[cfg(not(target_os = "android"))]
+    eprintln!(
+        "{}: [{}] {}",
+        tag.to_string_lossy(),
+        priority as i32,
+        msg.to_string_lossy()
+    );
+}
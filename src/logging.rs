@@ -0,0 +1,161 @@
+//! A [log] backend writing to logcat.
+//!
+//! With the `logging` feature, modules registered with [zygisk_module!](crate::zygisk_module)
+//! get it installed before [on_load](crate::ZygiskModule::on_load), tagged with the name of the
+//! module crate, so the `log` macros work out of the box:
+//!
+//! ```
+//! use zygisk::{ZygiskApi, ZygiskModule};
+//! use zygisk::jni::JNIEnv;
+//!
+//! struct MyModule;
+//! impl ZygiskModule for MyModule {
+//!     fn on_load(&self, api: ZygiskApi, _env: JNIEnv) {
+//!         log::info!("loaded with API {:?}", api.api_version());
+//!     }
+//! }
+//! ```
+//!
+//! Elsewhere, such as in the companion process, call [init()].
+//!
+//! Specialized app processes cannot write files, and logcat may be filtered on the device. Call
+//! [forward_to_companion()] in `pre_app_specialize` to also ship the log output to the companion,
+//! which keeps it in the module directory.
+
+use std::{
+    ffi::CString,
+    io::Write,
+    os::unix::net::UnixStream,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{
+    companion::handshake::Service,
+    logcat::{self, Priority},
+    SocketExt, ZygiskApi, ZygiskError,
+};
+
+/// The size of the batches of lines sent to the companion.
+const BATCH_SIZE: usize = 4096;
+
+struct Logger {
+    tag: CString,
+    forward: Mutex<Option<Forwarder>>,
+}
+
+struct Forwarder {
+    stream: UnixStream,
+    batch: Vec<u8>,
+}
+
+impl Forwarder {
+    fn send(&mut self) -> std::io::Result<()> {
+        if !self.batch.is_empty() {
+            self.stream.send_bytes(&self.batch)?;
+            self.batch.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let priority = match record.level() {
+            Level::Error => Priority::Error,
+            Level::Warn => Priority::Warn,
+            Level::Info => Priority::Info,
+            Level::Debug => Priority::Debug,
+            Level::Trace => Priority::Verbose,
+        };
+        let message = record.args().to_string();
+        logcat::write_tagged(priority, &self.tag, &message);
+
+        let mut forward = self.forward.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(forwarder) = forward.as_mut() {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let _ = writeln!(
+                forwarder.batch,
+                "{}.{:03} {} {} {}: {message}",
+                time.as_secs(),
+                time.subsec_millis(),
+                std::process::id(),
+                record.level(),
+                record.target(),
+            );
+            // Errors and warnings often come right before the process dies, so send them away.
+            if (forwarder.batch.len() >= BATCH_SIZE || record.level() <= Level::Warn)
+                && forwarder.send().is_err()
+            {
+                // The companion is gone; keep logging to logcat only.
+                *forward = None;
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let mut forward = self.forward.lock().unwrap_or_else(|e| e.into_inner());
+        if forward.as_mut().is_some_and(|f| f.send().is_err()) {
+            *forward = None;
+        }
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Install the logcat logger with `tag`, unless a logger is already installed.
+///
+/// Everything is logged in debug builds, and messages up to [Level::Info] in release builds.
+pub fn init(tag: &str) {
+    let logger = LOGGER.get_or_init(|| Logger {
+        tag: CString::new(tag.replace('\0', "")).unwrap_or_default(),
+        forward: Mutex::new(None),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(if cfg!(debug_assertions) {
+            LevelFilter::Trace
+        } else {
+            LevelFilter::Info
+        });
+    }
+}
+
+/// Also send the log output of this process to the companion, which appends it to
+/// `logs/<tag>.log` in the module directory, rotating the file once it reaches 1 MiB.
+///
+/// Lines are sent in batches, right away for warnings and errors; call [log::logger()]`.flush()`
+/// to send the pending ones. This is one of the
+/// [built-in services](crate::companion#built-in-services), enabled with
+/// `zygisk_companion!(handler, services = [logs], module_dir = "...")`. The connection is
+/// exempted from being closed by zygote where supported.
+pub fn forward_to_companion(api: &ZygiskApi) -> Result<(), ZygiskError> {
+    // The glue installs the logger before `on_load`, so this only matters for modules registered
+    // by hand.
+    init(&logcat::TAG.to_string_lossy());
+    let logger = LOGGER.get().expect("initialized above");
+    let mut stream = api.connect_companion_service(Service::Logs)?;
+    stream
+        .send_str(&logger.tag.to_string_lossy())
+        .map_err(ZygiskError::CompanionRequestFailed)?;
+    #[cfg(feature = "api-v4")]
+    if api.supports_exempt_fd() {
+        api.exempt_fd(&stream)?;
+    }
+
+    *logger.forward.lock().unwrap_or_else(|e| e.into_inner()) = Some(Forwarder {
+        stream,
+        batch: Vec::new(),
+    });
+    Ok(())
+}
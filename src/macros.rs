@@ -1,33 +1,394 @@
 pub use crate::jni::JNIEnv;
 
+use std::{
+    os::{fd::FromRawFd, unix::io::RawFd, unix::net::UnixStream},
+    sync::OnceLock,
+};
+
 use crate::{
-    binding::{ModuleAbi, RawApiTable},
+    binding::{ApiVersion, ModuleAbi, RawApiTable},
+    companion::{
+        self,
+        handshake::{self, Service},
+        BuiltinServices,
+    },
+    logcat,
     module::RawModule,
-    ZygiskApi, ZygiskModule,
+    CompanionHandler, ZygiskApi, ZygiskError, ZygiskModule,
 };
 
+/// Install the logcat logger, tagged with the name of the module crate, if the `logging` feature
+/// is enabled.
+#[allow(unused_variables)]
+pub fn init_logging(tag: &str) {
+    #[cfg(feature = "logging")]
+    crate::logging::init(tag);
+}
+
+/// Install a panic hook that writes the panic message and a backtrace to logcat, tagged with the
+/// name of the module crate, before running the previous hook.
+///
+/// Release builds of zygote children usually have no stderr, so the glue aborts on panics without
+/// any trace otherwise.
+///
+/// With the `tiny` feature, only the message and the file of the panic are written, and the hook
+/// aborts the process right away.
+pub fn install_panic_hook(tag: &str) {
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    INSTALLED.get_or_init(|| {
+        let tag = std::ffi::CString::new(tag.replace('\0', "")).unwrap_or_default();
+        #[cfg(not(feature = "tiny"))]
+        let previous = std::panic::take_hook();
+        #[cfg(not(feature = "tiny"))]
+        std::panic::set_hook(Box::new(move |info| {
+            let backtrace = std::backtrace::Backtrace::force_capture();
+            // logcat truncates long messages, so write the backtrace line by line.
+            let message = format!("{info}\n{backtrace}");
+            for line in message.lines() {
+                logcat::write_tagged(logcat::Priority::Error, &tag, line);
+            }
+            previous(info);
+        }));
+        #[cfg(feature = "tiny")]
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => message,
+                None => payload.downcast_ref::<String>().map_or("", String::as_str),
+            };
+            let mut line = String::from("panicked: ");
+            line.push_str(message);
+            if let Some(location) = info.location() {
+                line.push_str(" at ");
+                line.push_str(location.file());
+            }
+            logcat::write_tagged(logcat::Priority::Error, &tag, &line);
+            std::process::abort();
+        }));
+    });
+}
+
+/// Run a part of the module, returning `None` if it panicked.
+///
+/// With the `tiny` feature, panics are not caught, and abort the process through the panic hook
+/// instead (see [install_panic_hook()]).
+#[inline(always)]
+pub fn catch_panic<R>(f: impl FnOnce() -> R) -> Option<R> {
+    #[cfg(not(feature = "tiny"))]
+    return std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).ok();
+    #[cfg(feature = "tiny")]
+    Some(f())
+}
+
+/// Log an error of the glue. With the `tiny` feature, only `summary` is logged, so that the
+/// formatting of the error is left out of the module.
+#[cfg(not(feature = "tiny"))]
+#[cold]
+#[inline(never)]
+fn log_error(error: impl std::fmt::Display, _summary: &str) {
+    logcat::write(logcat::Priority::Error, &error.to_string());
+}
+
+#[cfg(feature = "tiny")]
+#[cold]
+#[inline(never)]
+fn log_error(_error: impl std::fmt::Display, summary: &str) {
+    logcat::write(logcat::Priority::Error, summary);
+}
+
+/// What the glue does when the module panics, set with `zygisk_module!(..., panic = "...")`.
+///
+/// With the `tiny` feature, panics always abort the process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Abort the process, taking the app down with the module.
+    Abort,
+    /// Stop calling the module in the current process and let the app run.
+    LogAndContinue,
+}
+
+impl PanicPolicy {
+    pub const fn parse(policy: &str) -> PanicPolicy {
+        match policy.as_bytes() {
+            b"abort" => PanicPolicy::Abort,
+            b"log-and-continue" => PanicPolicy::LogAndContinue,
+            _ => panic!("the panic policy must be \"abort\" or \"log-and-continue\""),
+        }
+    }
+
+    /// Handle a panic caught by the glue. Panic messages are written to logcat by the panic hook.
+    #[cold]
+    pub fn on_panic(self) {
+        match self {
+            PanicPolicy::Abort => std::process::abort(),
+            PanicPolicy::LogAndContinue => logcat::write(
+                logcat::Priority::Error,
+                "the module panicked and is disabled in this process",
+            ),
+        }
+    }
+}
+
 #[inline(always)]
-pub fn module_entry_impl(module: &'static dyn ZygiskModule, table: *const (), env: *mut ()) {
-    // Currently a Zygisk module doesn't have a destructor, so we just have to
-    // leak some heap memory. (And yes, we have to do `Box::leak` TWICE: one
-    // for `RawModule`, and the other for `ModuleAbi`.)
-    // Note that the original version also leaks memory, but it saves one leak
-    // compared to us, thanks to C++ not using fat pointers. Lucky them :(
-    let raw_module = Box::leak(Box::new(RawModule {
+pub fn module_entry_impl(
+    module: &'static dyn ZygiskModule,
+    protocol: u32,
+    panic_policy: PanicPolicy,
+    table: *const (),
+    env: *mut (),
+) {
+    handshake::set_module_protocol(protocol);
+
+    // Currently a Zygisk module doesn't have a destructor, so the module and the callback table
+    // are leaked, in a single allocation.
+    let module_abi = ModuleAbi::leak(RawModule {
         inner: module,
         api_table: table.cast(),
+        api_version: ApiVersion::LATEST,
         jni_env: env.cast(),
-    }));
+        skipped: false,
+        panic_policy,
+    });
 
     // Cast arguments to their concrete types.
     let table: &'static RawApiTable = unsafe { &*table.cast() };
     let env: JNIEnv = unsafe { JNIEnv::from_raw(env.cast()).unwrap() };
+    crate::jvm::capture(&env);
+
+    match register_module(table, module_abi) {
+        Ok(version) => {
+            let api = ZygiskApi::from_raw(table, version);
+            #[cfg(feature = "tracing")]
+            let _span = tracing::info_span!("zygisk_on_load", api_version = ?version).entered();
+            if catch_panic(|| module.on_load(api, env)).is_none() {
+                panic_policy.on_panic();
+                module_abi.this.skipped = true;
+            }
+        }
+        Err(e) => log_error(e, "failed to register the module"),
+    }
+}
+
+/// Register the module with the newest API version that the host accepts.
+///
+/// The host rejects versions newer than what it supports, so walk down from
+/// the latest one until registration succeeds.
+fn register_module(
+    table: &RawApiTable,
+    module_abi: &mut ModuleAbi,
+) -> Result<ApiVersion, ZygiskError> {
+    let register = table
+        .register_module
+        .ok_or(ZygiskError::ApiFunctionUnavailable("register_module"))?;
+    let mut version = Some(ApiVersion::LATEST);
+    while let Some(current) = version {
+        module_abi.api_version = current as _;
+        module_abi.this.api_version = current;
+        if register(table, module_abi) {
+            return Ok(current);
+        }
+        version = current.previous();
+    }
+    Err(ZygiskError::RegisterModuleRejected)
+}
+
+#[cfg(feature = "api-v3")]
+#[test]
+fn test_register_module_fallback() {
+    extern "C" fn register_v3(_table: *const RawApiTable, module: *mut ModuleAbi) -> bool {
+        unsafe { (*module).api_version <= 3 }
+    }
+    extern "C" fn register_none(_table: *const RawApiTable, _module: *mut ModuleAbi) -> bool {
+        false
+    }
+
+    struct DummyModule;
+    impl ZygiskModule for DummyModule {}
 
-    let module_abi = Box::leak(Box::new(ModuleAbi::from_module(raw_module)));
-    if table.register_module.unwrap()(table, module_abi) {
-        let api = ZygiskApi::from_raw(table);
-        module.on_load(api, env);
+    let mut table = RawApiTable::empty();
+    let module_abi = ModuleAbi::leak(RawModule {
+        inner: &DummyModule,
+        api_table: &table,
+        api_version: ApiVersion::LATEST,
+        jni_env: std::ptr::null_mut(),
+        skipped: false,
+        panic_policy: PanicPolicy::Abort,
+    });
+
+    table.register_module = Some(register_v3);
+    assert_eq!(register_module(&table, module_abi).unwrap(), ApiVersion::V3);
+    assert_eq!(module_abi.this.api_version, ApiVersion::V3);
+
+    table.register_module = Some(register_none);
+    assert!(matches!(
+        register_module(&table, module_abi),
+        Err(ZygiskError::RegisterModuleRejected)
+    ));
+}
+
+/// Take ownership of the socket passed by zygiskd and run the protocol handshake on it.
+///
+/// Returns `None` if the handshake failed, in which case the connection should be dropped, or if
+/// the module asked for one of the built-in services, which is handled here. The services of
+/// [BuiltinServices] are only served if they are in `services`, and through the hooks of
+/// `handler`. Those working in the module directory open it at `module_dir`.
+///
+/// [CompanionHandler::on_daemon_load()] runs once the handshake is answered, so that a slow load
+/// does not look like a companion that never answers to the module.
+pub fn companion_accept(
+    socket_fd: RawFd,
+    protocol: u32,
+    services: BuiltinServices,
+    module_dir: Option<&str>,
+    handler: Option<&'static dyn CompanionHandler>,
+) -> Option<UnixStream> {
+    // SAFETY: it is guaranteed by zygiskd that the argument is a valid socket fd.
+    let mut stream = unsafe { UnixStream::from_raw_fd(socket_fd) };
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("zygisk_companion_request", protocol).entered();
+    let service = handshake::server(&mut stream, protocol);
+    #[cfg(feature = "tracing")]
+    tracing::debug!(service = ?service.as_ref().ok());
+    if let (Ok(_), Some(handler)) = (&service, handler) {
+        companion_handler_load(handler);
+    }
+    match service {
+        Ok(Service::Handler) => Some(stream),
+        Ok(service) => {
+            companion::serve_builtin(service, stream, services, module_dir, handler);
+            None
+        }
+        Err(e) => {
+            log_error(e, "companion handshake failed");
+            None
+        }
+    }
+}
+
+static COMPANION_HANDLER: OnceLock<&'static dyn CompanionHandler> = OnceLock::new();
+
+/// Run [CompanionHandler::on_daemon_load()] on the first connection.
+pub fn companion_handler_load(handler: &'static dyn CompanionHandler) {
+    COMPANION_HANDLER.get_or_init(|| {
+        handler.on_daemon_load();
+        // SAFETY: `companion_handler_exit` only reads the handler, which is `'static`.
+        unsafe { crate::libc::atexit(companion_handler_exit) };
+        handler
+    });
+}
+
+pub fn companion_handler_entry(handler: &'static dyn CompanionHandler, stream: UnixStream) {
+    companion_handler_load(handler);
+    if !companion::authorize(handler, &stream) {
+        return;
     }
+    let _guard = companion::watch(handler, &stream);
+    handler.on_connect(stream);
+}
+
+extern "C" fn companion_handler_exit() {
+    if let Some(handler) = COMPANION_HANDLER.get() {
+        handler.on_daemon_exit();
+    }
+}
+
+/// The runtime shared by all async companion handlers of the companion process.
+#[cfg(feature = "tokio")]
+pub fn companion_runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .thread_name("zygisk-companion")
+            .build()
+            .expect("failed to build the companion runtime")
+    })
+}
+
+#[cfg(feature = "tokio")]
+pub fn companion_entry_async<F, Fut>(
+    socket_fd: RawFd,
+    protocol: u32,
+    services: BuiltinServices,
+    module_dir: Option<&str>,
+    func: F,
+) where
+    F: FnOnce(tokio::net::UnixStream) -> Fut,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    let Some(stream) = companion_accept(socket_fd, protocol, services, module_dir, None) else {
+        return;
+    };
+
+    // zygiskd closes the fd it passed in if it is still open after we return, so hand a
+    // duplicate over to the task and close the original one here.
+    let stream = match stream.try_clone().and_then(|dup| {
+        dup.set_nonblocking(true)?;
+        Ok(dup)
+    }) {
+        Ok(dup) => dup,
+        Err(e) => {
+            logcat::write(
+                logcat::Priority::Error,
+                &format!("failed to set up the companion stream: {e}"),
+            );
+            return;
+        }
+    };
+
+    let runtime = companion_runtime();
+    let _guard = runtime.enter();
+    let stream = match tokio::net::UnixStream::from_std(stream) {
+        Ok(stream) => stream,
+        Err(e) => {
+            logcat::write(
+                logcat::Priority::Error,
+                &format!("failed to register the companion stream: {e}"),
+            );
+            return;
+        }
+    };
+
+    let task = runtime.spawn(func(stream));
+    runtime.spawn(async move {
+        if task.await.is_err_and(|e| e.is_panic()) {
+            // Panic messages are written to logcat by the panic hook.
+            std::process::abort();
+        }
+    });
+}
+
+/// Implemented for every [Sync] type, so that passing a non-Sync module to `zygisk_module!`
+/// reports what the macro needs rather than only the missing `Sync` bound.
+#[diagnostic::on_unimplemented(
+    message = "ZygiskModule used with zygisk_module! must be Sync",
+    label = "`{Self}` is not Sync",
+    note = "the module is shared by every callback, which may run on different threads"
+)]
+pub trait SyncModule {}
+
+#[diagnostic::do_not_recommend]
+impl<T: Sync + ?Sized> SyncModule for T {}
+
+// Named so that the compiler error reads "required by a bound in `module_must_be_sync`" when
+// a non-Sync module is passed to `zygisk_module!`.
+#[inline(always)]
+pub fn module_must_be_sync<T: SyncModule + ?Sized>(_module: &T) {}
+
+/// Where `zygisk_module!(|| ...)` keeps the module it built, in a static of its own.
+pub type ModuleCell = OnceLock<&'static (dyn ZygiskModule + Sync)>;
+
+/// Leak a module built at load time by `zygisk_module!(|| ...)`, like the glue does for the rest
+/// of the module state.
+pub fn module_from_constructor<M, F>(
+    cell: &'static ModuleCell,
+    constructor: F,
+) -> &'static (dyn ZygiskModule + Sync)
+where
+    M: ZygiskModule + Sync + 'static,
+    F: FnOnce() -> M,
+{
+    *cell.get_or_init(|| Box::leak(Box::new(constructor())))
 }
 
 /// Register a static variable as a Zygisk module.
@@ -43,18 +404,179 @@ pub fn module_entry_impl(module: &'static dyn ZygiskModule, table: *const (), en
 /// static MODULE: DummyModule = DummyModule;
 /// zygisk_module!(&MODULE);
 /// ```
+///
+/// To build the module at load time, from system properties or files in the module directory
+/// for instance, pass a closure constructing it instead. It is called once, when the module is
+/// loaded, and the module then lives as long as the process:
+///
+/// ```
+/// use zygisk::{zygisk_module, ZygiskModule};
+///
+/// struct VerboseModule {
+///     verbose: bool,
+/// }
+/// impl ZygiskModule for VerboseModule {}
+///
+/// impl VerboseModule {
+///     fn from_env() -> Self {
+///         VerboseModule {
+///             verbose: std::env::var_os("VERBOSE").is_some(),
+///         }
+///     }
+/// }
+///
+/// zygisk_module!(|| VerboseModule::from_env());
+/// ```
+///
+/// By default, a panic in the module aborts the process, which kills the app. To disable the
+/// module in the process instead and let the app run, add `panic = "log-and-continue"`
+/// (`panic = "abort"` being the default). Either way, the panic is logged to logcat:
+///
+/// ```
+/// # use zygisk::{zygisk_module, ZygiskModule};
+/// # struct DummyModule;
+/// # impl ZygiskModule for DummyModule {}
+/// # static MODULE: DummyModule = DummyModule;
+/// zygisk_module!(&MODULE, panic = "log-and-continue");
+/// ```
+///
+/// With the `tiny` feature, panics always abort right away, and are logged without a backtrace;
+/// building the module with `panic = "abort"` in its profile then also drops the unwinding
+/// tables.
+///
+/// Some Zygisk implementations look for other entry symbols, such as versioned ones. Add
+/// `aliases = [...]` to export the entry point under these names too, after `panic` if both are
+/// given:
+///
+/// ```
+/// # use zygisk::{zygisk_module, ZygiskModule};
+/// # struct DummyModule;
+/// # impl ZygiskModule for DummyModule {}
+/// # static MODULE: DummyModule = DummyModule;
+/// zygisk_module!(&MODULE, aliases = ["zygisk_module_entry_v4"]);
+/// ```
+///
+/// `zygisk_module_entry` and its aliases are the only symbols the macro exports; see
+/// [exports](crate::exports) to check that the rest of the module does not export any either.
+///
+/// The module is shared by every callback, so it has to be [Sync]. Non-Sync modules are
+/// rejected at compile time with
+/// ``error[E0277]: ZygiskModule used with zygisk_module! must be Sync``, pointing at the
+/// module expression:
+///
+/// ```compile_fail,E0277
+/// use std::cell::Cell;
+/// use zygisk::{zygisk_module, ZygiskModule};
+///
+/// struct CounterModule(Cell<u32>);
+/// impl ZygiskModule for CounterModule {}
+///
+/// zygisk_module!(Box::leak(Box::new(CounterModule(Cell::new(0)))));
+/// ```
 #[macro_export]
 macro_rules! zygisk_module {
-    ($module: expr) => {
-        #[no_mangle]
-        extern "C" fn zygisk_module_entry(table: *const (), env: *mut ()) {
-            if let Err(_) = std::panic::catch_unwind(|| {
-                $crate::macros::module_entry_impl($module, table, env);
-            }) {
-                // Panic messages should be displayed by the default panic hook.
-                std::process::abort();
+    (
+        || $constructor: expr
+        $(, panic = $policy: literal)?
+        $(, aliases = [$($alias: literal),* $(,)?])?
+    ) => {
+        // Kept out of the namespace of the crate; only the symbol itself is exported.
+        const _: () = {
+            #[no_mangle]
+            extern "C" fn zygisk_module_entry(table: *const (), env: *mut ()) {
+                const POLICY: $crate::macros::PanicPolicy = $crate::__panic_policy!($($policy)?);
+                static MODULE: $crate::macros::ModuleCell = $crate::macros::ModuleCell::new();
+                $crate::macros::install_panic_hook(env!("CARGO_PKG_NAME"));
+                if $crate::macros::catch_panic(|| {
+                    $crate::macros::init_logging(env!("CARGO_PKG_NAME"));
+                    $crate::macros::module_entry_impl(
+                        $crate::macros::module_from_constructor(&MODULE, || $constructor),
+                        $crate::__companion_protocol!(),
+                        POLICY,
+                        table,
+                        env,
+                    );
+                })
+                .is_none()
+                {
+                    POLICY.on_panic();
+                }
             }
-        }
+
+            $($(
+                const _: () = {
+                    #[export_name = $alias]
+                    extern "C" fn alias(table: *const (), env: *mut ()) {
+                        zygisk_module_entry(table, env)
+                    }
+                };
+            )*)?
+        };
+    };
+    (
+        $module: expr
+        $(, panic = $policy: literal)?
+        $(, aliases = [$($alias: literal),* $(,)?])?
+    ) => {
+        const _: fn() = || {
+            $crate::macros::module_must_be_sync($module);
+        };
+
+        const _: () = {
+            #[no_mangle]
+            extern "C" fn zygisk_module_entry(table: *const (), env: *mut ()) {
+                const POLICY: $crate::macros::PanicPolicy = $crate::__panic_policy!($($policy)?);
+                $crate::macros::install_panic_hook(env!("CARGO_PKG_NAME"));
+                if $crate::macros::catch_panic(|| {
+                    $crate::macros::init_logging(env!("CARGO_PKG_NAME"));
+                    $crate::macros::module_entry_impl(
+                        $module,
+                        $crate::__companion_protocol!(),
+                        POLICY,
+                        table,
+                        env,
+                    );
+                })
+                .is_none()
+                {
+                    POLICY.on_panic();
+                }
+            }
+
+            $($(
+                const _: () = {
+                    #[export_name = $alias]
+                    extern "C" fn alias(table: *const (), env: *mut ()) {
+                        zygisk_module_entry(table, env)
+                    }
+                };
+            )*)?
+        };
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __panic_policy {
+    () => {
+        $crate::macros::PanicPolicy::Abort
+    };
+    ($policy: literal) => {
+        $crate::macros::PanicPolicy::parse($policy)
+    };
+}
+
+// The protocol version exchanged by `ZygiskApi::connect_companion` and the companion entry, taken
+// from the crate invoking the macros so that every release of a module gets a new one.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __companion_protocol {
+    () => {
+        $crate::companion::handshake::protocol_version(concat!(
+            env!("CARGO_PKG_NAME"),
+            "@",
+            env!("CARGO_PKG_VERSION")
+        ))
     };
 }
 
@@ -67,6 +589,30 @@ macro_rules! zygisk_module {
 ///
 /// Note: the function may be run concurrently on multiple threads.
 ///
+/// Before the function is called, the companion checks that the connecting module was built from
+/// the same crate version (see [ZygiskApi::connect_companion()]). Connections from a mismatched
+/// module are logged and dropped.
+///
+/// To get daemon lifecycle hooks, pass a reference to a static [CompanionHandler](crate::CompanionHandler)
+/// instead: `zygisk_companion!(&HANDLER)`.
+///
+/// The [built-in services](crate::companion::BuiltinServices) that act as root for the module are
+/// disabled unless they are listed after the handler. Those working in the module directory also
+/// need to know where the module is installed:
+///
+/// ```
+/// use std::os::unix::net::UnixStream;
+/// use zygisk::zygisk_companion;
+///
+/// fn companion_main(_socket: UnixStream) {}
+///
+/// zygisk_companion!(
+///     companion_main,
+///     services = [open_as_root, config],
+///     module_dir = "/data/adb/modules/my-module",
+/// );
+/// ```
+///
 /// ## Example
 ///
 /// ```
@@ -88,28 +634,224 @@ macro_rules! zygisk_module {
 ///    // ...
 /// });
 /// ```
+///
+/// With the `tokio` feature, prefix the function with `async` to register an async handler. It
+/// receives a `tokio::net::UnixStream` and is spawned onto a multi-threaded runtime shared by the
+/// whole companion process, so the zygiskd thread is released right away:
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # mod example {
+/// use zygisk::{tokio::net::UnixStream, zygisk_companion};
+///
+/// async fn companion_main(_socket: UnixStream) {}
+///
+/// zygisk_companion!(async companion_main);
+/// # }
+/// ```
 #[macro_export]
 macro_rules! zygisk_companion {
-    ($func: expr) => {
-        #[no_mangle]
-        extern "C" fn zygisk_companion_entry(socket_fd: ::std::os::unix::io::RawFd) {
-            // SAFETY: it is guaranteed by zygiskd that the argument is a valid
-            // socket fd.
-            let stream = unsafe {
-                <::std::os::unix::net::UnixStream as ::std::os::fd::FromRawFd>::from_raw_fd(
+    (
+        async $func: expr $(, services = [$($service: ident),* $(,)?])?
+        $(, module_dir = $module_dir: literal)? $(,)?
+    ) => {
+        // Kept out of the namespace of the crate; only the symbol itself is exported.
+        const _: () = {
+            #[no_mangle]
+            extern "C" fn zygisk_companion_entry(socket_fd: ::std::os::unix::io::RawFd) {
+                $crate::macros::install_panic_hook(env!("CARGO_PKG_NAME"));
+                let _type_check: fn($crate::tokio::net::UnixStream) -> _ = $func;
+                if $crate::macros::catch_panic(|| {
+                    $crate::macros::companion_entry_async(
+                        socket_fd,
+                        $crate::__companion_protocol!(),
+                        $crate::__companion_services!($($($service),*)?),
+                        $crate::__companion_module_dir!($($module_dir)?),
+                        _type_check,
+                    )
+                })
+                .is_none()
+                {
+                    // Panic messages are written to logcat by the panic hook.
+                    ::std::process::abort();
+                }
+            }
+        };
+    };
+    (
+        & $handler: expr $(, services = [$($service: ident),* $(,)?])?
+        $(, module_dir = $module_dir: literal)? $(,)?
+    ) => {
+        const _: () = {
+            #[no_mangle]
+            extern "C" fn zygisk_companion_entry(socket_fd: ::std::os::unix::io::RawFd) {
+                $crate::macros::install_panic_hook(env!("CARGO_PKG_NAME"));
+                if $crate::macros::catch_panic(|| {
+                    let Some(stream) = $crate::macros::companion_accept(
+                        socket_fd,
+                        $crate::__companion_protocol!(),
+                        $crate::__companion_services!($($($service),*)?),
+                        $crate::__companion_module_dir!($($module_dir)?),
+                        ::std::option::Option::Some(&$handler),
+                    ) else {
+                        return;
+                    };
+                    $crate::macros::companion_handler_entry(&$handler, stream)
+                })
+                .is_none()
+                {
+                    // Panic messages are written to logcat by the panic hook.
+                    ::std::process::abort();
+                }
+            }
+        };
+    };
+    (
+        $func: expr $(, services = [$($service: ident),* $(,)?])?
+        $(, module_dir = $module_dir: literal)? $(,)?
+    ) => {
+        const _: () = {
+            #[no_mangle]
+            extern "C" fn zygisk_companion_entry(socket_fd: ::std::os::unix::io::RawFd) {
+                $crate::macros::install_panic_hook(env!("CARGO_PKG_NAME"));
+                let Some(stream) = $crate::macros::companion_accept(
                     socket_fd,
-                )
-            };
+                    $crate::__companion_protocol!(),
+                    $crate::__companion_services!($($($service),*)?),
+                    $crate::__companion_module_dir!($($module_dir)?),
+                    ::std::option::Option::None,
+                ) else {
+                    return;
+                };
 
-            // Call the actual function.
-            let _type_check: fn(::std::os::unix::net::UnixStream) = $func;
-            if let Err(_) = ::std::panic::catch_unwind(|| _type_check(stream)) {
-                // Panic messages should be displayed by the default panic hook.
-                ::std::process::abort();
+                // Call the actual function.
+                let _type_check: fn(::std::os::unix::net::UnixStream) = $func;
+                if $crate::macros::catch_panic(|| _type_check(stream)).is_none() {
+                    // Panic messages are written to logcat by the panic hook.
+                    ::std::process::abort();
+                }
+
+                // It is both OK for us to close the fd or not to, since zygiskd
+                // makes use of some nasty `fstat` tricks to handle both situations.
             }
+        };
+    };
+}
 
-            // It is both OK for us to close the fd or not to, since zygiskd
-            // makes use of some nasty `fstat` tricks to handle both situations.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __companion_services {
+    ($($service: ident),*) => {
+        $crate::companion::BuiltinServices::empty()
+            $(.union($crate::companion::service_names::$service))*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __companion_module_dir {
+    () => {
+        ::std::option::Option::None
+    };
+    ($module_dir: literal) => {
+        ::std::option::Option::Some($module_dir)
+    };
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_companion_entry_async() {
+    use crate::SocketExt;
+    use std::{io::Read, os::fd::IntoRawFd};
+
+    let (mut module_side, companion_side) = UnixStream::pair().unwrap();
+    module_side.send_u32(7).unwrap();
+    module_side.send_u8(Service::Handler as u8).unwrap();
+    let services = BuiltinServices::empty();
+    companion_entry_async(
+        companion_side.into_raw_fd(),
+        7,
+        services,
+        None,
+        |stream| async move {
+            stream.writable().await.unwrap();
+            stream.try_write(b"pong").unwrap();
+        },
+    );
+
+    assert_eq!(module_side.recv_u32().unwrap(), 7);
+    let mut buf = [0u8; 4];
+    module_side.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"pong");
+}
+
+#[test]
+fn test_panic_policy() {
+    assert_eq!(PanicPolicy::parse("abort"), PanicPolicy::Abort);
+    assert_eq!(
+        PanicPolicy::parse("log-and-continue"),
+        PanicPolicy::LogAndContinue
+    );
+    assert!(std::panic::catch_unwind(|| PanicPolicy::parse("ignore")).is_err());
+}
+
+#[cfg(not(feature = "tiny"))]
+#[test]
+fn test_catch_panic() {
+    assert_eq!(catch_panic(|| 1), Some(1));
+    assert_eq!(catch_panic(|| -> i32 { panic!("caught") }), None);
+}
+
+#[test]
+fn test_module_from_constructor() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Not zero-sized, so that leaked modules have distinct addresses.
+    struct Module(#[allow(dead_code)] u8);
+    impl ZygiskModule for Module {}
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static FIRST: ModuleCell = ModuleCell::new();
+    static SECOND: ModuleCell = ModuleCell::new();
+    let constructor = || {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        Module(0)
+    };
+    let first = module_from_constructor(&FIRST, constructor) as *const _ as *const ();
+    let again = module_from_constructor(&FIRST, constructor) as *const _ as *const ();
+    assert_eq!(first, again);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+    // Each call site has its own module, even with the same module type.
+    let second = module_from_constructor(&SECOND, constructor) as *const _ as *const ();
+    assert_ne!(first, second);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_companion_handler_lifecycle() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counter {
+        loads: AtomicUsize,
+        connects: AtomicUsize,
+    }
+    impl CompanionHandler for Counter {
+        fn on_daemon_load(&self) {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+        }
+        fn on_connect(&self, _stream: UnixStream) {
+            self.connects.fetch_add(1, Ordering::SeqCst);
         }
+    }
+
+    static COUNTER: Counter = Counter {
+        loads: AtomicUsize::new(0),
+        connects: AtomicUsize::new(0),
     };
+    for _ in 0..3 {
+        companion_handler_entry(&COUNTER, UnixStream::pair().unwrap().0);
+    }
+    assert_eq!(COUNTER.loads.load(Ordering::SeqCst), 1);
+    assert_eq!(COUNTER.connects.load(Ordering::SeqCst), 3);
 }
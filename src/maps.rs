@@ -0,0 +1,199 @@
+//! Parsing of `/proc/self/maps`, mostly to find the `dev` and `inode` pairs needed by
+//! [ZygiskApi::plt_hook_register()](crate::ZygiskApi::plt_hook_register).
+
+use std::{io, path::Path};
+
+use crate::libc::{self, dev_t, ino_t};
+
+/// A single mapping of `/proc/self/maps`.
+///
+/// See [proc(5)](https://man7.org/linux/man-pages/man5/proc.5.html) for the meaning of the fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapEntry {
+    /// Start address of the mapping.
+    pub start: usize,
+    /// End address of the mapping (exclusive).
+    pub end: usize,
+    /// Permissions, such as `r-xp`.
+    pub perms: String,
+    /// Offset into the mapped file.
+    pub offset: u64,
+    /// Device of the mapped file.
+    pub dev: dev_t,
+    /// Inode of the mapped file, `0` for anonymous mappings.
+    pub inode: ino_t,
+    /// Path of the mapped file, or a pseudo path such as `[stack]`. Empty for anonymous mappings.
+    pub pathname: String,
+}
+
+impl MapEntry {
+    /// Whether the mapping is executable.
+    pub fn is_executable(&self) -> bool {
+        self.perms.as_bytes().get(2) == Some(&b'x')
+    }
+
+    /// The address at which the mapped file would start, i.e. the load base of an ELF when this
+    /// is one of its segments.
+    pub fn base_address(&self) -> usize {
+        self.start.wrapping_sub(self.offset as usize)
+    }
+}
+
+/// Read all mappings of the current process.
+pub fn entries() -> io::Result<Vec<MapEntry>> {
+    parse_maps(&std::fs::read_to_string("/proc/self/maps")?)
+}
+
+/// Find the mappings of a library loaded in the current process.
+///
+/// `name` is either a full path, or a file name such as `libart.so` that is matched against the
+/// last component of each path.
+///
+/// ## Example
+///
+/// ```no_run
+/// let art = zygisk::maps::find_library("libart.so").unwrap();
+/// if let Some(entry) = art.first() {
+///     println!("libart.so: dev {} inode {} base {:#x}", entry.dev, entry.inode, entry.base_address());
+/// }
+/// ```
+pub fn find_library(name: &str) -> io::Result<Vec<MapEntry>> {
+    Ok(entries()?
+        .into_iter()
+        .filter(|entry| library_matches(entry, name))
+        .collect())
+}
+
+/// Find the first mapping of the module's own library, i.e. the one at its load base.
+///
+/// This is the library containing the code of this crate, so its `dev` and `inode` are the ones
+/// to exclude when hooking every library with
+/// [ZygiskApi::plt_hook_register()](crate::ZygiskApi::plt_hook_register), and its address range
+/// is what a module hiding itself has to take care of. Other mappings of the library share the
+/// same `dev`, `inode` and `pathname`.
+///
+/// ## Example
+///
+/// ```no_run
+/// if let Some(module) = zygisk::self_map() {
+///     println!("loaded from {} at {:#x}", module.pathname, module.base_address());
+/// }
+/// ```
+pub fn self_map() -> Option<MapEntry> {
+    let address = self_map as fn() -> Option<MapEntry> as usize;
+    find_containing(entries().ok()?, address)
+}
+
+/// The first mapping of the file that contains `address`.
+fn find_containing(entries: Vec<MapEntry>, address: usize) -> Option<MapEntry> {
+    let containing = entries
+        .iter()
+        .find(|entry| (entry.start..entry.end).contains(&address))?
+        .clone();
+    if containing.inode == 0 {
+        return Some(containing);
+    }
+    entries
+        .into_iter()
+        .filter(|entry| {
+            entry.dev == containing.dev
+                && entry.inode == containing.inode
+                && entry.pathname == containing.pathname
+        })
+        .min_by_key(|entry| entry.start)
+}
+
+pub(crate) fn library_matches(entry: &MapEntry, name: &str) -> bool {
+    if entry.inode == 0 {
+        return false;
+    }
+    if name.starts_with('/') {
+        entry.pathname == name
+    } else {
+        Path::new(&entry.pathname)
+            .file_name()
+            .is_some_and(|file_name| file_name == name)
+    }
+}
+
+fn parse_maps(maps: &str) -> io::Result<Vec<MapEntry>> {
+    maps.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            parse_line(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed line in /proc/self/maps: {line}"),
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<MapEntry> {
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?;
+    let offset = fields.next()?;
+    let (major, minor) = fields.next()?.split_once(':')?;
+    let inode = fields.next()?;
+    let pathname = fields.next().unwrap_or("").trim_start();
+
+    Some(MapEntry {
+        start: usize::from_str_radix(start, 16).ok()?,
+        end: usize::from_str_radix(end, 16).ok()?,
+        perms: perms.to_owned(),
+        offset: u64::from_str_radix(offset, 16).ok()?,
+        dev: libc::makedev(
+            u32::from_str_radix(major, 16).ok()?,
+            u32::from_str_radix(minor, 16).ok()?,
+        ),
+        inode: inode.parse().ok()?,
+        pathname: pathname.to_owned(),
+    })
+}
+
+#[test]
+fn test_parse_maps() {
+    let maps = "\
+56b4346000-56b4347000 r-xp 00002000 fe:00 235                        /system/bin/app_process64
+7f0000000-7f0001000 rw-p 00000000 00:00 0                            [anon:libc_malloc]
+7f0001000-7f0002000 rw-p 00000000 00:00 0
+";
+    let entries = parse_maps(maps).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].start, 0x56b4346000);
+    assert_eq!(entries[0].dev, libc::makedev(0xfe, 0));
+    assert_eq!(entries[0].inode, 235);
+    assert_eq!(entries[0].base_address(), 0x56b4344000);
+    assert!(entries[0].is_executable());
+    assert!(library_matches(&entries[0], "app_process64"));
+    assert!(library_matches(&entries[0], "/system/bin/app_process64"));
+    assert!(!library_matches(&entries[0], "process64"));
+    assert_eq!(entries[1].pathname, "[anon:libc_malloc]");
+    assert_eq!(entries[2].pathname, "");
+
+    assert!(!self::entries().unwrap().is_empty());
+}
+
+#[test]
+fn test_self_map() {
+    let maps = "\
+7f1000-7f2000 r--p 00000000 fe:00 42                         /data/adb/modules/m/zygisk/arm64-v8a.so
+7f2000-7f4000 r-xp 00001000 fe:00 42                         /data/adb/modules/m/zygisk/arm64-v8a.so
+7f4000-7f5000 rw-p 00000000 00:00 0
+";
+    let entries = parse_maps(maps).unwrap();
+    let module = find_containing(entries.clone(), 0x7f3000).unwrap();
+    assert_eq!(module.start, 0x7f1000);
+    assert_eq!(module.base_address(), 0x7f1000);
+    assert_eq!(
+        find_containing(entries.clone(), 0x7f4800).unwrap().start,
+        0x7f4000
+    );
+    assert_eq!(find_containing(entries, 0x1000), None);
+
+    // The tests are linked into the test executable.
+    let exe = std::env::current_exe().unwrap();
+    assert_eq!(self_map().unwrap().pathname, exe.to_str().unwrap());
+}
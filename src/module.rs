@@ -1,8 +1,13 @@
+use std::{mem::MaybeUninit, ptr::addr_of_mut};
+
 use crate::jni::JNIEnv;
 
 use crate::{
-    binding::{ModuleAbi, RawApiTable},
-    AppSpecializeArgs, ServerSpecializeArgs, ZygiskApi,
+    binding::{with_app_args, ApiVersion, ModuleAbi, RawApiTable},
+    logcat,
+    macros::PanicPolicy,
+    AppSpecializeArgs, PostSpecializeApi, ProcessDecision, ProcessFilter, ServerSpecializeArgs,
+    ZygiskApi,
 };
 
 // Note: in stub implementations, all the arguments are unused.
@@ -14,6 +19,17 @@ pub trait ZygiskModule {
     /// with Zygisk through this handle.
     fn on_load(&self, api: ZygiskApi, env: JNIEnv) {}
 
+    /// The processes this module wants to run in. It is checked before
+    /// [Self::pre_app_specialize] and [Self::pre_server_specialize]; in other processes, none of
+    /// the specialize callbacks are called and the module library is unloaded afterwards (see
+    /// [ProcessFilter]).
+    ///
+    /// Since the module gets unloaded, [Self::on_load] must not install any hooks when this is
+    /// used. By default, every process matches.
+    fn targets(&self) -> ProcessFilter {
+        ProcessFilter::all()
+    }
+
     /// This function is called before the app process is specialized.
     /// At this point, the process just got forked from zygote, but no app specific specialization
     /// is applied. This means that the process does not have any sandbox restrictions and
@@ -26,12 +42,22 @@ pub trait ZygiskModule {
     /// If you need to run some operations as superuser, you can call `ZygiskApi::connect_companion()`
     /// to get a socket to do IPC calls with a root companion process.
     /// See [ZygiskApi::connect_companion] for more info.
-    fn pre_app_specialize(&self, api: ZygiskApi, env: JNIEnv, args: &mut AppSpecializeArgs) {}
+    ///
+    /// The returned [ProcessDecision] tells what to do with the process afterwards, such as
+    /// unloading the module from processes it has nothing to do in.
+    fn pre_app_specialize(
+        &self,
+        api: ZygiskApi,
+        env: JNIEnv,
+        args: &mut AppSpecializeArgs,
+    ) -> ProcessDecision {
+        ProcessDecision::Continue
+    }
 
     /// This function is called after the app process is specialized.
     /// At this point, the process has all sandbox restrictions enabled for this application.
     /// This means that this function runs as the same privilege of the app's own code.
-    fn post_app_specialize(&self, api: ZygiskApi, env: JNIEnv, args: &AppSpecializeArgs) {}
+    fn post_app_specialize(&self, api: PostSpecializeApi, env: JNIEnv, args: &AppSpecializeArgs) {}
 
     /// This function is called before the system server process is specialized.
     /// See [Self::pre_app_specialize] for more info.
@@ -39,42 +65,235 @@ pub trait ZygiskModule {
 
     /// This function is called after the system server process is specialized.
     /// At this point, the process runs with the privilege of `system_server`.
-    fn post_server_specialize(&self, api: ZygiskApi, env: JNIEnv, args: &ServerSpecializeArgs) {}
+    fn post_server_specialize(
+        &self,
+        api: PostSpecializeApi,
+        env: JNIEnv,
+        args: &ServerSpecializeArgs,
+    ) {
+    }
 }
 
 /// Information about a registered module, for use in FFI functions.
 ///
 /// This exists since the Zygisk API binding requires any `this` pointers to be thin,
 /// while Rust's `dyn` pointers are not.
-pub(crate) struct RawModule {
-    pub inner: &'static dyn ZygiskModule,
-    pub api_table: *const RawApiTable,
-    pub jni_env: *mut jni::sys::JNIEnv,
+pub struct RawModule {
+    pub(crate) inner: &'static dyn ZygiskModule,
+    pub(crate) api_table: *const RawApiTable,
+    pub(crate) api_version: ApiVersion,
+    pub(crate) jni_env: *mut jni::sys::JNIEnv,
+    /// Set when the process does not match [ZygiskModule::targets()], or the module panicked.
+    pub(crate) skipped: bool,
+    pub(crate) panic_policy: PanicPolicy,
+}
+
+impl RawModule {
+    fn decide(&mut self, decision: ProcessDecision) {
+        let api = unsafe { ZygiskApi::from_raw(&*self.api_table, self.api_version) };
+        // Zygisk logs failures itself, and there is nothing else to do about them here.
+        match decision {
+            ProcessDecision::Continue => {}
+            ProcessDecision::SkipAndUnload => {
+                self.skipped = true;
+                // Unloading with hooks in place would crash the process, so keep the library.
+                if let Err(e) = api.skip_and_unload() {
+                    logcat::write(
+                        logcat::Priority::Error,
+                        &format!("cannot unload the module: {e}"),
+                    );
+                }
+            }
+            ProcessDecision::ForceDenylistUnmount => {
+                let _ = api.force_denylist_unmount();
+            }
+        }
+    }
+}
+
+/// The return types of the `pre[XXX]Specialize` callbacks.
+trait IntoDecision {
+    fn into_decision(self) -> ProcessDecision;
+}
+
+impl IntoDecision for () {
+    fn into_decision(self) -> ProcessDecision {
+        ProcessDecision::Continue
+    }
+}
+
+impl IntoDecision for ProcessDecision {
+    fn into_decision(self) -> ProcessDecision {
+        self
+    }
+}
+
+/// The span covering a callback of the module, describing the process being specialized.
+#[cfg(feature = "tracing")]
+fn callback_span(
+    callback: &'static str,
+    api: &ZygiskApi,
+    process: &str,
+    uid: jni::sys::jint,
+) -> tracing::Span {
+    tracing::info_span!(
+        "zygisk_callback",
+        callback,
+        process,
+        uid,
+        flags = ?api.get_flags().ok(),
+    )
+}
+
+/// A [ModuleAbi] together with the [RawModule] it points to, so that registering a module only
+/// takes one allocation.
+#[repr(C)]
+struct LeakedModule {
+    abi: ModuleAbi,
+    module: RawModule,
 }
 
 impl crate::binding::ModuleAbi {
-    pub(crate) fn from_module(module: &'static mut RawModule) -> ModuleAbi {
+    /// Move `module` to the heap next to its [ModuleAbi], and leak both.
+    ///
+    /// Zygisk modules are never destroyed, so there is nothing to free them.
+    pub(crate) fn leak(module: RawModule) -> &'static mut ModuleAbi {
+        let leaked = Box::leak(Box::new(MaybeUninit::<LeakedModule>::uninit())).as_mut_ptr();
+        // SAFETY: both fields are initialized before the reference to `abi` is created, and
+        // `this` only ever borrows the disjoint `module` field.
+        unsafe {
+            let raw = addr_of_mut!((*leaked).module);
+            raw.write(module);
+            addr_of_mut!((*leaked).abi).write(ModuleAbi::from_module(&mut *raw));
+            &mut (*leaked).abi
+        }
+    }
+
+    fn from_module(module: &'static mut RawModule) -> ModuleAbi {
         macro_rules! def_func {
+            ($name: ident, $arg_type: ty, $process: expr, $matches: expr) => {
+                fn $name(module: &mut RawModule, args: $arg_type) {
+                    if module.skipped {
+                        return;
+                    }
+                    let inner = module.inner;
+                    let mut env = unsafe { JNIEnv::from_raw(module.jni_env) }.unwrap();
+                    let api =
+                        unsafe { ZygiskApi::from_raw(&*module.api_table, module.api_version) };
+                    // The filter, the span and the tracing subscriber run module code and JNI
+                    // calls, so they may panic just as well.
+                    let call = || {
+                        let process: fn(&mut JNIEnv, &$arg_type) -> String = $process;
+                        let process = process(&mut env, &args);
+                        #[cfg(feature = "tracing")]
+                        let _span =
+                            callback_span(stringify!($name), &api, &process, args.uid().as_raw())
+                                .entered();
+                        let matches: fn(&ProcessFilter, &str, &$arg_type) -> bool = $matches;
+                        if !matches(&inner.targets(), &process, &args) {
+                            #[cfg(feature = "tracing")]
+                            tracing::debug!("process not targeted, unloading the module");
+                            return ProcessDecision::SkipAndUnload;
+                        }
+                        let decision = inner.$name(api, env, args).into_decision();
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(?decision);
+                        decision
+                    };
+                    #[cfg(feature = "debug-fd-audit")]
+                    let call = || crate::exempt::audit(stringify!($name), call);
+                    let decision = match crate::macros::catch_panic(call) {
+                        Some(decision) => decision,
+                        None => {
+                            module.panic_policy.on_panic();
+                            module.skipped = true;
+                            return;
+                        }
+                    };
+                    module.decide(decision);
+                }
+            };
             ($name: ident, $arg_type: ty) => {
-                extern "C" fn $name(module: &mut RawModule, args: $arg_type) {
-                    let api = unsafe { ZygiskApi::from_raw(&*module.api_table) };
-                    let env = unsafe { JNIEnv::from_raw(module.jni_env) }.unwrap();
-                    module.inner.$name(api, env, args);
+                fn $name(module: &mut RawModule, args: $arg_type) {
+                    if !module.skipped {
+                        let api =
+                            unsafe { ZygiskApi::from_raw(&*module.api_table, module.api_version) };
+                        let env = unsafe { JNIEnv::from_raw(module.jni_env) }.unwrap();
+                        let call = || {
+                            // The name was cached by the `pre[XXX]Specialize` glue.
+                            #[cfg(feature = "tracing")]
+                            let _span = callback_span(
+                                stringify!($name),
+                                &api,
+                                &crate::process_name(),
+                                args.uid().as_raw(),
+                            )
+                            .entered();
+                            module.inner.$name(PostSpecializeApi::new(api), env, args)
+                        };
+                        if crate::macros::catch_panic(call).is_none() {
+                            module.panic_policy.on_panic();
+                            module.skipped = true;
+                        }
+                    }
+                    // Zygisk unloads the API table once this returns.
+                    crate::api::mark_unloaded();
                 }
             };
         }
-        def_func!(pre_app_specialize, &mut AppSpecializeArgs);
+        def_func!(
+            pre_app_specialize,
+            &mut AppSpecializeArgs,
+            |env, args| args.nice_name(env).unwrap_or_default(),
+            |filter, name, args| {
+                crate::process::set_specialization(crate::process::Specialization::App);
+                crate::process::set_process_name(name);
+                filter.matches_app(name, args.uid().as_raw())
+            }
+        );
         def_func!(post_app_specialize, &AppSpecializeArgs);
-        def_func!(pre_server_specialize, &mut ServerSpecializeArgs);
+        def_func!(
+            pre_server_specialize,
+            &mut ServerSpecializeArgs,
+            |_env, _args| "system_server".to_owned(),
+            |filter, name, _args| {
+                crate::process::set_specialization(crate::process::Specialization::Server);
+                crate::process::set_process_name(name);
+                filter.matches_system_server()
+            }
+        );
         def_func!(post_server_specialize, &ServerSpecializeArgs);
 
+        // SAFETY: Zygisk passes the app arguments in the layout of the registered version.
+        extern "C" fn pre_app_abi(module: &mut RawModule, args: *mut AppSpecializeArgs) {
+            unsafe {
+                with_app_args(module.api_version, args, |args| {
+                    pre_app_specialize(module, args)
+                })
+            }
+        }
+        extern "C" fn post_app_abi(module: &mut RawModule, args: *const AppSpecializeArgs) {
+            let version = module.api_version;
+            unsafe {
+                with_app_args(version, args.cast_mut(), |args| {
+                    post_app_specialize(module, args)
+                })
+            }
+        }
+        extern "C" fn pre_server_abi(module: &mut RawModule, args: &mut ServerSpecializeArgs) {
+            pre_server_specialize(module, args)
+        }
+        extern "C" fn post_server_abi(module: &mut RawModule, args: &ServerSpecializeArgs) {
+            post_server_specialize(module, args)
+        }
+
         ModuleAbi {
-            api_version: crate::API_VERSION,
+            api_version: module.api_version as _,
             this: module,
-            pre_app_specialize,
-            post_app_specialize,
-            pre_server_specialize,
-            post_server_specialize,
+            pre_app_specialize: pre_app_abi,
+            post_app_specialize: post_app_abi,
+            pre_server_specialize: pre_server_abi,
+            post_server_specialize: post_server_abi,
         }
     }
 }
@@ -0,0 +1,333 @@
+use std::{
+    ffi::{CStr, CString, OsString},
+    fs::{File, Metadata},
+    io::{self, Read},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+    path::{Component, Path},
+};
+
+use crate::libc;
+
+/// A handle to the root folder of the current module, as returned by
+/// [ZygiskApi::module_dir()](crate::ZygiskApi::module_dir).
+///
+/// All paths are resolved relative to the module directory with `openat`, so files can be
+/// accessed without knowing where the module is installed. Absolute paths and paths with `..`
+/// components are rejected, but symlinks in the module directory are still followed.
+///
+/// The same restrictions as for [ZygiskApi::get_module_dir()](crate::ZygiskApi::get_module_dir)
+/// apply: the directory is only accessible in the `pre[XXX]Specialize` functions or in the root
+/// companion process.
+#[derive(Debug)]
+pub struct ModuleDir {
+    fd: OwnedFd,
+}
+
+impl ModuleDir {
+    /// Open a file in the module directory for reading.
+    pub fn open(&self, path: impl AsRef<Path>) -> io::Result<File> {
+        self.open_raw(path.as_ref(), libc::O_RDONLY).map(File::from)
+    }
+
+    /// Read the entire contents of a file in the module directory into a string.
+    pub fn read_to_string(&self, path: impl AsRef<Path>) -> io::Result<String> {
+        let mut buf = String::new();
+        self.open(path)?.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the entire contents of a file in the module directory into a byte vector.
+    pub fn read(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.open(path)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Query metadata about a file or directory in the module directory. Symlinks are followed.
+    pub fn metadata(&self, path: impl AsRef<Path>) -> io::Result<Metadata> {
+        File::from(self.open_raw(path.as_ref(), libc::O_PATH)?).metadata()
+    }
+
+    /// Iterate over the entries of a directory in the module directory. Use `"."` to list the
+    /// module directory itself.
+    ///
+    /// The entries `.` and `..` are skipped.
+    pub fn read_dir(&self, path: impl AsRef<Path>) -> io::Result<ReadDir> {
+        let fd = self.open_raw(path.as_ref(), libc::O_RDONLY | libc::O_DIRECTORY)?;
+        let dir = unsafe { libc::fdopendir(fd.as_raw_fd()) };
+        if dir.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        // The fd is now owned by `dir`.
+        let _ = fd.into_raw_fd();
+        Ok(ReadDir { dir })
+    }
+
+    /// Open the module directory at `path`, for the companion process that knows where the module
+    /// is installed.
+    pub(crate) fn open_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        let flags = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let fd = unsafe { libc::open(path.as_ptr(), flags) };
+        if fd < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ModuleDir::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+        }
+    }
+
+    /// Open `path` without following any symlink, for the companion acting as root on paths sent
+    /// by the module. Files created with `O_CREAT` get mode `0600`.
+    pub(crate) fn open_nofollow(&self, path: &Path, flags: libc::c_int) -> io::Result<OwnedFd> {
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(CString::new(name.as_bytes())?),
+                Component::CurDir => {}
+                // Reported as an errno, since the companion sends it back to the module.
+                _ => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            }
+        }
+        if names.is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        // Walk the directories one at a time, since `O_NOFOLLOW` only applies to the last one.
+        let mut dir: Option<OwnedFd> = None;
+        for (i, name) in names.iter().enumerate() {
+            let parent = dir.as_ref().unwrap_or(&self.fd).as_raw_fd();
+            let flags = if i + 1 == names.len() {
+                flags
+            } else {
+                libc::O_PATH | libc::O_DIRECTORY
+            };
+            let fd = unsafe {
+                libc::openat(
+                    parent,
+                    name.as_ptr(),
+                    flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                    0o600,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            dir = Some(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+        Ok(dir.expect("at least one component"))
+    }
+
+    fn open_raw(&self, path: &Path, flags: libc::c_int) -> io::Result<OwnedFd> {
+        let inside = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "paths in the module directory must be relative and without `..`",
+            ));
+        }
+        let path = CString::new(path.as_os_str().as_bytes())?;
+
+        let fd =
+            unsafe { libc::openat(self.fd.as_raw_fd(), path.as_ptr(), flags | libc::O_CLOEXEC) };
+        if fd < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        }
+    }
+}
+
+impl From<OwnedFd> for ModuleDir {
+    fn from(fd: OwnedFd) -> Self {
+        ModuleDir { fd }
+    }
+}
+
+impl From<ModuleDir> for OwnedFd {
+    fn from(dir: ModuleDir) -> Self {
+        dir.fd
+    }
+}
+
+impl AsFd for ModuleDir {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for ModuleDir {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Iterator over the entries of a directory, returned by [ModuleDir::read_dir()].
+pub struct ReadDir {
+    dir: *mut libc::DIR,
+}
+
+/// An entry returned by [ReadDir].
+#[derive(Debug)]
+pub struct DirEntry {
+    name: OsString,
+    d_type: u8,
+}
+
+impl DirEntry {
+    /// The file name of this entry, relative to the directory being iterated.
+    pub fn file_name(&self) -> &OsString {
+        &self.name
+    }
+
+    /// Whether this entry is a directory. Returns `None` if the filesystem does not report
+    /// file types while iterating; use [ModuleDir::metadata()] in that case.
+    pub fn is_dir(&self) -> Option<bool> {
+        match self.d_type {
+            libc::DT_UNKNOWN => None,
+            d_type => Some(d_type == libc::DT_DIR),
+        }
+    }
+}
+
+/// Reset `errno`, for functions that only report errors through it.
+pub(crate) fn clear_errno() {
+    #[cfg(target_os = "android")]
+    unsafe {
+        *libc::__errno() = 0
+    };
+    #[cfg(not(target_os = "android"))]
+    unsafe {
+        *libc::__errno_location() = 0
+    };
+}
+
+impl Iterator for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // `readdir` only reports errors through `errno`, so reset it beforehand.
+            clear_errno();
+            let entry = unsafe { libc::readdir(self.dir) };
+            if entry.is_null() {
+                let err = io::Error::last_os_error();
+                return match err.raw_os_error() {
+                    Some(0) => None,
+                    _ => Some(Err(err)),
+                };
+            }
+
+            let entry = unsafe { &*entry };
+            let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) }.to_bytes();
+            if name == b"." || name == b".." {
+                continue;
+            }
+            return Some(Ok(DirEntry {
+                name: OsString::from_vec(name.to_vec()),
+                d_type: entry.d_type,
+            }));
+        }
+    }
+}
+
+impl Drop for ReadDir {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.dir) };
+    }
+}
+
+/// A directory in the system temporary directory, removed with its contents when dropped.
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("zygisk-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn test_module_dir() {
+    let root = TempDir::new("module-dir");
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("assets/config.txt"), "hello").unwrap();
+
+    let dir = ModuleDir::from(OwnedFd::from(File::open(&root).unwrap()));
+    assert_eq!(dir.read_to_string("assets/config.txt").unwrap(), "hello");
+    assert_eq!(dir.metadata("assets/config.txt").unwrap().len(), 5);
+    assert!(dir.metadata("assets").unwrap().is_dir());
+    assert!(dir.open(root.join("assets/config.txt")).is_err());
+    assert_eq!(dir.read_to_string("./assets/config.txt").unwrap(), "hello");
+    for escaping in [
+        "..",
+        "assets/../../etc/passwd",
+        "../module-dir/assets/config.txt",
+    ] {
+        let error = dir.read(escaping).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{escaping}");
+    }
+
+    let entries: Vec<_> = dir.read_dir(".").unwrap().map(Result::unwrap).collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].file_name(), "assets");
+}
+
+#[test]
+fn test_open_nofollow() {
+    let root = TempDir::new("nofollow");
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("assets/config.txt"), "hello").unwrap();
+    std::os::unix::fs::symlink("/etc", root.join("etc")).unwrap();
+    std::os::unix::fs::symlink("config.txt", root.join("assets/link.txt")).unwrap();
+
+    let dir = ModuleDir::open_path(&*root).unwrap();
+    let read = |path: &str| {
+        let fd = dir.open_nofollow(Path::new(path), libc::O_RDONLY)?;
+        io::read_to_string(File::from(fd))
+    };
+    assert_eq!(read("assets/config.txt").unwrap(), "hello");
+    assert_eq!(read("./assets/config.txt").unwrap(), "hello");
+    assert_eq!(
+        read("etc/passwd").unwrap_err().raw_os_error(),
+        Some(libc::ENOTDIR)
+    );
+    assert_eq!(
+        read("assets/link.txt").unwrap_err().raw_os_error(),
+        Some(libc::ELOOP)
+    );
+    for escaping in ["", "..", "/etc/passwd", "assets/../../etc/passwd"] {
+        let error = read(escaping).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{escaping}");
+    }
+}
@@ -0,0 +1,214 @@
+//! Loading native libraries that the app cannot open by path.
+//!
+//! After specialization, the module directory is hidden from the app, and SELinux usually keeps
+//! the app from reading it anyway. Secondary libraries of the module are therefore opened while
+//! the directory is still accessible, and handed to the linker as a file descriptor.
+//!
+//! Libraries that should not exist on disk at all, such as one embedded in the module or sent by
+//! the companion, can be loaded from a sealed memfd with [dlopen_memfd()].
+
+use std::{
+    ffi::{CStr, CString},
+    fs::File,
+    io::{self, Seek, Write},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+        raw::c_void,
+    },
+    path::Path,
+    ptr::NonNull,
+};
+
+use crate::{libc, ModuleDir};
+
+/// A library loaded by [dlopen_from_module_dir()] or [dlopen_fd()].
+///
+/// Libraries are never unloaded, since hooks or threads of the module may still run their code.
+#[derive(Debug)]
+pub struct Library {
+    handle: NonNull<c_void>,
+}
+
+// SAFETY: the handle is only passed to `dlsym`, which is thread safe.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+impl Library {
+    /// The handle returned by the linker.
+    pub fn as_raw(&self) -> *mut c_void {
+        self.handle.as_ptr()
+    }
+
+    /// Look up the address of a symbol of the library, or of its dependencies.
+    pub fn symbol(&self, name: &CStr) -> Option<NonNull<c_void>> {
+        NonNull::new(unsafe { libc::dlsym(self.handle.as_ptr(), name.as_ptr()) })
+    }
+}
+
+/// Load a library from the module directory, such as `lib/arm64-v8a/libextra.so`.
+///
+/// Like the module directory itself, this only works in the `pre[XXX]Specialize` callbacks; the
+/// library can be used anywhere afterwards. Its dependencies are looked up by the linker as
+/// usual, so they must be loaded first if they are in the module directory too.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{native, ZygiskApi};
+///
+/// fn load_extra(api: &ZygiskApi) -> Option<native::Library> {
+///     let dir = api.module_dir().ok()?;
+///     native::dlopen_from_module_dir(&dir, "lib/libextra.so").ok()
+/// }
+/// ```
+pub fn dlopen_from_module_dir(dir: &ModuleDir, path: impl AsRef<Path>) -> io::Result<Library> {
+    let path = path.as_ref();
+    let file = dir.open(path)?;
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    dlopen_fd(file.as_fd(), &name.to_string_lossy())
+}
+
+/// Load a library from a file descriptor, with `android_dlopen_ext` and
+/// `ANDROID_DLEXT_USE_LIBRARY_FD`.
+///
+/// `name` identifies the library to the linker, which returns the already loaded library when
+/// the same name is loaded again. The file descriptor can be closed once this returns.
+pub fn dlopen_fd(fd: BorrowedFd, name: &str) -> io::Result<Library> {
+    let name = CString::new(name)?;
+    let handle = unsafe { open(fd, &name) };
+    match NonNull::new(handle) {
+        Some(handle) => Ok(Library { handle }),
+        None => Err(dl_error()),
+    }
+}
+
+/// Load a library from memory, through a sealed memfd.
+///
+/// The library never touches the filesystem: it only shows up in `/proc/self/maps` as
+/// `/memfd:<name> (deleted)`. See [memfd_from_bytes()] for the memfd itself, which is how the
+/// companion can hand out a library without the module having to copy it.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::native;
+///
+/// // Usually `include_bytes!(concat!(env!("OUT_DIR"), "/libpayload.so"))`.
+/// static PAYLOAD: &[u8] = b"\x7fELF";
+///
+/// let payload = native::dlopen_memfd("libpayload.so", PAYLOAD).unwrap();
+/// let entry = payload.symbol(c"payload_main");
+/// ```
+pub fn dlopen_memfd(name: &str, elf: &[u8]) -> io::Result<Library> {
+    let memfd = memfd_from_bytes(name, elf)?;
+    dlopen_fd(memfd.as_fd(), name)
+}
+
+/// Copy bytes into a new memfd, and seal it so that its contents can no longer change.
+///
+/// The memfd is created with `MFD_CLOEXEC`, so it is not inherited by programs the process runs.
+/// `memfd_create` is called as a raw syscall, since bionic only exports it from Android 11.
+pub fn memfd_from_bytes(name: &str, bytes: &[u8]) -> io::Result<OwnedFd> {
+    let name = CString::new(name)?;
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = File::from(unsafe { OwnedFd::from_raw_fd(fd as _) });
+    file.write_all(bytes)?;
+    file.rewind()?;
+
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file.into())
+}
+
+#[cfg(target_os = "android")]
+unsafe fn open(fd: BorrowedFd, name: &CStr) -> *mut c_void {
+    use std::os::raw::{c_char, c_int};
+
+    /// `android_dlextinfo` of `<android/dlext.h>`.
+    #[repr(C)]
+    struct DlExtInfo {
+        flags: u64,
+        reserved_addr: *mut c_void,
+        reserved_size: usize,
+        relro_fd: c_int,
+        library_fd: c_int,
+        library_fd_offset: i64,
+        library_namespace: *mut c_void,
+    }
+
+    const ANDROID_DLEXT_USE_LIBRARY_FD: u64 = 0x10;
+
+    extern "C" {
+        fn android_dlopen_ext(
+            filename: *const c_char,
+            flags: c_int,
+            extinfo: *const DlExtInfo,
+        ) -> *mut c_void;
+    }
+
+    let info = DlExtInfo {
+        flags: ANDROID_DLEXT_USE_LIBRARY_FD,
+        reserved_addr: std::ptr::null_mut(),
+        reserved_size: 0,
+        relro_fd: -1,
+        library_fd: fd.as_raw_fd(),
+        library_fd_offset: 0,
+        library_namespace: std::ptr::null_mut(),
+    };
+    android_dlopen_ext(name.as_ptr(), libc::RTLD_NOW, &info)
+}
+
+/// Other linkers (i.e. for host tests) cannot load from a file descriptor, but can open it again.
+#[cfg(not(target_os = "android"))]
+unsafe fn open(fd: BorrowedFd, _name: &CStr) -> *mut c_void {
+    let path = CString::new(format!("/proc/self/fd/{}", fd.as_raw_fd())).unwrap();
+    libc::dlopen(path.as_ptr(), libc::RTLD_NOW)
+}
+
+fn dl_error() -> io::Error {
+    let error = unsafe { libc::dlerror() };
+    let message = if error.is_null() {
+        "unknown dlopen error".into()
+    } else {
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[test]
+fn test_dlopen_from_module_dir() {
+    use std::{fs::File, os::fd::OwnedFd};
+
+    let root = crate::module_dir::TempDir::new("native");
+    std::fs::write(root.join("libbroken.so"), b"not an ELF").unwrap();
+    let dir = ModuleDir::from(OwnedFd::from(File::open(&root).unwrap()));
+
+    let missing = dlopen_from_module_dir(&dir, "libmissing.so").unwrap_err();
+    assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+    let broken = dlopen_from_module_dir(&dir, "libbroken.so").unwrap_err();
+    assert_eq!(broken.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_memfd() {
+    use std::io::Read;
+
+    let mut memfd = File::from(memfd_from_bytes("zygisk-test", b"contents").unwrap());
+    let mut contents = String::new();
+    memfd.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "contents");
+    let sealed = memfd.write_all(b"more").unwrap_err();
+    assert_eq!(sealed.raw_os_error(), Some(libc::EPERM));
+    assert!(memfd.set_len(0).is_err());
+
+    let broken = dlopen_memfd("libbroken.so", b"not an ELF").unwrap_err();
+    assert_eq!(broken.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// The name of the process being specialized, set by the module glue.
+static NAME: Mutex<Option<String>> = Mutex::new(None);
+
+/// Get the name of the current process, usually the package name of an app.
+///
+/// During `pre_app_specialize`, this is the `nice_name` of the
+/// [AppSpecializeArgs](crate::AppSpecializeArgs) (`system_server` in `pre_server_specialize`),
+/// since `/proc/self/cmdline` still reads `zygote64` at that point. Outside of the callbacks, such
+/// as in hooks that run after specialization or in the companion, it is read from
+/// `/proc/self/cmdline`. Either way, the name is cached after the first call.
+///
+/// Returns an empty string if the name cannot be determined.
+///
+/// ## Example
+///
+/// ```no_run
+/// if zygisk::process_name().starts_with("com.android.vending") {
+///     // ...
+/// }
+/// ```
+pub fn process_name() -> String {
+    lock()
+        .get_or_insert_with(|| cmdline_name().unwrap_or_default())
+        .clone()
+}
+
+/// Cache the name of the process being specialized.
+pub(crate) fn set_process_name(name: &str) {
+    *lock() = Some(name.into());
+}
+
+/// Which specialization callback the module glue ran in this process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Specialization {
+    App = 1,
+    Server = 2,
+}
+
+/// The [Specialization] recorded by the module glue, or 0 if neither callback ran.
+static SPECIALIZATION: AtomicU8 = AtomicU8::new(0);
+
+/// Record which specialization callback is running.
+pub(crate) fn set_specialization(specialization: Specialization) {
+    SPECIALIZATION.store(specialization as u8, Ordering::Relaxed);
+}
+
+/// Get the specialization callback that ran in this process, if any.
+pub(crate) fn specialization() -> Option<Specialization> {
+    match SPECIALIZATION.load(Ordering::Relaxed) {
+        1 => Some(Specialization::App),
+        2 => Some(Specialization::Server),
+        _ => None,
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<String>> {
+    NAME.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The first argument of `/proc/self/cmdline`, which Android sets to the process name.
+fn cmdline_name() -> Option<String> {
+    let cmdline = std::fs::read("/proc/self/cmdline").ok()?;
+    let name = cmdline.split(|&b| b == 0).next()?;
+    Some(String::from_utf8_lossy(name).into_owned())
+}
+
+#[test]
+fn test_cmdline_name() {
+    let exe = std::env::current_exe().unwrap();
+    let name = cmdline_name().unwrap();
+    assert_eq!(
+        std::path::Path::new(&name).file_name(),
+        exe.file_name(),
+        "{name}"
+    );
+}
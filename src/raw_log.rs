@@ -0,0 +1,227 @@
+//! Logging from contexts where allocating or locking is not allowed, such as hooks of libc
+//! functions that may run in signal handlers or between `fork` and `exec`.
+//!
+//! [raw_log!](crate::raw_log!) formats into a buffer on the stack and writes the message with a
+//! single system call, straight to `logd` through `/dev/socket/logdw` or to the file descriptor
+//! set with [set_fd()]. Messages longer than [MAX_MESSAGE] bytes are truncated.
+//!
+//! Formatting itself does not allocate, but the `Display` and `Debug` implementations of the
+//! arguments might; stick to integers, strings and raw pointers.
+//!
+//! ```
+//! use zygisk::raw_log;
+//!
+//! extern "C" fn close_hook(fd: i32) -> i32 {
+//!     raw_log!(Debug, "close({fd})");
+//!     // ...
+//!     # 0
+//! }
+//! # close_hook(-1);
+//! ```
+
+use std::{
+    fmt,
+    os::fd::{IntoRawFd, OwnedFd, RawFd},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use crate::libc;
+
+/// The maximum length of a message, tag excluded.
+pub const MAX_MESSAGE: usize = 1024;
+
+/// The priority of a message, as in `android/log.h`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Level {
+    Verbose = 2,
+    Debug = 3,
+    Info = 4,
+    Warn = 5,
+    Error = 6,
+}
+
+// -1 until a destination is known, then either the file descriptor set with `set_fd()`, or the
+// connected `logdw` socket with `LOGD` set.
+static FD: AtomicI32 = AtomicI32::new(-1);
+static LOGD: AtomicI32 = AtomicI32::new(0);
+
+/// Write raw log messages to `fd` as plain text lines instead of sending them to `logd`.
+///
+/// The file descriptor is kept open for the lifetime of the process.
+pub fn set_fd(fd: OwnedFd) {
+    LOGD.store(0, Ordering::Relaxed);
+    // Another thread may still be writing to the previous one, so it is leaked.
+    FD.store(fd.into_raw_fd(), Ordering::Release);
+}
+
+/// Connect to `logd` ahead of time. Otherwise the connection is made by the first message, which
+/// is safe as well but slower.
+pub fn connect() {
+    if FD.load(Ordering::Acquire) < 0 {
+        connect_logd();
+    }
+}
+
+fn connect_logd() -> RawFd {
+    // SAFETY: only async-signal-safe system calls on stack memory.
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_UNIX,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+            0,
+        );
+        if fd < 0 {
+            return -1;
+        }
+        let mut addr: libc::sockaddr_un = std::mem::zeroed();
+        addr.sun_family = libc::AF_UNIX as _;
+        for (dst, src) in addr.sun_path.iter_mut().zip(b"/dev/socket/logdw") {
+            *dst = *src as _;
+        }
+        let len = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+        if libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len) < 0 {
+            libc::close(fd);
+            return -1;
+        }
+        match FD.compare_exchange(-1, fd, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => {
+                LOGD.store(1, Ordering::Release);
+                fd
+            }
+            // Another thread won the race.
+            Err(current) => {
+                libc::close(fd);
+                current
+            }
+        }
+    }
+}
+
+/// A fixed-size buffer on the stack that silently truncates what does not fit.
+#[doc(hidden)]
+pub struct StackBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> StackBuf<N> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        StackBuf {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(N - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+}
+
+impl<const N: usize> fmt::Write for StackBuf<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Write a formatted message. Used by [raw_log!](crate::raw_log!).
+#[doc(hidden)]
+pub fn write(level: Level, tag: &str, args: fmt::Arguments) {
+    let mut message = StackBuf::<MAX_MESSAGE>::new();
+    let _ = fmt::Write::write_fmt(&mut message, args);
+
+    let mut fd = FD.load(Ordering::Acquire);
+    if fd < 0 && cfg!(target_os = "android") {
+        fd = connect_logd();
+    }
+
+    let mut packet = StackBuf::<{ MAX_MESSAGE + 128 }>::new();
+    if fd >= 0 && LOGD.load(Ordering::Acquire) != 0 {
+        // The `logdw` protocol: log id, thread id, realtime timestamp, then the payload of
+        // `__android_log_write`.
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: async-signal-safe system calls.
+        let tid = unsafe {
+            libc::clock_gettime(libc::CLOCK_REALTIME, &mut now);
+            libc::syscall(libc::SYS_gettid)
+        };
+        packet.push(&[0]); // LOG_ID_MAIN
+        packet.push(&(tid as u16).to_le_bytes());
+        packet.push(&(now.tv_sec as u32).to_le_bytes());
+        packet.push(&(now.tv_nsec as u32).to_le_bytes());
+        packet.push(&[level as u8]);
+        packet.push(tag.as_bytes());
+        packet.push(&[0]);
+        packet.push(message.as_bytes());
+        packet.push(&[0]);
+    } else {
+        if fd < 0 {
+            fd = libc::STDERR_FILENO;
+        }
+        packet.push(tag.as_bytes());
+        packet.push(b": ");
+        packet.push(message.as_bytes());
+        packet.push(b"\n");
+    }
+    let bytes = packet.as_bytes();
+    // SAFETY: `bytes` is valid for its length. Errors are ignored, there is nowhere to report them.
+    unsafe { libc::write(fd, bytes.as_ptr().cast(), bytes.len()) };
+}
+
+/// Log a message without allocating or locking, for use inside hooks. See [raw_log](crate::raw_log)
+/// for details.
+///
+/// The tag is the name of the calling crate. Messages are logged at the `Info` level unless a
+/// [Level](crate::raw_log::Level) is given first: `raw_log!(Warn, "...")`.
+#[macro_export]
+macro_rules! raw_log {
+    ($level: ident, $fmt: literal $($arg: tt)*) => {
+        $crate::raw_log::write(
+            $crate::raw_log::Level::$level,
+            env!("CARGO_PKG_NAME"),
+            format_args!($fmt $($arg)*),
+        )
+    };
+    ($fmt: literal $($arg: tt)*) => {
+        $crate::raw_log!(Info, $fmt $($arg)*)
+    };
+}
+
+#[test]
+fn test_raw_log() {
+    use std::{
+        fmt::Write,
+        io::Read,
+        os::{fd::AsRawFd, unix::net::UnixStream},
+    };
+
+    let mut buf = StackBuf::<8>::new();
+    let word = "truncated";
+    write!(buf, "{}-{word}", 1234).unwrap();
+    assert_eq!(buf.as_bytes(), b"1234-tru");
+
+    let (mut reader, writer) = UnixStream::pair().unwrap();
+    let raw = writer.as_raw_fd();
+    set_fd(writer.into());
+    raw_log!(Warn, "fd {}", raw);
+    raw_log!("done");
+    let mut out = [0; 64];
+    let n = reader.read(&mut out).unwrap();
+    let mut text = String::from_utf8_lossy(&out[..n]).into_owned();
+    if !text.ends_with("done\n") {
+        let n = reader.read(&mut out).unwrap();
+        text.push_str(&String::from_utf8_lossy(&out[..n]));
+    }
+    assert_eq!(text, format!("zygisk: fd {raw}\nzygisk: done\n"));
+}
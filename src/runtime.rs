@@ -0,0 +1,150 @@
+//! Detection of the Zygisk implementation that loaded the module.
+//!
+//! The implementations follow the same API, but differ in details that modules sometimes have to
+//! work around, such as how long the companion process lives and whether unloading the module
+//! with `DlcloseModuleLibrary` actually unmaps it. There is no API to ask the loader for its name,
+//! so it is guessed from its observable markers: the libraries mapped in the process, and the
+//! directories of the root solution and of the loader module under `/data/adb`.
+//!
+//! Zygote and apps usually may not look into `/data/adb`, so the detection is more reliable in
+//! the companion, which runs as root.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use zygisk::runtime::{self, Implementation};
+//!
+//! if runtime::implementation() == Implementation::Magisk {
+//!     // ...
+//! }
+//! ```
+
+use std::{fmt, path::Path, sync::OnceLock};
+
+/// A Zygisk implementation, as returned by [implementation()].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Implementation {
+    /// The Zygisk built into Magisk.
+    Magisk,
+
+    /// Zygisk Next, the `zygisksu` module.
+    ZygiskNext,
+
+    /// ReZygisk, the `rezygisk` module.
+    ReZygisk,
+
+    /// Another loader running on KernelSU or APatch.
+    KernelSu,
+
+    /// None of the markers were found.
+    Unknown,
+}
+
+impl Implementation {
+    /// A human readable name of the implementation.
+    pub fn name(self) -> &'static str {
+        match self {
+            Implementation::Magisk => "Magisk",
+            Implementation::ZygiskNext => "Zygisk Next",
+            Implementation::ReZygisk => "ReZygisk",
+            Implementation::KernelSu => "KernelSU",
+            Implementation::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Guess the Zygisk implementation that loaded the module. The result is cached after the first
+/// call.
+pub fn implementation() -> Implementation {
+    static IMPLEMENTATION: OnceLock<Implementation> = OnceLock::new();
+    *IMPLEMENTATION.get_or_init(|| {
+        let paths = crate::maps::entries()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.pathname);
+        detect(paths, |path| Path::new(path).exists())
+    })
+}
+
+/// The directories of the loader modules, which are also part of the paths of their libraries.
+const MODULES: [(&str, Implementation); 2] = [
+    ("/data/adb/modules/zygisksu", Implementation::ZygiskNext),
+    ("/data/adb/modules/rezygisk", Implementation::ReZygisk),
+];
+
+fn detect(
+    mapped: impl IntoIterator<Item = String>,
+    exists: impl Fn(&str) -> bool,
+) -> Implementation {
+    for path in mapped {
+        for (dir, implementation) in MODULES {
+            if path.starts_with(dir) {
+                return implementation;
+            }
+        }
+        // Magisk runs from its tmpfs, which is /debug_ramdisk or /sbin depending on the device.
+        if path.contains("/.magisk/") {
+            return Implementation::Magisk;
+        }
+    }
+
+    // The loader modules take over even if Magisk is installed, as long as its own Zygisk is off.
+    for (dir, implementation) in MODULES {
+        if exists(dir) && !exists(&format!("{dir}/disable")) {
+            return implementation;
+        }
+    }
+    if exists("/data/adb/magisk") {
+        Implementation::Magisk
+    } else if exists("/data/adb/ksu") || exists("/data/adb/ap") {
+        Implementation::KernelSu
+    } else {
+        Implementation::Unknown
+    }
+}
+
+#[test]
+fn test_detect() {
+    let none = |_: &str| false;
+    let mapped = |path: &str| vec!["[stack]".to_owned(), path.to_owned()];
+
+    assert_eq!(
+        detect(
+            mapped("/data/adb/modules/zygisksu/lib64/libzygisk.so"),
+            none
+        ),
+        Implementation::ZygiskNext
+    );
+    assert_eq!(
+        detect(mapped("/debug_ramdisk/.magisk/zygisk/libzygisk.so"), none),
+        Implementation::Magisk
+    );
+    assert_eq!(
+        detect(mapped("/system/lib64/libart.so"), none),
+        Implementation::Unknown
+    );
+
+    let rezygisk_on_ksu =
+        |path: &str| matches!(path, "/data/adb/ksu" | "/data/adb/modules/rezygisk");
+    assert_eq!(
+        detect(Vec::new(), rezygisk_on_ksu),
+        Implementation::ReZygisk
+    );
+    let disabled =
+        |path: &str| path.starts_with("/data/adb/modules/zygisksu") || path == "/data/adb/magisk";
+    assert_eq!(detect(Vec::new(), disabled), Implementation::Magisk);
+    assert_eq!(
+        detect(Vec::new(), |path| path == "/data/adb/ap"),
+        Implementation::KernelSu
+    );
+
+    assert_eq!(Implementation::ZygiskNext.to_string(), "Zygisk Next");
+    implementation();
+}
@@ -0,0 +1,79 @@
+//! SELinux queries, to find out which domain code runs in and why privileged operations fail.
+//!
+//! App processes run in domains such as `untrusted_app` once specialized, and zygote runs in
+//! `zygote` before that. Operations that the domain does not allow fail with `EACCES`, even as
+//! root; the companion runs in the domain of the Zygisk daemon instead.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use zygisk::selinux;
+//!
+//! if let Ok(context) = selinux::current_context() {
+//!     eprintln!("running as {context}, enforcing: {:?}", selinux::is_enforcing());
+//! }
+//! ```
+
+use std::{fs, io};
+
+use crate::libc::pid_t;
+
+/// Get the security context of the current thread, such as `u:r:zygote:s0`.
+pub fn current_context() -> io::Result<String> {
+    read_context("/proc/thread-self/attr/current")
+}
+
+/// Get the security context of a process.
+pub fn context_of(pid: pid_t) -> io::Result<String> {
+    read_context(&format!("/proc/{pid}/attr/current"))
+}
+
+/// Get the type (domain) of a security context, such as `zygote` for `u:r:zygote:s0`.
+pub fn context_type(context: &str) -> Option<&str> {
+    context.split(':').nth(2)
+}
+
+/// Check whether SELinux is enforcing, from `/sys/fs/selinux/enforce`.
+///
+/// Domains that may not read the file, which includes apps, get an error. Note that a single
+/// domain can also be permissive while the system is enforcing.
+pub fn is_enforcing() -> io::Result<bool> {
+    let enforce = fs::read_to_string("/sys/fs/selinux/enforce")?;
+    Ok(enforce.trim() != "0")
+}
+
+/// Check whether SELinux is permissive. See [is_enforcing()].
+pub fn is_permissive() -> io::Result<bool> {
+    is_enforcing().map(|enforcing| !enforcing)
+}
+
+fn read_context(path: &str) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    // The kernel terminates the context with a NUL, and some kernels add a newline.
+    let context = String::from_utf8_lossy(&bytes)
+        .trim_end_matches(['\0', '\n'])
+        .to_owned();
+    if context.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no security context",
+        ));
+    }
+    Ok(context)
+}
+
+#[test]
+fn test_selinux() {
+    assert_eq!(
+        context_type("u:r:untrusted_app:s0:c512,c768"),
+        Some("untrusted_app")
+    );
+    assert_eq!(context_type("unconfined"), None);
+
+    // Hosts without SELinux may have another LSM, or none at all.
+    let pid = std::process::id() as pid_t;
+    match (current_context(), context_of(pid)) {
+        (Ok(current), Ok(process)) => assert_eq!(current, process),
+        (current, process) => assert_eq!(current.is_err(), process.is_err()),
+    }
+}
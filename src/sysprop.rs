@@ -0,0 +1,159 @@
+//! Reading Android system properties, such as `ro.build.version.sdk`, with the bionic property
+//! API.
+//!
+//! Properties can be read anywhere in the module, including from zygote before specialization.
+//! On other targets (i.e. host tests), there are no properties and every lookup returns `None`.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use zygisk::sysprop;
+//!
+//! let sdk = sysprop::get_int("ro.build.version.sdk").unwrap_or(0);
+//! let verbose = sysprop::get_bool("persist.mymodule.verbose").unwrap_or(false);
+//! ```
+
+use std::ffi::CString;
+
+mod flags;
+pub use flags::FeatureFlags;
+
+#[cfg(target_os = "android")]
+mod bionic {
+    use std::{
+        ffi::{c_void, CStr},
+        os::raw::{c_char, c_int},
+        sync::OnceLock,
+    };
+
+    use crate::libc;
+
+    /// The size of the buffer of `__system_property_get`, including the NUL.
+    const PROP_VALUE_MAX: usize = 92;
+
+    #[repr(C)]
+    struct PropInfo {
+        _private: [u8; 0],
+    }
+
+    type ReadCallback = unsafe extern "C" fn(
+        *const PropInfo,
+        unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char, u32),
+        *mut c_void,
+    );
+
+    extern "C" {
+        fn __system_property_find(name: *const c_char) -> *const PropInfo;
+        fn __system_property_get(name: *const c_char, value: *mut c_char) -> c_int;
+    }
+
+    /// `__system_property_read_callback`, which is the only way to read values longer than
+    /// `PROP_VALUE_MAX`, but only exists from Android 8.0.
+    fn read_callback() -> Option<ReadCallback> {
+        static READ_CALLBACK: OnceLock<usize> = OnceLock::new();
+        let address = *READ_CALLBACK.get_or_init(|| unsafe {
+            libc::dlsym(
+                libc::RTLD_DEFAULT,
+                c"__system_property_read_callback".as_ptr(),
+            ) as usize
+        });
+        // SAFETY: the symbol has this signature in every bionic that has it.
+        (address != 0).then(|| unsafe { std::mem::transmute::<usize, ReadCallback>(address) })
+    }
+
+    unsafe extern "C" fn store(
+        cookie: *mut c_void,
+        _name: *const c_char,
+        value: *const c_char,
+        _serial: u32,
+    ) {
+        let value = CStr::from_ptr(value).to_string_lossy().into_owned();
+        *cookie.cast::<Option<String>>() = Some(value);
+    }
+
+    pub(super) fn get(name: &CStr) -> Option<String> {
+        let info = unsafe { __system_property_find(name.as_ptr()) };
+        if info.is_null() {
+            return None;
+        }
+        if let Some(read) = read_callback() {
+            let mut value = None;
+            unsafe { read(info, store, (&mut value as *mut Option<String>).cast()) };
+            return value;
+        }
+        let mut buf = [0 as c_char; PROP_VALUE_MAX];
+        unsafe { __system_property_get(name.as_ptr(), buf.as_mut_ptr()) };
+        let value = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        Some(value.to_string_lossy().into_owned())
+    }
+}
+
+/// Get the value of a property, or `None` if it is not set.
+///
+/// Like `getprop`, a property that was set to an empty string reads as unset.
+pub fn get(name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    #[cfg(target_os = "android")]
+    let value = bionic::get(&name);
+    #[cfg(not(target_os = "android"))]
+    let value = {
+        let _ = name;
+        None::<String>
+    };
+    value.filter(|value| !value.is_empty())
+}
+
+/// Get a boolean property, or `None` if it is not set or not a boolean.
+///
+/// As in `android::base::GetBoolProperty`, `1`, `y`, `yes`, `on` and `true` are true, and `0`,
+/// `n`, `no`, `off` and `false` are false.
+pub fn get_bool(name: &str) -> Option<bool> {
+    get(name).and_then(|value| parse_bool(&value))
+}
+
+/// Get an integer property, or `None` if it is not set or not an integer.
+///
+/// Values may be decimal or, with a `0x` prefix, hexadecimal.
+pub fn get_int(name: &str) -> Option<i64> {
+    get(name).and_then(|value| parse_int(&value))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "y" | "yes" | "on" | "true" => Some(true),
+        "0" | "n" | "no" | "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_int(value: &str) -> Option<i64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    let magnitude = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(parse_bool("yes"), Some(true));
+    assert_eq!(parse_bool("off"), Some(false));
+    assert_eq!(parse_bool("True"), None);
+
+    assert_eq!(parse_int("34"), Some(34));
+    assert_eq!(parse_int("-0x10"), Some(-16));
+    assert_eq!(parse_int("12abc"), None);
+
+    assert_eq!(get("ro.build.version.sdk"), None);
+    assert_eq!(get("invalid\0name"), None);
+}
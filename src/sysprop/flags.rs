@@ -0,0 +1,129 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Module settings read from `persist.<module>.<name>` properties, so that they can be changed
+/// with `adb shell setprop` without rebuilding the module.
+///
+/// Each flag is read once per process and then cached, so that a process sees consistent values
+/// even if the property changes while it runs. The cache is not inherited from zygote: new
+/// processes read the current values again. Call [Self::load()] in `pre_app_specialize` to read
+/// the flags while the process is being set up rather than from the first hook that needs them.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::sysprop::FeatureFlags;
+///
+/// static FLAGS: FeatureFlags = FeatureFlags::new("mymodule");
+///
+/// // After `adb shell setprop persist.mymodule.verbose 1`:
+/// if FLAGS.bool("verbose", false) {
+///     // ...
+/// }
+/// let delay = FLAGS.int("delay_ms", 100);
+/// ```
+pub struct FeatureFlags {
+    module: &'static str,
+    source: fn(&str) -> Option<String>,
+    cache: Mutex<Cache>,
+}
+
+struct Cache {
+    pid: u32,
+    values: BTreeMap<String, Option<String>>,
+}
+
+impl FeatureFlags {
+    /// Flags read from the `persist.<module>.` properties.
+    pub const fn new(module: &'static str) -> Self {
+        Self::with_source(module, super::get)
+    }
+
+    const fn with_source(module: &'static str, source: fn(&str) -> Option<String>) -> Self {
+        FeatureFlags {
+            module,
+            source,
+            cache: Mutex::new(Cache {
+                pid: 0,
+                values: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Read the given flags now, unless they were already read in this process.
+    pub fn load(&self, names: &[&str]) {
+        for name in names {
+            self.get(name);
+        }
+    }
+
+    /// Forget the values read so far, so that they are read again.
+    pub fn reload(&self) {
+        self.lock().values.clear();
+    }
+
+    /// The value of a flag, or `None` if it is not set.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let mut cache = self.lock();
+        if let Some(value) = cache.values.get(name) {
+            return value.clone();
+        }
+        let value = (self.source)(&format!("persist.{}.{name}", self.module));
+        cache.values.insert(name.into(), value.clone());
+        value
+    }
+
+    /// A boolean flag, or `default` if it is not set or not a boolean. See
+    /// [get_bool()](super::get_bool) for the accepted values.
+    pub fn bool(&self, name: &str, default: bool) -> bool {
+        self.get(name)
+            .and_then(|value| super::parse_bool(&value))
+            .unwrap_or(default)
+    }
+
+    /// An integer flag, or `default` if it is not set or not an integer.
+    pub fn int(&self, name: &str, default: i64) -> i64 {
+        self.get(name)
+            .and_then(|value| super::parse_int(&value))
+            .unwrap_or(default)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        // Values cached in zygote, or in the parent of a fork, are stale.
+        let pid = std::process::id();
+        if cache.pid != pid {
+            cache.pid = pid;
+            cache.values.clear();
+        }
+        cache
+    }
+}
+
+#[test]
+fn test_feature_flags() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static READS: AtomicUsize = AtomicUsize::new(0);
+    fn source(name: &str) -> Option<String> {
+        READS.fetch_add(1, Ordering::Relaxed);
+        match name {
+            "persist.test.verbose" => Some("on".into()),
+            "persist.test.delay_ms" => Some("0x20".into()),
+            "persist.test.mode" => Some("fast".into()),
+            _ => None,
+        }
+    }
+
+    let flags = FeatureFlags::with_source("test", source);
+    flags.load(&["verbose", "delay_ms"]);
+    assert_eq!(READS.load(Ordering::Relaxed), 2);
+    assert!(flags.bool("verbose", false));
+    assert_eq!(flags.int("delay_ms", 0), 32);
+    assert_eq!(flags.int("mode", 7), 7);
+    assert!(flags.bool("missing", true));
+    assert_eq!(READS.load(Ordering::Relaxed), 4);
+
+    flags.reload();
+    assert_eq!(flags.get("mode").as_deref(), Some("fast"));
+    assert_eq!(READS.load(Ordering::Relaxed), 5);
+}
@@ -0,0 +1,676 @@
+//! Stand-ins for the Zygisk runtime, to unit test modules on the host without a device.
+//!
+//! [MockApi] hands out a real [ZygiskApi] whose function table is backed by the mock: flags and
+//! the module directory are configured up front, the companion is a closure running on a thread
+//! of the test process, and every option, hook and exempted fd is recorded for assertions.
+//!
+//! Enable the `testing` feature in your `[dev-dependencies]` to use this module:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! zygisk = { version = "0.3", features = ["testing"] }
+//! ```
+
+use std::{
+    cell::{Cell, OnceCell},
+    collections::{BTreeMap, VecDeque},
+    ffi::{CStr, CString},
+    fs::File,
+    os::{
+        fd::{IntoRawFd, RawFd},
+        raw::{c_char, c_int},
+        unix::net::UnixStream,
+    },
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    binding::{LegacyApiTable, RawApiTable},
+    companion::{handshake, BuiltinServices},
+    jni::sys::{JNIEnv, JNINativeMethod},
+    libc::{self, dev_t, ino_t},
+    ApiVersion, PostSpecializeApi, StateFlags, ZygiskApi, ZygiskOption,
+};
+
+mod env;
+mod harness;
+mod record;
+mod trace;
+
+pub use env::StubEnv;
+pub use harness::{AppProcess, Harness, Outcome, ServerProcess};
+pub use record::Recorder;
+pub use trace::{Call, ParseTraceError, Trace};
+
+thread_local! {
+    /// The mock whose API was handed out last on this thread, for the table functions that Zygisk
+    /// does not pass a context to.
+    static CURRENT: Cell<*const MockState> = const { Cell::new(std::ptr::null()) };
+}
+
+type CompanionFn = Arc<dyn Fn(UnixStream) + Send + Sync>;
+type ExemptFn = Box<dyn Fn(RawFd) -> bool>;
+
+/// A fake Zygisk runtime backing a [ZygiskApi], for unit tests.
+///
+/// Functions that Zygisk calls without a context (the JNI and PLT hooks, and
+/// [ZygiskApi::exempt_fd()]) are recorded on the mock whose [Self::api()] was called last on the
+/// current thread.
+///
+/// ## Example
+///
+/// ```
+/// use zygisk::{testing::MockApi, StateFlags, ZygiskApi, ZygiskOption};
+///
+/// fn unload_unless_rooted(api: &ZygiskApi) {
+///     let flags = api.get_flags().unwrap();
+///     if !flags.contains(StateFlags::PROCESS_GRANTED_ROOT) {
+///         api.set_option(ZygiskOption::DlcloseModuleLibrary).unwrap();
+///     }
+/// }
+///
+/// let mock = MockApi::new();
+/// unload_unless_rooted(&mock.api());
+/// assert_eq!(mock.options(), [ZygiskOption::DlcloseModuleLibrary]);
+///
+/// let mock = MockApi::new().flags(StateFlags::PROCESS_GRANTED_ROOT);
+/// unload_unless_rooted(&mock.api());
+/// assert!(mock.options().is_empty());
+/// ```
+pub struct MockApi {
+    state: Box<MockState>,
+    table: OnceCell<Box<RawApiTable>>,
+    version: ApiVersion,
+}
+
+struct MockState {
+    flags: u32,
+    module_dir: Option<PathBuf>,
+    companion: Option<CompanionFn>,
+    companion_services: BuiltinServices,
+    exempt: Option<ExemptFn>,
+    unavailable: Vec<&'static str>,
+    natives: Mutex<BTreeMap<(String, String, String), usize>>,
+    records: Mutex<Records>,
+    replay: Option<Mutex<Replay>>,
+}
+
+struct Replay {
+    calls: VecDeque<Call>,
+    mismatches: Vec<String>,
+}
+
+#[derive(Default)]
+struct Records {
+    options: Vec<ZygiskOption>,
+    jni_hooks: Vec<MockJniHook>,
+    plt_hooks: Vec<MockPltHook>,
+    plt_commits: usize,
+    exempted_fds: Vec<RawFd>,
+}
+
+/// A JNI native method hooked through a [MockApi].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockJniHook {
+    pub class: String,
+    pub name: String,
+    pub signature: String,
+    /// The address of the hook function.
+    pub function: usize,
+}
+
+/// A PLT hook registered through a [MockApi].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockPltHook {
+    /// `"<device>:<inode>"` of the ELF for [ZygiskApi::plt_hook_register()], or the regular
+    /// expression for [ZygiskApi::plt_hook_register_regex()].
+    pub library: String,
+    pub symbol: String,
+    /// The address of the hook function.
+    pub function: usize,
+}
+
+impl MockApi {
+    /// A runtime of [ApiVersion::LATEST] with every function available, no flags set, no module
+    /// directory and no companion.
+    pub fn new() -> Self {
+        MockApi {
+            state: Box::new(MockState {
+                flags: 0,
+                module_dir: None,
+                companion: None,
+                companion_services: BuiltinServices::empty(),
+                exempt: None,
+                unavailable: Vec::new(),
+                natives: Mutex::new(BTreeMap::new()),
+                records: Mutex::new(Records::default()),
+                replay: None,
+            }),
+            table: OnceCell::new(),
+            version: ApiVersion::LATEST,
+        }
+    }
+
+    /// Use the table layout and functions of an older API version.
+    pub fn version(mut self, version: ApiVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Return `flags` from [ZygiskApi::get_flags()].
+    pub fn flags(self, flags: StateFlags) -> Self {
+        self.raw_flags(flags.bits())
+    }
+
+    /// Return `flags` from [ZygiskApi::get_flags()], including bits this crate does not know.
+    pub fn raw_flags(mut self, flags: u32) -> Self {
+        self.state.flags = flags;
+        self
+    }
+
+    /// Open `path` for [ZygiskApi::get_module_dir()].
+    pub fn module_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state.module_dir = Some(path.into());
+        self
+    }
+
+    /// Run `handler` on a new thread for every [ZygiskApi::connect_companion()].
+    ///
+    /// If the module is registered with `zygisk_module!`, the connection goes through the same
+    /// handshake as with `zygisk_companion!`, and the services of [companion](crate::companion)
+    /// enabled with [Self::companion_services()] are served without involving `handler`.
+    pub fn companion(mut self, handler: impl Fn(UnixStream) + Send + Sync + 'static) -> Self {
+        self.state.companion = Some(Arc::new(handler));
+        self
+    }
+
+    /// Serve `services` in the companion, like `zygisk_companion!(handler, services = [...])`.
+    ///
+    /// The services working in the module directory use the one of [Self::module_dir()].
+    pub fn companion_services(mut self, services: BuiltinServices) -> Self {
+        self.state.companion_services = services;
+        self
+    }
+
+    /// Decide the result of [ZygiskApi::exempt_fd()]. By default every fd is exempted.
+    pub fn exempt_fd(mut self, exempt: impl Fn(RawFd) -> bool + 'static) -> Self {
+        self.state.exempt = Some(Box::new(exempt));
+        self
+    }
+
+    /// Leave the table function `name` out, as if the host did not provide it.
+    ///
+    /// Names are the ones reported by [ZygiskError::ApiFunctionUnavailable](crate::ZygiskError),
+    /// such as `"exempt_fd"`.
+    pub fn without(mut self, name: &'static str) -> Self {
+        self.state.unavailable.push(name);
+        self
+    }
+
+    /// Answer the API calls with the results recorded in `trace`, such as on a device with a
+    /// [Recorder].
+    ///
+    /// The calls are expected in the same order and with the same arguments, which
+    /// [Self::assert_replayed()] checks. Functions returning a file descriptor only replay
+    /// failures: when they succeeded on the device, the companion and module directory configured
+    /// on the mock are used. Hooked JNI methods that were found on the device are handed back the
+    /// function registered with [Self::jni_native()], or a function that aborts if called.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use zygisk::{testing::MockApi, ZygiskOption};
+    ///
+    /// let trace = "get_flags\t0x2\nset_option\tForceDenylistUnmount\n";
+    /// let mock = MockApi::new().replay(trace.parse().unwrap());
+    /// let api = mock.api();
+    /// if api.get_flags().unwrap().bits() != 0 {
+    ///     api.set_option(ZygiskOption::ForceDenylistUnmount).unwrap();
+    /// }
+    /// mock.assert_replayed();
+    /// ```
+    pub fn replay(mut self, trace: Trace) -> Self {
+        self.state.replay = Some(Mutex::new(Replay {
+            calls: trace.calls.into(),
+            mismatches: Vec::new(),
+        }));
+        self
+    }
+
+    /// Panic if the calls did not follow the trace passed to [Self::replay()], or if some of its
+    /// calls were not made.
+    pub fn assert_replayed(&self) {
+        let Some(replay) = &self.state.replay else {
+            return;
+        };
+        let replay = lock(replay);
+        let mut problems = replay.mismatches.clone();
+        if let Some(call) = replay.calls.front() {
+            problems.push(format!(
+                "{} calls of the trace were not made, starting with `{call}`",
+                replay.calls.len()
+            ));
+        }
+        assert!(problems.is_empty(), "{}", problems.join("\n"));
+    }
+
+    /// Register a JNI native method that hooks of the same method replace.
+    pub fn jni_native(self, class: &str, name: &str, signature: &str, function: usize) -> Self {
+        let key = (class.into(), name.into(), signature.into());
+        lock(&self.state.natives).insert(key, function);
+        self
+    }
+
+    /// The API handle backed by the mock.
+    pub fn api(&self) -> ZygiskApi<'_> {
+        CURRENT.with(|current| current.set(&*self.state));
+        ZygiskApi::from_raw(self.table(), self.version)
+    }
+
+    /// The function table, which stays in place when the mock is moved.
+    pub(crate) fn table(&self) -> &RawApiTable {
+        self.table
+            .get_or_init(|| Box::new(self.state.table(self.version)))
+    }
+
+    /// The API handle passed to the `post[XXX]Specialize` callbacks, backed by the mock.
+    pub fn post_api(&self) -> PostSpecializeApi<'_> {
+        PostSpecializeApi::new(self.api())
+    }
+
+    /// The options set so far, in order.
+    pub fn options(&self) -> Vec<ZygiskOption> {
+        lock(&self.state.records).options.clone()
+    }
+
+    /// The JNI native methods hooked so far, in order.
+    pub fn jni_hooks(&self) -> Vec<MockJniHook> {
+        lock(&self.state.records).jni_hooks.clone()
+    }
+
+    /// The PLT hooks registered so far, in order.
+    pub fn plt_hooks(&self) -> Vec<MockPltHook> {
+        lock(&self.state.records).plt_hooks.clone()
+    }
+
+    /// How many times the PLT hooks were committed.
+    pub fn plt_commits(&self) -> usize {
+        lock(&self.state.records).plt_commits
+    }
+
+    /// The fds passed to [ZygiskApi::exempt_fd()] so far, in order, whether or not they were
+    /// exempted.
+    pub fn exempted_fds(&self) -> Vec<RawFd> {
+        lock(&self.state.records).exempted_fds.clone()
+    }
+}
+
+impl Default for MockApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MockApi {
+    fn drop(&mut self) {
+        CURRENT.with(|current| {
+            if std::ptr::eq(current.get(), &*self.state) {
+                current.set(std::ptr::null());
+            }
+        });
+    }
+}
+
+impl MockState {
+    fn table(&self, version: ApiVersion) -> RawApiTable {
+        let available = |name| !self.unavailable.contains(&name);
+        let this = self as *const MockState as *const ();
+        let connect_companion = available("connect_companion").then_some(connect_companion as _);
+        let set_option = available("set_option").then_some(set_option as _);
+        let get_module_dir = available("get_module_dir").then_some(get_module_dir as _);
+        let get_flags = available("get_flags").then_some(get_flags as _);
+        let hook_jni_native_methods =
+            available("hook_jni_native_methods").then_some(hook_jni_native_methods as _);
+        let plt_hook_commit = available("plt_hook_commit").then_some(plt_hook_commit as _);
+
+        if version >= ApiVersion::V4 {
+            RawApiTable {
+                this,
+                register_module: None,
+                hook_jni_native_methods,
+                plt_hook_register: available("plt_hook_register").then_some(plt_hook_register as _),
+                plt_hook_commit,
+                connect_companion,
+                set_option,
+                get_module_dir,
+                get_flags,
+                exempt_fd: available("exempt_fd").then_some(exempt_fd as _),
+            }
+        } else {
+            let legacy = LegacyApiTable {
+                this,
+                register_module: None,
+                hook_jni_native_methods,
+                plt_hook_register: available("plt_hook_register_regex")
+                    .then_some(plt_hook_register_regex as _),
+                plt_hook_exclude: available("plt_hook_exclude").then_some(plt_hook_exclude as _),
+                plt_hook_commit,
+                connect_companion,
+                set_option,
+                get_module_dir,
+                get_flags,
+            };
+            // SAFETY: both layouts are `repr(C)` structs of the same number of pointers, and
+            // `ZygiskApi` reads the table back as a `LegacyApiTable` for this version.
+            unsafe { std::mem::transmute::<LegacyApiTable, RawApiTable>(legacy) }
+        }
+    }
+}
+
+impl MockState {
+    /// The next call of the trace being replayed, if any, when `function` is called.
+    ///
+    /// Mismatches are kept for [MockApi::assert_replayed()], since panics cannot unwind out of
+    /// the table functions.
+    fn replayed(&self, function: &'static str, check: impl FnOnce(&Call) -> bool) -> Option<Call> {
+        let mut replay = lock(self.replay.as_ref()?);
+        let call = replay.calls.pop_front();
+        let mismatch = match &call {
+            None => format!("unexpected call to {function}, after the end of the trace"),
+            Some(call) if call.function() != function || !check(call) => {
+                format!("expected `{call}`, got a different call to {function}")
+            }
+            Some(_) => return call,
+        };
+        replay.mismatches.push(mismatch);
+        None
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // Assertions failing in a companion thread should not hide the records from the test.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn state<'a>(this: *const ()) -> &'a MockState {
+    // SAFETY: `this` is the state of the mock that handed out the API, which outlives the API.
+    unsafe { &*this.cast::<MockState>() }
+}
+
+fn with_current<R: Default>(f: impl FnOnce(&MockState) -> R) -> R {
+    let current = CURRENT.with(Cell::get);
+    if current.is_null() {
+        R::default()
+    } else {
+        // SAFETY: the pointer is cleared when the mock is dropped.
+        f(unsafe { &*current })
+    }
+}
+
+fn string(ptr: *const c_char) -> String {
+    // SAFETY: the crate only passes C strings to the table functions.
+    unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+extern "C" fn connect_companion(this: *const ()) -> c_int {
+    let state = state(this);
+    let replayed = state.replayed("connect_companion", |_| true);
+    if let Some(Call::ConnectCompanion { success: false }) = replayed {
+        return -1;
+    }
+    let Ok((module, companion)) = UnixStream::pair() else {
+        return -1;
+    };
+    let Some(handler) = state.companion.clone() else {
+        // The companion answered on the device, but there is none to talk to here.
+        return if replayed.is_some() {
+            module.into_raw_fd()
+        } else {
+            -1
+        };
+    };
+    let services = state.companion_services;
+    let module_dir = state.module_dir.clone();
+    std::thread::spawn(move || {
+        let protocol = handshake::module_protocol();
+        let stream = crate::macros::companion_accept(
+            companion.into_raw_fd(),
+            protocol,
+            services,
+            module_dir.as_deref().and_then(|dir| dir.to_str()),
+            None,
+        );
+        if let Some(stream) = stream {
+            handler(stream);
+        }
+    });
+    module.into_raw_fd()
+}
+
+extern "C" fn set_option(this: *const (), option: ZygiskOption) {
+    let state = state(this);
+    state.replayed("set_option", |call| *call == Call::SetOption(option));
+    lock(&state.records).options.push(option);
+}
+
+extern "C" fn get_module_dir(this: *const ()) -> c_int {
+    let state = state(this);
+    if let Some(Call::GetModuleDir { success: false }) = state.replayed("get_module_dir", |_| true)
+    {
+        return -1;
+    }
+    state
+        .module_dir
+        .as_ref()
+        .and_then(|path| File::open(path).ok())
+        .map_or(-1, IntoRawFd::into_raw_fd)
+}
+
+extern "C" fn get_flags(this: *const ()) -> u32 {
+    let state = state(this);
+    match state.replayed("get_flags", |_| true) {
+        Some(Call::GetFlags(flags)) => flags,
+        _ => state.flags,
+    }
+}
+
+extern "C" fn exempt_fd(fd: c_int) -> bool {
+    with_current(|state| {
+        lock(&state.records).exempted_fds.push(fd);
+        // The fds are numbered differently from one run to the next.
+        match state.replayed("exempt_fd", |_| true) {
+            Some(Call::ExemptFd { success, .. }) => success,
+            _ => state.exempt.as_ref().is_none_or(|exempt| exempt(fd)),
+        }
+    })
+}
+
+extern "C" fn hook_jni_native_methods(
+    _env: *mut JNIEnv,
+    class: *const c_char,
+    methods: *mut JNINativeMethod,
+    count: c_int,
+) {
+    let class = string(class);
+    // SAFETY: the crate passes the methods it was given, which are valid for `count` entries.
+    let methods = unsafe { std::slice::from_raw_parts_mut(methods, count as usize) };
+    with_current(|state| {
+        let mut natives = lock(&state.natives);
+        let mut records = lock(&state.records);
+        for method in methods {
+            let name = string(method.name);
+            let signature = string(method.signature);
+            records.jni_hooks.push(MockJniHook {
+                class: class.clone(),
+                name: name.clone(),
+                signature: signature.clone(),
+                function: method.fnPtr as usize,
+            });
+            let replayed = state.replayed("hook_jni_native_methods", |call| {
+                matches!(call, Call::HookJniNativeMethod { class: c, name: n, signature: s, .. }
+                    if *c == class && *n == name && *s == signature)
+            });
+            // Like Zygisk, hand back the previous function, or null if the method does not exist.
+            let key = (class.clone(), name, signature);
+            let previous = natives.insert(key, method.fnPtr as usize);
+            method.fnPtr = match replayed {
+                Some(Call::HookJniNativeMethod { found: false, .. }) => std::ptr::null_mut(),
+                Some(_) => previous.unwrap_or(unreplayable_native as *const () as usize) as *mut _,
+                None => previous.unwrap_or(0) as *mut _,
+            };
+        }
+    });
+}
+
+fn record_plt_hook(
+    library: String,
+    symbol: *const c_char,
+    new_func: *mut (),
+    old_func: *mut *mut (),
+) {
+    with_current(|state| {
+        let symbol = string(symbol);
+        // ELFs have different devices and inodes from one device to the next.
+        state.replayed(
+            "plt_hook_register",
+            |call| matches!(call, Call::PltHookRegister { symbol: s, .. } if *s == symbol),
+        );
+        if !old_func.is_null() {
+            // The original function is whatever the symbol resolves to in the test process.
+            let name = CString::new(symbol.clone()).unwrap_or_default();
+            // SAFETY: `old_func` is required to stay valid until the hooks are committed.
+            unsafe { *old_func = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()).cast() };
+        }
+        lock(&state.records).plt_hooks.push(MockPltHook {
+            library,
+            symbol,
+            function: new_func as usize,
+        });
+    })
+}
+
+extern "C" fn plt_hook_register(
+    device: dev_t,
+    inode: ino_t,
+    symbol: *const c_char,
+    new_func: *mut (),
+    old_func: *mut *mut (),
+) {
+    record_plt_hook(format!("{device}:{inode}"), symbol, new_func, old_func);
+}
+
+extern "C" fn plt_hook_register_regex(
+    regex: *const c_char,
+    symbol: *const c_char,
+    new_func: *mut (),
+    old_func: *mut *mut (),
+) {
+    record_plt_hook(string(regex), symbol, new_func, old_func);
+}
+
+extern "C" fn plt_hook_exclude(regex: *const c_char, symbol: *const c_char) {
+    // Exclusions only affect which ELFs get hooked, and the mock hooks none.
+    with_current(|state| {
+        let call = Call::PltHookExclude {
+            regex: string(regex),
+            symbol: string(symbol),
+        };
+        state.replayed("plt_hook_exclude", |expected| *expected == call);
+    })
+}
+
+extern "C" fn plt_hook_commit() -> bool {
+    with_current(|state| {
+        lock(&state.records).plt_commits += 1;
+        match state.replayed("plt_hook_commit", |_| true) {
+            Some(Call::PltHookCommit { success }) => success,
+            _ => true,
+        }
+    })
+}
+
+/// Handed back as the original of JNI methods that existed on the device but not in the test.
+extern "C" fn unreplayable_native() {
+    eprintln!("called the original of a JNI method hooked in a replayed trace");
+    std::process::abort();
+}
+
+#[test]
+fn test_mock_api() {
+    use crate::{SocketExt, ZygiskError};
+
+    let mock = MockApi::new()
+        .raw_flags(0x8000_0001)
+        .module_dir(std::env::temp_dir())
+        .companion(|mut stream| {
+            let value = stream.recv_u32().unwrap();
+            stream.send_u32(value * 2).unwrap();
+        })
+        .jni_native("a/B", "run", "()V", 0x111);
+    let api = mock.api();
+
+    assert_eq!(api.get_flags().unwrap(), StateFlags::PROCESS_GRANTED_ROOT);
+    assert!(api.try_get_flags().is_err());
+    assert!(api.get_module_dir().is_ok());
+    api.set_option(ZygiskOption::ForceDenylistUnmount).unwrap();
+    assert_eq!(mock.options(), [ZygiskOption::ForceDenylistUnmount]);
+
+    let answer = api.with_companion(|stream| {
+        stream.send_u32(21)?;
+        stream.recv_u32()
+    });
+    assert_eq!(answer.unwrap(), 42);
+
+    let env = unsafe { crate::jni::JNIEnv::from_raw(std::ptr::NonNull::dangling().as_ptr()) };
+    let class = unsafe { crate::jni::strings::JNIStr::from_ptr(c"a/B".as_ptr()) };
+    let mut methods = [JNINativeMethod {
+        name: c"run".as_ptr() as *mut _,
+        signature: c"()V".as_ptr() as *mut _,
+        fnPtr: 0x222 as *mut _,
+    }];
+    unsafe { api.hook_jni_native_methods(env.unwrap(), class, &mut methods) }.unwrap();
+    assert_eq!(methods[0].fnPtr as usize, 0x111);
+    assert_eq!(mock.jni_hooks()[0].function, 0x222);
+
+    let mock = MockApi::new().without("set_option");
+    assert!(matches!(
+        mock.api().set_option(ZygiskOption::DlcloseModuleLibrary),
+        Err(ZygiskError::ApiFunctionUnavailable("set_option"))
+    ));
+    assert!(mock.api().connect_companion().is_err());
+}
+
+#[cfg(feature = "api-v4")]
+#[test]
+fn test_mock_api_plt_hooks() {
+    let mock = MockApi::new().exempt_fd(|fd| fd != 2);
+    let api = mock.api();
+
+    let mut old = std::ptr::null_mut();
+    unsafe { api.plt_hook_register(1, 2, c"getpid", 0x123 as *mut (), Some(&mut old)) }.unwrap();
+    api.plt_hook_commit().unwrap();
+    assert!(!old.is_null());
+    assert_eq!(mock.plt_commits(), 1);
+    assert_eq!(
+        mock.plt_hooks(),
+        [MockPltHook {
+            library: "1:2".into(),
+            symbol: "getpid".into(),
+            function: 0x123,
+        }]
+    );
+
+    assert!(api.exempt_fd(std::io::stdout()).is_ok());
+    assert!(api.exempt_fd(std::io::stderr()).is_err());
+    assert_eq!(mock.exempted_fds(), [1, 2]);
+
+    let legacy = MockApi::new().version(ApiVersion::V3);
+    let api = legacy.api();
+    unsafe { api.plt_hook_register_regex(c".*libc\\.so$", c"getpid", 0x456 as *mut (), None) }
+        .unwrap();
+    assert_eq!(legacy.plt_hooks()[0].library, ".*libc\\.so$");
+    assert!(api.exempt_fd(std::io::stdout()).is_err());
+}
@@ -0,0 +1,324 @@
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::jni::{
+    sys::{self, jboolean, jclass, jint, jintArray, jobject, jsize, jstring, JNI_FALSE, JNI_TRUE},
+    JNIEnv,
+};
+
+/// A `JNIEnv` for tests that need one without a Java VM.
+///
+/// The stub implements just enough of JNI for the arguments of the specialize callbacks:
+/// `String`s and `int[]`s can be created and read, anything else fails with
+/// [JNIEnvMethodNotFound](crate::jni::errors::Error::JNIEnvMethodNotFound).
+///
+/// ## Example
+///
+/// ```
+/// use zygisk::testing::StubEnv;
+///
+/// let stub = StubEnv::new();
+/// let mut env = stub.env();
+/// let string = env.new_string("com.example").unwrap();
+/// assert_eq!(String::from(env.get_string(&string).unwrap()), "com.example");
+/// assert!(env.find_class("android/app/Activity").is_err());
+/// ```
+pub struct StubEnv {
+    inner: Box<Inner>,
+}
+
+// `JNIEnv` points to the first field, which the functions cast back to the whole struct.
+#[repr(C)]
+struct Inner {
+    functions: *const sys::JNINativeInterface_,
+    table: sys::JNINativeInterface_,
+    objects: Mutex<Vec<Object>>,
+}
+
+enum Object {
+    Class(CString),
+    String(CString),
+    IntArray(Vec<jint>),
+}
+
+const STRING_CLASS: &CStr = c"java/lang/String";
+const INT_ARRAY_CLASS: &CStr = c"[I";
+
+impl StubEnv {
+    pub fn new() -> Self {
+        // SAFETY: the table only holds optional function pointers and reserved null pointers.
+        let mut table: sys::JNINativeInterface_ = unsafe { std::mem::zeroed() };
+        table.FindClass = Some(find_class);
+        table.GetObjectClass = Some(get_object_class);
+        table.IsAssignableFrom = Some(is_assignable_from);
+        table.IsInstanceOf = Some(is_instance_of);
+        table.ExceptionCheck = Some(exception_check);
+        table.DeleteLocalRef = Some(delete_local_ref);
+        table.NewStringUTF = Some(new_string_utf);
+        table.GetStringUTFLength = Some(get_string_utf_length);
+        table.GetStringUTFChars = Some(get_string_utf_chars);
+        table.ReleaseStringUTFChars = Some(release_string_utf_chars);
+        table.GetArrayLength = Some(get_array_length);
+        table.NewIntArray = Some(new_int_array);
+        table.GetIntArrayRegion = Some(get_int_array_region);
+        table.SetIntArrayRegion = Some(set_int_array_region);
+
+        let mut inner = Box::new(Inner {
+            functions: std::ptr::null(),
+            table,
+            objects: Mutex::new(Vec::new()),
+        });
+        inner.functions = &inner.table;
+        StubEnv { inner }
+    }
+
+    /// The environment, valid as long as the stub.
+    pub fn env(&self) -> JNIEnv<'_> {
+        // SAFETY: the pointer is not null, and points to a function table.
+        unsafe { JNIEnv::from_raw(self.as_raw()) }.unwrap()
+    }
+
+    /// The raw environment pointer, valid as long as the stub.
+    pub fn as_raw(&self) -> *mut sys::JNIEnv {
+        &*self.inner as *const Inner as *mut sys::JNIEnv
+    }
+
+    /// Create a `String`. The local reference is valid as long as the stub.
+    pub fn new_string(&self, value: &str) -> jstring {
+        let value = CString::new(value.replace('\0', "")).unwrap();
+        self.inner.push(Object::String(value))
+    }
+
+    /// The value of a `String` created by the stub.
+    pub fn string(&self, string: jstring) -> Option<String> {
+        match self.inner.objects().get(index(string)?) {
+            Some(Object::String(value)) => Some(value.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    /// Create an `int[]`. The local reference is valid as long as the stub.
+    pub fn new_int_array(&self, values: &[jint]) -> jintArray {
+        self.inner.push(Object::IntArray(values.to_vec()))
+    }
+
+    /// The contents of an `int[]` created by the stub.
+    pub fn int_array(&self, array: jintArray) -> Option<Vec<jint>> {
+        match self.inner.objects().get(index(array)?) {
+            Some(Object::IntArray(values)) => Some(values.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for StubEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inner {
+    fn objects(&self) -> MutexGuard<'_, Vec<Object>> {
+        self.objects.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Store an object, returning a reference to it: its index plus one, so that it is not null.
+    fn push(&self, object: Object) -> jobject {
+        let mut objects = self.objects();
+        objects.push(object);
+        objects.len() as jobject
+    }
+}
+
+fn index(object: jobject) -> Option<usize> {
+    (object as usize).checked_sub(1)
+}
+
+unsafe fn inner<'a>(env: *mut sys::JNIEnv) -> &'a Inner {
+    &*env.cast::<Inner>()
+}
+
+fn with_object<R>(
+    env: *mut sys::JNIEnv,
+    object: jobject,
+    f: impl FnOnce(&mut Object) -> R,
+) -> Option<R> {
+    // SAFETY: the functions are only reachable through a `StubEnv`.
+    let mut objects = unsafe { inner(env) }.objects();
+    index(object)
+        .and_then(|index| objects.get_mut(index))
+        .map(f)
+}
+
+fn class_of(object: &Object) -> &'static CStr {
+    match object {
+        Object::Class(_) => c"java/lang/Class",
+        Object::String(_) => STRING_CLASS,
+        Object::IntArray(_) => INT_ARRAY_CLASS,
+    }
+}
+
+fn class_name(env: *mut sys::JNIEnv, class: jclass) -> Option<CString> {
+    with_object(env, class, |object| match object {
+        Object::Class(name) => Some(name.clone()),
+        _ => None,
+    })
+    .flatten()
+}
+
+unsafe extern "system" fn find_class(env: *mut sys::JNIEnv, name: *const c_char) -> jclass {
+    let name = CStr::from_ptr(name);
+    if name == STRING_CLASS || name == INT_ARRAY_CLASS {
+        inner(env).push(Object::Class(name.into()))
+    } else {
+        std::ptr::null_mut()
+    }
+}
+
+unsafe extern "system" fn get_object_class(env: *mut sys::JNIEnv, object: jobject) -> jclass {
+    match with_object(env, object, |object| class_of(object)) {
+        Some(class) => inner(env).push(Object::Class(class.into())),
+        None => std::ptr::null_mut(),
+    }
+}
+
+unsafe extern "system" fn is_assignable_from(
+    env: *mut sys::JNIEnv,
+    sub: jclass,
+    sup: jclass,
+) -> jboolean {
+    let same = class_name(env, sub).is_some_and(|sub| Some(sub) == class_name(env, sup));
+    if same {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+unsafe extern "system" fn is_instance_of(
+    env: *mut sys::JNIEnv,
+    object: jobject,
+    class: jclass,
+) -> jboolean {
+    let object_class = with_object(env, object, |object| class_of(object));
+    let class = class_name(env, class);
+    if object_class.is_some_and(|object_class| Some(object_class) == class.as_deref()) {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+unsafe extern "system" fn exception_check(_env: *mut sys::JNIEnv) -> jboolean {
+    JNI_FALSE
+}
+
+unsafe extern "system" fn delete_local_ref(_env: *mut sys::JNIEnv, _object: jobject) {
+    // References stay valid as long as the stub, like in the JNI frame of a specialization.
+}
+
+unsafe extern "system" fn new_string_utf(env: *mut sys::JNIEnv, utf: *const c_char) -> jstring {
+    inner(env).push(Object::String(CStr::from_ptr(utf).into()))
+}
+
+unsafe extern "system" fn get_string_utf_length(env: *mut sys::JNIEnv, string: jstring) -> jsize {
+    with_object(env, string, |object| match object {
+        Object::String(value) => value.as_bytes().len() as jsize,
+        _ => 0,
+    })
+    .unwrap_or(0)
+}
+
+unsafe extern "system" fn get_string_utf_chars(
+    env: *mut sys::JNIEnv,
+    string: jstring,
+    is_copy: *mut jboolean,
+) -> *const c_char {
+    if !is_copy.is_null() {
+        *is_copy = JNI_FALSE;
+    }
+    // The contents of a `CString` do not move when the list of objects grows.
+    with_object(env, string, |object| match object {
+        Object::String(value) => value.as_ptr(),
+        _ => std::ptr::null(),
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+unsafe extern "system" fn release_string_utf_chars(
+    _env: *mut sys::JNIEnv,
+    _string: jstring,
+    _chars: *const c_char,
+) {
+}
+
+unsafe extern "system" fn get_array_length(env: *mut sys::JNIEnv, array: jobject) -> jsize {
+    with_object(env, array, |object| match object {
+        Object::IntArray(values) => values.len() as jsize,
+        _ => 0,
+    })
+    .unwrap_or(0)
+}
+
+unsafe extern "system" fn new_int_array(env: *mut sys::JNIEnv, len: jsize) -> jintArray {
+    inner(env).push(Object::IntArray(vec![0; len.max(0) as usize]))
+}
+
+unsafe extern "system" fn get_int_array_region(
+    env: *mut sys::JNIEnv,
+    array: jintArray,
+    start: jsize,
+    len: jsize,
+    buf: *mut jint,
+) {
+    with_object(env, array, |object| {
+        if let Object::IntArray(values) = object {
+            let range = start as usize..(start + len) as usize;
+            if let Some(values) = values.get(range) {
+                std::ptr::copy_nonoverlapping(values.as_ptr(), buf, values.len());
+            }
+        }
+    });
+}
+
+unsafe extern "system" fn set_int_array_region(
+    env: *mut sys::JNIEnv,
+    array: jintArray,
+    start: jsize,
+    len: jsize,
+    buf: *const jint,
+) {
+    with_object(env, array, |object| {
+        if let Object::IntArray(values) = object {
+            let range = start as usize..(start + len) as usize;
+            if let Some(values) = values.get_mut(range) {
+                std::ptr::copy_nonoverlapping(buf, values.as_mut_ptr(), values.len());
+            }
+        }
+    });
+}
+
+#[test]
+fn test_stub_env() {
+    let stub = StubEnv::new();
+    let mut env = stub.env();
+
+    let string = env.new_string("hello").unwrap();
+    assert_eq!(String::from(env.get_string(&string).unwrap()), "hello");
+    assert_eq!(stub.string(string.as_raw()).as_deref(), Some("hello"));
+
+    let array = env.new_int_array(3).unwrap();
+    env.set_int_array_region(&array, 1, &[7, 8]).unwrap();
+    assert_eq!(stub.int_array(array.as_raw()), Some(vec![0, 7, 8]));
+    let mut buf = [0; 3];
+    env.get_int_array_region(&array, 0, &mut buf).unwrap();
+    assert_eq!(buf, [0, 7, 8]);
+
+    // An int[] is not a String.
+    let not_a_string = unsafe { crate::jni::objects::JString::from_raw(array.as_raw()) };
+    assert!(env.get_string(&not_a_string).is_err());
+    assert!(env.find_class("java/lang/Object").is_err());
+}
@@ -0,0 +1,508 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::Mutex,
+};
+
+use super::{MockApi, StubEnv};
+use crate::{
+    binding::{with_raw_app_args, ModuleAbi},
+    jni::{
+        objects::{JObjectArray, JString},
+        sys::{self, jboolean, jint, jintArray, jlong},
+        JNIEnv,
+    },
+    macros::PanicPolicy,
+    module::RawModule,
+    AppSpecializeArgs, Capabilities, MountExternal, PostSpecializeApi, ProcessDecision,
+    ProcessFilter, RuntimeFlags, ServerSpecializeArgs, ZygiskApi, ZygiskModule, ZygiskOption,
+};
+
+/// Runs a [ZygiskModule] through the lifecycle of the processes zygote forks, off-device.
+///
+/// Every run loads the module into a new simulated process, like Zygisk does after each fork: it
+/// calls [ZygiskModule::on_load()], then the `pre` and `post` specialize callbacks through the
+/// same glue as `zygisk_module!`, so [ZygiskModule::targets()] and the returned
+/// [ProcessDecision] are applied too. The callbacks get the API of a [MockApi], and a
+/// [StubEnv] unless a real `JNIEnv` is provided with [Self::with_jni_env()].
+///
+/// Panics in the module are propagated out of the run, failing the test.
+///
+/// ## Example
+///
+/// ```
+/// use zygisk::{
+///     jni::JNIEnv,
+///     testing::{AppProcess, Harness},
+///     AppSpecializeArgs, ProcessDecision, ProcessFilter, ZygiskApi, ZygiskModule, ZygiskOption,
+/// };
+///
+/// struct Module;
+///
+/// impl ZygiskModule for Module {
+///     fn targets(&self) -> ProcessFilter {
+///         ProcessFilter::apps().package("com.example.*")
+///     }
+///
+///     fn pre_app_specialize(
+///         &self,
+///         _api: ZygiskApi,
+///         mut env: JNIEnv,
+///         args: &mut AppSpecializeArgs,
+///     ) -> ProcessDecision {
+///         args.set_nice_name(&mut env, "com.example.renamed").unwrap();
+///         ProcessDecision::ForceDenylistUnmount
+///     }
+/// }
+///
+/// let harness = Harness::new(Module);
+///
+/// let mut app = AppProcess::new("com.example.app", 10123);
+/// let outcome = harness.run_app(&mut app);
+/// assert_eq!(outcome.options, [ZygiskOption::ForceDenylistUnmount]);
+/// assert_eq!(app.nice_name, "com.example.renamed");
+///
+/// let outcome = harness.run_app(&mut AppProcess::new("org.other", 10124));
+/// assert!(outcome.skipped);
+/// assert!(outcome.unloaded());
+/// ```
+pub struct Harness<M: ZygiskModule + 'static> {
+    module: &'static Guarded<M>,
+    mock: MockApi,
+    stub: StubEnv,
+    jni_env: Option<*mut sys::JNIEnv>,
+}
+
+/// The arguments of an app process run by a [Harness].
+///
+/// After the run, the fields hold the arguments as left by the module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppProcess {
+    pub nice_name: String,
+    pub uid: jint,
+    pub gid: jint,
+    pub gids: Vec<jint>,
+    pub runtime_flags: RuntimeFlags,
+    pub mount_external: MountExternal,
+    pub se_info: String,
+    pub instruction_set: String,
+    pub app_data_dir: String,
+    pub is_child_zygote: Option<bool>,
+    pub is_top_app: Option<bool>,
+}
+
+impl AppProcess {
+    /// A regular app of the primary user, with the arguments of a typical arm64 device.
+    pub fn new(nice_name: &str, uid: jint) -> Self {
+        AppProcess {
+            nice_name: nice_name.into(),
+            uid,
+            gid: uid,
+            gids: vec![3003, 9997],
+            runtime_flags: RuntimeFlags::empty(),
+            mount_external: MountExternal::Default,
+            se_info: "default:targetSdkVersion=34:complete".into(),
+            instruction_set: "arm64".into(),
+            app_data_dir: format!("/data/user/0/{nice_name}"),
+            is_child_zygote: Some(false),
+            is_top_app: Some(false),
+        }
+    }
+}
+
+/// The arguments of the `system_server` process run by a [Harness].
+///
+/// After the run, the fields hold the arguments as left by the module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerProcess {
+    pub uid: jint,
+    pub gid: jint,
+    pub gids: Vec<jint>,
+    pub runtime_flags: RuntimeFlags,
+    pub permitted_capabilities: Capabilities,
+    pub effective_capabilities: Capabilities,
+}
+
+impl Default for ServerProcess {
+    fn default() -> Self {
+        ServerProcess {
+            uid: 1000,
+            gid: 1000,
+            gids: vec![1001, 1002, 1003, 3003],
+            runtime_flags: RuntimeFlags::empty(),
+            permitted_capabilities: Capabilities::empty(),
+            effective_capabilities: Capabilities::empty(),
+        }
+    }
+}
+
+/// What happened in a process run by a [Harness].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    /// The options set during the run, in order.
+    pub options: Vec<ZygiskOption>,
+    /// Whether the `post` callback was skipped, because the process did not match
+    /// [ZygiskModule::targets()] or the module asked to be unloaded.
+    pub skipped: bool,
+}
+
+impl Outcome {
+    /// Whether the module library would be unloaded after the run.
+    pub fn unloaded(&self) -> bool {
+        self.options.contains(&ZygiskOption::DlcloseModuleLibrary)
+    }
+}
+
+impl<M: ZygiskModule + 'static> Harness<M> {
+    /// Run `module` against a [MockApi::new()].
+    pub fn new(module: M) -> Self {
+        Self::with_api(module, MockApi::new())
+    }
+
+    /// Run `module` against `mock`, such as one with flags or a companion.
+    ///
+    /// The module is leaked, like the glue of `zygisk_module!` does.
+    pub fn with_api(module: M, mock: MockApi) -> Self {
+        Harness {
+            module: Box::leak(Box::new(Guarded {
+                inner: module,
+                panic: Mutex::new(None),
+            })),
+            mock,
+            stub: StubEnv::new(),
+            jni_env: None,
+        }
+    }
+
+    /// Pass a real `JNIEnv` to the module instead of a [StubEnv].
+    ///
+    /// ## Safety
+    ///
+    /// `env` must stay valid for the lifetime of the harness, and belong to the thread running it.
+    pub unsafe fn with_jni_env(mut self, env: *mut sys::JNIEnv) -> Self {
+        self.jni_env = Some(env);
+        self
+    }
+
+    /// The module being run.
+    pub fn module(&self) -> &M {
+        &self.module.inner
+    }
+
+    /// The mock API, to check the hooks and fds recorded across runs.
+    pub fn mock(&self) -> &MockApi {
+        &self.mock
+    }
+
+    /// Load the module into a new app process and specialize it with `process`.
+    pub fn run_app(&self, process: &mut AppProcess) -> Outcome {
+        let mut env = self.env();
+        let new_string = |env: &mut JNIEnv, value: &str| {
+            let string = env
+                .new_string(value)
+                .expect("the JNIEnv cannot create strings");
+            // SAFETY: the reference stays valid for the whole run, like in zygote.
+            unsafe { JString::from_raw(string.into_raw()) }
+        };
+
+        let mut uid = process.uid;
+        let mut gid = process.gid;
+        let mut gids = new_int_array(&mut env, &process.gids);
+        let mut runtime_flags = process.runtime_flags.into();
+        // SAFETY: a null reference, which modules have to expect from zygote too.
+        let mut rlimits = unsafe { JObjectArray::from_raw(std::ptr::null_mut()) };
+        let mut mount_external = process.mount_external.into();
+        let mut se_info = new_string(&mut env, &process.se_info);
+        let mut nice_name = new_string(&mut env, &process.nice_name);
+        let mut instruction_set = new_string(&mut env, &process.instruction_set);
+        let mut app_data_dir = new_string(&mut env, &process.app_data_dir);
+        let is_child_zygote = process.is_child_zygote.map(jboolean::from);
+        let is_top_app = process.is_top_app.map(jboolean::from);
+        let mut args = AppSpecializeArgs {
+            uid: &mut uid,
+            gid: &mut gid,
+            gids: &mut gids,
+            runtime_flags: &mut runtime_flags,
+            rlimits: &mut rlimits,
+            mount_external: &mut mount_external,
+            se_info: &mut se_info,
+            nice_name: &mut nice_name,
+            instruction_set: &mut instruction_set,
+            app_data_dir: &mut app_data_dir,
+            fds_to_ignore: None,
+            is_child_zygote: is_child_zygote.as_ref(),
+            is_top_app: is_top_app.as_ref(),
+            pkg_data_info_list: None,
+            whitelisted_data_info_list: None,
+            mount_data_dirs: None,
+            mount_sysprop_overrides: None,
+            mount_storage_dirs: None,
+        };
+
+        let outcome = self.run(|abi, resume_panic| {
+            let version = abi.this.api_version;
+            with_raw_app_args(version, &mut args, |raw| {
+                (abi.pre_app_specialize)(abi.this, raw);
+                resume_panic();
+                // Zygisk always calls `post`, the glue skips the module if needed.
+                (abi.post_app_specialize)(abi.this, raw);
+            });
+        });
+
+        let string = |env: &mut JNIEnv, string: &JString| {
+            String::from(
+                env.get_string(string)
+                    .expect("the JNIEnv cannot read strings"),
+            )
+        };
+        process.uid = args.uid().into();
+        process.gid = args.gid();
+        process.gids = args
+            .gids(&mut env)
+            .to_vec()
+            .expect("the JNIEnv cannot read arrays");
+        process.runtime_flags = args.runtime_flags();
+        process.mount_external = args.mount_external();
+        process.se_info = string(&mut env, args.se_info);
+        process.nice_name = string(&mut env, args.nice_name);
+        process.instruction_set = string(&mut env, args.instruction_set);
+        process.app_data_dir = string(&mut env, args.app_data_dir);
+        outcome
+    }
+
+    /// Load the module into a new `system_server` process and specialize it with `process`.
+    pub fn run_server(&self, process: &mut ServerProcess) -> Outcome {
+        let mut env = self.env();
+        let mut uid = process.uid;
+        let mut gid = process.gid;
+        let mut gids = new_int_array(&mut env, &process.gids);
+        let mut runtime_flags = process.runtime_flags.into();
+        let mut permitted_capabilities = process.permitted_capabilities.bits() as jlong;
+        let mut effective_capabilities = process.effective_capabilities.bits() as jlong;
+        let mut args = ServerSpecializeArgs {
+            uid: &mut uid,
+            gid: &mut gid,
+            gids: &mut gids,
+            runtime_flags: &mut runtime_flags,
+            permitted_capabilities: &mut permitted_capabilities,
+            effective_capabilities: &mut effective_capabilities,
+        };
+
+        let outcome = self.run(|abi, resume_panic| {
+            (abi.pre_server_specialize)(abi.this, &mut args);
+            resume_panic();
+            // Zygisk always calls `post`, the glue skips the module if needed.
+            (abi.post_server_specialize)(abi.this, &args);
+        });
+
+        process.uid = args.uid().into();
+        process.gid = args.gid();
+        process.gids = args
+            .gids(&mut env)
+            .to_vec()
+            .expect("the JNIEnv cannot read arrays");
+        process.permitted_capabilities = args.permitted_capabilities();
+        process.effective_capabilities = args.effective_capabilities();
+        outcome
+    }
+
+    fn env(&self) -> JNIEnv<'_> {
+        let env = self.jni_env.unwrap_or_else(|| self.stub.as_raw());
+        // SAFETY: either the stub, or a valid environment as promised by the caller.
+        unsafe { JNIEnv::from_raw(env) }.expect("the JNIEnv is null")
+    }
+
+    /// Run a simulated process: `on_load`, then the callbacks called by `specialize`.
+    fn run(&self, specialize: impl FnOnce(&mut ModuleAbi, &dyn Fn())) -> Outcome {
+        let before = self.mock.options().len();
+        let api = self.mock.api();
+        // Every process gets its own copy of the module state, leaked like in `zygisk_module!`.
+        let abi = ModuleAbi::leak(RawModule {
+            inner: self.module,
+            api_table: self.mock.table(),
+            api_version: api.api_version(),
+            jni_env: self.env().get_raw(),
+            skipped: false,
+            panic_policy: PanicPolicy::Abort,
+        });
+
+        self.module.on_load(api, self.env());
+        self.module.resume_panic();
+        specialize(abi, &|| self.module.resume_panic());
+        self.module.resume_panic();
+
+        Outcome {
+            options: self.mock.options().split_off(before),
+            skipped: abi.this.skipped,
+        }
+    }
+}
+
+fn new_int_array(env: &mut JNIEnv, values: &[jint]) -> jintArray {
+    let array = env
+        .new_int_array(values.len() as _)
+        .expect("the JNIEnv cannot create arrays");
+    env.set_int_array_region(&array, 0, values)
+        .expect("the JNIEnv cannot write arrays");
+    array.into_raw()
+}
+
+/// Catches the panics of the module before the glue does, so that the harness can propagate them
+/// whatever the panic policy.
+struct Guarded<M> {
+    inner: M,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl<M> Guarded<M> {
+    fn guard<R: Default>(&self, f: impl FnOnce(&M) -> R) -> R {
+        panic::catch_unwind(AssertUnwindSafe(|| f(&self.inner))).unwrap_or_else(|payload| {
+            *self.panic.lock().unwrap_or_else(|e| e.into_inner()) = Some(payload);
+            R::default()
+        })
+    }
+
+    fn resume_panic(&self) {
+        if let Some(payload) = self.panic.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl<M: ZygiskModule> ZygiskModule for Guarded<M> {
+    fn on_load(&self, api: ZygiskApi, env: JNIEnv) {
+        self.guard(|module| module.on_load(api, env))
+    }
+
+    fn targets(&self) -> ProcessFilter {
+        self.guard(|module| module.targets())
+    }
+
+    fn pre_app_specialize(
+        &self,
+        api: ZygiskApi,
+        env: JNIEnv,
+        args: &mut AppSpecializeArgs,
+    ) -> ProcessDecision {
+        self.guard(|module| module.pre_app_specialize(api, env, args))
+    }
+
+    fn post_app_specialize(&self, api: PostSpecializeApi, env: JNIEnv, args: &AppSpecializeArgs) {
+        self.guard(|module| module.post_app_specialize(api, env, args))
+    }
+
+    fn pre_server_specialize(&self, api: ZygiskApi, env: JNIEnv, args: &mut ServerSpecializeArgs) {
+        self.guard(|module| module.pre_server_specialize(api, env, args))
+    }
+
+    fn post_server_specialize(
+        &self,
+        api: PostSpecializeApi,
+        env: JNIEnv,
+        args: &ServerSpecializeArgs,
+    ) {
+        self.guard(|module| module.post_server_specialize(api, env, args))
+    }
+}
+
+#[test]
+fn test_harness() {
+    use crate::StateFlags;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Module {
+        loads: AtomicUsize,
+        posts: AtomicUsize,
+    }
+
+    impl ZygiskModule for Module {
+        fn on_load(&self, _api: ZygiskApi, _env: JNIEnv) {
+            self.loads.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn pre_app_specialize(
+            &self,
+            api: ZygiskApi,
+            mut env: JNIEnv,
+            args: &mut AppSpecializeArgs,
+        ) -> ProcessDecision {
+            if args.nice_name(&mut env).unwrap() == "panic" {
+                panic!("asked to");
+            }
+            args.gids(&mut env).push(1234).unwrap();
+            args.set_se_info(&mut env, "changed").unwrap();
+            if api
+                .get_flags()
+                .unwrap()
+                .contains(StateFlags::PROCESS_ON_DENYLIST)
+            {
+                ProcessDecision::ForceDenylistUnmount
+            } else {
+                ProcessDecision::SkipAndUnload
+            }
+        }
+
+        fn post_app_specialize(
+            &self,
+            api: PostSpecializeApi,
+            _env: JNIEnv,
+            _args: &AppSpecializeArgs,
+        ) {
+            self.posts.fetch_add(1, Ordering::Relaxed);
+            assert!(api.get_flags().is_ok());
+        }
+
+        fn pre_server_specialize(
+            &self,
+            _api: ZygiskApi,
+            _env: JNIEnv,
+            args: &mut ServerSpecializeArgs,
+        ) {
+            args.set_permitted_capabilities(Capabilities::KILL);
+        }
+    }
+
+    let harness = Harness::with_api(
+        Module::default(),
+        MockApi::new().flags(StateFlags::PROCESS_ON_DENYLIST),
+    );
+    let mut app = AppProcess::new("com.example", 10001);
+    let outcome = harness.run_app(&mut app);
+    assert_eq!(outcome.options, [ZygiskOption::ForceDenylistUnmount]);
+    assert!(!outcome.skipped && !outcome.unloaded());
+    assert!(app.gids.ends_with(&[1234]));
+    assert_eq!(app.se_info, "changed");
+    assert_eq!(app.nice_name, "com.example");
+
+    let mut server = ServerProcess::default();
+    assert!(harness.run_server(&mut server).options.is_empty());
+    assert_eq!(server.permitted_capabilities, Capabilities::KILL);
+    assert_eq!(harness.module().loads.load(Ordering::Relaxed), 2);
+    assert_eq!(harness.module().posts.load(Ordering::Relaxed), 1);
+
+    let harness = Harness::new(Module::default());
+    let outcome = harness.run_app(&mut AppProcess::new("com.example", 10001));
+    // Hooks of other tests, which share the hook registry, may keep the library loaded.
+    assert!(outcome.skipped);
+    assert!(outcome
+        .options
+        .iter()
+        .all(|option| *option == ZygiskOption::DlcloseModuleLibrary));
+    assert_eq!(harness.module().posts.load(Ordering::Relaxed), 0);
+
+    let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+        harness.run_app(&mut AppProcess::new("panic", 10002))
+    }));
+    assert_eq!(
+        *panicked.unwrap_err().downcast::<&str>().unwrap(),
+        "asked to"
+    );
+
+    // The module unwraps the flags, which the mock does not provide here.
+    let harness = Harness::with_api(Module::default(), MockApi::new().without("get_flags"));
+    let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+        harness.run_app(&mut AppProcess::new("com.example", 10001))
+    }));
+    assert!(panicked.is_err());
+}
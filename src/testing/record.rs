@@ -0,0 +1,330 @@
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    os::raw::{c_char, c_int},
+    sync::Mutex,
+};
+
+use super::{lock, string, Call, Trace};
+use crate::{
+    binding::{LegacyApiTable, RawApiTable},
+    jni::sys::{JNIEnv, JNINativeMethod},
+    libc::{dev_t, ino_t},
+    ApiVersion, ZygiskApi, ZygiskOption,
+};
+
+thread_local! {
+    /// The recorder whose API was handed out last on this thread, for the table functions that
+    /// Zygisk does not pass a context to.
+    static CURRENT: Cell<*const RecorderState> = const { Cell::new(std::ptr::null()) };
+}
+
+/// Records the calls made to the Zygisk API and what the host returned, to replay them off-device
+/// with [MockApi::replay()](super::MockApi::replay).
+///
+/// The recorder wraps the API handle passed to a callback: use the handle returned by
+/// [Self::api()] in place of the original one, then save the [Trace], for example with the
+/// companion. Enable the `testing` feature in the build of the module that records the trace.
+///
+/// ## Example
+///
+/// ```no_run
+/// use zygisk::{jni::JNIEnv, testing::Recorder, AppSpecializeArgs, ProcessDecision, ZygiskApi};
+///
+/// fn pre_app_specialize(api: ZygiskApi, env: JNIEnv, args: &mut AppSpecializeArgs) {
+///     let recorder = Recorder::new(&api);
+///     let decision = module_logic(recorder.api(), env, args);
+///     let trace = recorder.trace().to_string();
+///     // Send the trace to the companion, which saves it in the module directory.
+/// }
+///
+/// fn module_logic(api: ZygiskApi, env: JNIEnv, args: &mut AppSpecializeArgs) -> ProcessDecision {
+///     // ...
+///     ProcessDecision::Continue
+/// }
+/// ```
+pub struct Recorder<'a> {
+    state: Box<RecorderState>,
+    table: Box<RawApiTable>,
+    _api: PhantomData<&'a RawApiTable>,
+}
+
+struct RecorderState {
+    original: *const RawApiTable,
+    version: ApiVersion,
+    calls: Mutex<Vec<Call>>,
+}
+
+// Gets a function of the original table, whichever its layout.
+macro_rules! original {
+    ($state: expr, $name: ident) => {
+        match $state.legacy() {
+            Some(table) => table.$name,
+            None => $state.current().$name,
+        }
+    };
+}
+
+impl<'a> Recorder<'a> {
+    /// Start recording the calls made through [Self::api()], which forwards them to `api`.
+    pub fn new(api: &ZygiskApi<'a>) -> Self {
+        let version = api.api_version();
+        let state = Box::new(RecorderState {
+            original: api.raw_table(),
+            version,
+            calls: Mutex::new(Vec::new()),
+        });
+        let table = Box::new(state.table());
+        Recorder {
+            state,
+            table,
+            _api: PhantomData,
+        }
+    }
+
+    /// The API handle that records the calls.
+    pub fn api(&self) -> ZygiskApi<'_> {
+        CURRENT.with(|current| current.set(&*self.state));
+        ZygiskApi::from_raw(&self.table, self.state.version)
+    }
+
+    /// The calls recorded so far.
+    pub fn trace(&self) -> Trace {
+        Trace {
+            calls: lock(&self.state.calls).clone(),
+        }
+    }
+}
+
+impl Drop for Recorder<'_> {
+    fn drop(&mut self) {
+        CURRENT.with(|current| {
+            if std::ptr::eq(current.get(), &*self.state) {
+                current.set(std::ptr::null());
+            }
+        });
+    }
+}
+
+impl RecorderState {
+    fn current(&self) -> &RawApiTable {
+        // SAFETY: the recorder does not outlive the API it was created from.
+        unsafe { &*self.original }
+    }
+
+    fn legacy(&self) -> Option<&LegacyApiTable> {
+        // SAFETY: see `ZygiskApi::legacy()`.
+        (self.version < ApiVersion::V4).then(|| unsafe { &*self.original.cast::<LegacyApiTable>() })
+    }
+
+    fn record(&self, call: Call) {
+        lock(&self.calls).push(call);
+    }
+
+    /// A table in the same layout as the original, with the same functions available.
+    fn table(&self) -> RawApiTable {
+        let this = self as *const RecorderState as *const ();
+        let hook_jni_native_methods =
+            original!(self, hook_jni_native_methods).map(|_| hook_jni_native_methods as _);
+        let plt_hook_commit = original!(self, plt_hook_commit).map(|_| plt_hook_commit as _);
+        let connect_companion = original!(self, connect_companion).map(|_| connect_companion as _);
+        let set_option = original!(self, set_option).map(|_| set_option as _);
+        let get_module_dir = original!(self, get_module_dir).map(|_| get_module_dir as _);
+        let get_flags = original!(self, get_flags).map(|_| get_flags as _);
+
+        match self.legacy() {
+            None => {
+                let original = self.current();
+                RawApiTable {
+                    this,
+                    register_module: None,
+                    hook_jni_native_methods,
+                    plt_hook_register: original.plt_hook_register.map(|_| plt_hook_register as _),
+                    plt_hook_commit,
+                    connect_companion,
+                    set_option,
+                    get_module_dir,
+                    get_flags,
+                    exempt_fd: original.exempt_fd.map(|_| exempt_fd as _),
+                }
+            }
+            Some(original) => {
+                let legacy = LegacyApiTable {
+                    this,
+                    register_module: None,
+                    hook_jni_native_methods,
+                    plt_hook_register: original
+                        .plt_hook_register
+                        .map(|_| plt_hook_register_regex as _),
+                    plt_hook_exclude: original.plt_hook_exclude.map(|_| plt_hook_exclude as _),
+                    plt_hook_commit,
+                    connect_companion,
+                    set_option,
+                    get_module_dir,
+                    get_flags,
+                };
+                // SAFETY: see `MockState::table()`.
+                unsafe { std::mem::transmute::<LegacyApiTable, RawApiTable>(legacy) }
+            }
+        }
+    }
+}
+
+fn state<'a>(this: *const ()) -> &'a RecorderState {
+    // SAFETY: `this` is the state of the recorder that handed out the API.
+    unsafe { &*this.cast::<RecorderState>() }
+}
+
+fn with_current<R>(f: impl FnOnce(&RecorderState) -> R) -> R {
+    let current = CURRENT.with(Cell::get);
+    assert!(!current.is_null(), "recorded API used after the recorder");
+    // SAFETY: the pointer is cleared when the recorder is dropped.
+    f(unsafe { &*current })
+}
+
+// Each function is only in the recording table if the original one is available.
+
+extern "C" fn connect_companion(this: *const ()) -> c_int {
+    let state = state(this);
+    let fd = original!(state, connect_companion).unwrap()(state.current().this);
+    state.record(Call::ConnectCompanion { success: fd >= 0 });
+    fd
+}
+
+extern "C" fn set_option(this: *const (), option: ZygiskOption) {
+    let state = state(this);
+    original!(state, set_option).unwrap()(state.current().this, option);
+    state.record(Call::SetOption(option));
+}
+
+extern "C" fn get_module_dir(this: *const ()) -> c_int {
+    let state = state(this);
+    let fd = original!(state, get_module_dir).unwrap()(state.current().this);
+    state.record(Call::GetModuleDir { success: fd >= 0 });
+    fd
+}
+
+extern "C" fn get_flags(this: *const ()) -> u32 {
+    let state = state(this);
+    let flags = original!(state, get_flags).unwrap()(state.current().this);
+    state.record(Call::GetFlags(flags));
+    flags
+}
+
+extern "C" fn exempt_fd(fd: c_int) -> bool {
+    with_current(|state| {
+        let success = state.current().exempt_fd.unwrap()(fd);
+        state.record(Call::ExemptFd { fd, success });
+        success
+    })
+}
+
+extern "C" fn hook_jni_native_methods(
+    env: *mut JNIEnv,
+    class: *const c_char,
+    methods: *mut JNINativeMethod,
+    count: c_int,
+) {
+    with_current(|state| {
+        original!(state, hook_jni_native_methods).unwrap()(env, class, methods, count);
+        // SAFETY: the crate passes the methods it was given, which are valid for `count` entries.
+        let methods = unsafe { std::slice::from_raw_parts(methods, count as usize) };
+        for method in methods {
+            state.record(Call::HookJniNativeMethod {
+                class: string(class),
+                name: string(method.name),
+                signature: string(method.signature),
+                found: !method.fnPtr.is_null(),
+            });
+        }
+    })
+}
+
+extern "C" fn plt_hook_register(
+    device: dev_t,
+    inode: ino_t,
+    symbol: *const c_char,
+    new_func: *mut (),
+    old_func: *mut *mut (),
+) {
+    with_current(|state| {
+        state.current().plt_hook_register.unwrap()(device, inode, symbol, new_func, old_func);
+        state.record(Call::PltHookRegister {
+            library: format!("{device}:{inode}"),
+            symbol: string(symbol),
+        });
+    })
+}
+
+extern "C" fn plt_hook_register_regex(
+    regex: *const c_char,
+    symbol: *const c_char,
+    new_func: *mut (),
+    old_func: *mut *mut (),
+) {
+    with_current(|state| {
+        let original = state.legacy().and_then(|table| table.plt_hook_register);
+        original.unwrap()(regex, symbol, new_func, old_func);
+        state.record(Call::PltHookRegister {
+            library: string(regex),
+            symbol: string(symbol),
+        });
+    })
+}
+
+extern "C" fn plt_hook_exclude(regex: *const c_char, symbol: *const c_char) {
+    with_current(|state| {
+        let original = state.legacy().and_then(|table| table.plt_hook_exclude);
+        original.unwrap()(regex, symbol);
+        state.record(Call::PltHookExclude {
+            regex: string(regex),
+            symbol: string(symbol),
+        });
+    })
+}
+
+extern "C" fn plt_hook_commit() -> bool {
+    with_current(|state| {
+        let success = original!(state, plt_hook_commit).unwrap()();
+        state.record(Call::PltHookCommit { success });
+        success
+    })
+}
+
+#[test]
+fn test_record_and_replay() {
+    use super::MockApi;
+    use crate::StateFlags;
+
+    fn module_logic(api: &ZygiskApi) {
+        let flags = api.get_flags().unwrap();
+        if flags.contains(StateFlags::PROCESS_ON_DENYLIST) {
+            api.set_option(ZygiskOption::ForceDenylistUnmount).unwrap();
+        }
+        let _ = api.connect_companion();
+        let _ = api.plt_hook_commit();
+    }
+
+    // Record against a mock standing in for a device.
+    let device = MockApi::new().flags(StateFlags::PROCESS_ON_DENYLIST);
+    let device_api = device.api();
+    let recorder = Recorder::new(&device_api);
+    module_logic(&recorder.api());
+    let trace = recorder.trace();
+    assert_eq!(
+        trace.calls,
+        [
+            Call::GetFlags(2),
+            Call::SetOption(ZygiskOption::ForceDenylistUnmount),
+            Call::ConnectCompanion { success: false },
+            Call::PltHookCommit { success: true },
+        ]
+    );
+    drop(recorder);
+
+    let text = trace.to_string();
+    let replay = MockApi::new().replay(text.parse().unwrap());
+    module_logic(&replay.api());
+    replay.assert_replayed();
+    assert_eq!(replay.options(), [ZygiskOption::ForceDenylistUnmount]);
+}
@@ -0,0 +1,263 @@
+use std::{fmt, os::fd::RawFd, str::FromStr};
+
+use crate::ZygiskOption;
+
+/// A call to the Zygisk API, with what the host returned, as recorded by a
+/// [Recorder](super::Recorder).
+///
+/// File descriptors returned by the host cannot be replayed, so only whether the call succeeded
+/// is kept for them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+    /// One method of a call to `hook_jni_native_methods`, and whether the host found it.
+    HookJniNativeMethod {
+        class: String,
+        name: String,
+        signature: String,
+        found: bool,
+    },
+    /// `plt_hook_register`, with the ELF as `"<device>:<inode>"` or the path regular expression
+    /// before [ApiVersion::V4](crate::ApiVersion::V4).
+    PltHookRegister {
+        library: String,
+        symbol: String,
+    },
+    PltHookExclude {
+        regex: String,
+        symbol: String,
+    },
+    PltHookCommit {
+        success: bool,
+    },
+    ConnectCompanion {
+        success: bool,
+    },
+    SetOption(ZygiskOption),
+    GetModuleDir {
+        success: bool,
+    },
+    GetFlags(u32),
+    ExemptFd {
+        fd: RawFd,
+        success: bool,
+    },
+}
+
+impl Call {
+    /// The name of the API table function, as in
+    /// [ZygiskError::ApiFunctionUnavailable](crate::ZygiskError::ApiFunctionUnavailable).
+    pub fn function(&self) -> &'static str {
+        match self {
+            Call::HookJniNativeMethod { .. } => "hook_jni_native_methods",
+            Call::PltHookRegister { .. } => "plt_hook_register",
+            Call::PltHookExclude { .. } => "plt_hook_exclude",
+            Call::PltHookCommit { .. } => "plt_hook_commit",
+            Call::ConnectCompanion { .. } => "connect_companion",
+            Call::SetOption(_) => "set_option",
+            Call::GetModuleDir { .. } => "get_module_dir",
+            Call::GetFlags(_) => "get_flags",
+            Call::ExemptFd { .. } => "exempt_fd",
+        }
+    }
+}
+
+/// Fields are separated by tabs, so that they may contain spaces.
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.function())?;
+        match self {
+            Call::HookJniNativeMethod {
+                class,
+                name,
+                signature,
+                found,
+            } => write!(f, "\t{class}\t{name}\t{signature}\t{found}"),
+            Call::PltHookRegister { library, symbol } => write!(f, "\t{library}\t{symbol}"),
+            Call::PltHookExclude { regex, symbol } => write!(f, "\t{regex}\t{symbol}"),
+            Call::PltHookCommit { success }
+            | Call::ConnectCompanion { success }
+            | Call::GetModuleDir { success } => write!(f, "\t{success}"),
+            Call::SetOption(option) => write!(f, "\t{option:?}"),
+            Call::GetFlags(flags) => write!(f, "\t{flags:#x}"),
+            Call::ExemptFd { fd, success } => write!(f, "\t{fd}\t{success}"),
+        }
+    }
+}
+
+impl FromStr for Call {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut fields = line.split('\t');
+        let function = fields.next().unwrap_or_default();
+        let fields: Vec<&str> = fields.collect();
+        let expect = |count: usize| {
+            if fields.len() == count {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{function} takes {count} fields, found {}",
+                    fields.len()
+                ))
+            }
+        };
+        let boolean = |field: &str| {
+            field
+                .parse::<bool>()
+                .map_err(|_| format!("expected true or false, found {field:?}"))
+        };
+
+        let call = match function {
+            "hook_jni_native_methods" => {
+                expect(4)?;
+                Call::HookJniNativeMethod {
+                    class: fields[0].into(),
+                    name: fields[1].into(),
+                    signature: fields[2].into(),
+                    found: boolean(fields[3])?,
+                }
+            }
+            "plt_hook_register" => {
+                expect(2)?;
+                Call::PltHookRegister {
+                    library: fields[0].into(),
+                    symbol: fields[1].into(),
+                }
+            }
+            "plt_hook_exclude" => {
+                expect(2)?;
+                Call::PltHookExclude {
+                    regex: fields[0].into(),
+                    symbol: fields[1].into(),
+                }
+            }
+            "plt_hook_commit" | "connect_companion" | "get_module_dir" => {
+                expect(1)?;
+                let success = boolean(fields[0])?;
+                match function {
+                    "plt_hook_commit" => Call::PltHookCommit { success },
+                    "connect_companion" => Call::ConnectCompanion { success },
+                    _ => Call::GetModuleDir { success },
+                }
+            }
+            "set_option" => {
+                expect(1)?;
+                Call::SetOption(match fields[0] {
+                    "ForceDenylistUnmount" => ZygiskOption::ForceDenylistUnmount,
+                    "DlcloseModuleLibrary" => ZygiskOption::DlcloseModuleLibrary,
+                    other => return Err(format!("unknown option {other:?}")),
+                })
+            }
+            "get_flags" => {
+                expect(1)?;
+                let flags = fields[0].trim_start_matches("0x");
+                Call::GetFlags(
+                    u32::from_str_radix(flags, 16)
+                        .map_err(|_| format!("invalid flags {:?}", fields[0]))?,
+                )
+            }
+            "exempt_fd" => {
+                expect(2)?;
+                Call::ExemptFd {
+                    fd: fields[0]
+                        .parse()
+                        .map_err(|_| format!("invalid fd {:?}", fields[0]))?,
+                    success: boolean(fields[1])?,
+                }
+            }
+            other => return Err(format!("unknown function {other:?}")),
+        };
+        Ok(call)
+    }
+}
+
+/// A sequence of API calls, as recorded by a [Recorder](super::Recorder) and replayed by
+/// [MockApi::replay()](super::MockApi::replay).
+///
+/// Traces are saved as text, one [Call] per line, so that they can be checked in next to the
+/// tests and reviewed in diffs. Empty lines and lines starting with `#` are ignored when parsing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub calls: Vec<Call>,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for call in &self.calls {
+            writeln!(f, "{call}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Trace {
+    type Err = ParseTraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let calls = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| {
+                line.parse().map_err(|reason| ParseTraceError {
+                    line: index + 1,
+                    reason,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Trace { calls })
+    }
+}
+
+/// The error returned when parsing a [Trace] fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTraceError {
+    /// The line of the invalid call, starting at 1.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid trace at line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseTraceError {}
+
+#[test]
+fn test_trace_round_trip() {
+    let trace = Trace {
+        calls: vec![
+            Call::GetFlags(0x8000_0002),
+            Call::SetOption(ZygiskOption::DlcloseModuleLibrary),
+            Call::HookJniNativeMethod {
+                class: "android/os/Process".into(),
+                name: "setArgV0".into(),
+                signature: "(Ljava/lang/String;)V".into(),
+                found: true,
+            },
+            Call::PltHookRegister {
+                library: ".*/lib c\\.so$".into(),
+                symbol: "open".into(),
+            },
+            Call::ExemptFd {
+                fd: 42,
+                success: false,
+            },
+            Call::ConnectCompanion { success: true },
+        ],
+    };
+    let text = trace.to_string();
+    assert_eq!(text.lines().next(), Some("get_flags\t0x80000002"));
+    assert_eq!(
+        format!("# recorded on a device\n\n{text}").parse(),
+        Ok(trace)
+    );
+
+    let error = "get_flags\t0x2\nset_option\tNope\n"
+        .parse::<Trace>()
+        .unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(error.to_string().contains("\"Nope\""));
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+
+use crate::jni::sys::jint;
+
+/// An Android uid, which encodes the user the process runs for and the app it belongs to
+/// (`android.os.UserHandle` in AOSP).
+///
+/// Each user gets a range of [Self::PER_USER_RANGE] uids, so the same app has a different uid in
+/// each user but always the same [app id](Self::app_id).
+///
+/// ## Example
+///
+/// ```
+/// use zygisk::Uid;
+///
+/// // An app installed in a work profile.
+/// let uid = Uid::of(10, 10_123);
+/// assert_eq!(uid, Uid::from(1_010_123));
+/// assert_eq!(uid.user_id(), 10);
+/// assert_eq!(uid.app_id(), 10_123);
+/// assert!(uid.is_app());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Uid(jint);
+
+impl Uid {
+    /// The number of uids reserved for each user.
+    pub const PER_USER_RANGE: jint = 100_000;
+
+    pub const ROOT: Uid = Uid(0);
+    /// The uid of `system_server`.
+    pub const SYSTEM: Uid = Uid(1000);
+
+    /// The first app id of installed apps.
+    pub const FIRST_APPLICATION_ID: jint = 10_000;
+    /// The last app id of installed apps.
+    pub const LAST_APPLICATION_ID: jint = 19_999;
+
+    /// The first app id of isolated processes spawned by app zygotes.
+    pub const FIRST_APP_ZYGOTE_ISOLATED_ID: jint = 90_000;
+    /// The first app id of isolated processes, such as isolated services.
+    pub const FIRST_ISOLATED_ID: jint = 99_000;
+    /// The last app id of isolated processes.
+    pub const LAST_ISOLATED_ID: jint = 99_999;
+
+    /// The uid of an app id in a user.
+    pub const fn of(user_id: jint, app_id: jint) -> Uid {
+        Uid(user_id * Self::PER_USER_RANGE + app_id % Self::PER_USER_RANGE)
+    }
+
+    pub const fn as_raw(self) -> jint {
+        self.0
+    }
+
+    /// The user the process runs for: 0 for the primary user, and for example 10 for a work
+    /// profile.
+    pub const fn user_id(self) -> jint {
+        self.0 / Self::PER_USER_RANGE
+    }
+
+    /// The uid without the user, identical for an app in every user.
+    pub const fn app_id(self) -> jint {
+        self.0 % Self::PER_USER_RANGE
+    }
+
+    /// Whether the uid belongs to an isolated process, including those spawned by app zygotes.
+    pub const fn is_isolated(self) -> bool {
+        let app_id = self.app_id();
+        app_id >= Self::FIRST_APP_ZYGOTE_ISOLATED_ID && app_id <= Self::LAST_ISOLATED_ID
+    }
+
+    /// Whether the uid belongs to an installed app.
+    pub const fn is_app(self) -> bool {
+        let app_id = self.app_id();
+        app_id >= Self::FIRST_APPLICATION_ID && app_id <= Self::LAST_APPLICATION_ID
+    }
+
+    /// Whether the uid is one of the core system uids below the app range, such as
+    /// [Self::SYSTEM], `radio` or `bluetooth`, in any user.
+    pub const fn is_system(self) -> bool {
+        self.app_id() < Self::FIRST_APPLICATION_ID
+    }
+}
+
+impl From<jint> for Uid {
+    fn from(raw: jint) -> Self {
+        Uid(raw)
+    }
+}
+
+impl From<Uid> for jint {
+    fn from(uid: Uid) -> Self {
+        uid.0
+    }
+}
+
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[test]
+fn test_uid() {
+    let system = Uid::SYSTEM;
+    assert_eq!((system.user_id(), system.app_id()), (0, 1000));
+    assert!(system.is_system() && !system.is_app() && !system.is_isolated());
+    assert!(Uid::of(10, 1000).is_system());
+
+    let app = Uid::from(1_010_123);
+    assert_eq!(app, Uid::of(10, 10_123));
+    assert!(app.is_app() && !app.is_system() && !app.is_isolated());
+
+    assert!(Uid::of(0, 99_000).is_isolated());
+    assert!(Uid::of(11, 90_001).is_isolated());
+    assert!(!Uid::of(0, 89_999).is_isolated());
+    assert_eq!(app.to_string(), "1010123");
+}
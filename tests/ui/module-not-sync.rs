@@ -0,0 +1,9 @@
+use std::cell::Cell;
+use zygisk::{zygisk_module, ZygiskModule};
+
+struct CounterModule(Cell<u32>);
+impl ZygiskModule for CounterModule {}
+
+zygisk_module!(Box::leak(Box::new(CounterModule(Cell::new(0)))));
+
+fn main() {}
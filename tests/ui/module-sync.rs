@@ -0,0 +1,10 @@
+use std::sync::atomic::AtomicU32;
+use zygisk::{zygisk_module, ZygiskModule};
+
+struct CounterModule(AtomicU32);
+impl ZygiskModule for CounterModule {}
+
+static MODULE: CounterModule = CounterModule(AtomicU32::new(0));
+zygisk_module!(&MODULE);
+
+fn main() {}